@@ -130,8 +130,15 @@ raise CustomError("This is a custom error with details")
         let result = sandbox.execute(code, None).await?;
 
         if !result.is_success() {
-            if let Some(exc) = parse_python_exception(&result.stderr) {
-                println!("Caught: {}", exc);
+            if let Some(SandboxError::SyntaxError {
+                message,
+                line,
+                offset,
+                ..
+            }) = parse_python_exception(&result.stderr)
+            {
+                println!("Caught: SyntaxError: {}", message);
+                println!("  Line: {:?}, column: {:?}", line, offset);
             }
         }
     }
@@ -1,13 +1,47 @@
 //! Error types for the Python sandbox.
 
+use std::fmt;
+
 use thiserror::Error;
 
+/// Phase of sandbox execution in which a timeout occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Instantiating the interpreter module, including its start function.
+    Instantiation,
+    /// Running the interpreter and the user code.
+    Execution,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutPhase::Instantiation => f.write_str("instantiation"),
+            TimeoutPhase::Execution => f.write_str("execution"),
+        }
+    }
+}
+
+/// Output captured before an execution was interrupted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartialOutput {
+    /// Stdout written before the interruption.
+    pub stdout: String,
+    /// Stderr written before the interruption.
+    pub stderr: String,
+}
+
 /// Errors that can occur during sandbox execution.
 #[derive(Error, Debug)]
 pub enum SandboxError {
     /// The execution exceeded the configured timeout.
-    #[error("execution timed out after {0:?}")]
-    Timeout(std::time::Duration),
+    #[error("{phase} timed out after {elapsed:?}")]
+    Timeout {
+        /// Time elapsed before the timeout was detected.
+        elapsed: std::time::Duration,
+        /// Phase that was running when the timeout occurred.
+        phase: TimeoutPhase,
+    },
 
     /// The execution exceeded memory limits.
     #[error("memory limit exceeded: {0}")]
@@ -36,6 +70,23 @@ pub enum SandboxError {
         traceback: Option<String>,
     },
 
+    /// Python rejected the code with a `SyntaxError`.
+    ///
+    /// Other exceptions, including the `IndentationError` and `TabError`
+    /// subclasses, are reported as `PythonException`.
+    #[error("Python SyntaxError: {message}{}", line.map(|line| format!(" (line {line})")).unwrap_or_default())]
+    SyntaxError {
+        /// The exception message.
+        message: String,
+        /// Line number from the innermost `File "...", line N` frame.
+        line: Option<u32>,
+        /// 1-based column of the `^` marker, counted within the source line
+        /// as printed, i.e. after Python strips its indentation.
+        offset: Option<u32>,
+        /// The full Python traceback, if available.
+        traceback: Option<String>,
+    },
+
     /// I/O error during execution.
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
@@ -54,6 +105,110 @@ pub enum SandboxError {
         /// Number of instructions consumed before running out.
         consumed: Option<u64>,
     },
+
+    /// The interpreter exhausted the Wasm call stack.
+    ///
+    /// Usually caused by deep or unbounded recursion that overflows the
+    /// native stack before Python's own recursion limit is reached.
+    #[error("execution exhausted the wasm call stack (likely unbounded recursion)")]
+    StackOverflow,
+
+    /// Execution raised more Python exceptions than the configured cap.
+    #[error("exception limit exceeded: more than {limit} exceptions raised")]
+    ExceptionLimitExceeded {
+        /// The configured maximum number of exceptions.
+        limit: u64,
+    },
+
+    /// Time spent in host (WASI) calls exceeded the configured limit.
+    #[error("time spent in host calls exceeded limit of {0:?}")]
+    IoTimeExceeded(std::time::Duration),
+
+    /// Output was written faster than the configured rate.
+    #[error("output rate exceeded: {rate} bytes/s (limit {limit} bytes/s)")]
+    OutputRateExceeded {
+        /// Average rate of the window that went over the limit, in bytes per second.
+        rate: u64,
+        /// The configured maximum rate in bytes per second.
+        limit: u64,
+    },
+
+    /// Files written to `/out` exceeded the output limit.
+    ///
+    /// See `SandboxConfig::collect_output_files`.
+    #[error("files written to /out exceeded the limit of {limit} bytes")]
+    OutputFilesExceeded {
+        /// The configured `max_output_bytes`.
+        limit: u64,
+    },
+
+    /// A value captured from the interpreter exceeded the size limit.
+    #[error("captured value too large: {size} bytes (limit {limit} bytes)")]
+    ValueTooLarge {
+        /// Size of the value in bytes.
+        size: u64,
+        /// The configured maximum size in bytes.
+        limit: u64,
+    },
+
+    /// The sandbox is older than its configured maximum age.
+    ///
+    /// Create a new sandbox to continue, see `SandboxConfig::max_age`.
+    #[error("sandbox expired: created {age:?} ago (max age {max_age:?})")]
+    Expired {
+        /// Time since the sandbox was created.
+        age: std::time::Duration,
+        /// The configured maximum age.
+        max_age: std::time::Duration,
+    },
+
+    /// The execution was cancelled by the caller before it finished.
+    #[error("execution was cancelled")]
+    Cancelled,
+
+    /// No pooled sandbox became free within the pool's acquire timeout.
+    #[error("no pooled sandbox became free within {waited:?}")]
+    PoolExhausted {
+        /// How long the caller waited.
+        waited: std::time::Duration,
+    },
+
+    /// The interpreter hit a Wasm trap that is not a Python-level error.
+    ///
+    /// Only returned when a coredump was written for the trap, see
+    /// `SharedEngine::with_coredump`.
+    #[error("interpreter trapped: {message} (coredump written to {})", coredump.display())]
+    InterpreterTrap {
+        /// Description of the trap.
+        message: String,
+        /// Path of the coredump file.
+        coredump: std::path::PathBuf,
+    },
+
+    /// Execution was interrupted after producing output.
+    ///
+    /// Only returned when `SandboxConfig::flush_on_trap` is set. The `is_*`
+    /// helpers look through this wrapper to the underlying error.
+    #[error("{error}")]
+    Interrupted {
+        /// The error that interrupted execution.
+        error: Box<SandboxError>,
+        /// Output captured before the interruption.
+        output: PartialOutput,
+    },
+
+    /// An execution started with `PythonSandbox::execute_labeled` failed.
+    ///
+    /// Carries the label, so the failure can be matched to the request
+    /// that caused it. The `is_*` helpers look through this wrapper to the
+    /// underlying error.
+    #[error("{label}: {error}")]
+    Labeled {
+        /// The label the execution was started with.
+        label: String,
+        /// The error the execution failed with.
+        error: Box<SandboxError>,
+    },
 }
 
 impl SandboxError {
@@ -64,44 +219,197 @@ impl SandboxError {
         parse_python_exception(stderr)
     }
 
+    /// Get the underlying error, looking through `Interrupted` and `Labeled`.
+    pub fn cause(&self) -> &SandboxError {
+        match self {
+            SandboxError::Interrupted { error, .. } | SandboxError::Labeled { error, .. } => {
+                error.cause()
+            }
+            other => other,
+        }
+    }
+
+    /// Get the output captured before an interruption, if preserved.
+    pub fn partial_output(&self) -> Option<&PartialOutput> {
+        match self {
+            SandboxError::Interrupted { output, .. } => Some(output),
+            SandboxError::Labeled { error, .. } => error.partial_output(),
+            _ => None,
+        }
+    }
+
+    /// Get the label of a failed `PythonSandbox::execute_labeled` call.
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            SandboxError::Labeled { label, .. } => Some(label),
+            _ => None,
+        }
+    }
+
     /// Check if this error represents a timeout.
     pub fn is_timeout(&self) -> bool {
-        matches!(self, SandboxError::Timeout(_))
+        matches!(self.cause(), SandboxError::Timeout { .. })
+    }
+
+    /// Get the phase in which a timeout occurred, if this is a timeout.
+    pub fn timeout_phase(&self) -> Option<TimeoutPhase> {
+        match self.cause() {
+            SandboxError::Timeout { phase, .. } => Some(*phase),
+            _ => None,
+        }
+    }
+
+    /// Check if this error represents a cancelled execution.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.cause(), SandboxError::Cancelled)
     }
 
     /// Check if this error represents a memory limit exceeded.
     pub fn is_memory_limit(&self) -> bool {
-        matches!(self, SandboxError::MemoryLimitExceeded(_))
+        matches!(self.cause(), SandboxError::MemoryLimitExceeded(_))
     }
 
     /// Check if this error represents a Python exception.
+    ///
+    /// True for `SyntaxError` too.
     pub fn is_python_exception(&self) -> bool {
-        matches!(self, SandboxError::PythonException { .. })
+        matches!(
+            self.cause(),
+            SandboxError::PythonException { .. } | SandboxError::SyntaxError { .. }
+        )
+    }
+
+    /// Check if this error represents a Python `SyntaxError`.
+    pub fn is_syntax_error(&self) -> bool {
+        matches!(self.cause(), SandboxError::SyntaxError { .. })
     }
 
     /// Check if this error represents an out-of-fuel condition.
     pub fn is_out_of_fuel(&self) -> bool {
-        matches!(self, SandboxError::OutOfFuel { .. })
+        matches!(self.cause(), SandboxError::OutOfFuel { .. })
+    }
+
+    /// Check if this error represents an exhausted Wasm call stack.
+    pub fn is_stack_overflow(&self) -> bool {
+        matches!(self.cause(), SandboxError::StackOverflow)
+    }
+
+    /// Check if this error represents an exceeded exception cap.
+    pub fn is_exception_limit(&self) -> bool {
+        matches!(self.cause(), SandboxError::ExceptionLimitExceeded { .. })
+    }
+
+    /// Check if this error represents an exceeded output rate.
+    pub fn is_output_rate_limit(&self) -> bool {
+        matches!(self.cause(), SandboxError::OutputRateExceeded { .. })
+    }
+
+    /// Check if this error represents too much written to `/out`.
+    pub fn is_output_files_limit(&self) -> bool {
+        matches!(self.cause(), SandboxError::OutputFilesExceeded { .. })
+    }
+
+    /// Check if this error represents a pool with no free sandbox.
+    pub fn is_pool_exhausted(&self) -> bool {
+        matches!(self.cause(), SandboxError::PoolExhausted { .. })
+    }
+
+    /// Check if this error means the sandbox must be recreated.
+    pub fn is_expired(&self) -> bool {
+        matches!(self.cause(), SandboxError::Expired { .. })
     }
 }
 
 /// Result type alias for sandbox operations.
 pub type Result<T> = std::result::Result<T, SandboxError>;
 
+/// A Python exception parsed from the traceback Python printed.
+///
+/// Unlike `SandboxError::PythonException`, this is plain data, as found in
+/// `ExecutionResult::exception` and in the links of a chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PythonException {
+    /// The type of Python exception (e.g., "KeyError").
+    pub exception_type: String,
+    /// The exception message.
+    pub message: String,
+    /// The exception's own traceback, if available.
+    pub traceback: Option<String>,
+}
+
+impl PythonException {
+    /// Parse every exception of the chain Python printed in `stderr`.
+    ///
+    /// Exceptions raised while handling another, or with `raise ... from
+    /// ...`, are printed one after the other. They are returned root cause
+    /// first, so the last one is the exception `parse_python_exception`
+    /// returns. Empty if `stderr` holds no exception.
+    pub fn chain(stderr: &str) -> Vec<PythonException> {
+        parse_chain(stderr)
+            .into_iter()
+            .map(|block| block.info)
+            .collect()
+    }
+}
+
+/// Lines Python prints between the exceptions of a chain.
+const CHAIN_CONNECTORS: &[&str] = &[
+    "During handling of the above exception, another exception occurred:",
+    "The above exception was the direct cause of the following exception:",
+];
+
 /// Parse a Python exception from stderr output.
 ///
 /// This attempts to extract the exception type, message, and traceback
-/// from Python's standard error output format.
+/// from Python's standard error output format. A `SyntaxError` is returned
+/// as `SandboxError::SyntaxError`, with the position Python reports for it.
+///
+/// When Python printed a chain of exceptions, the last one is returned; see
+/// `PythonException::chain` for the others.
 pub fn parse_python_exception(stderr: &str) -> Option<SandboxError> {
+    let last = parse_chain(stderr).pop()?;
+
+    if last.info.exception_type == "SyntaxError" {
+        return Some(SandboxError::SyntaxError {
+            message: last.info.message,
+            line: last.line,
+            offset: last.offset,
+            traceback: last.info.traceback,
+        });
+    }
+
+    Some(SandboxError::PythonException {
+        exception_type: last.info.exception_type,
+        message: last.info.message,
+        traceback: last.info.traceback,
+    })
+}
+
+/// Parse each exception of the chain printed in `stderr`, root cause first.
+fn parse_chain(stderr: &str) -> Vec<ParsedException> {
     if stderr.trim().is_empty() {
-        return None;
+        return Vec::new();
     }
 
     let lines: Vec<&str> = stderr.lines().collect();
-    if lines.is_empty() {
-        return None;
-    }
+    lines
+        .split(|line| CHAIN_CONNECTORS.contains(&line.trim()))
+        .filter_map(parse_exception_block)
+        .collect()
+}
 
+/// One exception parsed from stderr.
+struct ParsedException {
+    info: PythonException,
+    /// Line number of a `SyntaxError`.
+    line: Option<u32>,
+    /// Caret column of a `SyntaxError`.
+    offset: Option<u32>,
+}
+
+/// Parse the exception printed in `lines`, which hold at most one
+/// exception of a chain.
+fn parse_exception_block(lines: &[&str]) -> Option<ParsedException> {
     // Look for the exception line (typically the last line with an exception)
     // Format: "ExceptionType: message" or "ExceptionType"
     let mut exception_line = None;
@@ -122,30 +430,117 @@ pub fn parse_python_exception(stderr: &str) -> Option<SandboxError> {
     }
 
     // Parse the exception line
-    if let Some((line_idx, exception_str)) = exception_line {
-        let (exception_type, message) = if let Some(colon_pos) = exception_str.find(':') {
-            let exc_type = exception_str[..colon_pos].trim().to_string();
-            let msg = exception_str[colon_pos + 1..].trim().to_string();
-            (exc_type, msg)
-        } else {
-            (exception_str.trim().to_string(), String::new())
-        };
-
-        // Extract traceback if present
-        let traceback = if let Some(start) = traceback_start {
-            Some(lines[start..=line_idx].join("\n"))
-        } else {
-            None
-        };
-
-        return Some(SandboxError::PythonException {
+    let (line_idx, exception_str) = exception_line?;
+    let (exception_type, message) = if let Some(colon_pos) = exception_str.find(':') {
+        let exc_type = exception_str[..colon_pos].trim().to_string();
+        let msg = exception_str[colon_pos + 1..].trim().to_string();
+        (exc_type, msg)
+    } else {
+        (exception_str.trim().to_string(), String::new())
+    };
+
+    // Extract traceback if present
+    let traceback = traceback_start.map(|start| lines[start..=line_idx].join("\n"));
+
+    let (line, offset) = if exception_type == "SyntaxError" {
+        syntax_error_position(&lines[..line_idx])
+    } else {
+        (None, None)
+    };
+
+    Some(ParsedException {
+        info: PythonException {
             exception_type,
             message,
             traceback,
+        },
+        line,
+        offset,
+    })
+}
+
+/// Find the line and caret column of a syntax error in the lines printed
+/// before its exception line.
+///
+/// Python prints the innermost frame, the offending source line indented
+/// by four spaces, and a caret line underneath:
+///
+/// ```text
+///   File "<string>", line 3
+///     y = (1 +
+///             ^
+/// ```
+fn syntax_error_position(lines: &[&str]) -> (Option<u32>, Option<u32>) {
+    let Some(frame_idx) = lines
+        .iter()
+        .rposition(|line| line.trim_start().starts_with("File \""))
+    else {
+        return (None, None);
+    };
+
+    let line = lines[frame_idx]
+        .rsplit_once(", line ")
+        .and_then(|(_, rest)| {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            rest[..end].parse().ok()
         });
+
+    // The caret line follows the source line it points into
+    let body = &lines[frame_idx + 1..];
+    let offset = body.iter().enumerate().skip(1).find_map(|(i, caret)| {
+        let marker = caret.trim();
+        if !marker.starts_with('^') || !marker.chars().all(|c| matches!(c, '^' | '~')) {
+            return None;
+        }
+        let source = body[i - 1];
+        let indent = source.len() - source.trim_start().len();
+        let column = caret.find('^')?.checked_sub(indent)?;
+        u32::try_from(column + 1).ok()
+    });
+
+    (line, offset)
+}
+
+/// Truncate long tracebacks in stderr output to their outermost and innermost frames.
+///
+/// Each traceback with more than `2 * max_frames` frames keeps its first and
+/// last `max_frames` frames, with a marker line in place of the rest. Other
+/// output is left untouched. Returns the new output and whether anything
+/// was elided.
+pub fn truncate_traceback(stderr: &str, max_frames: usize) -> (String, bool) {
+    let mut output = String::with_capacity(stderr.len().min(64 * 1024));
+    let mut truncated = false;
+    let mut lines = stderr.split_inclusive('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        output.push_str(line);
+        if !line.starts_with("Traceback (most recent call last):") {
+            continue;
+        }
+
+        // Group the indented lines that follow into frames
+        let mut frames: Vec<String> = Vec::new();
+        while let Some(line) = lines.next_if(|line| line.starts_with(' ')) {
+            match frames.last_mut() {
+                Some(frame) if !line.starts_with("  File ") => frame.push_str(line),
+                _ => frames.push(line.to_string()),
+            }
+        }
+
+        if frames.len() > 2 * max_frames {
+            let elided = frames.len() - 2 * max_frames;
+            frames.splice(
+                max_frames..frames.len() - max_frames,
+                [format!("  ... {} frames elided ...\n", elided)],
+            );
+            truncated = true;
+        }
+        output.extend(frames);
     }
 
-    None
+    (output, truncated)
 }
 
 /// Check if a line looks like a Python exception.
@@ -264,6 +659,124 @@ ValueError: invalid value"#;
         }
     }
 
+    #[test]
+    fn test_parse_chained_exceptions() {
+        let stderr = r#"Traceback (most recent call last):
+  File "<string>", line 3, in <module>
+KeyError: 'price'
+
+During handling of the above exception, another exception occurred:
+
+Traceback (most recent call last):
+  File "<string>", line 5, in <module>
+ValueError: row 7 is incomplete
+
+The above exception was the direct cause of the following exception:
+
+Traceback (most recent call last):
+  File "<string>", line 7, in <module>
+RuntimeError: pipeline failed"#;
+
+        match parse_python_exception(stderr).unwrap() {
+            SandboxError::PythonException {
+                exception_type,
+                message,
+                traceback,
+            } => {
+                assert_eq!(exception_type, "RuntimeError");
+                assert_eq!(message, "pipeline failed");
+                assert!(traceback.unwrap().contains("line 7"));
+            }
+            other => panic!("Expected PythonException, got {other:?}"),
+        }
+
+        let chain = PythonException::chain(stderr);
+        let causes: Vec<_> = chain
+            .iter()
+            .map(|info| (info.exception_type.as_str(), info.message.as_str()))
+            .collect();
+        assert_eq!(
+            causes,
+            [
+                ("KeyError", "'price'"),
+                ("ValueError", "row 7 is incomplete"),
+                ("RuntimeError", "pipeline failed")
+            ]
+        );
+        assert!(chain[0].traceback.as_ref().unwrap().contains("line 3"));
+        assert!(PythonException::chain("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_syntax_error_position() {
+        let stderr = "  File \"<string>\", line 3\n    y = (1 +\n            ^\nSyntaxError: unexpected EOF while parsing";
+
+        let error = parse_python_exception(stderr).unwrap();
+        assert!(error.is_syntax_error());
+        assert!(error.is_python_exception());
+        match error {
+            SandboxError::SyntaxError {
+                message,
+                line,
+                offset,
+                ..
+            } => {
+                assert_eq!(message, "unexpected EOF while parsing");
+                assert_eq!(line, Some(3));
+                assert_eq!(offset, Some(9));
+            }
+            other => panic!("Expected SyntaxError, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_syntax_error_in_traceback() {
+        // Raised by compile() at runtime, below a regular traceback
+        let stderr = r#"Traceback (most recent call last):
+  File "<string>", line 1, in <module>
+  File "<input>", line 3
+    if True print(1)
+            ^^^^^
+SyntaxError: invalid syntax"#;
+
+        let error = parse_python_exception(stderr).unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Python SyntaxError: invalid syntax (line 3)"
+        );
+        match error {
+            SandboxError::SyntaxError {
+                line,
+                offset,
+                traceback,
+                ..
+            } => {
+                assert_eq!(line, Some(3));
+                assert_eq!(offset, Some(9));
+                assert!(traceback.unwrap().contains("<input>"));
+            }
+            other => panic!("Expected SyntaxError, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_syntax_error_without_position() {
+        let error = parse_python_exception("SyntaxError: invalid syntax").unwrap();
+        assert!(matches!(
+            error,
+            SandboxError::SyntaxError {
+                line: None,
+                offset: None,
+                ..
+            }
+        ));
+
+        // Subclasses stay generic exceptions
+        let error = parse_python_exception("IndentationError: unexpected indent").unwrap();
+        assert!(!error.is_syntax_error());
+        assert!(error.is_python_exception());
+    }
+
     #[test]
     fn test_parse_empty_stderr() {
         assert!(parse_python_exception("").is_none());
@@ -272,8 +785,13 @@ ValueError: invalid value"#;
 
     #[test]
     fn test_error_helpers() {
-        let timeout = SandboxError::Timeout(std::time::Duration::from_secs(5));
+        let timeout = SandboxError::Timeout {
+            elapsed: std::time::Duration::from_secs(5),
+            phase: TimeoutPhase::Execution,
+        };
         assert!(timeout.is_timeout());
+        assert_eq!(timeout.timeout_phase(), Some(TimeoutPhase::Execution));
+        assert_eq!(timeout.to_string(), "execution timed out after 5s");
         assert!(!timeout.is_memory_limit());
         assert!(!timeout.is_python_exception());
 
@@ -288,4 +806,48 @@ ValueError: invalid value"#;
         };
         assert!(python_exc.is_python_exception());
     }
+
+    #[test]
+    fn test_interrupted_helpers() {
+        let interrupted = SandboxError::Interrupted {
+            error: Box::new(SandboxError::OutOfFuel { consumed: Some(10) }),
+            output: PartialOutput {
+                stdout: "partial\n".to_string(),
+                stderr: String::new(),
+            },
+        };
+
+        assert!(interrupted.is_out_of_fuel());
+        assert!(!interrupted.is_timeout());
+        assert!(!interrupted.is_stack_overflow());
+        assert_eq!(interrupted.partial_output().unwrap().stdout, "partial\n");
+        assert_eq!(interrupted.to_string(), interrupted.cause().to_string());
+    }
+
+    #[test]
+    fn test_truncate_traceback() {
+        let mut stderr = String::from("Traceback (most recent call last):\n");
+        for depth in 0..10 {
+            stderr.push_str(&format!(
+                "  File \"<string>\", line {}, in f\n    f()\n",
+                depth
+            ));
+        }
+        stderr.push_str("RecursionError: maximum recursion depth exceeded\n");
+
+        let (truncated, elided) = truncate_traceback(&stderr, 2);
+
+        assert!(elided);
+        assert_eq!(truncated.matches("  File ").count(), 4);
+        assert!(truncated.contains("line 1, in f\n    f()\n  ... 6 frames elided ...\n"));
+        assert!(truncated.contains("line 8, in f"));
+        assert!(truncated.ends_with("RecursionError: maximum recursion depth exceeded\n"));
+    }
+
+    #[test]
+    fn test_truncate_short_traceback() {
+        let stderr = "Traceback (most recent call last):\n  File \"<string>\", line 1, in <module>\nValueError: x\n";
+
+        assert_eq!(truncate_traceback(stderr, 2), (stderr.to_string(), false));
+    }
 }
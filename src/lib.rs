@@ -8,7 +8,8 @@
 //!
 //! - **Memory limits**: Configurable maximum memory allocation
 //! - **Timeout protection**: Epoch-based interruption for infinite loop protection
-//! - **Filesystem isolation**: No access to the host filesystem
+//! - **Filesystem isolation**: No access to the host filesystem beyond
+//!   explicitly mounted files and an optional, size-capped output directory
 //! - **Network isolation**: No network access (WASI Preview 1)
 //! - **Process isolation**: Cannot spawn subprocesses
 //!
@@ -40,7 +41,9 @@
 //! The sandbox provides defense-in-depth through multiple isolation layers:
 //!
 //! 1. **WebAssembly sandboxing**: Code runs in Wasm with no direct host access
-//! 2. **WASI restrictions**: No preopened directories or network capabilities
+//! 2. **WASI restrictions**: No network capabilities, and no host files beyond
+//!    explicitly mounted read-only ones and, with `collect_output_files`, a
+//!    fresh `/out` directory whose size is capped by `max_output_bytes`
 //! 3. **Resource limits**: Memory and execution time are bounded
 //! 4. **Epoch interruption**: Cooperative timeout even for tight loops
 
@@ -49,7 +52,27 @@ pub mod prelude;
 pub mod sandbox;
 
 // Re-export main types at crate root for convenience
-pub use error::{Result, SandboxError};
-pub use sandbox::cache::{global_cache, ModuleCache, SharedEngine};
-pub use sandbox::config::{SandboxConfig, SandboxConfigBuilder};
-pub use sandbox::executor::{ExecutionMetadata, ExecutionResult, PythonSandbox, SandboxOptions};
+pub use error::{PartialOutput, PythonException, Result, SandboxError, TimeoutPhase};
+#[cfg(feature = "audit")]
+pub use sandbox::audit::AccessAttempt;
+pub use sandbox::cache::{
+    global_cache, reset_globals, CacheStats, CacheStatus, CompileEvent, EngineHealth, ModuleCache,
+    SharedEngine, WasmFeatures,
+};
+pub use sandbox::cancel::CancellationToken;
+pub use sandbox::compare::{normalize_output, DiffOptions};
+pub use sandbox::compile::{CompileError, CompileReport, CompileWarning};
+pub use sandbox::config::{InputSpec, Profile, SandboxConfig, SandboxConfigBuilder};
+pub use sandbox::doctest::{DoctestFailure, DoctestReport};
+pub use sandbox::events::{ExecEvent, ExecEvents, OutputChunk, OutputClosedAction, OutputStream};
+pub use sandbox::executor::{
+    DurationStats, EvalResult, ExecutionMetadata, ExecutionOverrides, ExecutionResult,
+    ExecutionSource, FailureKind, InputRuns, MemoryProbe, PythonSandbox, SandboxOptions,
+};
+pub use sandbox::interpreter::{Interpreter, RustPythonInterpreter, Script};
+pub use sandbox::persistent::PersistentSession;
+pub use sandbox::pool::{PooledSandbox, SandboxPool};
+pub use sandbox::preludes::{global_preludes, PreludeRegistry};
+pub use sandbox::sequence::BlockResult;
+pub use sandbox::session::{RunOpts, Session, SessionBuilder};
+pub use sandbox::termination::Termination;
@@ -1,8 +1,28 @@
 //! Prelude module for convenient imports.
 
-pub use crate::error::{Result, SandboxError};
+pub use crate::error::{PartialOutput, PythonException, Result, SandboxError, TimeoutPhase};
+#[cfg(feature = "audit")]
+pub use crate::sandbox::audit::AccessAttempt;
 pub use crate::sandbox::{
-    cache::{global_cache, ModuleCache, SharedEngine},
-    config::{SandboxConfig, SandboxConfigBuilder},
-    executor::{ExecutionMetadata, ExecutionResult, PythonSandbox, SandboxOptions},
+    cache::{
+        global_cache, reset_globals, CacheStats, CacheStatus, CompileEvent, EngineHealth,
+        ModuleCache, SharedEngine, WasmFeatures,
+    },
+    cancel::CancellationToken,
+    compare::{normalize_output, DiffOptions},
+    compile::{CompileError, CompileReport, CompileWarning},
+    config::{InputSpec, Profile, SandboxConfig, SandboxConfigBuilder},
+    doctest::{DoctestFailure, DoctestReport},
+    events::{ExecEvent, ExecEvents, OutputChunk, OutputClosedAction, OutputStream},
+    executor::{
+        DurationStats, EvalResult, ExecutionMetadata, ExecutionOverrides, ExecutionResult,
+        ExecutionSource, FailureKind, InputRuns, MemoryProbe, PythonSandbox, SandboxOptions,
+    },
+    interpreter::{Interpreter, RustPythonInterpreter, Script},
+    persistent::PersistentSession,
+    pool::{PooledSandbox, SandboxPool},
+    preludes::{global_preludes, PreludeRegistry},
+    sequence::BlockResult,
+    session::{RunOpts, Session, SessionBuilder},
+    termination::Termination,
 };
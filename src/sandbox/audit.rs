@@ -0,0 +1,110 @@
+//! Recording filesystem and network access attempts.
+//!
+//! Under isolation these attempts fail, and most never reach the host at
+//! all: without preopened directories, the guest's libc rejects a path like
+//! `/etc/passwd` before making any WASI call. The attempts are therefore
+//! observed inside the interpreter, by wrapping the Python functions that
+//! open files, inspect directories and connect sockets. Each call is
+//! reported through the guest report channel before it is forwarded.
+//!
+//! This is telemetry, not enforcement: code that reaches the underlying
+//! `_io` or `_socket` functions directly is not recorded.
+
+use serde_json::Value;
+
+use crate::sandbox::guest::GuestReports;
+
+/// Report key carrying one JSON-encoded access attempt.
+pub(crate) const ACCESS_KEY: &str = "access";
+
+/// An attempt by guest code to access the filesystem or network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessAttempt {
+    /// Operation attempted, e.g. `open`, `listdir` or `socket.connect`.
+    pub operation: String,
+    /// Path or address the operation targeted.
+    pub target: String,
+}
+
+impl AccessAttempt {
+    /// Decode every attempt reported during an execution, in order.
+    pub(crate) fn from_reports(reports: &GuestReports) -> Vec<Self> {
+        reports
+            .get_all(ACCESS_KEY)
+            .filter_map(|json| serde_json::from_str::<Value>(json).ok())
+            .map(|value| Self {
+                operation: value[0].as_str().unwrap_or_default().to_string(),
+                target: value[1].as_str().unwrap_or_default().to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Instrumentation that reports every filesystem and network access attempt.
+pub(crate) fn harness() -> String {
+    format!(
+        r#"def __sandbox_audit_access(report):
+    import builtins, io, os, json
+    def record(operation, target):
+        if isinstance(target, (str, bytes)):
+            target = os.fsdecode(target)
+        else:
+            target = repr(target)
+        report('{ACCESS_KEY}', json.dumps([operation, target]))
+    def wrap(owner, name, operation):
+        original = getattr(owner, name, None)
+        if original is None:
+            return
+        def audited(*args, **kwargs):
+            record(operation, args[0] if args else kwargs.get('path', kwargs.get('file', '.')))
+            return original(*args, **kwargs)
+        setattr(owner, name, audited)
+    def wrap_method(owner, name):
+        original = getattr(owner, name, None)
+        if original is None:
+            return
+        def audited(self, address, *args):
+            record('socket.' + name, address)
+            return original(self, address, *args)
+        try:
+            setattr(owner, name, audited)
+        except TypeError:
+            pass
+    wrap(builtins, 'open', 'open')
+    wrap(io, 'open', 'open')
+    for name in ('open', 'listdir', 'scandir', 'stat', 'remove', 'unlink',
+                 'mkdir', 'rmdir', 'rename', 'chdir'):
+        wrap(os, name, name)
+    try:
+        import socket
+    except ImportError:
+        return
+    for name in ('connect', 'connect_ex', 'bind'):
+        wrap_method(socket.socket, name)
+__sandbox_audit_access(__sandbox_report)
+del __sandbox_audit_access
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attempts_from_reports() {
+        let reports = GuestReports::from_entries(&[
+            (ACCESS_KEY, r#"["open", "/etc/passwd"]"#),
+            ("exceptions", "1"),
+            (ACCESS_KEY, r#"["socket.connect", "('example.com', 80)"]"#),
+        ]);
+
+        let attempts = AccessAttempt::from_reports(&reports);
+
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].operation, "open");
+        assert_eq!(attempts[0].target, "/etc/passwd");
+        assert_eq!(attempts[1].operation, "socket.connect");
+        assert_eq!(attempts[1].target, "('example.com', 80)");
+    }
+}
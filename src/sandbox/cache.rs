@@ -3,19 +3,65 @@
 //! This module provides a thread-safe cache for compiled WASM modules,
 //! enabling efficient reuse across multiple sandbox instances.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
 
+use sha2::{Digest, Sha256};
 use wasmtime::{Engine, Module};
 
 use crate::error::{Result, SandboxError};
+use crate::sandbox::preludes::global_preludes;
+use crate::sandbox::ticker::EpochTicker;
+
+/// Progress events emitted by [`ModuleCache::get_or_compile_with_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompileEvent {
+    /// The module was found in the cache.
+    CacheHit,
+    /// Compilation has started.
+    Compiling,
+    /// Compilation finished.
+    Compiled {
+        /// Time taken to compile the module.
+        duration: Duration,
+    },
+}
+
+/// How a sandbox obtained its compiled interpreter module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The module was already in the cache.
+    Hit,
+    /// The module was not cached; it was compiled and added to the cache.
+    Compiled,
+    /// Caching was disabled; the module was compiled for this sandbox only.
+    Disabled,
+}
+
+/// How effective a `ModuleCache` has been, from `ModuleCache::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups that found the module in the cache.
+    pub hits: u64,
+    /// Lookups that did not, and compiled the module.
+    pub misses: u64,
+    /// Number of modules currently cached in memory.
+    pub entries: usize,
+}
 
 /// A thread-safe cache for compiled WASM modules.
 ///
-/// The cache stores compiled modules keyed by their filesystem path,
-/// allowing multiple `PythonSandbox` instances to share the same
-/// compiled module and avoid redundant compilation.
+/// The cache stores compiled modules keyed by their filesystem path, or
+/// by the SHA-256 of their contents for modules given as bytes, allowing
+/// multiple `PythonSandbox` instances to share the same compiled module
+/// and avoid redundant compilation.
+///
+/// A cache created with `new_with_disk` also keeps compiled artifacts in a
+/// directory, so a new process can skip compilation altogether.
 ///
 /// # Example
 ///
@@ -37,18 +83,81 @@ use crate::error::{Result, SandboxError};
 /// ```
 #[derive(Debug, Default)]
 pub struct ModuleCache {
-    /// The cached modules, keyed by canonical path.
-    cache: RwLock<HashMap<PathBuf, Arc<Module>>>,
+    /// The cached modules, keyed by canonical path or content digest.
+    cache: RwLock<HashMap<CacheKey, Arc<Module>>>,
+    /// Directory holding precompiled artifacts, if any.
+    disk_dir: Option<PathBuf>,
+    /// Lookups served from the cache.
+    hits: AtomicU64,
+    /// Lookups that had to compile.
+    misses: AtomicU64,
+}
+
+/// What a cached module is keyed by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    /// Canonical path of the module file.
+    Path(PathBuf),
+    /// SHA-256 of the module bytes.
+    Content([u8; 32]),
+}
+
+impl CacheKey {
+    /// Key for a module given as bytes.
+    ///
+    /// A hit hands out the module compiled from the bytes with the same
+    /// key, so the hash must be collision resistant.
+    fn content(wasm_bytes: &[u8]) -> Self {
+        CacheKey::Content(Sha256::digest(wasm_bytes).into())
+    }
 }
 
 impl ModuleCache {
     /// Create a new empty module cache.
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a cache that also persists compiled modules in `cache_dir`.
+    ///
+    /// On a miss in memory, the cache looks for an artifact precompiled
+    /// by an earlier process (with `Engine::precompile_module`) before
+    /// compiling, and writes one after compiling. Artifacts are named by the
+    /// SHA-256 of the module bytes and of the engine's compilation settings,
+    /// including fuel and epoch interruption, so an engine only ever loads
+    /// artifacts compiled for a compatible configuration. An artifact that
+    /// fails to load is compiled again and replaced.
+    ///
+    /// The directory is created when the first artifact is written.
+    /// Failing to write an artifact does not fail the lookup.
+    ///
+    /// # Safety of the directory
+    ///
+    /// Artifacts are native code loaded with `Module::deserialize_file`,
+    /// which cannot validate them. Only point this at a directory that
+    /// nothing untrusted can write to.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let cache = Arc::new(ModuleCache::new_with_disk("/var/cache/my-service/wasm".into()));
+    /// let options = SandboxOptions::default().cache(cache);
+    ///
+    /// // Compiles on the first launch, loads the artifact afterwards
+    /// let sandbox = PythonSandbox::new_with_options(config, options)?;
+    /// ```
+    pub fn new_with_disk(cache_dir: PathBuf) -> Self {
         Self {
-            cache: RwLock::new(HashMap::new()),
+            disk_dir: Some(cache_dir),
+            ..Self::default()
         }
     }
 
+    /// Get the directory holding precompiled artifacts, if any.
+    pub fn disk_dir(&self) -> Option<&Path> {
+        self.disk_dir.as_deref()
+    }
+
     /// Get a cached module or compile it if not present.
     ///
     /// The path is canonicalized before lookup to ensure consistent caching
@@ -63,6 +172,35 @@ impl ModuleCache {
     ///
     /// An `Arc<Module>` that can be shared across threads.
     pub fn get_or_compile(&self, engine: &Engine, path: impl AsRef<Path>) -> Result<Arc<Module>> {
+        self.get_or_compile_with_progress(engine, path, |_| {})
+    }
+
+    /// Like [`get_or_compile`](Self::get_or_compile), reporting progress events.
+    ///
+    /// Compiling a large interpreter can take seconds. Wasmtime does not
+    /// report fine-grained progress, but these events are enough for a UI
+    /// to show a spinner and the time taken:
+    ///
+    /// - cache hit, in memory or on disk: `CacheHit`
+    /// - cache miss: `Compiling`, then `Compiled { duration }` on success
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let module = cache.get_or_compile_with_progress(&engine, path, |event| {
+    ///     match event {
+    ///         CompileEvent::Compiling => println!("compiling interpreter..."),
+    ///         CompileEvent::Compiled { duration } => println!("done in {:?}", duration),
+    ///         CompileEvent::CacheHit => {}
+    ///     }
+    /// })?;
+    /// ```
+    pub fn get_or_compile_with_progress(
+        &self,
+        engine: &Engine,
+        path: impl AsRef<Path>,
+        on_event: impl FnMut(CompileEvent),
+    ) -> Result<Arc<Module>> {
         let path = path.as_ref();
 
         // Canonicalize the path for consistent caching
@@ -74,31 +212,94 @@ impl ModuleCache {
             }
         })?;
 
+        self.get_or_insert(
+            engine,
+            CacheKey::Path(canonical_path.clone()),
+            || std::fs::read(&canonical_path).map_err(SandboxError::Io),
+            on_event,
+        )
+    }
+
+    /// Get a cached module or compile it from `wasm_bytes` if not present.
+    ///
+    /// Modules are keyed by the SHA-256 of their bytes, so sandboxes sharing one
+    /// embedded interpreter (e.g. from `include_bytes!`) compile it once.
+    pub fn get_or_compile_bytes(&self, engine: &Engine, wasm_bytes: &[u8]) -> Result<Arc<Module>> {
+        self.get_or_compile_bytes_with_progress(engine, wasm_bytes, |_| {})
+    }
+
+    /// Like [`get_or_compile_bytes`](Self::get_or_compile_bytes), reporting progress events.
+    pub fn get_or_compile_bytes_with_progress(
+        &self,
+        engine: &Engine,
+        wasm_bytes: &[u8],
+        on_event: impl FnMut(CompileEvent),
+    ) -> Result<Arc<Module>> {
+        self.get_or_insert(
+            engine,
+            CacheKey::content(wasm_bytes),
+            || Ok(wasm_bytes.to_vec()),
+            on_event,
+        )
+    }
+
+    /// Look up `key`, compiling the bytes from `read` on a miss.
+    fn get_or_insert(
+        &self,
+        engine: &Engine,
+        key: CacheKey,
+        read: impl FnOnce() -> Result<Vec<u8>>,
+        mut on_event: impl FnMut(CompileEvent),
+    ) -> Result<Arc<Module>> {
         // Try to get from cache first (read lock)
         {
             let cache = self.cache.read().unwrap();
-            if let Some(module) = cache.get(&canonical_path) {
+            if let Some(module) = cache.get(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                on_event(CompileEvent::CacheHit);
                 return Ok(Arc::clone(module));
             }
         }
 
-        // Not in cache, compile the module (outside any lock)
-        let wasm_bytes = std::fs::read(&canonical_path).map_err(SandboxError::Io)?;
+        // Not in cache, load or compile the module (outside any lock)
+        let wasm_bytes = read()?;
+        let artifact = self
+            .disk_dir
+            .as_deref()
+            .map(|dir| artifact_path(dir, engine, &wasm_bytes));
 
-        let module = Module::new(engine, &wasm_bytes).map_err(|e| {
-            SandboxError::ModuleLoad(anyhow::anyhow!("failed to compile module: {}", e))
-        })?;
-
-        let module = Arc::new(module);
+        let module = match artifact
+            .as_deref()
+            .and_then(|path| load_artifact(engine, path))
+        {
+            Some(module) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                on_event(CompileEvent::CacheHit);
+                Arc::new(module)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                on_event(CompileEvent::Compiling);
+                let start = Instant::now();
+                let module = match artifact {
+                    Some(ref path) => compile_to_disk(engine, &wasm_bytes, path)?,
+                    None => compile_module(engine, &wasm_bytes)?,
+                };
+                on_event(CompileEvent::Compiled {
+                    duration: start.elapsed(),
+                });
+                Arc::new(module)
+            }
+        };
 
         // Insert into cache (write lock)
         {
             let mut cache = self.cache.write().unwrap();
             // Double-check pattern: another thread might have compiled while we were
-            if let Some(existing) = cache.get(&canonical_path) {
+            if let Some(existing) = cache.get(&key) {
                 return Ok(Arc::clone(existing));
             }
-            cache.insert(canonical_path, Arc::clone(&module));
+            cache.insert(key, Arc::clone(&module));
         }
 
         Ok(module)
@@ -109,12 +310,18 @@ impl ModuleCache {
         let path = path.as_ref();
         if let Ok(canonical) = std::fs::canonicalize(path) {
             let cache = self.cache.read().unwrap();
-            cache.contains_key(&canonical)
+            cache.contains_key(&CacheKey::Path(canonical))
         } else {
             false
         }
     }
 
+    /// Check if a module compiled from `wasm_bytes` is cached.
+    pub fn contains_bytes(&self, wasm_bytes: &[u8]) -> bool {
+        let cache = self.cache.read().unwrap();
+        cache.contains_key(&CacheKey::content(wasm_bytes))
+    }
+
     /// Remove a module from the cache.
     ///
     /// Returns `true` if the module was present and removed.
@@ -122,12 +329,40 @@ impl ModuleCache {
         let path = path.as_ref();
         if let Ok(canonical) = std::fs::canonicalize(path) {
             let mut cache = self.cache.write().unwrap();
-            cache.remove(&canonical).is_some()
+            cache.remove(&CacheKey::Path(canonical)).is_some()
         } else {
             false
         }
     }
 
+    /// Remove the module cached for `path` if another engine compiled it.
+    ///
+    /// Returns `true` if a module was removed.
+    pub(crate) fn evict_foreign(&self, engine: &Engine, path: impl AsRef<Path>) -> bool {
+        let Ok(canonical) = std::fs::canonicalize(path.as_ref()) else {
+            return false;
+        };
+        self.evict_foreign_key(engine, &CacheKey::Path(canonical))
+    }
+
+    /// Remove the module cached for `wasm_bytes` if another engine compiled it.
+    ///
+    /// Returns `true` if a module was removed.
+    pub(crate) fn evict_foreign_bytes(&self, engine: &Engine, wasm_bytes: &[u8]) -> bool {
+        self.evict_foreign_key(engine, &CacheKey::content(wasm_bytes))
+    }
+
+    fn evict_foreign_key(&self, engine: &Engine, key: &CacheKey) -> bool {
+        let mut cache = self.cache.write().unwrap();
+        match cache.get(key) {
+            Some(module) if !Engine::same(module.engine(), engine) => {
+                cache.remove(key);
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Clear all cached modules.
     pub fn clear(&self) {
         let mut cache = self.cache.write().unwrap();
@@ -144,6 +379,145 @@ impl ModuleCache {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Get the hit and miss counts of lookups so far, and the number of
+    /// cached modules.
+    ///
+    /// Every `get_or_compile*` call, including those made when sandboxes
+    /// are created, counts as a hit if it finds the module, in memory or
+    /// on disk, and as a miss if it compiles it. The counters run until
+    /// `reset_stats` is called; `clear` and `evict*` do not reset them.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.len(),
+        }
+    }
+
+    /// Reset the hit and miss counts to zero, keeping cached modules.
+    pub fn reset_stats(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Reset all per-process state held by the crate.
+///
+/// This currently consists of the global module cache, including its hit
+/// and miss counts, and the global prelude registry. Any module still in
+/// use by an existing sandbox stays alive until that sandbox is dropped;
+/// new sandboxes will recompile. Existing sandboxes keep the prelude
+/// fragments they were created with.
+///
+/// Primarily intended for tests that share a process and must not see
+/// state left behind by other tests.
+pub fn reset_globals() {
+    global_cache().clear();
+    global_cache().reset_stats();
+    global_preludes().clear();
+}
+
+/// Held by the crate's tests while they reset or rely on per-process
+/// state, so `reset_globals` in one cannot clear it under another.
+#[cfg(test)]
+pub(crate) static GLOBALS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Compile interpreter bytes into a module for the given engine.
+///
+/// Validation failures caused by a Wasm feature that is disabled on the
+/// engine (see [`WasmFeatures`]) are reported as such.
+pub(crate) fn compile_module(engine: &Engine, wasm_bytes: &[u8]) -> Result<Module> {
+    Module::new(engine, wasm_bytes).map_err(compile_error)
+}
+
+/// Describe a compilation failure, pointing out disabled Wasm features.
+fn compile_error(e: anyhow::Error) -> SandboxError {
+    let msg = format!("{:#}", e);
+    if msg.contains("support is not enabled") {
+        SandboxError::ModuleLoad(anyhow::anyhow!(
+            "interpreter requires a Wasm feature disabled on this engine: {}",
+            msg
+        ))
+    } else {
+        SandboxError::ModuleLoad(anyhow::anyhow!("failed to compile module: {}", e))
+    }
+}
+
+/// Feeds everything hashed into it to a SHA-256 digest.
+///
+/// `Engine::precompile_compatibility_hash` only implements `Hash`, so this
+/// lets it go into the same digest as the module bytes.
+struct DigestHasher(Sha256);
+
+impl Hasher for DigestHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+}
+
+/// Path of the precompiled artifact for `wasm_bytes` under `dir`.
+///
+/// The name is the SHA-256 of the module bytes and of the engine's
+/// compatibility hash, which covers the Wasmtime version and every setting
+/// that affects the compiled code (fuel, epoch interruption, Wasm
+/// features, target, ...). Wasmtime rejects artifacts from another version
+/// on load as well.
+fn artifact_path(dir: &Path, engine: &Engine, wasm_bytes: &[u8]) -> PathBuf {
+    let mut hasher = DigestHasher(Sha256::new());
+    hasher.write(wasm_bytes);
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    let name: String = hasher
+        .0
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    dir.join(format!("{name}.cwasm"))
+}
+
+/// Distinguishes temporary artifact files written by the same process.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Load a precompiled artifact, or `None` if there is no usable one.
+fn load_artifact(engine: &Engine, path: &Path) -> Option<Module> {
+    if !path.is_file() {
+        return None;
+    }
+    // SAFETY: artifacts are only written by `compile_to_disk`, into a
+    // directory the caller of `ModuleCache::new_with_disk` vouches for.
+    // Wasmtime still rejects artifacts from an incompatible engine.
+    unsafe { Module::deserialize_file(engine, path) }.ok()
+}
+
+/// Compile `wasm_bytes` and store the artifact at `path`.
+fn compile_to_disk(engine: &Engine, wasm_bytes: &[u8], path: &Path) -> Result<Module> {
+    let artifact = engine
+        .precompile_module(wasm_bytes)
+        .map_err(compile_error)?;
+    // Best effort: write to a temporary file and rename it into place, so
+    // concurrent processes never load a partially written artifact
+    if let Some(dir) = path.parent() {
+        let tmp = path.with_extension(format!(
+            "tmp{}-{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = std::fs::create_dir_all(dir)
+            .and_then(|()| std::fs::write(&tmp, &artifact))
+            .and_then(|()| std::fs::rename(&tmp, path));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+    // SAFETY: the bytes were just produced by `precompile_module` on this
+    // engine.
+    unsafe { Module::deserialize(engine, &artifact) }.map_err(compile_error)
 }
 
 /// Global module cache for convenient access.
@@ -160,18 +534,179 @@ pub fn global_cache() -> &'static ModuleCache {
     &GLOBAL_CACHE
 }
 
+/// Optional Wasm features to enable on an engine.
+///
+/// Only features relevant to reproducibility are exposed here:
+///
+/// - `threads`: shared memories and atomics make execution order depend on
+///   host scheduling. Disable for determinism.
+/// - `relaxed_simd`: results of relaxed SIMD instructions may differ between
+///   host CPUs. Disable (or keep `relaxed_simd_deterministic`) for determinism.
+/// - `simd`: fixed-width SIMD is deterministic except for NaN bit patterns,
+///   which `nan_canonicalization` makes consistent.
+///
+/// Use [`WasmFeatures::deterministic`] for the strictest settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmFeatures {
+    /// Enable fixed-width SIMD (`wasm_simd`).
+    pub simd: bool,
+    /// Enable relaxed SIMD (`wasm_relaxed_simd`).
+    pub relaxed_simd: bool,
+    /// Force relaxed SIMD to behave identically on all hosts.
+    pub relaxed_simd_deterministic: bool,
+    /// Enable the threads proposal (`wasm_threads`).
+    pub threads: bool,
+    /// Canonicalize NaN values produced by floating-point operations.
+    pub nan_canonicalization: bool,
+}
+
+impl Default for WasmFeatures {
+    /// Matches Wasmtime's defaults.
+    fn default() -> Self {
+        Self {
+            simd: true,
+            relaxed_simd: true,
+            relaxed_simd_deterministic: false,
+            threads: true,
+            nan_canonicalization: false,
+        }
+    }
+}
+
+impl WasmFeatures {
+    /// Features tuned for reproducible execution across hosts.
+    ///
+    /// Disables threads and relaxed SIMD and canonicalizes NaNs. Fixed-width
+    /// SIMD stays enabled since the interpreter build may rely on it.
+    pub fn deterministic() -> Self {
+        Self {
+            simd: true,
+            relaxed_simd: false,
+            relaxed_simd_deterministic: true,
+            threads: false,
+            nan_canonicalization: true,
+        }
+    }
+
+    /// Apply these features to an engine configuration.
+    pub fn apply(&self, config: &mut wasmtime::Config) {
+        config.wasm_simd(self.simd);
+        config.wasm_relaxed_simd(self.relaxed_simd);
+        config.relaxed_simd_deterministic(self.relaxed_simd_deterministic);
+        config.wasm_threads(self.threads);
+        config.cranelift_nan_canonicalization(self.nan_canonicalization);
+    }
+}
+
+/// Failures within the window that mark a shared engine unhealthy, by default.
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long a failure counts against a shared engine's health, by default.
+const DEFAULT_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Health of a shared engine, as tracked by its circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineHealth {
+    /// Failures recorded within the failure window.
+    pub recent_failures: u32,
+    /// Whether recent failures reached the threshold (the breaker is open).
+    pub unhealthy: bool,
+    /// Number of times the engine has been recreated.
+    pub recreations: u64,
+}
+
+impl EngineHealth {
+    /// Check whether the engine can be used as is.
+    pub fn is_healthy(&self) -> bool {
+        !self.unhealthy
+    }
+}
+
+/// Recent failures of a shared engine.
+#[derive(Debug)]
+struct HealthState {
+    failures: VecDeque<Instant>,
+    threshold: u32,
+    window: Duration,
+    recreations: u64,
+}
+
+impl HealthState {
+    /// Forget failures older than the window.
+    fn prune(&mut self) {
+        let now = Instant::now();
+        while let Some(&oldest) = self.failures.front() {
+            if now.duration_since(oldest) < self.window {
+                break;
+            }
+            self.failures.pop_front();
+        }
+    }
+
+    fn health(&mut self) -> EngineHealth {
+        self.prune();
+        let recent_failures = self.failures.len() as u32;
+        EngineHealth {
+            recent_failures,
+            unhealthy: recent_failures >= self.threshold,
+            recreations: self.recreations,
+        }
+    }
+}
+
+/// One engine used by a `SharedEngine`, followed by the engine that replaced it.
+struct Generation {
+    /// The engine, with the ticker driving its epoch.
+    ticker: EpochTicker,
+    /// The engine created by the next `recreate`, once there is one.
+    next: OnceLock<Box<Generation>>,
+}
+
+impl Generation {
+    fn new(engine: Arc<Engine>) -> Self {
+        Self {
+            ticker: EpochTicker::new(engine),
+            next: OnceLock::new(),
+        }
+    }
+}
+
+/// The engine slot shared by every clone of a `SharedEngine`.
+struct EngineSlot {
+    /// Every engine used so far, oldest first; the last one is current.
+    ///
+    /// Replaced engines are kept so references handed out by
+    /// `SharedEngine::engine` stay valid.
+    first: Generation,
+    /// Configuration to recreate the engine from (unknown for `from_arc`).
+    config: Option<wasmtime::Config>,
+    health: Mutex<HealthState>,
+}
+
 /// A shared engine that can be reused across sandbox instances.
 ///
-/// Wraps an `Arc<Engine>` for thread-safe sharing.
+/// Wraps an `Arc<Engine>` for thread-safe sharing. Clones share the same
+/// engine slot and health state. Dereferences to the current engine.
+///
+/// # Circuit breaker
+///
+/// Failures initializing the runtime (`SandboxError::RuntimeInit`) are
+/// recorded against the engine. Once `failure_threshold` failures fall
+/// within the failure window, `health` reports it unhealthy and the next
+/// sandbox created with it calls `recreate` first. Services can also
+/// record their own failures with `record_failure`.
 #[derive(Clone)]
 pub struct SharedEngine {
-    engine: Arc<Engine>,
+    slot: Arc<EngineSlot>,
+    coredump_dir: Option<PathBuf>,
 }
 
 impl std::fmt::Debug for SharedEngine {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SharedEngine")
             .field("engine", &"<wasmtime::Engine>")
+            .field("coredump_dir", &self.coredump_dir)
+            .field("health", &self.health())
             .finish()
     }
 }
@@ -179,21 +714,45 @@ impl std::fmt::Debug for SharedEngine {
 impl SharedEngine {
     /// Create a new shared engine with the default configuration.
     pub fn new() -> Result<Self> {
-        let config = Self::default_config(false)?;
-        let engine = Engine::new(&config)
-            .map_err(|e| SandboxError::RuntimeInit(anyhow::anyhow!("{}", e)))?;
-        Ok(Self {
-            engine: Arc::new(engine),
-        })
+        Self::from_config(&Self::default_config(false)?)
     }
 
     /// Create a new shared engine with fuel consumption enabled.
     pub fn with_fuel() -> Result<Self> {
-        let config = Self::default_config(true)?;
-        let engine = Engine::new(&config)
-            .map_err(|e| SandboxError::RuntimeInit(anyhow::anyhow!("{}", e)))?;
+        Self::from_config(&Self::default_config(true)?)
+    }
+
+    /// Create a new shared engine with a specific set of Wasm features.
+    ///
+    /// Sandboxes using this engine fail to load with
+    /// `SandboxError::ModuleLoad` if the interpreter needs a disabled feature.
+    pub fn with_features(features: WasmFeatures) -> Result<Self> {
+        let mut config = Self::default_config(false)?;
+        features.apply(&mut config);
+        Self::from_config(&config)
+    }
+
+    /// Create a new shared engine that writes a coredump when the interpreter traps.
+    ///
+    /// When execution stops on a trap that is not a Python-level error
+    /// (e.g. `unreachable` or an out-of-bounds access inside the
+    /// interpreter), a Wasm coredump is written to `dir` and its path is
+    /// returned in `SandboxError::InterpreterTrap` for post-mortem
+    /// debugging. Timeouts, fuel exhaustion and memory limits do not
+    /// produce a dump.
+    ///
+    /// Coredumps contain a full copy of the guest's linear memory and can
+    /// be tens of megabytes each; this is intended for debugging and should
+    /// be disabled in production.
+    pub fn with_coredump(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut config = Self::default_config(false)?;
+        config.coredump_on_trap(true);
         Ok(Self {
-            engine: Arc::new(engine),
+            coredump_dir: Some(dir),
+            ..Self::from_config(&config)?
         })
     }
 
@@ -201,24 +760,141 @@ impl SharedEngine {
     pub fn from_config(config: &wasmtime::Config) -> Result<Self> {
         let engine =
             Engine::new(config).map_err(|e| SandboxError::RuntimeInit(anyhow::anyhow!("{}", e)))?;
-        Ok(Self {
-            engine: Arc::new(engine),
-        })
+        Ok(Self::from_slot(Arc::new(engine), Some(config.clone())))
     }
 
     /// Create a shared engine wrapper from an existing `Arc<Engine>`.
+    ///
+    /// The engine's configuration is unknown, so it cannot be recreated.
     pub fn from_arc(engine: Arc<Engine>) -> Self {
-        Self { engine }
+        Self::from_slot(engine, None)
+    }
+
+    fn from_slot(engine: Arc<Engine>, config: Option<wasmtime::Config>) -> Self {
+        Self {
+            slot: Arc::new(EngineSlot {
+                first: Generation::new(engine),
+                config,
+                health: Mutex::new(HealthState {
+                    failures: VecDeque::new(),
+                    threshold: DEFAULT_FAILURE_THRESHOLD,
+                    window: DEFAULT_FAILURE_WINDOW,
+                    recreations: 0,
+                }),
+            }),
+            coredump_dir: None,
+        }
+    }
+
+    /// Set how many failures within `window` mark the engine unhealthy.
+    ///
+    /// Defaults to 5 failures within 60 seconds.
+    pub fn failure_threshold(self, failures: u32, window: Duration) -> Self {
+        {
+            let mut health = self.slot.health.lock().unwrap();
+            health.threshold = failures.max(1);
+            health.window = window;
+        }
+        self
     }
 
-    /// Get a reference to the underlying engine.
+    /// Get a reference to the current engine.
+    ///
+    /// After `recreate`, this returns the new engine.
     pub fn engine(&self) -> &Engine {
-        &self.engine
+        self.current().ticker.engine()
+    }
+
+    /// Get the directory coredumps are written to, if enabled.
+    pub fn coredump_dir(&self) -> Option<&Path> {
+        self.coredump_dir.as_deref()
     }
 
     /// Get the `Arc<Engine>` for sharing.
+    ///
+    /// After `recreate`, this returns the new engine.
     pub fn arc(&self) -> Arc<Engine> {
-        Arc::clone(&self.engine)
+        Arc::clone(self.current().ticker.engine())
+    }
+
+    /// Get the epoch ticker shared by all sandboxes using the current engine.
+    pub(crate) fn ticker(&self) -> EpochTicker {
+        self.current().ticker.clone()
+    }
+
+    /// Get the generation holding the current engine.
+    fn current(&self) -> &Generation {
+        let mut generation = &self.slot.first;
+        while let Some(next) = generation.next.get() {
+            generation = next;
+        }
+        generation
+    }
+
+    /// Get the engine's health.
+    pub fn health(&self) -> EngineHealth {
+        self.slot.health.lock().unwrap().health()
+    }
+
+    /// Record a failure against the engine's health.
+    pub fn record_failure(&self) {
+        let mut health = self.slot.health.lock().unwrap();
+        health.failures.push_back(Instant::now());
+        health.prune();
+    }
+
+    /// Replace the engine with a new one built from the same configuration.
+    ///
+    /// The swap is atomic: the new engine is published in one step to the
+    /// slot shared by all clones, so every clone sees either the old engine
+    /// or the new one, and `engine` or `arc` called after `recreate`
+    /// returns always return the new one. Sandboxes (and compiled modules)
+    /// created from the old engine keep their own reference to it and
+    /// carry on unaffected; only sandboxes created afterwards use the new
+    /// engine, and they compile the interpreter for it again. Recreation
+    /// clears recorded failures.
+    ///
+    /// The old engine stays allocated until every clone of this
+    /// `SharedEngine` is dropped, so references returned by `engine`
+    /// remain valid.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if the engine was created with
+    /// `from_arc`, whose configuration is unknown, or
+    /// `SandboxError::RuntimeInit` if creating the new engine fails.
+    pub fn recreate(&self) -> Result<()> {
+        let mut health = self.slot.health.lock().unwrap();
+        self.recreate_locked(&mut health)
+    }
+
+    /// Recreate the engine if it is unhealthy.
+    ///
+    /// Concurrent callers recreate it only once.
+    pub(crate) fn recover(&self) -> Result<()> {
+        let mut health = self.slot.health.lock().unwrap();
+        if health.health().unhealthy {
+            self.recreate_locked(&mut health)?;
+        }
+        Ok(())
+    }
+
+    fn recreate_locked(&self, health: &mut HealthState) -> Result<()> {
+        let Some(ref config) = self.slot.config else {
+            return Err(SandboxError::Config(
+                "an engine created from an existing Arc cannot be recreated".to_string(),
+            ));
+        };
+        let engine =
+            Engine::new(config).map_err(|e| SandboxError::RuntimeInit(anyhow::anyhow!("{}", e)))?;
+        // Recreations are serialized by the health lock, so the current
+        // generation has no successor yet
+        let _ = self
+            .current()
+            .next
+            .set(Box::new(Generation::new(Arc::new(engine))));
+        health.failures.clear();
+        health.recreations += 1;
+        Ok(())
     }
 
     /// Create the default engine configuration.
@@ -240,7 +916,7 @@ impl std::ops::Deref for SharedEngine {
     type Target = Engine;
 
     fn deref(&self) -> &Self::Target {
-        &self.engine
+        self.engine()
     }
 }
 
@@ -255,6 +931,206 @@ mod tests {
         assert_eq!(cache.len(), 0);
     }
 
+    #[test]
+    fn test_cache_stats() {
+        let engine = Engine::default();
+        let cache = ModuleCache::new();
+        assert_eq!(cache.stats(), CacheStats::default());
+
+        let wasm = b"(module)";
+        cache.get_or_compile_bytes(&engine, wasm).unwrap();
+        cache.get_or_compile_bytes(&engine, wasm).unwrap();
+        cache.get_or_compile_bytes(&engine, wasm).unwrap();
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                entries: 1,
+            }
+        );
+
+        // Clearing keeps the counters
+        cache.clear();
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().hits, 2);
+
+        cache.get_or_compile_bytes(&engine, wasm).unwrap();
+        cache.reset_stats();
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 0,
+                entries: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_compile_progress_events() {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-progress-{}.wat",
+            std::process::id()
+        ));
+        std::fs::write(&path, "(module)").unwrap();
+
+        let engine = Engine::default();
+        let cache = ModuleCache::new();
+
+        let mut cold = Vec::new();
+        cache
+            .get_or_compile_with_progress(&engine, &path, |e| cold.push(e))
+            .unwrap();
+        let mut warm = Vec::new();
+        cache
+            .get_or_compile_with_progress(&engine, &path, |e| warm.push(e))
+            .unwrap();
+
+        assert_eq!(cold.len(), 2);
+        assert_eq!(cold[0], CompileEvent::Compiling);
+        assert!(matches!(cold[1], CompileEvent::Compiled { .. }));
+        assert_eq!(warm, vec![CompileEvent::CacheHit]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_get_or_compile_bytes() {
+        let engine = Engine::default();
+        let cache = ModuleCache::new();
+        let wasm = b"(module (memory 1))";
+
+        let first = cache.get_or_compile_bytes(&engine, wasm).unwrap();
+        // A separate copy of the same bytes hits the same entry
+        let copy = wasm.to_vec();
+        let mut events = Vec::new();
+        let second = cache
+            .get_or_compile_bytes_with_progress(&engine, &copy, |e| events.push(e))
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(events, vec![CompileEvent::CacheHit]);
+        assert!(cache.contains_bytes(wasm));
+        assert!(!cache.contains_bytes(b"(module)"));
+
+        let other = cache.get_or_compile_bytes(&engine, b"(module)").unwrap();
+        assert!(!Arc::ptr_eq(&first, &other));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_content_key_is_sha256() {
+        // Reference value of SHA-256("abc")
+        let CacheKey::Content(digest) = CacheKey::content(b"abc") else {
+            panic!("expected a content key");
+        };
+        assert_eq!(
+            digest[..4],
+            [0xba, 0x78, 0x16, 0xbf],
+            "unexpected digest {digest:02x?}"
+        );
+        assert_eq!(digest[28..], [0xf2, 0x00, 0x15, 0xad]);
+    }
+
+    #[test]
+    fn test_artifact_path_is_stable() {
+        let dir = Path::new("/cache");
+        let engine = Engine::default();
+        let path = artifact_path(dir, &engine, b"a");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let (digest, ext) = name.split_once('.').unwrap();
+        assert_eq!(ext, "cwasm");
+        assert_eq!(digest.len(), 64);
+        assert!(digest.bytes().all(|b| b.is_ascii_hexdigit()));
+
+        assert_eq!(artifact_path(dir, &Engine::default(), b"a"), path);
+        assert_ne!(artifact_path(dir, &engine, b"b"), path);
+
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let fuel = Engine::new(&config).unwrap();
+        assert_ne!(artifact_path(dir, &fuel, b"a"), path);
+    }
+
+    #[test]
+    fn test_disk_cache() {
+        let dir = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-disk-cache-{}",
+            std::process::id()
+        ));
+        let wasm = b"(module (memory 1))";
+        let engine = Engine::default();
+
+        let mut cold = Vec::new();
+        ModuleCache::new_with_disk(dir.clone())
+            .get_or_compile_bytes_with_progress(&engine, wasm, |e| cold.push(e))
+            .unwrap();
+        assert_eq!(cold[0], CompileEvent::Compiling);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // A fresh cache, as in a new process, loads the artifact
+        let cache = ModuleCache::new_with_disk(dir.clone());
+        let mut warm = Vec::new();
+        cache
+            .get_or_compile_bytes_with_progress(&engine, wasm, |e| warm.push(e))
+            .unwrap();
+        assert_eq!(warm, vec![CompileEvent::CacheHit]);
+        assert_eq!(cache.disk_dir(), Some(dir.as_path()));
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 0));
+
+        // An engine with other settings gets an artifact of its own
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let fuel_engine = Engine::new(&config).unwrap();
+        let mut other = Vec::new();
+        ModuleCache::new_with_disk(dir.clone())
+            .get_or_compile_bytes_with_progress(&fuel_engine, wasm, |e| other.push(e))
+            .unwrap();
+        assert_eq!(other[0], CompileEvent::Compiling);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // A corrupt artifact is compiled again
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            std::fs::write(entry.unwrap().path(), b"garbage").unwrap();
+        }
+        let mut events = Vec::new();
+        ModuleCache::new_with_disk(dir.clone())
+            .get_or_compile_bytes_with_progress(&engine, wasm, |e| events.push(e))
+            .unwrap();
+        assert_eq!(events[0], CompileEvent::Compiling);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reset_globals() {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-reset-{}.wat",
+            std::process::id()
+        ));
+        std::fs::write(&path, "(module)").unwrap();
+        let _globals = GLOBALS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let engine = Engine::default();
+        global_cache().get_or_compile(&engine, &path).unwrap();
+        global_cache().get_or_compile(&engine, &path).unwrap();
+        global_preludes().register("test_reset", "x = 1");
+        assert!(!global_cache().is_empty());
+        assert!(global_cache().stats().hits > 0);
+
+        reset_globals();
+        assert!(global_cache().is_empty());
+        assert_eq!(
+            (global_cache().stats().hits, global_cache().stats().misses),
+            (0, 0)
+        );
+        assert!(!global_preludes().contains("test_reset"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_shared_engine_creation() {
         let engine = SharedEngine::new().unwrap();
@@ -268,6 +1144,19 @@ mod tests {
         engine.engine().increment_epoch();
     }
 
+    #[test]
+    fn test_shared_engine_with_features() {
+        let engine = SharedEngine::with_features(WasmFeatures::deterministic()).unwrap();
+        engine.engine().increment_epoch();
+
+        let no_simd = WasmFeatures {
+            simd: false,
+            relaxed_simd: false,
+            ..WasmFeatures::default()
+        };
+        assert!(SharedEngine::with_features(no_simd).is_ok());
+    }
+
     #[test]
     fn test_shared_engine_clone() {
         let engine1 = SharedEngine::new().unwrap();
@@ -276,4 +1165,56 @@ mod tests {
         // Both should reference the same underlying engine
         assert!(Arc::ptr_eq(&engine1.arc(), &engine2.arc()));
     }
+
+    #[test]
+    fn test_shared_engine_breaker_trips_and_recreates() {
+        let engine = SharedEngine::new()
+            .unwrap()
+            .failure_threshold(3, Duration::from_secs(60));
+        let clone = engine.clone();
+        let original = engine.arc();
+        let borrowed = clone.engine();
+
+        engine.record_failure();
+        engine.record_failure();
+        assert!(engine.health().is_healthy());
+        assert_eq!(engine.health().recent_failures, 2);
+
+        clone.record_failure();
+        assert!(!engine.health().is_healthy());
+
+        engine.recover().unwrap();
+        let health = clone.health();
+        assert!(health.is_healthy());
+        assert_eq!(health.recent_failures, 0);
+        assert_eq!(health.recreations, 1);
+        assert!(!Engine::same(&original, &clone.arc()));
+        // References taken before still point at the old engine; new ones,
+        // and deref, see the new engine
+        assert!(Engine::same(borrowed, &original));
+        assert!(Engine::same(clone.engine(), &clone.arc()));
+        assert!(Engine::same(&clone, &engine.arc()));
+
+        // A healthy engine is left alone
+        engine.recover().unwrap();
+        assert_eq!(engine.health().recreations, 1);
+    }
+
+    #[test]
+    fn test_shared_engine_failures_expire() {
+        let engine = SharedEngine::new()
+            .unwrap()
+            .failure_threshold(1, Duration::ZERO);
+
+        engine.record_failure();
+
+        assert!(engine.health().is_healthy());
+    }
+
+    #[test]
+    fn test_shared_engine_from_arc_cannot_recreate() {
+        let engine = SharedEngine::from_arc(Arc::new(Engine::default()));
+
+        assert!(matches!(engine.recreate(), Err(SandboxError::Config(_))));
+    }
 }
@@ -0,0 +1,150 @@
+//! Cancelling executions from outside the sandbox.
+//!
+//! `CancellationToken` follows the API of `tokio_util::sync::CancellationToken`
+//! (`cancel`, `is_cancelled`, `cancelled`) without the extra dependency.
+//! With the `tokio-util` feature, a `tokio_util` token converts into one,
+//! so services that already use them can pass theirs straight through.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+/// A token that cancels the executions it was passed to.
+///
+/// Clones share their state: cancelling any clone cancels them all.
+/// Cancellation is permanent, so a token cancelled before an execution
+/// starts cancels it straight away.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let token = CancellationToken::new();
+/// let on_disconnect = token.clone();
+/// tokio::spawn(async move {
+///     client_gone().await;
+///     on_disconnect.cancel();
+/// });
+/// let result = sandbox.execute_cancellable(code, None, token).await;
+/// ```
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    inner: Source,
+}
+
+/// Where a token's state lives.
+#[derive(Debug, Clone)]
+enum Source {
+    Own(Arc<Inner>),
+    #[cfg(feature = "tokio-util")]
+    TokioUtil(tokio_util::sync::CancellationToken),
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self {
+            inner: Source::Own(Arc::default()),
+        }
+    }
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the token, waking everything waiting in `cancelled`.
+    pub fn cancel(&self) {
+        match &self.inner {
+            Source::Own(inner) => {
+                inner.cancelled.store(true, Ordering::Release);
+                inner.notify.notify_waiters();
+            }
+            #[cfg(feature = "tokio-util")]
+            Source::TokioUtil(token) => token.cancel(),
+        }
+    }
+
+    /// Check if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        match &self.inner {
+            Source::Own(inner) => inner.cancelled.load(Ordering::Acquire),
+            #[cfg(feature = "tokio-util")]
+            Source::TokioUtil(token) => token.is_cancelled(),
+        }
+    }
+
+    /// Wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        let inner = match &self.inner {
+            Source::Own(inner) => inner,
+            #[cfg(feature = "tokio-util")]
+            Source::TokioUtil(token) => return token.cancelled().await,
+        };
+        loop {
+            // Register before checking the flag so a concurrent `cancel`
+            // cannot be missed
+            let notified = inner.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Share the state of a `tokio_util` token: cancelling either cancels both.
+#[cfg(feature = "tokio-util")]
+impl From<tokio_util::sync::CancellationToken> for CancellationToken {
+    fn from(token: tokio_util::sync::CancellationToken) -> Self {
+        Self {
+            inner: Source::TokioUtil(token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiters() {
+        let token = CancellationToken::new();
+        let waiter = token.clone();
+        let handle = tokio::spawn(async move { waiter.cancelled().await });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!token.is_cancelled());
+        token.cancel();
+
+        handle.await.unwrap();
+        assert!(token.is_cancelled());
+        // Already cancelled tokens resolve immediately
+        token.cancelled().await;
+    }
+
+    #[cfg(feature = "tokio-util")]
+    #[tokio::test]
+    async fn test_tokio_util_token() {
+        let source = tokio_util::sync::CancellationToken::new();
+        let token = CancellationToken::from(source.clone());
+        let waiter = token.clone();
+        let handle = tokio::spawn(async move { waiter.cancelled().await });
+
+        assert!(!token.is_cancelled());
+        source.cancel();
+
+        handle.await.unwrap();
+        assert!(token.is_cancelled());
+    }
+}
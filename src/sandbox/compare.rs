@@ -0,0 +1,152 @@
+//! Comparing program output against expected output.
+//!
+//! Used by `ExecutionResult::matches_expected`, mainly for grading.
+//! Output is compared line by line, so a missing final newline or `\r\n`
+//! line endings do not cause a mismatch.
+//!
+//! Output whose line order is not deterministic can be compared after
+//! passing both sides through `normalize_output`.
+
+/// Sort the lines of `output`, so outputs differing only in line order compare equal.
+///
+/// Line endings are normalized to `\n` and every line, including the
+/// last, ends with one. Empty output stays empty.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// assert_eq!(normalize_output("b\r\na\n"), normalize_output("a\nb"));
+/// ```
+pub fn normalize_output(output: &str) -> String {
+    let mut lines: Vec<&str> = output.lines().collect();
+    lines.sort_unstable();
+    lines.iter().map(|line| format!("{line}\n")).collect()
+}
+
+/// Options controlling how output is compared with expected output.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiffOptions {
+    tolerance: Option<Tolerance>,
+}
+
+/// Allowed difference between two numeric lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tolerance {
+    abs: f64,
+    rel: f64,
+}
+
+impl DiffOptions {
+    /// Compare every line exactly.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compare lines that parse as numbers within a tolerance.
+    ///
+    /// Two numeric lines match if they differ by at most `abs`, or by at
+    /// most `rel` times the larger of their magnitudes (as Python's
+    /// `math.isclose`). Infinities only match an infinity of the same
+    /// sign, and NaN matches nothing numerically, whatever the tolerance.
+    /// Lines that are not numbers on both sides still compare exactly,
+    /// surrounding whitespace included.
+    pub fn numeric_tolerance(mut self, abs: f64, rel: f64) -> Self {
+        self.tolerance = Some(Tolerance { abs, rel });
+        self
+    }
+
+    /// Check whether `actual` matches `expected` under these options.
+    pub fn matches(&self, actual: &str, expected: &str) -> bool {
+        let mut actual = actual.lines();
+        let mut expected = expected.lines();
+        loop {
+            match (actual.next(), expected.next()) {
+                (None, None) => return true,
+                (Some(a), Some(e)) if self.line_matches(a, e) => {}
+                _ => return false,
+            }
+        }
+    }
+
+    fn line_matches(&self, actual: &str, expected: &str) -> bool {
+        if actual == expected {
+            return true;
+        }
+        let Some(tolerance) = self.tolerance else {
+            return false;
+        };
+        match (actual.trim().parse::<f64>(), expected.trim().parse::<f64>()) {
+            // Infinite differences are within any relative tolerance
+            (Ok(a), Ok(e)) if !a.is_finite() || !e.is_finite() => a == e,
+            (Ok(a), Ok(e)) => {
+                let diff = (a - e).abs();
+                diff <= tolerance.abs || diff <= tolerance.rel * a.abs().max(e.abs())
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_output() {
+        assert_eq!(
+            normalize_output("cherry\napple\r\nbanana"),
+            "apple\nbanana\ncherry\n"
+        );
+        assert_eq!(normalize_output(""), "");
+        assert_eq!(normalize_output("b\na\n"), normalize_output("a\nb\n"));
+    }
+
+    #[test]
+    fn test_exact_by_default() {
+        let options = DiffOptions::new();
+
+        assert!(options.matches("1\n2\n", "1\n2"));
+        assert!(options.matches("1\r\n2\r\n", "1\n2\n"));
+        assert!(!options.matches("3.14159\n", "3.1416\n"));
+        assert!(!options.matches("1\n2\n", "1\n"));
+    }
+
+    #[test]
+    fn test_numeric_tolerance() {
+        let options = DiffOptions::new().numeric_tolerance(1e-4, 0.0);
+
+        assert!(options.matches("3.14159\n", "3.1416\n"));
+        assert!(!options.matches("3.2\n", "3.1416\n"));
+    }
+
+    #[test]
+    fn test_relative_tolerance() {
+        let options = DiffOptions::new().numeric_tolerance(0.0, 1e-3);
+
+        assert!(options.matches("1000000\n", "1000500\n"));
+        assert!(!options.matches("1.0\n", "1.01\n"));
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let options = DiffOptions::new().numeric_tolerance(1e-3, 1e-3);
+
+        assert!(!options.matches("inf\n", "1.0\n"));
+        assert!(!options.matches("1e308\n", "inf\n"));
+        assert!(!options.matches("-inf\n", "inf\n"));
+        assert!(options.matches("inf\n", "inf\n"));
+        assert!(options.matches("inf\n", "infinity\n"));
+        assert!(!options.matches("nan\n", "1.0\n"));
+        assert!(!options.matches("NaN\n", "nan\n"));
+    }
+
+    #[test]
+    fn test_mixed_text_and_numbers() {
+        let options = DiffOptions::new().numeric_tolerance(1e-3, 0.0);
+
+        assert!(options.matches("mean:\n2.0001\ndone\n", "mean:\n2.0\ndone\n"));
+        assert!(!options.matches("Mean:\n2.0001\ndone\n", "mean:\n2.0\ndone\n"));
+        // Text lines are never compared numerically
+        assert!(!options.matches("mean = 2.0001\n", "mean = 2.0\n"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Validating code by compiling it without running it.
+//!
+//! A harness compiles the user code with the builtin `compile()` while
+//! recording warnings, and reports the outcome as JSON through the guest
+//! report channel. The compiled code object is discarded, so nothing in
+//! the user code is executed.
+
+use serde_json::Value;
+
+use crate::error::{Result, SandboxError};
+
+/// Report key carrying the JSON-encoded compile results.
+pub(crate) const COMPILE_KEY: &str = "compile";
+
+/// Outcome of compiling a piece of code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileReport {
+    /// The syntax error that stopped compilation, if any.
+    pub error: Option<CompileError>,
+    /// Warnings emitted by the compiler, such as `SyntaxWarning`.
+    pub warnings: Vec<CompileWarning>,
+}
+
+impl CompileReport {
+    /// Check if the code compiled without errors.
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Decode the report sent by the harness.
+    pub(crate) fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json).map_err(|e| {
+            SandboxError::ExecutionFailed(format!("failed to decode compile report: {}", e))
+        })?;
+
+        let error = value
+            .get("error")
+            .filter(|error| !error.is_null())
+            .map(CompileError::from_value);
+        let warnings = value["warnings"]
+            .as_array()
+            .map(|warnings| warnings.iter().map(CompileWarning::from_value).collect())
+            .unwrap_or_default();
+
+        Ok(Self { error, warnings })
+    }
+}
+
+/// A syntax error raised while compiling.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileError {
+    /// Exception type, e.g. `SyntaxError` or `IndentationError`.
+    pub exception_type: String,
+    /// Error message.
+    pub message: String,
+    /// Line number of the error, if known.
+    pub line: Option<usize>,
+    /// Column offset of the error, if known.
+    pub column: Option<usize>,
+    /// Source line containing the error, if known.
+    pub text: Option<String>,
+}
+
+impl CompileError {
+    fn from_value(value: &Value) -> Self {
+        let number = |key: &str| value[key].as_u64().map(|n| n as usize);
+        Self {
+            exception_type: value["type"].as_str().unwrap_or_default().to_string(),
+            message: value["message"].as_str().unwrap_or_default().to_string(),
+            line: number("line"),
+            column: number("column"),
+            text: value["text"].as_str().map(str::to_string),
+        }
+    }
+}
+
+/// A warning emitted while compiling.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileWarning {
+    /// Warning category, e.g. `SyntaxWarning`.
+    pub category: String,
+    /// Warning message.
+    pub message: String,
+    /// Line number the warning refers to, if known.
+    pub line: Option<usize>,
+}
+
+impl CompileWarning {
+    fn from_value(value: &Value) -> Self {
+        Self {
+            category: value["category"].as_str().unwrap_or_default().to_string(),
+            message: value["message"].as_str().unwrap_or_default().to_string(),
+            line: value["line"].as_u64().map(|l| l as usize),
+        }
+    }
+}
+
+/// Harness that compiles `code` without running it and reports the results.
+pub(crate) fn harness(code: &str) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let literal = serde_json::to_string(code).expect("string serialization cannot fail");
+    format!(
+        r#"def __sandbox_compile_check(source):
+    import json, warnings
+    report = {{'error': None, 'warnings': []}}
+    with warnings.catch_warnings(record=True) as caught:
+        warnings.simplefilter('always')
+        try:
+            compile(source, '<string>', 'exec')
+        except SyntaxError as e:
+            report['error'] = {{'type': type(e).__name__, 'message': e.msg,
+                                'line': e.lineno, 'column': e.offset, 'text': e.text}}
+    for w in caught:
+        report['warnings'].append({{'category': w.category.__name__,
+                                    'message': str(w.message), 'line': w.lineno}})
+    __sandbox_report('{COMPILE_KEY}', json.dumps(report))
+__sandbox_compile_check({literal})
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_from_json() {
+        let json = r#"{"error": {"type": "SyntaxError", "message": "invalid syntax",
+            "line": 2, "column": 5, "text": "x = = 1\n"},
+            "warnings": [{"category": "SyntaxWarning", "message": "bad", "line": 1}]}"#;
+        let report = CompileReport::from_json(json).unwrap();
+
+        assert!(!report.is_valid());
+        let error = report.error.unwrap();
+        assert_eq!(error.exception_type, "SyntaxError");
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.column, Some(5));
+        assert_eq!(report.warnings[0].category, "SyntaxWarning");
+    }
+
+    #[test]
+    fn test_valid_report_from_json() {
+        let report = CompileReport::from_json(r#"{"error": null, "warnings": []}"#).unwrap();
+
+        assert!(report.is_valid());
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_harness_embeds_code_as_literal() {
+        let script = harness("print(\"hi\")\n");
+
+        assert!(script.contains(r#"__sandbox_compile_check("print(\"hi\")\n")"#));
+    }
+}
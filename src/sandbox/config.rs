@@ -1,27 +1,159 @@
 //! Sandbox configuration with builder pattern.
 
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::error::{Result, SandboxError};
+use crate::sandbox::deterministic::HASH_SEED_VAR;
+use crate::sandbox::events::OutputClosedAction;
+use crate::sandbox::interpreter::{Interpreter, RustPythonInterpreter};
+use crate::sandbox::limits::MemoryPolicy;
+use crate::sandbox::mounts;
+use crate::sandbox::preludes::{global_preludes, PreludeRegistry};
+
 /// Configuration for the Python sandbox.
+///
+/// With the `serde` feature, the configuration can be loaded from a file,
+/// see `SandboxConfig::from_json`. Durations are written as milliseconds,
+/// paths as strings, `env_vars` as `[key, value]` pairs and redaction
+/// patterns as strings. Missing fields take their default value. Fields
+/// that only make sense in code are skipped, so they keep their default:
+/// `interpreter_bytes`, `interpreter`, `mounted_files` and `memory_policy`.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SandboxConfig {
     /// Maximum execution time before timeout.
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis"))]
     pub timeout: Duration,
     /// Maximum memory in bytes.
     pub max_memory: u64,
     /// Maximum fuel (instruction count limit).
     pub max_fuel: Option<u64>,
+    /// Maximum Wasm stack size in bytes, or `None` for wasmtime's default.
+    pub max_wasm_stack: Option<usize>,
     /// Path to the RustPython wasm file.
     pub interpreter_path: PathBuf,
+    /// The interpreter wasm, used instead of `interpreter_path` when set.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub interpreter_bytes: Option<Arc<[u8]>>,
+    /// How to run the interpreter module.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub interpreter: Arc<dyn Interpreter>,
+    /// Interpreter to load if the primary fails to compile or load.
+    pub fallback_interpreter_path: Option<PathBuf>,
+    /// Maximum size in bytes of the interpreter wasm file.
+    pub max_module_bytes: Option<usize>,
     /// Epoch interruption interval for cooperative timeout.
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis"))]
     pub epoch_tick_interval: Duration,
-    /// Stdin data to provide to the sandbox.
+    /// Default stdin data, used when a call provides no input of its own.
     pub stdin: Option<String>,
+    /// Arguments passed to the script after the code, as `sys.argv[1:]`.
+    pub args: Vec<String>,
     /// Environment variables to set in the sandbox.
     pub env_vars: Vec<(String, String)>,
+    /// Maximum size in bytes of a single environment variable value.
+    pub max_env_value_bytes: Option<usize>,
+    /// Maximum number of environment variables.
+    pub max_env_count: Option<usize>,
+    /// Read-only files mounted into the guest filesystem, by guest path.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub mounted_files: Vec<(String, Arc<[u8]>)>,
+    /// Maximum combined size in bytes of `stdin` and the mounted files.
+    pub max_input_bytes: Option<usize>,
+    /// Whether to give the guest a writable `/out` directory and return its files.
+    ///
+    /// Requires `max_output_bytes`, which also caps what is written there.
+    pub collect_output_files: bool,
+    /// Permission bits given to files collected from `/out`.
+    pub default_file_mode: Option<u32>,
     /// Prelude Python code to run before user code.
     pub prelude: Option<String>,
+    /// Names of registered prelude fragments to run before `prelude`.
+    pub preludes: Vec<String>,
+    /// Whether to count Python exceptions raised during execution.
+    pub count_exceptions: bool,
+    /// Whether to record which lines of user code run.
+    pub line_coverage: bool,
+    /// Whether to disable Python's cyclic garbage collector.
+    pub disable_gc: bool,
+    /// Whether to return an uncaught exception apart from stderr.
+    pub separate_exceptions: bool,
+    /// Maximum number of Python exceptions before the run is terminated.
+    pub max_exceptions: Option<u64>,
+    /// Maximum size in bytes of values captured from the interpreter.
+    pub max_value_bytes: usize,
+    /// Report memory exhaustion handled by Python as a normal result.
+    pub memory_limit_as_python_error: bool,
+    /// Pass only the variables configured with `env` to the interpreter,
+    /// apart from the report marker of the instrumentation options.
+    pub strict_env: bool,
+    /// Fix the string hash seed so set iteration order is reproducible.
+    pub deterministic_collections: bool,
+    /// Seed making randomness, the clock and string hashes reproducible.
+    pub deterministic_seed: Option<u64>,
+    /// Whether to measure time spent in host (WASI) calls.
+    pub track_host_calls: bool,
+    /// Maximum total time spent in host (WASI) calls.
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis::option"))]
+    pub max_io_time: Option<Duration>,
+    /// Initial module search path, which user code may reassign.
+    pub sys_path: Option<Vec<String>>,
+    /// Modules imported before user code runs.
+    pub preimport: Vec<String>,
+    /// Dynamic memory growth policy overriding `max_memory`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub memory_policy: Option<MemoryPolicy>,
+    /// Whether to record the size of each stdin read.
+    pub trace_stdin: bool,
+    /// Convert `\r\n` and `\r` line endings in stdin to `\n`.
+    pub normalize_stdin_newlines: bool,
+    /// Soft memory threshold in bytes that triggers a warning.
+    pub memory_warn_at: Option<u64>,
+    /// Run without stdin, stdout or stderr.
+    pub no_stdio: bool,
+    /// Preserve output captured before a trap (timeout, memory, fuel).
+    pub flush_on_trap: bool,
+    /// Frames kept at each end of a long traceback.
+    pub max_traceback_frames: Option<usize>,
+    /// Largest injected context embedded in the code rather than sent via stdin.
+    pub max_context_bytes: usize,
+    /// Remove a single trailing newline from captured stdout.
+    pub trim_trailing_newline: bool,
+    /// Maximum number of modules user code may import (best effort, not a
+    /// security boundary).
+    pub max_imports: Option<u64>,
+    /// Maximum number of stdout lines to capture.
+    pub max_output_lines: Option<usize>,
+    /// Stop the execution once stdout goes past `max_output_lines`.
+    pub terminate_on_output_lines: bool,
+    /// Maximum sustained output rate in bytes per second.
+    pub max_output_rate: Option<u64>,
+    /// Maximum number of stdout and stderr bytes to capture.
+    pub max_output_bytes: Option<u64>,
+    /// Cumulative time limit for APIs that execute code several times.
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis::option"))]
+    pub total_budget: Option<Duration>,
+    /// How long a sandbox may be used after it was created.
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis::option"))]
+    pub max_age: Option<Duration>,
+    /// Exit codes accepted as a completed execution (any if `None`).
+    pub allowed_exit_codes: Option<Vec<i32>>,
+    /// Remove interpreter startup banner lines from captured stdout.
+    pub suppress_banner: bool,
+    /// Display the value of a final bare expression, as at a REPL.
+    pub auto_print_last_expr: bool,
+    /// What streamed stdout writes do after the consumer stops listening.
+    pub on_output_closed: OutputClosedAction,
+    /// Whether to record filesystem and network access attempts.
+    #[cfg(feature = "audit")]
+    pub audit_access: bool,
+    /// Patterns masked in captured stdout and stderr.
+    #[cfg(feature = "regex")]
+    #[cfg_attr(feature = "serde", serde(with = "serde_regexes"))]
+    pub redact_output_patterns: Vec<regex::Regex>,
 }
 
 impl Default for SandboxConfig {
@@ -30,11 +162,62 @@ impl Default for SandboxConfig {
             timeout: Duration::from_secs(30),
             max_memory: 64 * 1024 * 1024, // 64MB
             max_fuel: None,
+            max_wasm_stack: None,
             interpreter_path: PathBuf::from("assets/rustpython.wasm"),
+            interpreter_bytes: None,
+            interpreter: Arc::new(RustPythonInterpreter),
+            fallback_interpreter_path: None,
+            max_module_bytes: None,
             epoch_tick_interval: Duration::from_millis(10),
             stdin: None,
+            args: Vec::new(),
             env_vars: Vec::new(),
+            max_env_value_bytes: None,
+            max_env_count: None,
+            mounted_files: Vec::new(),
+            max_input_bytes: None,
+            collect_output_files: false,
+            default_file_mode: None,
             prelude: None,
+            preludes: Vec::new(),
+            count_exceptions: false,
+            line_coverage: false,
+            disable_gc: false,
+            separate_exceptions: false,
+            max_exceptions: None,
+            max_value_bytes: 1024 * 1024, // 1MB
+            memory_limit_as_python_error: false,
+            strict_env: false,
+            deterministic_collections: false,
+            deterministic_seed: None,
+            track_host_calls: false,
+            max_io_time: None,
+            sys_path: None,
+            preimport: Vec::new(),
+            memory_policy: None,
+            trace_stdin: false,
+            normalize_stdin_newlines: false,
+            memory_warn_at: None,
+            no_stdio: false,
+            flush_on_trap: false,
+            max_traceback_frames: None,
+            max_context_bytes: 128 * 1024, // 128KB
+            trim_trailing_newline: false,
+            max_imports: None,
+            max_output_lines: None,
+            terminate_on_output_lines: false,
+            max_output_rate: None,
+            max_output_bytes: None,
+            total_budget: None,
+            max_age: None,
+            allowed_exit_codes: None,
+            suppress_banner: true,
+            auto_print_last_expr: false,
+            on_output_closed: OutputClosedAction::Discard,
+            #[cfg(feature = "audit")]
+            audit_access: false,
+            #[cfg(feature = "regex")]
+            redact_output_patterns: Vec::new(),
         }
     }
 }
@@ -44,6 +227,318 @@ impl SandboxConfig {
     pub fn builder() -> SandboxConfigBuilder {
         SandboxConfigBuilder::default()
     }
+
+    /// Create a configuration tuned for a common scenario.
+    ///
+    /// To adjust a profile, start from `SandboxConfigBuilder::profile`
+    /// instead and override individual limits.
+    pub fn profile(profile: Profile) -> Self {
+        Self::builder().profile(profile).build()
+    }
+
+    /// Parse a configuration from JSON and validate it.
+    ///
+    /// Fields left out of the JSON keep their default value.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if the JSON does not describe a
+    /// configuration, or the configuration is invalid.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let config = SandboxConfig::from_json(
+    ///     r#"{"timeout": 5000, "max_memory": 33554432, "env_vars": [["KEY", "value"]]}"#,
+    /// )?;
+    /// assert_eq!(config.timeout, Duration::from_secs(5));
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: Self = serde_json::from_str(json)
+            .map_err(|e| SandboxError::Config(format!("invalid configuration JSON: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Fold the registered fragments named in `preludes` into `prelude`.
+    ///
+    /// Afterwards `prelude` is the full effective prelude and `preludes`
+    /// is empty, so resolving again changes nothing.
+    pub(crate) fn resolve_preludes(self) -> Result<Self> {
+        self.resolve_preludes_from(global_preludes())
+    }
+
+    fn resolve_preludes_from(mut self, registry: &PreludeRegistry) -> Result<Self> {
+        if self.preludes.is_empty() {
+            return Ok(self);
+        }
+        let mut prelude = registry.compose(&self.preludes)?;
+        if let Some(own) = self.prelude.take() {
+            prelude.push_str(&own);
+        }
+        self.prelude = Some(prelude);
+        self.preludes.clear();
+        Ok(self)
+    }
+
+    /// Check the configuration against its own input limits.
+    ///
+    /// Called when a sandbox is created, since fields may be changed
+    /// after `build`.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if there are more environment
+    /// variables than `max_env_count`, a value is larger than
+    /// `max_env_value_bytes`, a mounted file path is not absolute,
+    /// `stdin` and the mounted files together exceed `max_input_bytes`, or
+    /// `collect_output_files` is set without `max_output_bytes`.
+    pub fn validate(&self) -> Result<()> {
+        if self.collect_output_files && self.max_output_bytes.is_none() {
+            return Err(SandboxError::Config(
+                "collect_output_files requires max_output_bytes to be set".to_string(),
+            ));
+        }
+        if self.default_file_mode.is_some_and(|mode| mode > 0o7777) {
+            return Err(SandboxError::Config(
+                "default_file_mode must only contain permission bits (at most 0o7777)".to_string(),
+            ));
+        }
+        if self.max_wasm_stack == Some(0) {
+            return Err(SandboxError::Config(
+                "max_wasm_stack must be greater than zero".to_string(),
+            ));
+        }
+        if let Some(limit) = self.max_env_count {
+            if self.env_vars.len() > limit {
+                return Err(SandboxError::Config(format!(
+                    "{} environment variables exceed the limit of {}",
+                    self.env_vars.len(),
+                    limit
+                )));
+            }
+        }
+        for (guest_path, _) in &self.mounted_files {
+            mounts::validate_guest_path(guest_path)?;
+        }
+        if let Some(limit) = self.max_input_bytes {
+            let total = input_bytes(self.stdin.as_deref(), &self.mounted_files);
+            if total > limit {
+                return Err(SandboxError::Config(format!(
+                    "inputs total {} bytes, exceeding the limit of {} bytes",
+                    total, limit
+                )));
+            }
+        }
+        if let Some(limit) = self.max_env_value_bytes {
+            if let Some((key, value)) = self.env_vars.iter().find(|(_, v)| v.len() > limit) {
+                return Err(SandboxError::Config(format!(
+                    "environment variable {} is {} bytes, exceeding the limit of {} bytes",
+                    key,
+                    value.len(),
+                    limit
+                )));
+            }
+        }
+        if self.strict_env
+            && (self.deterministic_seed.is_some() || self.deterministic_collections)
+            && !self.env_vars.iter().any(|(key, _)| key == HASH_SEED_VAR)
+        {
+            return Err(SandboxError::Config(format!(
+                "strict_env leaves out the {} that deterministic hashing needs; set it with env",
+                HASH_SEED_VAR
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Combined size of stdin data and mounted files.
+fn input_bytes(stdin: Option<&str>, files: &[(String, Arc<[u8]>)]) -> usize {
+    stdin.map_or(0, str::len) + files.iter().map(|(_, data)| data.len()).sum::<usize>()
+}
+
+/// Serde format of `Duration` fields: whole milliseconds.
+#[cfg(feature = "serde")]
+mod serde_millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let millis = u64::try_from(duration.as_millis()).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_u64(millis)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+
+    /// The same format for optional durations, with `null` for `None`.
+    pub(super) mod option {
+        use std::time::Duration;
+
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub(crate) fn serialize<S: Serializer>(
+            duration: &Option<Duration>,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error> {
+            match duration {
+                Some(duration) => super::serialize(duration, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> std::result::Result<Option<Duration>, D::Error> {
+            Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+        }
+    }
+}
+
+/// Serde format of regex fields: the pattern strings.
+#[cfg(all(feature = "serde", feature = "regex"))]
+mod serde_regexes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        patterns: &[regex::Regex],
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(patterns.iter().map(regex::Regex::as_str))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Vec<regex::Regex>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pattern| regex::Regex::new(pattern).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+/// Everything a program reads, declared in one place.
+///
+/// Bundles default stdin data and read-only files, and optionally caps
+/// their combined size. Pass it to `SandboxConfigBuilder::inputs`; the
+/// result is the same as calling `stdin` and `mount_file` individually,
+/// with the size cap checked when a sandbox is created.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let inputs = InputSpec::new()
+///     .stdin("3\n")
+///     .file("/data/points.csv", points)
+///     .file("/data/weights.json", weights)
+///     .max_total_bytes(10 * 1024 * 1024);
+///
+/// let config = SandboxConfig::builder().inputs(inputs).build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InputSpec {
+    stdin: Option<String>,
+    files: Vec<(String, Arc<[u8]>)>,
+    max_total_bytes: Option<usize>,
+}
+
+impl InputSpec {
+    /// Create an empty input specification.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the data available on stdin, as with `SandboxConfigBuilder::stdin`.
+    pub fn stdin(mut self, data: impl Into<String>) -> Self {
+        self.stdin = Some(data.into());
+        self
+    }
+
+    /// Add a read-only file, as with `SandboxConfigBuilder::mount_file`.
+    pub fn file(mut self, guest_path: impl Into<String>, data: Vec<u8>) -> Self {
+        self.files.push((guest_path.into(), Arc::from(data)));
+        self
+    }
+
+    /// Limit the combined size of stdin and all files.
+    pub fn max_total_bytes(mut self, limit: usize) -> Self {
+        self.max_total_bytes = Some(limit);
+        self
+    }
+
+    /// Combined size in bytes of stdin and all files.
+    pub fn total_bytes(&self) -> usize {
+        input_bytes(self.stdin.as_deref(), &self.files)
+    }
+}
+
+/// Preset limit combinations for common scenarios.
+///
+/// Fuel limits include the interpreter's own startup, which accounts for a
+/// sizeable part of the budget for short scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Untrusted code that should run briefly and see as little as possible.
+    ///
+    /// 1s timeout, 16MB memory, 1 billion fuel for deterministic limits, a
+    /// stripped environment, at most 20 imports and tracebacks cut to 10
+    /// frames at each end.
+    UntrustedStrict,
+    /// Trusted code where throughput matters more than tight limits.
+    ///
+    /// 30s timeout and 256MB memory, without fuel metering overhead.
+    TrustedFast,
+    /// Grading submissions against expected output.
+    ///
+    /// 5s timeout, 64MB memory and 5 billion fuel, so the same submission
+    /// always hits the same limit. Output printed before a limit is hit is
+    /// kept for feedback, the trailing newline is trimmed for comparison,
+    /// and tracebacks are cut to 10 frames at each end.
+    Grading,
+    /// Interactive use, one snippet at a time.
+    ///
+    /// 10s timeout and 128MB memory without fuel; output printed before a
+    /// limit is hit is kept.
+    Repl,
+}
+
+impl SandboxConfigBuilder {
+    /// Start from the limits of a `Profile`.
+    ///
+    /// Call this first: it overrides the limits the profile covers, and
+    /// later builder calls override the profile.
+    pub fn profile(self, profile: Profile) -> Self {
+        match profile {
+            Profile::UntrustedStrict => self
+                .timeout(Duration::from_secs(1))
+                .max_memory(16 * 1024 * 1024)
+                .max_fuel(1_000_000_000)
+                .strict_env(true)
+                .max_imports(20)
+                .max_traceback_frames(10),
+            Profile::TrustedFast => self
+                .timeout(Duration::from_secs(30))
+                .max_memory(256 * 1024 * 1024),
+            Profile::Grading => self
+                .timeout(Duration::from_secs(5))
+                .max_memory(64 * 1024 * 1024)
+                .max_fuel(5_000_000_000)
+                .flush_on_trap(true)
+                .trim_trailing_newline(true)
+                .max_traceback_frames(10),
+            Profile::Repl => self
+                .timeout(Duration::from_secs(10))
+                .max_memory(128 * 1024 * 1024)
+                .flush_on_trap(true),
+        }
+    }
 }
 
 /// Builder for creating SandboxConfig instances.
@@ -52,15 +547,72 @@ pub struct SandboxConfigBuilder {
     timeout: Option<Duration>,
     max_memory: Option<u64>,
     max_fuel: Option<u64>,
+    max_wasm_stack: Option<usize>,
     interpreter_path: Option<PathBuf>,
+    interpreter_bytes: Option<Arc<[u8]>>,
+    interpreter: Option<Arc<dyn Interpreter>>,
+    fallback_interpreter_path: Option<PathBuf>,
+    max_module_bytes: Option<usize>,
     epoch_tick_interval: Option<Duration>,
     stdin: Option<String>,
+    args: Vec<String>,
     env_vars: Vec<(String, String)>,
+    max_env_value_bytes: Option<usize>,
+    max_env_count: Option<usize>,
+    mounted_files: Vec<(String, Arc<[u8]>)>,
+    max_input_bytes: Option<usize>,
+    collect_output_files: bool,
+    default_file_mode: Option<u32>,
     prelude: Option<String>,
+    preludes: Vec<String>,
+    count_exceptions: bool,
+    line_coverage: bool,
+    disable_gc: bool,
+    separate_exceptions: bool,
+    max_exceptions: Option<u64>,
+    max_value_bytes: Option<usize>,
+    memory_limit_as_python_error: bool,
+    strict_env: bool,
+    deterministic_collections: bool,
+    deterministic_seed: Option<u64>,
+    track_host_calls: bool,
+    max_io_time: Option<Duration>,
+    sys_path: Option<Vec<String>>,
+    preimport: Vec<String>,
+    memory_policy: Option<MemoryPolicy>,
+    trace_stdin: bool,
+    normalize_stdin_newlines: bool,
+    memory_warn_at: Option<u64>,
+    no_stdio: bool,
+    flush_on_trap: bool,
+    max_traceback_frames: Option<usize>,
+    max_context_bytes: Option<usize>,
+    trim_trailing_newline: bool,
+    max_imports: Option<u64>,
+    max_output_lines: Option<usize>,
+    terminate_on_output_lines: bool,
+    max_output_rate: Option<u64>,
+    max_output_bytes: Option<u64>,
+    total_budget: Option<Duration>,
+    max_age: Option<Duration>,
+    allowed_exit_codes: Option<Vec<i32>>,
+    suppress_banner: Option<bool>,
+    auto_print_last_expr: bool,
+    on_output_closed: OutputClosedAction,
+    #[cfg(feature = "audit")]
+    audit_access: bool,
+    #[cfg(feature = "regex")]
+    redact_output_patterns: Vec<regex::Regex>,
 }
 
 impl SandboxConfigBuilder {
     /// Set the maximum execution timeout.
+    ///
+    /// The timeout is enforced with an epoch interrupt, which traps the
+    /// Wasm guest and unwinds the whole interpreter at once. It is not
+    /// delivered as a Python exception: no `KeyboardInterrupt` or
+    /// `SystemExit` is raised, so `except:`, `except BaseException` and
+    /// `finally` blocks cannot catch it or run any code after it fires.
     pub fn timeout(mut self, timeout: Duration) -> Self {
         self.timeout = Some(timeout);
         self
@@ -81,24 +633,84 @@ impl SandboxConfigBuilder {
         self
     }
 
+    /// Set the maximum Wasm stack size in bytes.
+    ///
+    /// This bounds how deep the interpreter can recurse before trapping
+    /// with `SandboxError::StackOverflow`: raise it for deeply recursive
+    /// code, lower it to fail fast. It must stay below the native stack of
+    /// the thread running the execution (2 MiB for Tokio's blocking threads).
+    ///
+    /// The setting is applied when the sandbox creates its own engine, so
+    /// it cannot be combined with `SandboxOptions::with_engine` or
+    /// `PythonSandbox::from_serialized_module`, which use a pre-built
+    /// `SharedEngine`. The interpreter is compiled for the sandbox instead
+    /// of being taken from the module cache, whose modules belong to
+    /// engines with the default stack size: the global cache is skipped,
+    /// and a cache passed with `SandboxOptions::with_cache` is rejected.
+    pub fn max_wasm_stack(mut self, bytes: usize) -> Self {
+        self.max_wasm_stack = Some(bytes);
+        self
+    }
+
     /// Set the path to the RustPython wasm interpreter.
     pub fn interpreter_path(mut self, path: impl Into<PathBuf>) -> Self {
         self.interpreter_path = Some(path.into());
         self
     }
 
+    /// Load the interpreter from `bytes` instead of from a file.
+    ///
+    /// For binaries that embed the interpreter (e.g. with `include_bytes!`)
+    /// and are deployed without it on disk. Takes precedence over
+    /// `interpreter_path`; the fallback interpreter is still read from its
+    /// path. Cached modules are keyed by a hash of the bytes, so sandboxes
+    /// sharing one blob compile it once.
+    pub fn interpreter_bytes(mut self, bytes: impl Into<Arc<[u8]>>) -> Self {
+        self.interpreter_bytes = Some(bytes.into());
+        self
+    }
+
+    /// Run the interpreter module through `interpreter`.
+    ///
+    /// Only needed for interpreter builds other than RustPython, whose
+    /// command line or entry point differ; see `sandbox::interpreter`.
+    /// Point `interpreter_path` (and any fallback) at a matching module.
+    pub fn interpreter(mut self, interpreter: impl Interpreter + 'static) -> Self {
+        self.interpreter = Some(Arc::new(interpreter));
+        self
+    }
+
+    /// Set an interpreter to use if the primary fails to compile or load.
+    pub fn fallback_interpreter_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.fallback_interpreter_path = Some(path.into());
+        self
+    }
+
+    /// Set the maximum size in bytes of the interpreter wasm file.
+    ///
+    /// Checked before the module is read or compiled, so a misconfigured
+    /// path to a huge file fails fast instead of exhausting memory at load.
+    pub fn max_module_bytes(mut self, bytes: usize) -> Self {
+        self.max_module_bytes = Some(bytes);
+        self
+    }
+
     /// Set the epoch tick interval for timeout checking.
     ///
     /// Smaller intervals provide more responsive timeout detection
-    /// but incur slightly more overhead.
+    /// but incur slightly more overhead. All executions on one engine share
+    /// a single ticker, which runs at the smallest interval requested by
+    /// the executions currently running on it.
     pub fn epoch_tick_interval(mut self, interval: Duration) -> Self {
         self.epoch_tick_interval = Some(interval);
         self
     }
 
-    /// Set stdin data to provide to the Python code.
+    /// Set default stdin data to provide to the Python code.
     ///
     /// This data will be available via `input()` or reading from `sys.stdin`.
+    /// It is used only when a call passes no `input` of its own; input
+    /// given to `PythonSandbox::execute` (and similar methods) replaces it.
     ///
     /// # Example
     ///
@@ -133,6 +745,18 @@ impl SandboxConfigBuilder {
         self
     }
 
+    /// Pass `args` to the script, as `sys.argv[1:]`.
+    ///
+    /// They are appended to the interpreter's command line after the code,
+    /// so argv is `["python", "-c", code, *args]` and, as with CPython,
+    /// `sys.argv[0]` is `"-c"`. Replaces any arguments set before; use
+    /// `PythonSandbox::execute_with_args` to pass different arguments to
+    /// one call.
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
     /// Add multiple environment variables to the sandbox.
     ///
     /// # Example
@@ -154,6 +778,113 @@ impl SandboxConfigBuilder {
         self
     }
 
+    /// Limit the size of each environment variable value.
+    ///
+    /// Where the environment is tenant-controlled, this bounds how much it
+    /// can inflate the WASI context. Checked by `SandboxConfig::validate`
+    /// when a sandbox is created.
+    pub fn max_env_value_bytes(mut self, bytes: usize) -> Self {
+        self.max_env_value_bytes = Some(bytes);
+        self
+    }
+
+    /// Limit the number of environment variables.
+    ///
+    /// Checked by `SandboxConfig::validate` when a sandbox is created.
+    pub fn max_env_count(mut self, count: usize) -> Self {
+        self.max_env_count = Some(count);
+        self
+    }
+
+    /// Mount a read-only file the code can `open()` and read.
+    ///
+    /// This is the scalable way to pass large inputs such as datasets or
+    /// configuration, which would not fit in argv or the environment.
+    /// `guest_path` must be absolute (e.g. `/data/input.json`); mounting
+    /// `.py` files under a directory listed in `sys_path` makes them
+    /// importable, so a whole package can be mounted file by file.
+    ///
+    /// Mounted files are the only files visible to the guest. They are
+    /// written to a private temporary directory on the host for each
+    /// execution (see `sandbox::mounts`), so very large files add to the
+    /// cost of every run.
+    pub fn mount_file(mut self, guest_path: impl Into<String>, data: Vec<u8>) -> Self {
+        self.mounted_files
+            .push((guest_path.into(), Arc::from(data)));
+        self
+    }
+
+    /// Supply a file the code can open by relative path, e.g. `open('data.csv')`.
+    ///
+    /// `path` is relative to `/sandbox`, which becomes the working
+    /// directory when any virtual file is set; the file can also be opened
+    /// at its absolute path (`/sandbox/data.csv`). Like `mount_file`, whose
+    /// storage it shares, the file is read-only: writes fail, so nothing
+    /// the code does to it outlives the execution.
+    pub fn virtual_file(mut self, path: impl AsRef<str>, contents: Vec<u8>) -> Self {
+        self.mounted_files
+            .push((mounts::virtual_path(path.as_ref()), Arc::from(contents)));
+        self
+    }
+
+    /// Give the code a writable `/out` directory and return what it writes there.
+    ///
+    /// Files left in `/out` when the code exits are returned in
+    /// `ExecutionResult::output_files`, keyed by their path relative to
+    /// `/out`; the directory starts empty for every execution. Like mounted
+    /// files it is backed by a private temporary directory on the host,
+    /// removed when the execution ends. Only regular files are read back;
+    /// symbolic links are skipped.
+    ///
+    /// Requires `max_output_bytes`, which caps the directory while the code
+    /// runs: it is measured on every epoch tick, and once its files add up
+    /// to more than the limit, or it holds more than 4096 entries, the
+    /// execution is stopped with `SandboxError::OutputFilesExceeded`. The
+    /// code can overshoot by what it writes between two ticks, but not
+    /// fill the host's disk. The files read back also count towards
+    /// `max_output_bytes` together with stdout and stderr: they are read in
+    /// path order and cut once the limit is reached, setting
+    /// `ExecutionResult::output_truncated`; no more of a file than fits is
+    /// read into memory.
+    pub fn collect_output_files(mut self, enabled: bool) -> Self {
+        self.collect_output_files = enabled;
+        self
+    }
+
+    /// Give files collected from `/out` the permission bits `mode` (e.g. `0o600`).
+    ///
+    /// WASI preview 1 has no permission model: the guest cannot `chmod` or
+    /// set a umask, and the modes it sees through `os.stat` are made up by
+    /// the interpreter. The mode is therefore applied by the host, to every
+    /// file collected by `collect_output_files` as it is read back, and
+    /// reported in `ExecutionResult::output_file_modes`. Without it, files
+    /// keep the mode the host created them with (subject to its umask).
+    /// On platforms other than Unix the mode is reported but not applied.
+    pub fn default_file_mode(mut self, mode: u32) -> Self {
+        self.default_file_mode = Some(mode);
+        self
+    }
+
+    /// Declare the program's stdin and input files in one place.
+    ///
+    /// Sets `stdin` (replacing any set before) when the spec has stdin
+    /// data, adds the spec's files after any already mounted, and sets
+    /// `max_input_bytes` when the spec has a size cap. The cap covers the
+    /// configured stdin and every mounted file, including ones mounted
+    /// with `mount_file`, and is checked by `SandboxConfig::validate` when
+    /// a sandbox is created. Input passed to an individual call replaces
+    /// the configured stdin and is not counted.
+    pub fn inputs(mut self, spec: InputSpec) -> Self {
+        if spec.stdin.is_some() {
+            self.stdin = spec.stdin;
+        }
+        self.mounted_files.extend(spec.files);
+        if spec.max_total_bytes.is_some() {
+            self.max_input_bytes = spec.max_total_bytes;
+        }
+        self
+    }
+
     /// Set a prelude script to run before user code.
     ///
     /// The prelude is executed in the same context as the user code,
@@ -181,6 +912,602 @@ impl SandboxConfigBuilder {
         self
     }
 
+    /// Run registered prelude fragments before the prelude.
+    ///
+    /// The fragments are looked up by name in `global_preludes()` when a
+    /// sandbox is created from this configuration, and concatenated in
+    /// the given order ahead of any `prelude`, which can therefore use
+    /// them. Creating the sandbox fails with `SandboxError::Config` if a
+    /// name is not registered. Each call replaces the previous list.
+    ///
+    /// To compose fragments from a registry of your own, pass the result
+    /// of `PreludeRegistry::compose` to `prelude` instead.
+    pub fn use_preludes(mut self, names: &[&str]) -> Self {
+        self.preludes = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Count Python exceptions raised during execution.
+    ///
+    /// The count is reported in `ExecutionMetadata::exceptions_raised`.
+    /// An exception propagating through several frames counts once.
+    ///
+    /// Counting installs a `sys.settrace` hook, which is invoked on every
+    /// line and call in user code. Expect execution to be several times
+    /// slower while it is enabled.
+    pub fn count_exceptions(mut self, enabled: bool) -> Self {
+        self.count_exceptions = enabled;
+        self
+    }
+
+    /// Record which lines of user code run.
+    ///
+    /// The distinct line numbers, counted from 1 at the first line of the
+    /// code passed to `execute` (the prelude is not included), are
+    /// reported in `ExecutionMetadata::lines_covered`. Lines of functions
+    /// are covered only if the functions are called. Nothing is reported
+    /// if the interpreter exits via `os._exit`, e.g. on reaching
+    /// `max_exceptions`.
+    ///
+    /// Like `count_exceptions`, this installs a `sys.settrace` hook invoked
+    /// on every line and call in user code; expect execution to be several
+    /// times slower, and slower still with both enabled.
+    pub fn line_coverage(mut self, enabled: bool) -> Self {
+        self.line_coverage = enabled;
+        self
+    }
+
+    /// Disable Python's cyclic garbage collector before user code runs.
+    ///
+    /// Runs `gc.disable()`, so collections of reference cycles do not
+    /// happen at allocation-dependent points, which makes memory use more
+    /// repeatable for careful measurement. Objects without cycles are still
+    /// freed as soon as they are unreferenced, but cyclic garbage is kept
+    /// until the run ends: code that creates many cycles can use much more
+    /// memory, and reach `max_memory` sooner, than with the collector on.
+    ///
+    /// Whether the collector was enabled when the interpreter exited
+    /// (user code may call `gc.enable()`) is reported in
+    /// `ExecutionMetadata::gc_enabled`. If the interpreter has no usable
+    /// `gc` module, the option has no effect and nothing is reported.
+    pub fn disable_gc(mut self, disabled: bool) -> Self {
+        self.disable_gc = disabled;
+        self
+    }
+
+    /// Return the traceback of an uncaught exception apart from stderr.
+    ///
+    /// Normally the traceback is printed to stderr after anything the code
+    /// wrote there itself, and the two are merged in both
+    /// `ExecutionResult::stderr` and `ExecutionResult::user_stderr`, with
+    /// `ExecutionResult::exception` left empty. With this enabled, a
+    /// `sys.excepthook` installed before user code hands the traceback to
+    /// the host instead: `user_stderr` holds only the program's own writes
+    /// and the parsed exception is in `exception`. `stderr` still holds
+    /// both, as without the hook.
+    ///
+    /// A syntax error in the code itself is raised before the hook is
+    /// installed, so it is still printed to stderr.
+    pub fn separate_exceptions(mut self, enabled: bool) -> Self {
+        self.separate_exceptions = enabled;
+        self
+    }
+
+    /// Terminate execution once more than `limit` exceptions have been raised.
+    ///
+    /// Implies `count_exceptions(true)`. Exceeding the limit fails the run
+    /// with `SandboxError::ExceptionLimitExceeded`.
+    ///
+    /// This is a best-effort guard against runaway `try`/`except` loops,
+    /// not a security control. It is enforced inside the interpreter by
+    /// the `sys.settrace` hook that counts exceptions, which user code can
+    /// replace or remove with `sys.settrace(None)`, after which nothing is
+    /// counted or limited. Bound untrusted code with the host-enforced
+    /// limits instead: `timeout`, `max_fuel` and `max_memory`.
+    pub fn max_exceptions(mut self, limit: u64) -> Self {
+        self.max_exceptions = Some(limit);
+        self.count_exceptions = true;
+        self
+    }
+
+    /// Set the maximum size of values captured from the interpreter.
+    ///
+    /// Applies to the JSON returned by `PythonSandbox::execute_with_globals`.
+    /// Defaults to 1MB.
+    pub fn max_value_bytes(mut self, bytes: usize) -> Self {
+        self.max_value_bytes = Some(bytes);
+        self
+    }
+
+    /// Let Python see memory exhaustion as a `MemoryError`.
+    ///
+    /// The limiter never traps: when a `memory.grow` would exceed
+    /// `max_memory` it fails the grow, which returns -1 to the guest. What
+    /// happens next is up to the interpreter. Allocations that RustPython
+    /// checks surface as a Python `MemoryError` that user code can catch,
+    /// while allocations that abort the interpreter end in a hard trap.
+    ///
+    /// By default any run that hit the limit and did not complete normally
+    /// fails with `SandboxError::MemoryLimitExceeded`, hiding the traceback.
+    /// When enabled, a run that exits through the interpreter (for example
+    /// an uncaught `MemoryError`) returns an `ExecutionResult` with the
+    /// traceback in `stderr` and a non-zero exit code instead. Hard traps
+    /// are still reported as `MemoryLimitExceeded`.
+    pub fn memory_limit_as_python_error(mut self, enabled: bool) -> Self {
+        self.memory_limit_as_python_error = enabled;
+        self
+    }
+
+    /// Make the iteration order of sets reproducible from run to run.
+    ///
+    /// Dicts already iterate in insertion order. Sets iterate in hash
+    /// order, and string hashes are salted with a random seed in every
+    /// interpreter, so printing a set of strings can differ between runs.
+    /// This passes `PYTHONHASHSEED=0` to the interpreter (unless `env`
+    /// sets it), which fixes the seed. A variable set this way is visible
+    /// in `os.environ`. With `strict_env`, the seed must be set with `env`
+    /// instead, or the configuration is rejected.
+    ///
+    /// Set order is only repeatable, not sorted: it still depends on the
+    /// values and on the order they were inserted, and may change between
+    /// interpreter versions. Other sources of nondeterminism, such as
+    /// `random`, the clock or object addresses in default `repr`s, are not
+    /// affected. Where output order cannot be fixed, compare it with
+    /// `normalize_output` instead.
+    pub fn deterministic_collections(mut self, enabled: bool) -> Self {
+        self.deterministic_collections = enabled;
+        self
+    }
+
+    /// Make every run of the same code observe the same randomness and time.
+    ///
+    /// For reproducible fixtures: with the same seed, `random.random()`,
+    /// `os.urandom` and `time.time()` return the same values on every run.
+    /// This
+    ///
+    /// - seeds `random` with `random.seed(seed)` before user code runs,
+    /// - serves the interpreter's random bytes (`os.urandom`, `uuid.uuid4`,
+    ///   the default seeds of `random`) from a stream derived from `seed`,
+    /// - stops the wall clock at 2020-01-01T00:00:00Z and the monotonic
+    ///   clock at zero, and
+    /// - passes `seed`, folded to 32 bits, as `PYTHONHASHSEED` (unless
+    ///   `env` sets it), which also makes `deterministic_collections`
+    ///   redundant. With `strict_env`, the hash seed must be set with
+    ///   `env` instead, or the configuration is rejected.
+    ///
+    /// Dict order is already deterministic. What remains nondeterministic
+    /// is what the guest does not read from WASI: object addresses (`id`
+    /// and default `repr`s) and anything that depends on the host, such as
+    /// timeouts, fuel and memory limits. Since the clocks never advance,
+    /// measured durations are zero and loops waiting for time to pass
+    /// never end; `time.sleep` still waits in real time. None of this is
+    /// cryptographically secure.
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.deterministic_seed = Some(seed);
+        self
+    }
+
+    /// Hide every environment variable that was not configured with `env`.
+    ///
+    /// The host never forwards its own environment, but the sandbox adds
+    /// a few variables of its own: `PYTHONHASHSEED` for the deterministic
+    /// options and `PYTHONPATH` for modules run against virtual files. In
+    /// strict mode neither is added, so they must be set with `env`
+    /// instead: a deterministic configuration without `PYTHONHASHSEED`
+    /// fails `validate`, and running a module against virtual files
+    /// without `PYTHONPATH` fails with `SandboxError::Config`, rather than
+    /// losing what the variable was for. The filtering happens on the
+    /// host, so it also covers `posix.environ` and anything else that
+    /// reads the WASI environment directly.
+    ///
+    /// One sandbox variable is still set in strict mode:
+    /// `__SANDBOX_REPORT_MARKER`, which hands the instrumentation options
+    /// (`separate_exceptions`, `count_exceptions`, `max_imports`, ...) a
+    /// random marker for their reports. It is only set when such an option
+    /// is in use, never for modules, and the instrumentation removes it
+    /// from `os.environ` and `posix.environ` before user code runs.
+    pub fn strict_env(mut self, enabled: bool) -> Self {
+        self.strict_env = enabled;
+        self
+    }
+
+    /// Measure the time spent inside host (WASI) calls.
+    ///
+    /// The total is reported in `ExecutionMetadata::host_call_duration`,
+    /// which helps tell I/O-bound scripts from CPU-bound ones. Timing uses
+    /// a Wasmtime call hook that reads the clock twice per host call; this
+    /// is negligible for most scripts but noticeable for scripts making
+    /// millions of tiny writes.
+    pub fn track_host_calls(mut self, enabled: bool) -> Self {
+        self.track_host_calls = enabled;
+        self
+    }
+
+    /// Limit the total time spent inside host (WASI) calls.
+    ///
+    /// Implies `track_host_calls(true)`. Exceeding the limit fails the run
+    /// with `SandboxError::IoTimeExceeded`.
+    pub fn max_io_time(mut self, limit: Duration) -> Self {
+        self.max_io_time = Some(limit);
+        self.track_host_calls = true;
+        self
+    }
+
+    /// Replace `sys.path` with the given entries before user code runs.
+    ///
+    /// This sets the initial search path, not a restriction: user code
+    /// can reassign or extend `sys.path` and then import from any
+    /// directory the sandbox can see. To keep modules out of reach, do not
+    /// mount them. The prelude runs before the path is replaced. Modules
+    /// frozen into the interpreter (the RustPython `freeze-stdlib` build)
+    /// do not come from `sys.path` and remain importable.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let config = SandboxConfig::builder()
+    ///     .sys_path(vec!["/modules".to_string()])
+    ///     .build();
+    /// ```
+    pub fn sys_path(mut self, paths: Vec<String>) -> Self {
+        self.sys_path = Some(paths);
+        self
+    }
+
+    /// Import `modules` before user code runs.
+    ///
+    /// Each module is bound as a global like `import json` would, so user
+    /// code can use it without importing it. If any of them cannot be
+    /// imported, execution stops before user code runs and fails with a
+    /// `SandboxError::Config` naming the module, instead of the code failing
+    /// later with an `ImportError` somewhere in its own traceback.
+    ///
+    /// The imports happen on every execution, after `sys_path` is applied,
+    /// and count towards `max_imports`; on their own they do not make
+    /// executions faster.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let config = SandboxConfig::builder()
+    ///     .preimport(&["json", "math"])
+    ///     .build();
+    /// ```
+    pub fn preimport(mut self, modules: &[&str]) -> Self {
+        self.preimport = modules.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Decide memory growth with a custom policy instead of `max_memory`.
+    ///
+    /// The policy is called with the desired total memory size in bytes
+    /// whenever the interpreter tries to grow its memory, and returns
+    /// whether to allow it. It replaces the `max_memory` comparison, so it
+    /// can grant trusted code more memory or implement stateful rules
+    /// such as throttling growth. `max_memory` remains the simple default
+    /// and is still used in error messages. A memory limit set for a
+    /// single call (`ExecutionOverrides::max_memory`,
+    /// `RunOpts::max_memory`, and the limits tried by
+    /// `PythonSandbox::probe_memory`) is enforced on top of the policy:
+    /// growth must then be allowed by both.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let config = SandboxConfig::builder()
+    ///     .memory_policy(Arc::new(|desired| desired <= 128 * 1024 * 1024))
+    ///     .build();
+    /// ```
+    pub fn memory_policy(mut self, policy: Arc<dyn Fn(u64) -> bool + Send + Sync>) -> Self {
+        self.memory_policy = Some(MemoryPolicy::new(policy));
+        self
+    }
+
+    /// Record the size of each read the interpreter makes from stdin.
+    ///
+    /// The sizes are reported in `ExecutionMetadata::stdin_reads`, which
+    /// helps diagnose scripts whose behavior depends on how they consume
+    /// input. Recording costs one vector push per read.
+    pub fn trace_stdin(mut self, enabled: bool) -> Self {
+        self.trace_stdin = enabled;
+        self
+    }
+
+    /// Convert `\r\n` and lone `\r` line endings in stdin to `\n`.
+    ///
+    /// Input written on Windows otherwise reaches the code with its `\r`
+    /// intact, e.g. at the end of every line returned by `sys.stdin.readline`,
+    /// which breaks comparisons against expected values. Applies to the
+    /// input of each call and to `stdin`, but not to the output of a stdin
+    /// generator. Off by default, so stdin is passed through byte for byte.
+    pub fn normalize_stdin_newlines(mut self, enabled: bool) -> Self {
+        self.normalize_stdin_newlines = enabled;
+        self
+    }
+
+    /// Set a soft memory threshold below `max_memory`.
+    ///
+    /// The first time memory grows past the threshold,
+    /// `ExecutionMetadata::exceeded_warn_threshold` is set and, with the
+    /// `tracing` feature, a warning is logged. Execution continues
+    /// normally; only `max_memory` is enforced.
+    pub fn memory_warn_at(mut self, bytes: u64) -> Self {
+        self.memory_warn_at = Some(bytes);
+        self
+    }
+
+    /// Run without standard streams, for headless computation.
+    ///
+    /// Stdin is empty and nothing written to stdout or stderr is captured,
+    /// so `ExecutionResult::stdout` and `stderr` are always empty. Writing
+    /// to `sys.stdout` or `sys.stderr` (e.g. `print`) raises `OSError` in
+    /// the script instead of affecting the host. Values can still be
+    /// returned through `PythonSandbox::execute_with_globals`.
+    pub fn no_stdio(mut self, enabled: bool) -> Self {
+        self.no_stdio = enabled;
+        self
+    }
+
+    /// Preserve output written before execution is interrupted.
+    ///
+    /// When enabled, errors raised while the interpreter is running
+    /// (timeout, memory limit, fuel exhaustion and other traps) are wrapped
+    /// in `SandboxError::Interrupted`, carrying the stdout and stderr
+    /// captured so far. Only bytes the interpreter actually wrote are
+    /// preserved; output still sitting in Python's own buffers when the
+    /// trap hits is lost. Disabled by default, in which case the error is
+    /// returned unwrapped and the output is discarded.
+    pub fn flush_on_trap(mut self, enabled: bool) -> Self {
+        self.flush_on_trap = enabled;
+        self
+    }
+
+    /// Limit the number of frames kept in captured tracebacks.
+    ///
+    /// Deep recursion produces enormous tracebacks. Tracebacks with more
+    /// than `2 * frames` frames are cut down to their first and last
+    /// `frames` frames, with a marker line noting how many were elided, so
+    /// both the entry point and the failing frame are kept. Applies to
+    /// `ExecutionResult::stderr` and the traceback in parsed exceptions.
+    pub fn max_traceback_frames(mut self, frames: usize) -> Self {
+        self.max_traceback_frames = Some(frames);
+        self
+    }
+
+    /// Set the size above which an injected context is sent via stdin.
+    ///
+    /// `PythonSandbox::execute_with_context` embeds a context of up to
+    /// `bytes` of serialized JSON directly in the code passed to the
+    /// interpreter. Larger contexts would make that code enormous, so they
+    /// are instead written to stdin ahead of any input and read back by
+    /// the guest before user code runs. Defaults to 128KB.
+    pub fn max_context_bytes(mut self, bytes: usize) -> Self {
+        self.max_context_bytes = Some(bytes);
+        self
+    }
+
+    /// Store stdout without a single trailing newline.
+    ///
+    /// Removes the newline `print` adds after the last line, as
+    /// `ExecutionResult::stdout_trimmed` does, so callers need not `trim`
+    /// every result. Only one line ending is removed: output ending in
+    /// blank lines keeps all but the last. A trailing `\r\n` counts as one
+    /// line ending. The removed ending is recorded in
+    /// `ExecutionMetadata::trimmed_newline`, and
+    /// `ExecutionResult::raw_stdout` restores it.
+    pub fn trim_trailing_newline(mut self, enabled: bool) -> Self {
+        self.trim_trailing_newline = enabled;
+        self
+    }
+
+    /// Limit the number of modules user code may import.
+    ///
+    /// Each `import` statement (or `__import__` call) that loads a module
+    /// not yet in `sys.modules` counts once, however many dependencies
+    /// that module pulls in itself. Re-importing a loaded module is free.
+    /// Once the limit is reached, further imports raise `ImportError` in
+    /// the guest, which user code may catch. The count is reported in
+    /// `ExecutionMetadata::imports`.
+    ///
+    /// This is a best-effort guard against accidental import sprawl, not a
+    /// security control. It wraps `builtins.__import__` inside the
+    /// interpreter, so imports that bypass it, such as
+    /// `importlib.import_module`, are neither counted nor limited, and user
+    /// code can restore the original function. To keep untrusted code away
+    /// from modules, control what the interpreter bundles and what is
+    /// mounted instead.
+    pub fn max_imports(mut self, limit: u64) -> Self {
+        self.max_imports = Some(limit);
+        self
+    }
+
+    /// Capture at most `limit` lines of stdout.
+    ///
+    /// Output after the `limit`-th newline is dropped as it is written, so
+    /// a runaway print loop cannot fill memory, and
+    /// `ExecutionResult::output_line_limited` is set. The code keeps
+    /// running unaware unless `terminate_on_output_lines` is set. Streamed
+    /// output events and sentinels only see the kept lines. Stderr is not
+    /// limited.
+    pub fn max_output_lines(mut self, limit: usize) -> Self {
+        self.max_output_lines = Some(limit);
+        self
+    }
+
+    /// Stop the execution as soon as stdout goes past `max_output_lines`.
+    ///
+    /// The guest is stopped at its first write past the limit, without
+    /// running any more Python code. The result is returned as usual with
+    /// exit code 0 and the kept lines; check
+    /// `ExecutionResult::output_line_limited` to tell it apart from a
+    /// normal exit.
+    pub fn terminate_on_output_lines(mut self, enabled: bool) -> Self {
+        self.terminate_on_output_lines = enabled;
+        self
+    }
+
+    /// Capture at most `limit` bytes of stdout and stderr combined.
+    ///
+    /// Output is counted as it is written; the write that crosses the limit
+    /// is cut at exactly `limit` bytes and later output is dropped, so a
+    /// print loop cannot grow the host's buffers without bound. The guest
+    /// sees every write succeed and keeps running, and
+    /// `ExecutionResult::output_truncated` is set. A cut may split a
+    /// UTF-8 sequence, which is then decoded lossily. Streamed output
+    /// events and sentinels only see the kept bytes. Guest instrumentation
+    /// reports on stderr are not counted. To stop the execution instead,
+    /// combine with `max_output_rate` or a timeout.
+    ///
+    /// Unlimited by default. Output is held in memory until the execution
+    /// ends, so set a limit for untrusted code.
+    pub fn max_output_bytes(mut self, limit: u64) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// Stop the execution if it writes more than `bytes_per_sec` on average.
+    ///
+    /// Output to stdout and stderr is counted together, before any of it is
+    /// dropped by `max_output_lines`, in consecutive one-second windows
+    /// starting when the execution starts. When a window ends with more
+    /// than `bytes_per_sec` bytes written in it, the guest is interrupted at
+    /// its next epoch tick and the execution fails with
+    /// `SandboxError::OutputRateExceeded`. Short bursts within a window are
+    /// allowed, and a print loop is caught about a second after it starts,
+    /// long before most timeouts.
+    pub fn max_output_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.max_output_rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// Limit the total time of APIs that execute code several times.
+    ///
+    /// `timeout` bounds each execution on its own. APIs that run the code
+    /// repeatedly for one call can take many times longer; the budget caps
+    /// their cumulative time. Each execution's timeout is cut to what is
+    /// left of the budget, and once it is used up the call fails with
+    /// `SandboxError::Timeout` instead of starting another execution.
+    ///
+    /// Consulted by:
+    ///
+    /// - `PythonSandbox::probe_memory`, for all of its runs.
+    /// - `PythonSandbox::run_inputs`, from the first call to
+    ///   `InputRuns::next` across every input; `next` returns the timeout
+    ///   error once, then `None`.
+    ///
+    /// Single executions such as `execute` only use `timeout`.
+    pub fn total_budget(mut self, budget: Duration) -> Self {
+        self.total_budget = Some(budget);
+        self
+    }
+
+    /// Remove interpreter startup banner lines from captured stdout.
+    ///
+    /// Code runs with `python -c`, which does not print RustPython's
+    /// startup banner, so stdout normally contains only the program's
+    /// output. If an interpreter build does print its banner before the
+    /// program starts, those leading lines are removed and kept in
+    /// `ExecutionMetadata::banner` instead. Enabled by default.
+    pub fn suppress_banner(mut self, enabled: bool) -> Self {
+        self.suppress_banner = Some(enabled);
+        self
+    }
+
+    /// Refuse to execute code once a sandbox is older than `max_age`.
+    ///
+    /// For policies that limit how long a compiled interpreter stays in
+    /// use. Every execution started after the sandbox has existed for
+    /// `max_age` fails with `SandboxError::Expired` without running any
+    /// code; the caller should create a new sandbox. This includes the
+    /// calls of a `PersistentSession` started from the sandbox. Executions
+    /// already running when the sandbox expires are not interrupted.
+    ///
+    /// The age counts from the sandbox's creation, not from when its
+    /// module was compiled: a sandbox created from a cached module starts
+    /// fresh. Disable caching (`SandboxOptions::no_cache`) to recompile
+    /// on every rebuild.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Treat exit codes outside `codes` as errors.
+    ///
+    /// By default any exit code is returned in `ExecutionResult::exit_code`
+    /// for the caller to inspect. With this set, an execution that exits
+    /// with a code not in `codes` fails with `SandboxError::ExecutionFailed`
+    /// naming the code, and its output is not returned. Pass `&[0]` to
+    /// accept only success, or e.g. `&[0, 2]` for tools that use 2 for a
+    /// non-fatal result.
+    ///
+    /// Internal runs whose exit code the sandbox interprets itself
+    /// (`compile_check`, `run_doctests`, `python_version`) are not affected.
+    pub fn allowed_exit_codes(mut self, codes: &[i32]) -> Self {
+        self.allowed_exit_codes = Some(codes.to_vec());
+        self
+    }
+
+    /// Display the value of a final bare expression, as at a REPL.
+    ///
+    /// When the last top-level statement of the code is an expression, its
+    /// `repr` is printed, so `1 + 1` outputs `2`. Values of `None` are not
+    /// shown, so code ending in `print(...)` is not printed twice, and code
+    /// ending in any other statement (such as an assignment) is unaffected.
+    ///
+    /// The code is parsed and run by a small harness, which appears in
+    /// tracebacks as extra frames.
+    pub fn auto_print_last_expr(mut self, enabled: bool) -> Self {
+        self.auto_print_last_expr = enabled;
+        self
+    }
+
+    /// Set what streamed stdout writes do after the consumer stops listening.
+    ///
+    /// Only affects `PythonSandbox::execute_events` once the events are
+    /// no longer received, as after `ExecEvents::finish`. The default,
+    /// `OutputClosedAction::Discard`, lets the code run on unaware;
+    /// `OutputClosedAction::BrokenPipe` makes its writes raise
+    /// `BrokenPipeError`, so code that only produces output can stop early.
+    pub fn on_output_closed(mut self, action: OutputClosedAction) -> Self {
+        self.on_output_closed = action;
+        self
+    }
+
+    /// Record filesystem and network access attempts made by the code.
+    ///
+    /// Attempts are returned in `ExecutionResult::access_attempts`. Every
+    /// such attempt fails under isolation; recording them turns silent
+    /// denials into telemetry. Adds a small per-call overhead to the
+    /// wrapped functions (see `sandbox::audit`).
+    #[cfg(feature = "audit")]
+    pub fn audit_access(mut self, enabled: bool) -> Self {
+        self.audit_access = enabled;
+        self
+    }
+
+    /// Mask text matching any of `patterns` in captured stdout and stderr.
+    ///
+    /// Each match is replaced with `[REDACTED]`, pattern by pattern in the
+    /// given order, for keeping secrets the code prints (tokens, keys) out
+    /// of logs. This is best-effort data-loss prevention, not a security
+    /// boundary: it runs on the output after capture, so it only catches
+    /// what the patterns describe and cannot see a secret the code encodes
+    /// or splits. It applies to `ExecutionResult` and partial output;
+    /// output streamed by `execute_events` as it is written is not masked.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let config = SandboxConfig::builder()
+    ///     .redact_output_patterns(vec![Regex::new(r"sk-[A-Za-z0-9]{20,}").unwrap()])
+    ///     .build();
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn redact_output_patterns(mut self, patterns: Vec<regex::Regex>) -> Self {
+        self.redact_output_patterns = patterns;
+        self
+    }
+
     /// Build the SandboxConfig.
     pub fn build(self) -> SandboxConfig {
         let default = SandboxConfig::default();
@@ -188,13 +1515,64 @@ impl SandboxConfigBuilder {
             timeout: self.timeout.unwrap_or(default.timeout),
             max_memory: self.max_memory.unwrap_or(default.max_memory),
             max_fuel: self.max_fuel.or(default.max_fuel),
+            max_wasm_stack: self.max_wasm_stack,
             interpreter_path: self.interpreter_path.unwrap_or(default.interpreter_path),
+            interpreter_bytes: self.interpreter_bytes,
+            interpreter: self.interpreter.unwrap_or(default.interpreter),
+            fallback_interpreter_path: self.fallback_interpreter_path,
+            max_module_bytes: self.max_module_bytes,
             epoch_tick_interval: self
                 .epoch_tick_interval
                 .unwrap_or(default.epoch_tick_interval),
             stdin: self.stdin,
+            args: self.args,
             env_vars: self.env_vars,
+            max_env_value_bytes: self.max_env_value_bytes,
+            max_env_count: self.max_env_count,
+            mounted_files: self.mounted_files,
+            collect_output_files: self.collect_output_files,
+            default_file_mode: self.default_file_mode,
+            max_input_bytes: self.max_input_bytes,
             prelude: self.prelude,
+            preludes: self.preludes,
+            count_exceptions: self.count_exceptions,
+            line_coverage: self.line_coverage,
+            disable_gc: self.disable_gc,
+            separate_exceptions: self.separate_exceptions,
+            max_exceptions: self.max_exceptions,
+            max_value_bytes: self.max_value_bytes.unwrap_or(default.max_value_bytes),
+            memory_limit_as_python_error: self.memory_limit_as_python_error,
+            strict_env: self.strict_env,
+            deterministic_collections: self.deterministic_collections,
+            deterministic_seed: self.deterministic_seed,
+            track_host_calls: self.track_host_calls,
+            max_io_time: self.max_io_time,
+            sys_path: self.sys_path,
+            preimport: self.preimport,
+            memory_policy: self.memory_policy,
+            trace_stdin: self.trace_stdin,
+            normalize_stdin_newlines: self.normalize_stdin_newlines,
+            memory_warn_at: self.memory_warn_at,
+            no_stdio: self.no_stdio,
+            flush_on_trap: self.flush_on_trap,
+            max_traceback_frames: self.max_traceback_frames,
+            max_context_bytes: self.max_context_bytes.unwrap_or(default.max_context_bytes),
+            trim_trailing_newline: self.trim_trailing_newline,
+            max_imports: self.max_imports,
+            max_output_lines: self.max_output_lines,
+            terminate_on_output_lines: self.terminate_on_output_lines,
+            max_output_rate: self.max_output_rate,
+            max_output_bytes: self.max_output_bytes,
+            total_budget: self.total_budget,
+            max_age: self.max_age,
+            allowed_exit_codes: self.allowed_exit_codes,
+            suppress_banner: self.suppress_banner.unwrap_or(default.suppress_banner),
+            auto_print_last_expr: self.auto_print_last_expr,
+            on_output_closed: self.on_output_closed,
+            #[cfg(feature = "audit")]
+            audit_access: self.audit_access,
+            #[cfg(feature = "regex")]
+            redact_output_patterns: self.redact_output_patterns,
         }
     }
 }
@@ -203,6 +1581,60 @@ impl SandboxConfigBuilder {
 mod tests {
     use super::*;
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_from_json() {
+        let config = SandboxConfig::from_json(
+            r#"{
+                "timeout": 5000,
+                "max_io_time": 250,
+                "interpreter_path": "/opt/python.wasm",
+                "env_vars": [["KEY", "value"]],
+                "on_output_closed": "broken_pipe"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.timeout, Duration::from_secs(5));
+        assert_eq!(config.max_io_time, Some(Duration::from_millis(250)));
+        assert_eq!(config.interpreter_path, PathBuf::from("/opt/python.wasm"));
+        assert_eq!(config.env_vars, [("KEY".to_string(), "value".to_string())]);
+        assert_eq!(config.on_output_closed, OutputClosedAction::BrokenPipe);
+        // Missing fields keep their defaults
+        assert_eq!(config.max_memory, SandboxConfig::default().max_memory);
+
+        // Round trip
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["timeout"], 5000);
+        assert_eq!(json["env_vars"], serde_json::json!([["KEY", "value"]]));
+        let again = SandboxConfig::from_json(&json.to_string()).unwrap();
+        assert_eq!(again.max_io_time, config.max_io_time);
+
+        assert!(matches!(
+            SandboxConfig::from_json(r#"{"timeout": "soon"}"#),
+            Err(SandboxError::Config(_))
+        ));
+        assert!(matches!(
+            SandboxConfig::from_json(r#"{"max_wasm_stack": 0}"#),
+            Err(SandboxError::Config(_))
+        ));
+    }
+
+    #[cfg(all(feature = "serde", feature = "regex"))]
+    #[test]
+    fn test_from_json_redact_patterns() {
+        let config =
+            SandboxConfig::from_json(r#"{"redact_output_patterns": ["sk-[a-z]+"]}"#).unwrap();
+        assert_eq!(config.redact_output_patterns[0].as_str(), "sk-[a-z]+");
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            json["redact_output_patterns"],
+            serde_json::json!(["sk-[a-z]+"])
+        );
+        assert!(SandboxConfig::from_json(r#"{"redact_output_patterns": ["("]}"#).is_err());
+    }
+
     #[test]
     fn test_default_config() {
         let config = SandboxConfig::default();
@@ -211,6 +1643,15 @@ mod tests {
         assert!(config.stdin.is_none());
         assert!(config.env_vars.is_empty());
         assert!(config.prelude.is_none());
+        assert!(config.suppress_banner);
+        assert_eq!(config.on_output_closed, OutputClosedAction::Discard);
+        assert!(config.max_output_bytes.is_none());
+        assert!(
+            !SandboxConfig::builder()
+                .suppress_banner(false)
+                .build()
+                .suppress_banner
+        );
     }
 
     #[test]
@@ -226,6 +1667,98 @@ mod tests {
         assert_eq!(config.max_fuel, Some(1_000_000));
     }
 
+    #[test]
+    fn test_validate_max_wasm_stack() {
+        let config = SandboxConfig::builder().max_wasm_stack(0).build();
+        assert!(matches!(config.validate(), Err(SandboxError::Config(_))));
+
+        let config = SandboxConfig::builder().max_wasm_stack(256 * 1024).build();
+        assert_eq!(config.max_wasm_stack, Some(256 * 1024));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_strict_env_hash_seed() {
+        let strict = SandboxConfig::builder().strict_env(true);
+        assert!(strict.clone().deterministic(7).build().validate().is_err());
+        assert!(matches!(
+            strict
+                .clone()
+                .deterministic_collections(true)
+                .build()
+                .validate(),
+            Err(SandboxError::Config(_))
+        ));
+        assert!(strict
+            .env("PYTHONHASHSEED", "0")
+            .deterministic_collections(true)
+            .build()
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate_collect_output_files() {
+        let config = SandboxConfig::builder()
+            .collect_output_files(true)
+            .max_output_bytes(1024)
+            .build();
+        assert!(config.validate().is_ok());
+
+        let unbounded = SandboxConfig {
+            max_output_bytes: None,
+            ..config
+        };
+        assert!(matches!(unbounded.validate(), Err(SandboxError::Config(_))));
+    }
+
+    #[test]
+    fn test_validate_default_file_mode() {
+        let config = SandboxConfig::builder().default_file_mode(0o640).build();
+        assert_eq!(config.default_file_mode, Some(0o640));
+        assert!(config.validate().is_ok());
+
+        let config = SandboxConfig::builder().default_file_mode(0o100644).build();
+        assert!(matches!(config.validate(), Err(SandboxError::Config(_))));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let strict = SandboxConfig::profile(Profile::UntrustedStrict);
+        assert_eq!(strict.timeout, Duration::from_secs(1));
+        assert_eq!(strict.max_memory, 16 * 1024 * 1024);
+        assert!(strict.max_fuel.is_some());
+        assert!(strict.strict_env);
+        assert_eq!(strict.max_imports, Some(20));
+
+        let fast = SandboxConfig::profile(Profile::TrustedFast);
+        assert_eq!(fast.timeout, Duration::from_secs(30));
+        assert_eq!(fast.max_memory, 256 * 1024 * 1024);
+        assert!(fast.max_fuel.is_none());
+
+        let grading = SandboxConfig::profile(Profile::Grading);
+        assert_eq!(grading.timeout, Duration::from_secs(5));
+        assert!(grading.max_fuel.is_some());
+        assert!(grading.flush_on_trap);
+        assert!(grading.trim_trailing_newline);
+
+        let repl = SandboxConfig::profile(Profile::Repl);
+        assert_eq!(repl.timeout, Duration::from_secs(10));
+        assert!(repl.max_fuel.is_none());
+        assert!(repl.flush_on_trap);
+    }
+
+    #[test]
+    fn test_profile_customized() {
+        let config = SandboxConfig::builder()
+            .profile(Profile::UntrustedStrict)
+            .timeout(Duration::from_secs(3))
+            .build();
+
+        assert_eq!(config.timeout, Duration::from_secs(3));
+        assert_eq!(config.max_memory, 16 * 1024 * 1024);
+    }
+
     #[test]
     fn test_builder_stdin() {
         let config = SandboxConfig::builder().stdin("hello world").build();
@@ -233,6 +1766,61 @@ mod tests {
         assert_eq!(config.stdin, Some("hello world".to_string()));
     }
 
+    #[test]
+    fn test_builder_inputs() {
+        let spec = InputSpec::new()
+            .stdin("3\n")
+            .file("/data/a.txt", b"abc".to_vec())
+            .file("/data/b.txt", b"de".to_vec())
+            .max_total_bytes(7);
+        assert_eq!(spec.total_bytes(), 7);
+
+        let config = SandboxConfig::builder()
+            .mount_file("/data/first.txt", Vec::new())
+            .inputs(spec)
+            .build();
+
+        assert_eq!(config.stdin.as_deref(), Some("3\n"));
+        let paths: Vec<_> = config
+            .mounted_files
+            .iter()
+            .map(|(p, _)| p.as_str())
+            .collect();
+        assert_eq!(paths, ["/data/first.txt", "/data/a.txt", "/data/b.txt"]);
+        assert_eq!(config.max_input_bytes, Some(7));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_builder_virtual_file() {
+        let config = SandboxConfig::builder()
+            .virtual_file("data.csv", b"a,b\n".to_vec())
+            .build();
+
+        assert_eq!(config.mounted_files[0].0, "/sandbox/data.csv");
+        assert!(config.validate().is_ok());
+
+        let escaping = SandboxConfig::builder()
+            .virtual_file("../etc/passwd", Vec::new())
+            .build();
+        assert!(matches!(escaping.validate(), Err(SandboxError::Config(_))));
+    }
+
+    #[test]
+    fn test_validate_input_size() {
+        let config = SandboxConfig::builder()
+            .inputs(
+                InputSpec::new()
+                    .stdin("12345")
+                    .file("/data/a.txt", vec![0; 4])
+                    .max_total_bytes(8),
+            )
+            .build();
+
+        let err = config.validate().unwrap_err();
+        assert!(matches!(err, SandboxError::Config(ref msg) if msg.contains("9 bytes")));
+    }
+
     #[test]
     fn test_builder_env_vars() {
         let config = SandboxConfig::builder()
@@ -251,6 +1839,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_env_value_size() {
+        let config = SandboxConfig::builder()
+            .max_env_value_bytes(8)
+            .env("SHORT", "12345678")
+            .build();
+        assert!(config.validate().is_ok());
+
+        let config = SandboxConfig::builder()
+            .max_env_value_bytes(8)
+            .env("LONG", "123456789")
+            .build();
+        let err = config.validate().unwrap_err();
+        assert!(matches!(err, SandboxError::Config(ref msg) if msg.contains("LONG")));
+    }
+
+    #[test]
+    fn test_validate_env_count() {
+        let config = SandboxConfig::builder()
+            .max_env_count(2)
+            .envs([("A", "1"), ("B", "2"), ("C", "3")])
+            .build();
+        assert!(matches!(config.validate(), Err(SandboxError::Config(_))));
+
+        let config = SandboxConfig::builder()
+            .max_env_count(3)
+            .envs([("A", "1"), ("B", "2"), ("C", "3")])
+            .build();
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn test_builder_envs() {
         let config = SandboxConfig::builder()
@@ -268,4 +1887,36 @@ mod tests {
 
         assert_eq!(config.prelude, Some("def helper(): pass".to_string()));
     }
+
+    #[test]
+    fn test_resolve_preludes() {
+        let registry = PreludeRegistry::new();
+        registry.register("math_helpers", "def double(x): return 2 * x");
+        registry.register("safe_io", "def read_all(): return ''\n");
+        let config = SandboxConfig::builder()
+            .use_preludes(&["math_helpers", "safe_io"])
+            .prelude("BASE = double(21)")
+            .build();
+
+        let resolved = config.clone().resolve_preludes_from(&registry).unwrap();
+        assert_eq!(
+            resolved.prelude.as_deref(),
+            Some("def double(x): return 2 * x\ndef read_all(): return ''\nBASE = double(21)")
+        );
+        assert!(resolved.preludes.is_empty());
+
+        let registry = PreludeRegistry::new();
+        assert!(matches!(
+            config.resolve_preludes_from(&registry),
+            Err(SandboxError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_builder_max_exceptions() {
+        let config = SandboxConfig::builder().max_exceptions(10).build();
+
+        assert!(config.count_exceptions);
+        assert_eq!(config.max_exceptions, Some(10));
+    }
 }
@@ -0,0 +1,181 @@
+//! Clock and randomness sources for deterministic execution.
+//!
+//! With `SandboxConfigBuilder::deterministic`, the WASI context reads time
+//! from clocks stopped at `FIXED_TIME` and draws random bytes from a stream
+//! derived from the seed, so nothing the guest observes changes between runs.
+
+use std::time::Duration;
+
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, RngCore, WasiCtxBuilder};
+
+use crate::sandbox::config::SandboxConfig;
+
+/// Environment variable fixing the interpreter's string hash seed.
+pub(crate) const HASH_SEED_VAR: &str = "PYTHONHASHSEED";
+
+/// Time since the Unix epoch that the wall clock reports:
+/// 2020-01-01T00:00:00Z.
+pub(crate) const FIXED_TIME: Duration = Duration::from_secs(1_577_836_800);
+
+/// A wall clock that always reports `FIXED_TIME`.
+pub(crate) struct FixedWallClock;
+
+impl HostWallClock for FixedWallClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        FIXED_TIME
+    }
+}
+
+/// A monotonic clock that never advances.
+pub(crate) struct FixedMonotonicClock;
+
+impl HostMonotonicClock for FixedMonotonicClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+/// A random byte source that yields the same stream for the same seed.
+///
+/// The stream is SplitMix64 output, generated as it is read, so it does
+/// not repeat however much the guest draws. Bytes come out in order
+/// whatever the size of each read.
+pub(crate) struct SeededRandom {
+    state: u64,
+    /// Output of the last step, of which `used` bytes were handed out.
+    block: [u8; 8],
+    used: usize,
+}
+
+/// A random byte source for `seed`.
+pub(crate) fn random(seed: u64) -> SeededRandom {
+    SeededRandom {
+        state: seed,
+        block: [0; 8],
+        used: 8,
+    }
+}
+
+impl SeededRandom {
+    /// Advance SplitMix64 by one step.
+    fn step(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RngCore for SeededRandom {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, mut dest: &mut [u8]) {
+        while !dest.is_empty() {
+            if self.used == self.block.len() {
+                self.block = self.step().to_le_bytes();
+                self.used = 0;
+            }
+            let n = dest.len().min(self.block.len() - self.used);
+            dest[..n].copy_from_slice(&self.block[self.used..self.used + n]);
+            self.used += n;
+            dest = &mut dest[n..];
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Make the WASI context `builder` builds as deterministic as `config` asks.
+///
+/// Sets `PYTHONHASHSEED` for `deterministic` or `deterministic_collections`
+/// (unless `env` sets it) and, with a seed, stops the clocks and derives
+/// randomness from it.
+pub(crate) fn configure(builder: &mut WasiCtxBuilder, config: &SandboxConfig) {
+    if !config.env_vars.iter().any(|(key, _)| key == HASH_SEED_VAR) {
+        if let Some(seed) = config.deterministic_seed {
+            builder.env(HASH_SEED_VAR, hash_seed(seed));
+        } else if config.deterministic_collections {
+            builder.env(HASH_SEED_VAR, "0");
+        }
+    }
+    if let Some(seed) = config.deterministic_seed {
+        builder
+            .wall_clock(FixedWallClock)
+            .monotonic_clock(FixedMonotonicClock)
+            .secure_random(random(seed))
+            .insecure_random(random(seed))
+            .insecure_random_seed(u128::from(seed));
+    }
+}
+
+/// Value of `PYTHONHASHSEED` for `seed`.
+///
+/// The interpreter takes a 32-bit seed, so the high half of `seed` is
+/// XORed into the low half; seeds below 2^32 are passed unchanged.
+pub(crate) fn hash_seed(seed: u64) -> String {
+    ((seed ^ (seed >> 32)) as u32).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let draw = |seed| {
+            let mut bytes = [0u8; 32];
+            random(seed).fill_bytes(&mut bytes);
+            bytes
+        };
+
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+
+        // Reads of any size see the same stream
+        let mut pieces = [0u8; 32];
+        let mut source = random(7);
+        for chunk in pieces.chunks_mut(3) {
+            source.fill_bytes(chunk);
+        }
+        assert_eq!(pieces, draw(7));
+    }
+
+    #[test]
+    fn test_random_does_not_repeat() {
+        // Bytes 16 KiB apart differ, so the stream is not a short cycle
+        let mut source = random(7);
+        let mut first = [0u8; 64];
+        source.fill_bytes(&mut first);
+        let mut skipped = vec![0u8; 16 * 1024 - 64];
+        source.fill_bytes(&mut skipped);
+        let mut later = [0u8; 64];
+        source.fill_bytes(&mut later);
+
+        assert_ne!(first, later);
+    }
+
+    #[test]
+    fn test_hash_seed_uses_whole_seed() {
+        assert_eq!(hash_seed(42), "42");
+        assert_eq!(hash_seed(u64::from(u32::MAX) + 5), "5");
+        assert_ne!(hash_seed(1 << 32), hash_seed(0));
+    }
+}
@@ -0,0 +1,148 @@
+//! Running doctests embedded in user code.
+//!
+//! A harness appended after the user code runs `doctest` over the
+//! `__main__` module and reports the outcome as JSON through the guest
+//! report channel. This requires the interpreter to bundle the `doctest`
+//! module, which RustPython builds with `freeze-stdlib` do.
+
+use serde_json::Value;
+
+use crate::error::{Result, SandboxError};
+
+/// Report key carrying the JSON-encoded doctest results.
+pub(crate) const DOCTESTS_KEY: &str = "doctests";
+
+/// Report key written when the interpreter does not bundle `doctest`.
+pub(crate) const DOCTEST_UNAVAILABLE_KEY: &str = "doctest_unavailable";
+
+/// Outcome of running the doctests in a piece of code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DoctestReport {
+    /// Number of examples that were run.
+    pub attempted: usize,
+    /// Number of examples that failed.
+    pub failed: usize,
+    /// Details of each failed example.
+    pub failures: Vec<DoctestFailure>,
+}
+
+impl DoctestReport {
+    /// Number of examples that passed.
+    pub fn passed(&self) -> usize {
+        self.attempted.saturating_sub(self.failed)
+    }
+
+    /// Check if every example passed.
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+
+    /// Decode the report sent by the harness.
+    pub(crate) fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json).map_err(|e| {
+            SandboxError::ExecutionFailed(format!("failed to decode doctest report: {}", e))
+        })?;
+
+        let count = |key: &str| value[key].as_u64().unwrap_or(0) as usize;
+        let failures = value["failures"]
+            .as_array()
+            .map(|failures| failures.iter().map(DoctestFailure::from_value).collect())
+            .unwrap_or_default();
+
+        Ok(Self {
+            attempted: count("attempted"),
+            failed: count("failed"),
+            failures,
+        })
+    }
+}
+
+/// A single failed doctest example.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DoctestFailure {
+    /// Qualified name of the object whose docstring contains the example.
+    pub name: String,
+    /// Line number of the example within the source, if known.
+    pub line: Option<usize>,
+    /// Source of the example.
+    pub source: String,
+    /// Expected output.
+    pub expected: String,
+    /// Actual output, or the exception raised by the example.
+    pub got: String,
+}
+
+impl DoctestFailure {
+    fn from_value(value: &Value) -> Self {
+        let text = |key: &str| value[key].as_str().unwrap_or_default().to_string();
+        Self {
+            name: text("name"),
+            line: value["line"].as_u64().map(|l| l as usize),
+            source: text("source"),
+            expected: text("expected"),
+            got: text("got"),
+        }
+    }
+}
+
+/// Harness that runs the doctests in `__main__` and reports the results.
+pub(crate) fn harness() -> String {
+    format!(
+        r#"def __sandbox_run_doctests():
+    import sys, json
+    try:
+        import doctest
+    except ImportError:
+        __sandbox_report('{DOCTEST_UNAVAILABLE_KEY}', 1)
+        return
+    failures = []
+    def record(test, example, got):
+        line = None
+        if test.lineno is not None and example.lineno is not None:
+            line = test.lineno + example.lineno + 1
+        failures.append({{'name': test.name, 'line': line, 'source': example.source,
+                          'expected': example.want, 'got': got}})
+    class Runner(doctest.DocTestRunner):
+        def report_start(self, out, test, example):
+            pass
+        def report_success(self, out, test, example, got):
+            pass
+        def report_failure(self, out, test, example, got):
+            record(test, example, got)
+        def report_unexpected_exception(self, out, test, example, exc_info):
+            import traceback
+            record(test, example, ''.join(traceback.format_exception_only(exc_info[0], exc_info[1])))
+    runner = Runner(verbose=False)
+    for test in doctest.DocTestFinder().find(sys.modules['__main__'], '__main__'):
+        runner.run(test, out=lambda s: None)
+    __sandbox_report('{DOCTESTS_KEY}', json.dumps({{'attempted': runner.tries,
+        'failed': runner.failures, 'failures': failures}}))
+__sandbox_run_doctests()
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_from_json() {
+        let json = r#"{"attempted": 3, "failed": 1, "failures": [
+            {"name": "__main__.add", "line": 4, "source": "add(1, 1)\n",
+             "expected": "3\n", "got": "2\n"}
+        ]}"#;
+        let report = DoctestReport::from_json(json).unwrap();
+
+        assert_eq!(report.passed(), 2);
+        assert!(!report.all_passed());
+        assert_eq!(report.failures[0].name, "__main__.add");
+        assert_eq!(report.failures[0].line, Some(4));
+        assert_eq!(report.failures[0].got, "2\n");
+    }
+
+    #[test]
+    fn test_report_from_invalid_json() {
+        assert!(DoctestReport::from_json("not json").is_err());
+    }
+}
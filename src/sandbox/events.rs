@@ -0,0 +1,401 @@
+//! Streaming events from a running execution.
+//!
+//! `PythonSandbox::execute_events` reports output chunks and resource
+//! usage samples while the code runs, followed by the final result. Events
+//! are produced on the blocking thread running the guest and handed to the
+//! caller through an unbounded channel, so a slow consumer never stalls
+//! the guest.
+//!
+//! `PythonSandbox::execute_streaming` uses the same channel for output
+//! only, running the execution as a task of its own.
+//!
+//! A consumer that stops listening (see `ExecEvents::finish`) closes the
+//! channel while the guest may still be writing. What the guest sees then
+//! is set by `OutputClosedAction`: by default its writes succeed as before,
+//! as if nothing had changed.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use wasmtime_wasi::pipe::MemoryOutputPipe;
+use wasmtime_wasi::{HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe};
+
+use crate::error::{Result, SandboxError};
+use crate::sandbox::executor::ExecutionResult;
+
+/// What a streaming execution does with stdout once nobody receives its events.
+///
+/// Set with `SandboxConfigBuilder::on_output_closed`. Applies only to
+/// stdout: stderr also carries the guest instrumentation's reports, so its
+/// writes always succeed. Output is still captured into the final result
+/// unless a write fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OutputClosedAction {
+    /// Keep accepting writes and drop their events.
+    ///
+    /// The guest cannot tell that the consumer went away.
+    #[default]
+    Discard,
+    /// Fail writes with `EPIPE`, like a closed pipe.
+    ///
+    /// Python raises `BrokenPipeError` from `print` and `sys.stdout.write`.
+    /// Unless the code handles it, the exception ends the execution with a
+    /// traceback on stderr and a non-zero exit code.
+    BrokenPipe,
+}
+
+/// An event from a running execution.
+#[derive(Debug)]
+pub enum ExecEvent {
+    /// A chunk of output written to stdout.
+    Stdout(Vec<u8>),
+    /// A chunk of output written to stderr.
+    Stderr(Vec<u8>),
+    /// A sample of the execution's resource usage.
+    ResourceSample {
+        /// Memory in use in bytes (as tracked by the limiter).
+        memory: u64,
+        /// Fuel consumed so far (if fuel limiting was enabled).
+        fuel: Option<u64>,
+        /// Time since the execution started.
+        elapsed: Duration,
+    },
+    /// The execution finished; always the last event.
+    ///
+    /// Boxed to keep the frequent output events small.
+    Finished(Box<ExecutionResult>),
+    /// The execution failed; always the last event.
+    Failed(SandboxError),
+}
+
+/// The events of one execution, in the order they happened.
+///
+/// Created by `PythonSandbox::execute_events`. The execution only makes
+/// progress while `next` is being awaited.
+///
+/// Ordering guarantees:
+///
+/// - Output chunks arrive in the order the guest wrote them, with stdout
+///   and stderr interleaved as written.
+/// - Resource samples are taken between guest writes, so they are ordered
+///   consistently with the output around them.
+/// - `Finished` or `Failed` is always the last event, after every output
+///   chunk and sample.
+pub struct ExecEvents<'a> {
+    run: Option<Pin<Box<dyn Future<Output = Result<ExecutionResult>> + Send + 'a>>>,
+    events: mpsc::UnboundedReceiver<ExecEvent>,
+    outcome: Option<Result<ExecutionResult>>,
+}
+
+impl<'a> ExecEvents<'a> {
+    pub(crate) fn new(
+        run: impl Future<Output = Result<ExecutionResult>> + Send + 'a,
+        events: mpsc::UnboundedReceiver<ExecEvent>,
+    ) -> Self {
+        Self {
+            run: Some(Box::pin(run)),
+            events,
+            outcome: None,
+        }
+    }
+
+    /// Wait for the next event, or `None` after the final one.
+    pub async fn next(&mut self) -> Option<ExecEvent> {
+        if let Some(run) = self.run.as_mut() {
+            let outcome = tokio::select! {
+                biased;
+                Some(event) = self.events.recv() => return Some(event),
+                outcome = run => outcome,
+            };
+            self.run = None;
+            self.outcome = Some(outcome);
+        }
+
+        // The run is over; deliver what it sent before the final event
+        if let Ok(event) = self.events.try_recv() {
+            return Some(event);
+        }
+        self.outcome.take().map(|outcome| match outcome {
+            Ok(result) => ExecEvent::Finished(Box::new(result)),
+            Err(error) => ExecEvent::Failed(error),
+        })
+    }
+
+    /// Stop receiving events and wait for the final result.
+    ///
+    /// Events not yet received are discarded, and later output is handled
+    /// as configured by `SandboxConfigBuilder::on_output_closed`. The
+    /// execution keeps running to completion, within its limits; drop the
+    /// `ExecEvents` instead to interrupt it.
+    pub async fn finish(mut self) -> Result<ExecutionResult> {
+        self.events.close();
+        match self.run.take() {
+            Some(run) => run.await,
+            None => self.outcome.take().unwrap_or_else(|| {
+                Err(SandboxError::ExecutionFailed(
+                    "execution already finished".to_string(),
+                ))
+            }),
+        }
+    }
+
+    /// Run to completion, collecting every event.
+    pub async fn collect_all(mut self) -> Vec<ExecEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = self.next().await {
+            events.push(event);
+        }
+        events
+    }
+}
+
+/// A chunk of output from `PythonSandbox::execute_streaming`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputChunk {
+    /// A chunk of output written to stdout.
+    Stdout(Vec<u8>),
+    /// A chunk of output written to stderr.
+    Stderr(Vec<u8>),
+}
+
+/// The output of a running execution, chunk by chunk.
+///
+/// Created by `PythonSandbox::execute_streaming`. Chunks arrive in the
+/// order the guest wrote them, with stdout and stderr interleaved as
+/// written, and the stream ends after the last one once the execution has
+/// finished. Use `next`, or the `Stream` implementation.
+#[derive(Debug)]
+pub struct OutputStream {
+    events: mpsc::UnboundedReceiver<ExecEvent>,
+}
+
+impl OutputStream {
+    pub(crate) fn new(events: mpsc::UnboundedReceiver<ExecEvent>) -> Self {
+        Self { events }
+    }
+
+    /// Wait for the next chunk, or `None` once the execution has finished.
+    pub async fn next(&mut self) -> Option<OutputChunk> {
+        std::future::poll_fn(|cx| self.poll_chunk(cx)).await
+    }
+
+    /// Poll for the next output event, skipping resource samples.
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<OutputChunk>> {
+        loop {
+            match ready!(self.events.poll_recv(cx)) {
+                Some(ExecEvent::Stdout(bytes)) => {
+                    return Poll::Ready(Some(OutputChunk::Stdout(bytes)))
+                }
+                Some(ExecEvent::Stderr(bytes)) => {
+                    return Poll::Ready(Some(OutputChunk::Stderr(bytes)))
+                }
+                Some(_) => continue,
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl Stream for OutputStream {
+    type Item = OutputChunk;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<OutputChunk>> {
+        self.get_mut().poll_chunk(cx)
+    }
+}
+
+/// Sending side of an execution's events.
+#[derive(Debug, Clone)]
+pub(crate) struct EventSink {
+    events: mpsc::UnboundedSender<ExecEvent>,
+}
+
+impl EventSink {
+    /// Create a sink and the receiver for its events.
+    pub(crate) fn new() -> (Self, mpsc::UnboundedReceiver<ExecEvent>) {
+        let (events, receiver) = mpsc::unbounded_channel();
+        (Self { events }, receiver)
+    }
+
+    /// Send an event, ignoring a receiver that has gone away.
+    pub(crate) fn send(&self, event: ExecEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Wrap `pipe` so writes to it are also sent as events made by `event`.
+    ///
+    /// `on_closed` decides what writes do once the receiver is gone.
+    pub(crate) fn output(
+        &self,
+        pipe: MemoryOutputPipe,
+        event: fn(Vec<u8>) -> ExecEvent,
+        on_closed: OutputClosedAction,
+    ) -> EventOutput {
+        EventOutput {
+            pipe,
+            sink: self.clone(),
+            event,
+            on_closed,
+        }
+    }
+}
+
+/// WASI output stream that sends each write as an event as well as capturing it.
+#[derive(Debug, Clone)]
+pub(crate) struct EventOutput {
+    pipe: MemoryOutputPipe,
+    sink: EventSink,
+    event: fn(Vec<u8>) -> ExecEvent,
+    on_closed: OutputClosedAction,
+}
+
+impl HostOutputStream for EventOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let sent = self.sink.events.send((self.event)(bytes.to_vec())).is_ok();
+        if !sent && self.on_closed == OutputClosedAction::BrokenPipe {
+            return Err(StreamError::LastOperationFailed(broken_pipe().into()));
+        }
+        self.pipe.write(bytes)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.pipe.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.pipe.check_write()
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for EventOutput {
+    async fn ready(&mut self) {}
+}
+
+impl StdoutStream for EventOutput {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+/// An `EPIPE` error, which WASI reports to the guest as `ERRNO_PIPE`.
+///
+/// wasmtime-wasi maps host errors to WASI errno values by their raw OS
+/// code, so `ErrorKind::BrokenPipe` alone would surface as a generic I/O
+/// error.
+fn broken_pipe() -> std::io::Error {
+    #[cfg(unix)]
+    const EPIPE: i32 = 32;
+    #[cfg(windows)]
+    const EPIPE: i32 = 109; // ERROR_BROKEN_PIPE
+    std::io::Error::from_raw_os_error(EPIPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::io::ReportFilter;
+
+    #[tokio::test]
+    async fn test_final_event_comes_last() {
+        let (sink, receiver) = EventSink::new();
+        let mut stdout = sink.output(
+            MemoryOutputPipe::new(1024),
+            ExecEvent::Stdout,
+            OutputClosedAction::Discard,
+        );
+        let run = async move {
+            stdout.write(Bytes::from_static(b"hello\n")).unwrap();
+            Err(SandboxError::Cancelled)
+        };
+
+        let events = ExecEvents::new(run, receiver).collect_all().await;
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], ExecEvent::Stdout(bytes) if bytes == b"hello\n"));
+        assert!(matches!(
+            &events[1],
+            ExecEvent::Failed(SandboxError::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_output_stream() {
+        let (sink, receiver) = EventSink::new();
+        let mut stdout = sink.output(
+            MemoryOutputPipe::new(1024),
+            ExecEvent::Stdout,
+            OutputClosedAction::Discard,
+        );
+        let stderr_pipe = MemoryOutputPipe::new(1024);
+        let reports = ReportFilter::new("\u{1e}report");
+        let mut stderr = reports
+            .wrap(sink.output(
+                stderr_pipe.clone(),
+                ExecEvent::Stderr,
+                OutputClosedAction::Discard,
+            ))
+            .stream();
+        stdout.write(Bytes::from_static(b"out")).unwrap();
+        sink.send(ExecEvent::ResourceSample {
+            memory: 0,
+            fuel: None,
+            elapsed: Duration::ZERO,
+        });
+        stderr
+            .write(Bytes::from_static(b"\x1ereport exceptions 1\n"))
+            .unwrap();
+        stderr.write(Bytes::from_static(b"err")).unwrap();
+        drop((sink, stdout, stderr));
+        // Report lines are neither sent nor captured with the output
+        assert_eq!(&stderr_pipe.contents()[..], b"err");
+        assert_eq!(reports.lines(), "\x1ereport exceptions 1\n");
+
+        let mut chunks = OutputStream::new(receiver);
+
+        assert_eq!(
+            chunks.next().await,
+            Some(OutputChunk::Stdout(b"out".to_vec()))
+        );
+        assert_eq!(
+            chunks.next().await,
+            Some(OutputChunk::Stderr(b"err".to_vec()))
+        );
+        assert_eq!(chunks.next().await, None);
+    }
+
+    #[test]
+    fn test_write_after_receiver_dropped() {
+        let (sink, receiver) = EventSink::new();
+        let pipe = MemoryOutputPipe::new(1024);
+        let mut discard = sink.output(pipe.clone(), ExecEvent::Stdout, OutputClosedAction::Discard);
+        let mut broken = sink.output(
+            MemoryOutputPipe::new(1024),
+            ExecEvent::Stdout,
+            OutputClosedAction::BrokenPipe,
+        );
+        assert!(broken.write(Bytes::from_static(b"sent\n")).is_ok());
+        drop(receiver);
+
+        assert!(discard.write(Bytes::from_static(b"kept\n")).is_ok());
+        assert_eq!(&pipe.contents()[..], b"kept\n");
+        match broken.write(Bytes::from_static(b"lost\n")) {
+            Err(StreamError::LastOperationFailed(e)) => {
+                let e = e.downcast::<std::io::Error>().unwrap();
+                assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe);
+            }
+            other => panic!("expected a broken pipe, got {:?}", other),
+        }
+    }
+}
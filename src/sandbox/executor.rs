@@ -1,33 +1,117 @@
 //! Core execution engine for the Python sandbox.
 
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[cfg(feature = "tracing")]
-use tracing::{debug, info, instrument, warn};
+use tracing::{debug, info, instrument, warn, Instrument};
 
-use wasmtime::{Engine, Linker, Module, Store, Trap};
-use wasmtime_wasi::pipe::MemoryInputPipe;
+use tokio::task::JoinHandle;
+use wasmtime::{Engine, InstancePre, Linker, Module, Store, Trap, UpdateDeadline, WasmCoreDump};
+use wasmtime_wasi::pipe::{ClosedOutputStream, MemoryOutputPipe};
 use wasmtime_wasi::preview1;
-use wasmtime_wasi::{I32Exit, WasiCtxBuilder};
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
 
-use crate::error::{Result, SandboxError};
-use crate::sandbox::cache::{global_cache, ModuleCache, SharedEngine};
+use crate::error::{
+    parse_python_exception, truncate_traceback, PartialOutput, PythonException, Result,
+    SandboxError, TimeoutPhase,
+};
+#[cfg(feature = "audit")]
+use crate::sandbox::audit::{self, AccessAttempt};
+use crate::sandbox::cache::{
+    compile_module, global_cache, CacheStatus, CompileEvent, ModuleCache, SharedEngine,
+};
+use crate::sandbox::cancel::CancellationToken;
+use crate::sandbox::compare::DiffOptions;
+use crate::sandbox::compile::{self, CompileReport, COMPILE_KEY};
 use crate::sandbox::config::SandboxConfig;
-use crate::sandbox::io::SandboxIo;
-use crate::sandbox::limits::{StoreData, StoreLimiterExt};
+use crate::sandbox::deterministic;
+use crate::sandbox::doctest::{self, DoctestReport, DOCTESTS_KEY, DOCTEST_UNAVAILABLE_KEY};
+use crate::sandbox::events::{EventSink, ExecEvent, ExecEvents, OutputClosedAction, OutputStream};
+use crate::sandbox::guest::{
+    self, GuestReports, ReportChannel, EXCEPTIONS_KEY, EXCEPTION_LIMIT_KEY, GC_ENABLED_KEY,
+    GLOBALS_KEY, GLOBALS_TOO_LARGE_KEY, IMPORTS_KEY, LINES_KEY, PREIMPORT_FAILED_KEY,
+    PRELUDE_BYTECODE_KEY, REPORT_MARKER_VAR, RESULT_KEY, RESULT_TOO_LARGE_KEY, TRACEBACK_KEY,
+    VAR_KEY, VAR_TOO_LARGE_KEY, VERSION_KEY,
+};
+use crate::sandbox::interpreter::Script;
+use crate::sandbox::io::{
+    self, GeneratedInput, OutputLimits, ReportFilter, SandboxIo, SentinelWatch, StdinGenerator,
+};
+use crate::sandbox::limits::{configured_store, StoreData, TotalBudget};
+use crate::sandbox::mounts::{
+    self, MountDir, OutputFiles, OutputQuota, MOUNT_ROOT, OUTPUT_ROOT, VIRTUAL_ROOT,
+};
+use crate::sandbox::persistent::PersistentSession;
+use crate::sandbox::sequence::{self, BlockResult, Boundary};
+#[cfg(feature = "tracing")]
+use crate::sandbox::telemetry;
+use crate::sandbox::termination::Termination;
+use crate::sandbox::ticker::EpochTicker;
+
+/// Capacity of the in-memory pipes capturing stdout and stderr.
+///
+/// A full pipe traps the guest, so the pipes themselves are unbounded;
+/// `SandboxConfig::max_output_bytes` cuts output before it reaches them.
+const OUTPUT_PIPE_CAPACITY: usize = usize::MAX;
+
+/// Environment variable listing extra directories to import modules from.
+const PYTHONPATH_VAR: &str = "PYTHONPATH";
+
+/// How long a timed-out execution may take to hand back partial output.
+const TRAP_FLUSH_GRACE: Duration = Duration::from_millis(100);
 
 /// Metadata about an execution, including resource usage.
 #[derive(Debug, Clone)]
 pub struct ExecutionMetadata {
     /// Wall-clock duration of execution.
+    ///
+    /// Covers `instantiation_time` and `execution_time` as well as setting
+    /// up the call and collecting its output, so it is at least their sum.
     pub duration: Duration,
+    /// Time spent instantiating the interpreter into a new store.
+    pub instantiation_time: Duration,
+    /// Time spent running the interpreter's entry point.
+    pub execution_time: Duration,
     /// Peak memory usage in bytes (as tracked by the limiter).
     pub peak_memory: u64,
     /// Fuel consumed during execution (if fuel limiting was enabled).
     pub fuel_consumed: Option<u64>,
     /// Whether this execution used a cached module.
     pub used_cached_module: bool,
+    /// Number of Python exceptions raised (if exception counting was enabled).
+    pub exceptions_raised: Option<u64>,
+    /// Sorted numbers of the user code lines that ran (if line coverage was enabled).
+    pub lines_covered: Option<Vec<u32>>,
+    /// Whether the garbage collector was enabled at exit (if `disable_gc` was set).
+    pub gc_enabled: Option<bool>,
+    /// Time spent inside host (WASI) calls (if host call tracking was enabled).
+    pub host_call_duration: Option<Duration>,
+    /// Size of each stdin read (empty unless stdin tracing was enabled).
+    pub stdin_reads: Vec<usize>,
+    /// Whether memory crossed the configured warning threshold.
+    pub exceeded_warn_threshold: bool,
+    /// Whether the fallback interpreter was used instead of the primary.
+    pub used_fallback_interpreter: bool,
+    /// Whether frames were elided from a traceback in stderr.
+    pub traceback_truncated: bool,
+    /// Line ending removed from the end of stdout (if trimming was enabled).
+    pub trimmed_newline: Option<&'static str>,
+    /// Number of modules imported by user code through `__import__` (if
+    /// imports were limited; see `SandboxConfigBuilder::max_imports`).
+    pub imports: Option<u64>,
+    /// Interpreter startup banner removed from stdout (if one was printed).
+    pub banner: Option<String>,
+    /// Time from the start of execution until the first byte of stdout or
+    /// stderr was written (`None` if the program wrote nothing).
+    pub time_to_first_output: Option<Duration>,
+    /// Caller-supplied label (if run with `PythonSandbox::execute_labeled`).
+    pub label: Option<String>,
 }
 
 impl ExecutionMetadata {
@@ -35,9 +119,24 @@ impl ExecutionMetadata {
     pub fn empty() -> Self {
         Self {
             duration: Duration::ZERO,
+            instantiation_time: Duration::ZERO,
+            execution_time: Duration::ZERO,
             peak_memory: 0,
             fuel_consumed: None,
             used_cached_module: false,
+            exceptions_raised: None,
+            lines_covered: None,
+            gc_enabled: None,
+            host_call_duration: None,
+            stdin_reads: Vec::new(),
+            exceeded_warn_threshold: false,
+            used_fallback_interpreter: false,
+            traceback_truncated: false,
+            trimmed_newline: None,
+            imports: None,
+            banner: None,
+            time_to_first_output: None,
+            label: None,
         }
     }
 }
@@ -47,12 +146,34 @@ impl ExecutionMetadata {
 pub struct ExecutionResult {
     /// Captured stdout output.
     pub stdout: String,
-    /// Captured stderr output.
+    /// Captured stderr output, followed by the traceback of an uncaught
+    /// exception.
     pub stderr: String,
+    /// Stderr the program wrote itself, without the traceback of an
+    /// uncaught exception (if `SandboxConfig::separate_exceptions` was
+    /// enabled; otherwise the two are merged, as in `stderr`).
+    pub user_stderr: String,
     /// Exit code (0 for success).
     pub exit_code: i32,
+    /// The uncaught exception, handed over apart from stderr (if
+    /// `SandboxConfig::separate_exceptions` was enabled).
+    pub exception: Option<PythonException>,
+    /// Files written to `/out`, keyed by path relative to it (if
+    /// `SandboxConfig::collect_output_files` was enabled).
+    pub output_files: HashMap<PathBuf, Vec<u8>>,
+    /// Permission bits of each file in `output_files` (see
+    /// `SandboxConfigBuilder::default_file_mode`).
+    pub output_file_modes: HashMap<PathBuf, u32>,
+    /// Whether stdout was cut at `SandboxConfig::max_output_lines`.
+    pub output_line_limited: bool,
+    /// Whether output was cut at `SandboxConfig::max_output_bytes`, in
+    /// stdout, stderr or `output_files`.
+    pub output_truncated: bool,
     /// Execution metadata including timing and resource usage.
     pub metadata: ExecutionMetadata,
+    /// Filesystem and network access attempts (if auditing was enabled).
+    #[cfg(feature = "audit")]
+    pub access_attempts: Vec<AccessAttempt>,
 }
 
 impl ExecutionResult {
@@ -60,6 +181,377 @@ impl ExecutionResult {
     pub fn is_success(&self) -> bool {
         self.exit_code == 0
     }
+
+    /// Get stdout without a single trailing newline.
+    ///
+    /// Removes the newline `print` adds after the last line, so
+    /// `print(2)` gives `"2"`, while `print("2\n")` gives `"2\n"`. A
+    /// trailing `\r\n` is removed as one line ending; a lone `\r` is kept.
+    pub fn stdout_trimmed(&self) -> &str {
+        split_trailing_newline(&self.stdout).0
+    }
+
+    /// Get stdout exactly as the code wrote it.
+    ///
+    /// Differs from `stdout` only when `SandboxConfig::trim_trailing_newline`
+    /// removed a line ending, which is restored here.
+    pub fn raw_stdout(&self) -> String {
+        format!(
+            "{}{}",
+            self.stdout,
+            self.metadata.trimmed_newline.unwrap_or_default()
+        )
+    }
+
+    /// Check whether stdout matches `expected`, line by line.
+    ///
+    /// With `DiffOptions::new()` every line must match exactly; see
+    /// `DiffOptions::numeric_tolerance` to compare numbers approximately.
+    pub fn matches_expected(&self, expected: &str, options: &DiffOptions) -> bool {
+        options.matches(&self.stdout, expected)
+    }
+
+    /// Create a result with no output and empty metadata.
+    #[cfg(test)]
+    pub(crate) fn with_exit_code(exit_code: i32) -> Self {
+        Self {
+            stdout: String::new(),
+            stderr: String::new(),
+            user_stderr: String::new(),
+            exit_code,
+            exception: None,
+            output_files: HashMap::new(),
+            output_file_modes: HashMap::new(),
+            output_line_limited: false,
+            output_truncated: false,
+            metadata: ExecutionMetadata::empty(),
+            #[cfg(feature = "audit")]
+            access_attempts: Vec::new(),
+        }
+    }
+
+    /// Remove a single trailing newline from stdout, recording what was removed.
+    fn trim_trailing_newline(&mut self) {
+        let (trimmed, newline) = split_trailing_newline(&self.stdout);
+        if newline.is_some() {
+            self.stdout.truncate(trimmed.len());
+            self.metadata.trimmed_newline = newline;
+        }
+    }
+
+    /// Parse the uncaught Python exception, if there was one.
+    ///
+    /// Returns a `SandboxError::PythonException` (or
+    /// `SandboxError::SyntaxError`) parsed from the traceback of
+    /// `exception` when the exception was separated from stderr, and from
+    /// `stderr` otherwise.
+    pub fn python_exception(&self) -> Option<SandboxError> {
+        let traceback = self
+            .exception
+            .as_ref()
+            .and_then(|exception| exception.traceback.as_deref());
+        parse_python_exception(traceback.unwrap_or(&self.stderr))
+    }
+
+    /// Classify why the execution failed, or `None` if it succeeded.
+    ///
+    /// A syntax error is detected before any code runs, so it is reported
+    /// as `FailureKind::SyntaxError` only when the program produced no
+    /// stdout. Any other Python exception found in stderr (including a
+    /// `SyntaxError` raised later, e.g. by `compile()`) is a
+    /// `RuntimeException`. A non-zero exit without a recognizable
+    /// exception (e.g. `sys.exit(3)`) is a `NonZeroExit`.
+    pub fn failure_kind(&self) -> Option<FailureKind> {
+        if self.is_success() {
+            return None;
+        }
+
+        match self.python_exception() {
+            Some(SandboxError::SyntaxError { .. }) if self.stdout.is_empty() => {
+                Some(FailureKind::SyntaxError)
+            }
+            Some(SandboxError::PythonException { exception_type, .. })
+                if SYNTAX_ERROR_TYPES.contains(&exception_type.as_str())
+                    && self.stdout.is_empty() =>
+            {
+                Some(FailureKind::SyntaxError)
+            }
+            Some(_) => Some(FailureKind::RuntimeException),
+            None => Some(FailureKind::NonZeroExit),
+        }
+    }
+}
+
+/// Split a single trailing `\n` or `\r\n` off the end of `text`.
+fn split_trailing_newline(text: &str) -> (&str, Option<&'static str>) {
+    if let Some(rest) = text.strip_suffix("\r\n") {
+        (rest, Some("\r\n"))
+    } else if let Some(rest) = text.strip_suffix('\n') {
+        (rest, Some("\n"))
+    } else {
+        (text, None)
+    }
+}
+
+/// Start of the banner RustPython prints when its interpreter starts up.
+const BANNER_PREFIX: &str = "Welcome to the magnificent Rust Python";
+
+/// Split leading interpreter banner lines off the start of `stdout`.
+///
+/// Returns the banner (if any) and the remaining output. Only lines at the
+/// very start are considered, so output matching the banner later on is kept.
+fn split_banner(stdout: &str) -> (Option<&str>, &str) {
+    let mut end = 0;
+    for line in stdout.split_inclusive('\n') {
+        if !line.starts_with(BANNER_PREFIX) {
+            break;
+        }
+        end += line.len();
+    }
+    if end == 0 {
+        (None, stdout)
+    } else {
+        (Some(&stdout[..end]), &stdout[end..])
+    }
+}
+
+/// Exception types raised by the compiler for invalid source code.
+const SYNTAX_ERROR_TYPES: &[&str] = &["IndentationError", "TabError"];
+
+/// Why an execution finished with a non-zero exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The code could not be compiled; nothing ran.
+    SyntaxError,
+    /// A Python exception was raised while the code was running.
+    RuntimeException,
+    /// The program exited with a non-zero code without an exception.
+    NonZeroExit,
+}
+
+/// Result of evaluating an expression with `PythonSandbox::execute_eval`.
+#[derive(Debug, Clone)]
+pub struct EvalResult {
+    /// `repr` of the expression's value.
+    pub value: String,
+    /// Output the expression printed while it was evaluated.
+    pub stdout: String,
+    /// Output the expression wrote to stderr while it was evaluated.
+    pub stderr: String,
+    /// Execution metadata including timing and resource usage.
+    pub metadata: ExecutionMetadata,
+}
+
+/// Per-call options layered on top of the sandbox configuration.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CallOptions {
+    /// Capture the final global namespace after user code runs.
+    pub(crate) capture_globals: bool,
+    /// Capture the final value of one global after user code runs.
+    pub(crate) capture_var: Option<String>,
+    /// Run the doctests in user code after it runs.
+    pub(crate) run_doctests: bool,
+    /// Compile user code without running it.
+    pub(crate) compile_only: bool,
+    /// Run this module with `-m` instead of the code.
+    pub(crate) module: Option<String>,
+    /// Run the prelude alone and report its bytecode.
+    pub(crate) prime_prelude: bool,
+    /// Report the interpreter's `sys.version` after user code runs.
+    pub(crate) report_version: bool,
+    /// Treat user code as an expression and report its `repr`.
+    pub(crate) eval_expr: bool,
+    /// Override the configured script arguments.
+    pub(crate) args: Option<Vec<String>>,
+    /// Keep output captured before an interruption, as with `flush_on_trap`.
+    pub(crate) keep_partial_output: bool,
+    /// Override the configured timeout.
+    pub(crate) timeout: Option<Duration>,
+    /// Override the configured memory limit.
+    pub(crate) max_memory: Option<u64>,
+    /// Override the configured fuel limit.
+    ///
+    /// Requires an engine with fuel consumption enabled.
+    pub(crate) max_fuel: Option<u64>,
+    /// Directory to write a coredump to when the interpreter traps.
+    pub(crate) coredump_dir: Option<PathBuf>,
+    /// Callback feeding stdin when no input is given.
+    pub(crate) stdin_generator: Option<StdinGenerator>,
+    /// Serialized JSON bound to the global `context` before user code runs.
+    pub(crate) context: Option<String>,
+    /// Watch stdout for a sentinel.
+    pub(crate) stdout_sentinel: Option<SentinelWatch>,
+    /// Stream output and resource samples as events.
+    pub(crate) events: Option<EventSink>,
+    /// Set to make the guest trap at its next epoch check.
+    ///
+    /// Other executions share the engine's epoch, so incrementing it alone
+    /// does not interrupt anything; this flag picks out the execution to stop.
+    pub(crate) stop: Arc<AtomicBool>,
+}
+
+/// Size of a WebAssembly memory page.
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// Memory requirements measured by `PythonSandbox::probe_memory`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryProbe {
+    /// Peak memory usage with the generous probe limit, in bytes.
+    pub peak_memory: u64,
+    /// Smallest limit, rounded to whole wasm pages, at which the code still
+    /// finishes with the same exit code.
+    pub minimum_limit: u64,
+    /// Number of executions the probe performed.
+    pub runs: u32,
+}
+
+/// How many times the configured timeout each `probe_duration` sample may take.
+const DURATION_PROBE_TIMEOUT_FACTOR: u32 = 10;
+
+/// Limits overriding the sandbox's configuration for one call.
+///
+/// Passed to `PythonSandbox::execute_with`. Unset fields keep the
+/// configured value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionOverrides {
+    /// Timeout for this call.
+    pub timeout: Option<Duration>,
+    /// Memory limit in bytes for this call.
+    ///
+    /// Enforced on top of `SandboxConfig::memory_policy`, if set.
+    pub max_memory: Option<u64>,
+    /// Fuel limit for this call.
+    ///
+    /// The engine must have been built with fuel consumption enabled.
+    pub max_fuel: Option<u64>,
+}
+
+impl ExecutionOverrides {
+    /// Create overrides that keep every configured limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout for this call.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the memory limit in bytes for this call.
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Set the fuel limit for this call.
+    pub fn max_fuel(mut self, fuel: u64) -> Self {
+        self.max_fuel = Some(fuel);
+        self
+    }
+}
+
+/// What `PythonSandbox::execute_source` runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionSource {
+    /// Python source, run as `python -c <code>`.
+    Code(String),
+    /// A module importable in the sandbox, run as `python -m <name>`.
+    Module(String),
+}
+
+/// Execution times measured by `PythonSandbox::probe_duration`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DurationStats {
+    /// Shortest sample.
+    pub min: Duration,
+    /// Mean of all samples.
+    pub mean: Duration,
+    /// 99th percentile (nearest rank); the longest sample below 100 samples.
+    pub p99: Duration,
+    /// Longest sample.
+    pub max: Duration,
+    /// Number of samples taken.
+    pub samples: usize,
+}
+
+impl DurationStats {
+    /// Summarize `samples`, which must not be empty.
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        let count = samples.len();
+        let total: Duration = samples.iter().sum();
+        let p99_rank = (count * 99).div_ceil(100);
+        Self {
+            min: samples[0],
+            mean: total / count as u32,
+            p99: samples[p99_rank - 1],
+            max: samples[count - 1],
+            samples: count,
+        }
+    }
+}
+
+/// Lazy runs of one piece of code over a corpus of inputs.
+///
+/// Created by `PythonSandbox::run_inputs`.
+pub struct InputRuns<'a, I> {
+    sandbox: &'a PythonSandbox,
+    code: String,
+    inputs: I,
+    stop_on_error: bool,
+    stopped: bool,
+    /// Started by the first run.
+    budget: Option<TotalBudget>,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> InputRuns<'_, I> {
+    /// Stop after the first run that errors or exits with a non-zero code.
+    pub fn stop_on_error(mut self, stop: bool) -> Self {
+        self.stop_on_error = stop;
+        self
+    }
+
+    /// Run the code against the next input.
+    ///
+    /// Returns `None` once the inputs are exhausted, or after a failed run
+    /// when `stop_on_error` is set.
+    pub async fn next(&mut self) -> Option<Result<ExecutionResult>> {
+        if self.stopped {
+            return None;
+        }
+        let input = self.inputs.next()?;
+        let config = &self.sandbox.config;
+        let budget = *self
+            .budget
+            .get_or_insert_with(|| TotalBudget::start(config.total_budget));
+        let result = match budget.timeout(config.timeout) {
+            Ok(timeout) => {
+                let call = CallOptions {
+                    timeout: Some(timeout),
+                    ..CallOptions::default()
+                };
+                let outcome = self.sandbox.run_call(&self.code, Some(&input), call).await;
+                budget.check(outcome.map(|(result, _)| result))
+            }
+            Err(e) => Err(e),
+        };
+
+        let budget_spent = result.is_err() && budget.exhausted();
+        if budget_spent || self.stop_on_error && !matches!(result, Ok(ref r) if r.is_success()) {
+            self.stopped = true;
+        }
+        Some(result)
+    }
+
+    /// Run the code against every remaining input and collect the results.
+    pub async fn collect_all(mut self) -> Vec<Result<ExecutionResult>> {
+        let mut results = Vec::new();
+        while let Some(result) = self.next().await {
+            results.push(result);
+        }
+        results
+    }
 }
 
 /// Options for creating a PythonSandbox.
@@ -71,6 +563,10 @@ pub struct SandboxOptions {
     pub cache: Option<Arc<ModuleCache>>,
     /// A shared engine to use (if provided).
     pub shared_engine: Option<SharedEngine>,
+    /// Whether to create a fresh engine for every execution.
+    pub isolated_engine: bool,
+    /// Callback producing stdin on demand (if provided).
+    pub stdin_generator: Option<StdinGenerator>,
 }
 
 impl Default for SandboxOptions {
@@ -79,6 +575,8 @@ impl Default for SandboxOptions {
             use_cache: true,
             cache: None,
             shared_engine: None,
+            isolated_engine: false,
+            stdin_generator: None,
         }
     }
 }
@@ -90,6 +588,8 @@ impl SandboxOptions {
             use_cache: false,
             cache: None,
             shared_engine: None,
+            isolated_engine: false,
+            stdin_generator: None,
         }
     }
 
@@ -99,6 +599,8 @@ impl SandboxOptions {
             use_cache: true,
             cache: Some(cache),
             shared_engine: None,
+            isolated_engine: false,
+            stdin_generator: None,
         }
     }
 
@@ -108,6 +610,8 @@ impl SandboxOptions {
             use_cache: true,
             cache: None,
             shared_engine: Some(engine),
+            isolated_engine: false,
+            stdin_generator: None,
         }
     }
 
@@ -129,15 +633,108 @@ impl SandboxOptions {
         self.shared_engine = Some(engine);
         self
     }
+
+    /// Set whether every execution gets its own freshly created engine.
+    ///
+    /// By default all executions of a sandbox share one engine, and with it
+    /// the compiled code and the epoch counter used for timeouts. For the
+    /// highest-security multi-tenant cases, isolation creates a new engine
+    /// for each `execute` call instead.
+    ///
+    /// This is expensive: the interpreter is recompiled from disk on every
+    /// call, which for RustPython takes far longer than running typical
+    /// code, and defeats the benefit of the module cache. Cannot be
+    /// combined with a shared engine.
+    pub fn isolated_engine(mut self, isolated: bool) -> Self {
+        self.isolated_engine = isolated;
+        self
+    }
+
+    /// Feed stdin from a callback that produces input lazily.
+    ///
+    /// Each time the guest's stdin buffer runs dry, the callback is called
+    /// for the next chunk (typically a line); returning `None` ends the
+    /// input. The generator is only consulted when an execution has no
+    /// input of its own, from either `SandboxConfig::stdin` or the `input`
+    /// argument.
+    ///
+    /// The callback runs synchronously on the blocking thread executing the
+    /// guest. It is shared by every execution of the sandbox behind a
+    /// mutex, so concurrent executions draw from the same sequence in turn,
+    /// and a callback that blocks also stalls the guest beyond the reach of
+    /// the timeout until it returns.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let mut n = 0;
+    /// let options = SandboxOptions::default().stdin_generator(move || {
+    ///     n += 1;
+    ///     (n <= 3).then(|| format!("line {}\n", n).into_bytes())
+    /// });
+    /// ```
+    pub fn stdin_generator(
+        mut self,
+        generator: impl FnMut() -> Option<Vec<u8>> + Send + 'static,
+    ) -> Self {
+        self.stdin_generator = Some(StdinGenerator::new(generator));
+        self
+    }
 }
 
 /// A sandboxed Python execution environment.
 pub struct PythonSandbox {
     config: SandboxConfig,
-    engine: Arc<Engine>,
+    /// The engine, with the ticker driving its epoch.
+    ticker: EpochTicker,
+    /// The interpreter, pre-linked against WASI and instantiated afresh
+    /// into a new store for every execution.
+    instance_pre: Arc<InstancePre<StoreData>>,
+    /// How the module was obtained.
+    cache_status: CacheStatus,
+    /// Whether the fallback interpreter was loaded instead of the primary.
+    used_fallback: bool,
+    /// Where the interpreter that was loaded came from.
+    interpreter: ModuleSource,
+    /// Whether each execution gets its own engine.
+    isolated_engine: bool,
+    /// Directory coredumps are written to, from the shared engine.
+    coredump_dir: Option<PathBuf>,
+    /// Prelude replacement running cached bytecode, once primed.
+    primed_prelude: Option<String>,
+    /// Callback feeding stdin when no input is given.
+    stdin_generator: Option<StdinGenerator>,
+    /// The interpreter's `sys.version`, once queried.
+    python_version: tokio::sync::OnceCell<String>,
+    /// When the sandbox was created, for `SandboxConfig::max_age`.
+    created_at: Instant,
+}
+
+/// The interpreter module selected for a sandbox.
+struct LoadedModule {
     module: Arc<Module>,
-    /// Whether the module came from cache.
-    module_was_cached: bool,
+    cache_status: CacheStatus,
+    used_fallback: bool,
+    source: ModuleSource,
+}
+
+/// Where an interpreter module is loaded from.
+#[derive(Clone)]
+enum ModuleSource {
+    /// A wasm file on disk.
+    Path(PathBuf),
+    /// Wasm bytes held in memory.
+    Bytes(Arc<[u8]>),
+}
+
+impl ModuleSource {
+    /// The source configured as the primary interpreter.
+    fn primary(config: &SandboxConfig) -> Self {
+        match config.interpreter_bytes {
+            Some(ref bytes) => ModuleSource::Bytes(Arc::clone(bytes)),
+            None => ModuleSource::Path(config.interpreter_path.clone()),
+        }
+    }
 }
 
 impl PythonSandbox {
@@ -172,18 +769,119 @@ impl PythonSandbox {
     /// let sandbox2 = PythonSandbox::new_with_options(config, options)?;
     /// ```
     #[cfg_attr(feature = "tracing", instrument(skip(config, options), fields(use_cache = options.use_cache, has_shared_engine = options.shared_engine.is_some())))]
-    pub fn new_with_options(config: SandboxConfig, options: SandboxOptions) -> Result<Self> {
-        let (engine, module, module_was_cached) =
-            Self::create_engine_and_module(&config, &options)?;
+    pub fn new_with_options(config: SandboxConfig, mut options: SandboxOptions) -> Result<Self> {
+        let config = config.resolve_preludes()?;
+        config.validate()?;
+        if options.isolated_engine && options.shared_engine.is_some() {
+            return Err(SandboxError::Config(
+                "isolated_engine cannot be combined with a shared engine".to_string(),
+            ));
+        }
+        if config.max_wasm_stack.is_some() {
+            if options.shared_engine.is_some() {
+                return Err(SandboxError::Config(
+                    "max_wasm_stack cannot be combined with a shared engine".to_string(),
+                ));
+            }
+            if options.cache.is_some() {
+                return Err(SandboxError::Config(
+                    "max_wasm_stack cannot be combined with a module cache".to_string(),
+                ));
+            }
+            // Cached modules run on the engine that compiled them, so the
+            // global cache that options use by default is passed over
+            options.use_cache = false;
+        }
+
+        // A shared engine whose breaker has tripped is replaced before use
+        if let Some(ref shared) = options.shared_engine {
+            shared.recover()?;
+        }
+        let created =
+            Self::create_engine_and_module(&config, &options).and_then(|(ticker, loaded)| {
+                Ok((Self::link(ticker.engine(), &loaded.module)?, ticker, loaded))
+            });
+        if let (Err(SandboxError::RuntimeInit(_)), Some(shared)) =
+            (&created, &options.shared_engine)
+        {
+            shared.record_failure();
+        }
+        let (instance_pre, ticker, loaded) = created?;
 
         #[cfg(feature = "tracing")]
-        info!(module_was_cached, "Sandbox created");
+        info!(cache_status = ?loaded.cache_status, used_fallback = loaded.used_fallback, "Sandbox created");
+
+        Ok(Self {
+            config,
+            ticker,
+            instance_pre: Arc::new(instance_pre),
+            cache_status: loaded.cache_status,
+            used_fallback: loaded.used_fallback,
+            interpreter: loaded.source,
+            isolated_engine: options.isolated_engine,
+            coredump_dir: options
+                .shared_engine
+                .as_ref()
+                .and_then(|shared| shared.coredump_dir().map(Path::to_path_buf)),
+            primed_prelude: None,
+            stdin_generator: options.stdin_generator,
+            python_version: tokio::sync::OnceCell::new(),
+            created_at: Instant::now(),
+        })
+    }
+
+    /// Create a sandbox from a module serialized by `serialize_module`.
+    ///
+    /// This is the worker side of compiling once and sharing the result
+    /// between processes: the parent compiles the interpreter, sends the
+    /// serialized bytes to each worker, and workers skip compilation
+    /// entirely. `config.interpreter_path` and `config.interpreter_bytes`
+    /// are not read, and the module
+    /// cache is not used.
+    ///
+    /// The bytes must have been produced by an engine with the same
+    /// configuration (including fuel consumption and Wasm features) and
+    /// the same wasmtime version as `engine`. A mismatch is detected and
+    /// returned as `SandboxError::ModuleLoad`.
+    ///
+    /// # Safety
+    ///
+    /// The bytes are native code that will be executed without further
+    /// validation. They must come from `serialize_module` (or
+    /// `wasmtime::Module::serialize`) and must not have been tampered with;
+    /// never pass bytes received from an untrusted source.
+    pub unsafe fn from_serialized_module(
+        engine: SharedEngine,
+        bytes: &[u8],
+        config: SandboxConfig,
+    ) -> Result<Self> {
+        let config = config.resolve_preludes()?;
+        config.validate()?;
+        if config.max_wasm_stack.is_some() {
+            return Err(SandboxError::Config(
+                "max_wasm_stack cannot be combined with a shared engine".to_string(),
+            ));
+        }
+        let shared = engine;
+        let ticker = shared.ticker();
+        let module = Module::deserialize(ticker.engine(), bytes).map_err(|e| {
+            SandboxError::ModuleLoad(anyhow::anyhow!("failed to deserialize module: {}", e))
+        })?;
+        let instance_pre = Self::link(ticker.engine(), &module)?;
 
         Ok(Self {
+            ticker,
+            instance_pre: Arc::new(instance_pre),
+            cache_status: CacheStatus::Disabled,
+            used_fallback: false,
+            interpreter: ModuleSource::primary(&config),
+            isolated_engine: false,
+            coredump_dir: shared.coredump_dir().map(Path::to_path_buf),
+            primed_prelude: None,
+            stdin_generator: None,
+            python_version: tokio::sync::OnceCell::new(),
+            created_at: Instant::now(),
             config,
-            engine,
-            module,
-            module_was_cached,
         })
     }
 
@@ -191,49 +889,213 @@ impl PythonSandbox {
     fn create_engine_and_module(
         config: &SandboxConfig,
         options: &SandboxOptions,
-    ) -> Result<(Arc<Engine>, Arc<Module>, bool)> {
+    ) -> Result<(EpochTicker, LoadedModule)> {
         // Create or reuse engine
-        let engine = if let Some(ref shared) = options.shared_engine {
-            shared.arc()
-        } else {
-            let mut engine_config = wasmtime::Config::new();
-            engine_config.epoch_interruption(true);
-            engine_config.consume_fuel(config.max_fuel.is_some());
+        let shared_ticker = options.shared_engine.as_ref().map(SharedEngine::ticker);
+        let engine = match shared_ticker {
+            Some(ref ticker) => Arc::clone(ticker.engine()),
+            None => Self::create_engine(config)?,
+        };
+
+        // Get or compile module, falling back to the secondary interpreter
+        let primary = ModuleSource::primary(config);
+        let loaded = match Self::load_module(&engine, &primary, config.max_module_bytes, options) {
+            Ok((module, cache_status)) => LoadedModule {
+                module,
+                cache_status,
+                used_fallback: false,
+                source: primary,
+            },
+            Err(primary_err) => {
+                let Some(ref fallback) = config.fallback_interpreter_path else {
+                    return Err(primary_err);
+                };
+
+                #[cfg(feature = "tracing")]
+                warn!(error = %primary_err, fallback = %fallback.display(), "Primary interpreter failed, trying fallback");
+
+                let fallback = ModuleSource::Path(fallback.clone());
+                let (module, cache_status) =
+                    Self::load_module(&engine, &fallback, config.max_module_bytes, options)
+                        .map_err(|fallback_err| {
+                            SandboxError::ModuleLoad(anyhow::anyhow!(
+                                "primary interpreter failed: {}; fallback interpreter failed: {}",
+                                primary_err,
+                                fallback_err
+                            ))
+                        })?;
+                LoadedModule {
+                    module,
+                    cache_status,
+                    used_fallback: true,
+                    source: fallback,
+                }
+            }
+        };
+
+        // A module from the cache may have been compiled by another
+        // sandbox's engine. Stores use the module's engine, so that is the
+        // one whose epoch must advance.
+        let ticker = shared_ticker
+            .unwrap_or_else(|| EpochTicker::new(Arc::new(loaded.module.engine().clone())));
+        Ok((ticker, loaded))
+    }
+
+    /// Create an engine configured for the sandbox.
+    fn create_engine(config: &SandboxConfig) -> Result<Arc<Engine>> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.epoch_interruption(true);
+        engine_config.consume_fuel(config.max_fuel.is_some());
+        if let Some(bytes) = config.max_wasm_stack {
+            engine_config.max_wasm_stack(bytes);
+        }
+
+        Ok(Arc::new(Engine::new(&engine_config).map_err(|e| {
+            SandboxError::RuntimeInit(anyhow::anyhow!("failed to create engine: {}", e))
+        })?))
+    }
+
+    /// Link the interpreter against WASI Preview 1.
+    ///
+    /// Instances are never reused: linear memory, tables and globals live in
+    /// a `Store`, and wasmtime cannot roll a store back to a clean state.
+    /// The reuse primitive is instead the `InstancePre`, which keeps the
+    /// compiled code and resolved imports, so each execution only pays for
+    /// instantiating into a new store.
+    fn link(engine: &Engine, module: &Module) -> Result<InstancePre<StoreData>> {
+        let mut linker = Linker::new(engine);
+        preview1::add_to_linker_sync(&mut linker, |data: &mut StoreData| &mut data.wasi).map_err(
+            |e| SandboxError::RuntimeInit(anyhow::anyhow!("failed to link WASI: {}", e)),
+        )?;
+        linker.instantiate_pre(module).map_err(|e| {
+            SandboxError::ModuleLoad(anyhow::anyhow!("failed to link interpreter: {}", e))
+        })
+    }
+
+    /// Get the engine and linked interpreter to use for one execution.
+    ///
+    /// With an isolated engine this creates a fresh engine and recompiles
+    /// the interpreter for it; otherwise the sandbox's own are reused.
+    fn execution_engine(&self) -> Result<(EpochTicker, Arc<InstancePre<StoreData>>)> {
+        if !self.isolated_engine {
+            return Ok((self.ticker.clone(), Arc::clone(&self.instance_pre)));
+        }
 
-            Arc::new(Engine::new(&engine_config).map_err(|e| {
-                SandboxError::RuntimeInit(anyhow::anyhow!("failed to create engine: {}", e))
-            })?)
+        let engine = Self::create_engine(&self.config)?;
+        let (module, _) = Self::load_module(
+            &engine,
+            &self.interpreter,
+            self.config.max_module_bytes,
+            &SandboxOptions::no_cache(),
+        )?;
+        let instance_pre = Self::link(&engine, &module)?;
+        Ok((EpochTicker::new(engine), Arc::new(instance_pre)))
+    }
+
+    /// Get or compile the interpreter module from `source`.
+    fn load_module(
+        engine: &Engine,
+        source: &ModuleSource,
+        max_bytes: Option<usize>,
+        options: &SandboxOptions,
+    ) -> Result<(Arc<Module>, CacheStatus)> {
+        let path = match source {
+            ModuleSource::Path(path) => path,
+            ModuleSource::Bytes(bytes) => {
+                return Self::load_module_bytes(engine, bytes, max_bytes, options)
+            }
+        };
+        let not_found = |e: std::io::Error| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                SandboxError::InterpreterNotFound(path.display().to_string())
+            } else {
+                SandboxError::Io(e)
+            }
         };
 
-        // Get or compile module
-        let (module, was_cached) = if options.use_cache {
+        if let Some(limit) = max_bytes {
+            let size = std::fs::metadata(path).map_err(not_found)?.len();
+            if size > limit as u64 {
+                return Err(SandboxError::ModuleLoad(anyhow::anyhow!(
+                    "interpreter module {} is {} bytes, exceeding the limit of {} bytes",
+                    path.display(),
+                    size,
+                    limit
+                )));
+            }
+        }
+
+        if options.use_cache {
             let cache = options
                 .cache
                 .as_ref()
                 .map(|c| c.as_ref())
                 .unwrap_or_else(|| global_cache());
 
-            let was_in_cache = cache.contains(&config.interpreter_path);
-            let module = cache.get_or_compile(&engine, &config.interpreter_path)?;
-            (module, was_in_cache)
-        } else {
-            // No caching, compile directly
-            let wasm_bytes = std::fs::read(&config.interpreter_path).map_err(|e| {
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    SandboxError::InterpreterNotFound(config.interpreter_path.display().to_string())
-                } else {
-                    SandboxError::Io(e)
+            // A module cached for a shared engine that has since been
+            // recreated belongs to the old engine; compile it again
+            if options.shared_engine.is_some() {
+                cache.evict_foreign(engine, path);
+            }
+
+            let mut status = CacheStatus::Compiled;
+            let module = cache.get_or_compile_with_progress(engine, path, |event| {
+                if event == CompileEvent::CacheHit {
+                    status = CacheStatus::Hit;
                 }
             })?;
+            Ok((module, status))
+        } else {
+            // No caching, compile directly
+            let wasm_bytes = std::fs::read(path).map_err(not_found)?;
 
-            let module = Module::new(&engine, &wasm_bytes).map_err(|e| {
-                SandboxError::ModuleLoad(anyhow::anyhow!("failed to compile module: {}", e))
-            })?;
+            Ok((
+                Arc::new(compile_module(engine, &wasm_bytes)?),
+                CacheStatus::Disabled,
+            ))
+        }
+    }
 
-            (Arc::new(module), false)
-        };
+    /// Get or compile the interpreter module from in-memory `bytes`.
+    fn load_module_bytes(
+        engine: &Engine,
+        bytes: &[u8],
+        max_bytes: Option<usize>,
+        options: &SandboxOptions,
+    ) -> Result<(Arc<Module>, CacheStatus)> {
+        if let Some(limit) = max_bytes {
+            if bytes.len() > limit {
+                return Err(SandboxError::ModuleLoad(anyhow::anyhow!(
+                    "interpreter module is {} bytes, exceeding the limit of {} bytes",
+                    bytes.len(),
+                    limit
+                )));
+            }
+        }
+
+        if !options.use_cache {
+            return Ok((
+                Arc::new(compile_module(engine, bytes)?),
+                CacheStatus::Disabled,
+            ));
+        }
 
-        Ok((engine, module, was_cached))
+        let cache = options
+            .cache
+            .as_ref()
+            .map(|c| c.as_ref())
+            .unwrap_or_else(|| global_cache());
+        if options.shared_engine.is_some() {
+            cache.evict_foreign_bytes(engine, bytes);
+        }
+
+        let mut status = CacheStatus::Compiled;
+        let module = cache.get_or_compile_bytes_with_progress(engine, bytes, |event| {
+            if event == CompileEvent::CacheHit {
+                status = CacheStatus::Hit;
+            }
+        })?;
+        Ok((module, status))
     }
 
     /// Execute Python code in the sandbox.
@@ -242,428 +1104,5810 @@ impl PythonSandbox {
     /// * `code` - The Python code to execute
     /// * `input` - Optional stdin input for the code
     ///
+    /// Stdin is taken from the first of these that is set: `input`, then
+    /// `SandboxConfig::stdin`, then the sandbox's stdin generator (see
+    /// `SandboxOptions::stdin_generator`). With none of them, stdin is empty.
+    ///
     /// # Returns
     /// The execution result containing stdout, stderr, exit code, and metadata.
     #[cfg_attr(feature = "tracing", instrument(skip(self, code, input), fields(code_len = code.len(), has_input = input.is_some())))]
     pub async fn execute(&self, code: &str, input: Option<&str>) -> Result<ExecutionResult> {
-        #[cfg(feature = "tracing")]
-        debug!("Starting Python code execution");
-
-        let code = code.to_string();
-        let input = input.map(|s| s.to_string());
-        let timeout = self.config.timeout;
-        let epoch_interval = self.config.epoch_tick_interval;
-        let max_memory = self.config.max_memory;
-        let max_fuel = self.config.max_fuel;
-        let engine = Arc::clone(&self.engine);
-        let module = Arc::clone(&self.module);
-        let module_was_cached = self.module_was_cached;
-        let stdin_data = self.config.stdin.clone();
-        let env_vars = self.config.env_vars.clone();
-        let prelude = self.config.prelude.clone();
-
-        // Spawn the epoch ticker task
-        let ticker_engine = Arc::clone(&engine);
-        let ticker_handle = tokio::spawn(async move {
-            let mut interval = tokio::time::interval(epoch_interval);
-            loop {
-                interval.tick().await;
-                ticker_engine.increment_epoch();
-            }
-        });
+        self.run_call(code, input.map(str::as_bytes), CallOptions::default())
+            .await
+            .map(|(result, _)| result)
+    }
 
-        // Clone engine for the blocking task
-        let exec_engine = Arc::clone(&engine);
-        let exec_handle = tokio::task::spawn_blocking(move || {
-            Self::execute_sync(
-                &exec_engine,
-                &module,
-                &code,
-                input.as_deref(),
-                stdin_data.as_deref(),
-                &env_vars,
-                prelude.as_deref(),
-                max_memory,
-                max_fuel,
-                module_was_cached,
-            )
-        });
+    /// Execute Python code and classify how the execution ended.
+    ///
+    /// An alternative to `execute` with one exhaustive match point: a run
+    /// that exits on its own and one the sandbox stops (timeout, memory,
+    /// fuel, stack, output limits) are all `Ok`, as a `Termination`.
+    ///
+    /// # Errors
+    /// Only errors that prevent the execution from starting, such as
+    /// `SandboxError::ModuleLoad`, `SandboxError::Config` or
+    /// `SandboxError::Expired`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// match sandbox.run("print(1 + 1)", None).await? {
+    ///     Termination::Completed { exit_code: 0, result } => println!("{}", result.stdout),
+    ///     Termination::Completed { result, .. } => eprintln!("{}", result.stderr),
+    ///     Termination::Timeout { .. } => eprintln!("too slow"),
+    ///     other => eprintln!("stopped: {other:?}"),
+    /// }
+    /// ```
+    pub async fn run(&self, code: &str, input: Option<&str>) -> Result<Termination> {
+        let outcome = self.execute(code, input).await;
+        Termination::from_outcome(outcome, self.config.terminate_on_output_lines)
+    }
 
-        // Race between execution and timeout
-        let result = tokio::select! {
-            result = exec_handle => {
-                ticker_handle.abort();
-                #[cfg(feature = "tracing")]
-                debug!("Execution completed normally");
-                match result {
-                    Ok(inner_result) => inner_result,
-                    Err(e) => Err(SandboxError::ExecutionFailed(format!("task panicked: {}", e))),
-                }
-            }
-            _ = tokio::time::sleep(timeout) => {
-                ticker_handle.abort();
-                engine.increment_epoch(); // Force interrupt
-                #[cfg(feature = "tracing")]
-                warn!(?timeout, "Execution timed out");
-                Err(SandboxError::Timeout(timeout))
+    /// Execute Python code, tagging the result with an opaque label.
+    ///
+    /// The label has no effect on the execution. It is returned in
+    /// `ExecutionMetadata::label`, or in `SandboxError::Labeled` if the
+    /// execution fails, and with the `tracing` feature recorded on the
+    /// execution's span, so an outcome can be matched to the request that
+    /// caused it (e.g. by passing a request ID).
+    #[cfg_attr(feature = "tracing", instrument(skip(self, code, input), fields(code_len = code.len(), has_input = input.is_some())))]
+    pub async fn execute_labeled(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        label: String,
+    ) -> Result<ExecutionResult> {
+        match self.execute(code, input).await {
+            Ok(mut result) => {
+                result.metadata.label = Some(label);
+                Ok(result)
             }
-        };
-
-        #[cfg(feature = "tracing")]
-        if let Ok(ref res) = result {
-            info!(
-                exit_code = res.exit_code,
-                duration_ms = res.metadata.duration.as_millis() as u64,
-                peak_memory = res.metadata.peak_memory,
-                "Execution finished"
-            );
+            Err(error) => Err(SandboxError::Labeled {
+                label,
+                error: Box::new(error),
+            }),
         }
-
-        result
     }
 
-    /// Synchronous execution (runs in blocking task).
-    #[allow(clippy::too_many_arguments)]
-    fn execute_sync(
-        engine: &Engine,
-        module: &Module,
+    /// Execute Python code with a fuel limit for this call only.
+    ///
+    /// Overrides `SandboxConfig::max_fuel` for a single execution, for
+    /// requests of varying complexity. The engine must have fuel consumption
+    /// enabled, either because the config sets `max_fuel` or because the
+    /// sandbox uses `SharedEngine::with_fuel`.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if the engine does not consume fuel,
+    /// and `SandboxError::OutOfFuel` if the code exhausts `fuel`.
+    pub async fn execute_with_fuel(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        fuel: u64,
+    ) -> Result<ExecutionResult> {
+        let call = CallOptions {
+            max_fuel: Some(fuel),
+            ..Default::default()
+        };
+        self.run_call(code, input.map(str::as_bytes), call)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Execute either Python code or a named module.
+    ///
+    /// `ExecutionSource::Code` behaves like `execute`.
+    /// `ExecutionSource::Module` runs the module as `python -m <name>`, so it
+    /// sees `__name__ == "__main__"` and the configured `args` in
+    /// `sys.argv[1:]`. The module must be importable in the sandbox: either
+    /// part of the interpreter's standard library, or supplied with
+    /// `SandboxConfigBuilder::virtual_file`, in which case `/sandbox` is put
+    /// on `PYTHONPATH`.
+    ///
+    /// A module runs without the sandbox's Python instrumentation, so
+    /// settings implemented by it, such as `count_exceptions`,
+    /// `max_imports`, `sys_path` or `preimport`, have no effect.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` for a module if a prelude is
+    /// configured, since there is no script to run it in, or if the
+    /// interpreter cannot run modules by name.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let config = SandboxConfig::builder()
+    ///     .virtual_file("greet.py", b"print('hello')".to_vec())
+    ///     .build();
+    /// let sandbox = PythonSandbox::new(config)?;
+    ///
+    /// let source = ExecutionSource::Module("greet".to_string());
+    /// let result = sandbox.execute_source(&source, None).await?;
+    /// assert_eq!(result.stdout, "hello\n");
+    /// ```
+    pub async fn execute_source(
+        &self,
+        source: &ExecutionSource,
+        input: Option<&str>,
+    ) -> Result<ExecutionResult> {
+        match source {
+            ExecutionSource::Code(code) => self.execute(code, input).await,
+            ExecutionSource::Module(name) => {
+                if name.is_empty() {
+                    return Err(SandboxError::Config(
+                        "module name cannot be empty".to_string(),
+                    ));
+                }
+                let call = CallOptions {
+                    module: Some(name.clone()),
+                    ..Default::default()
+                };
+                self.run_call("", input.map(str::as_bytes), call)
+                    .await
+                    .map(|(result, _)| result)
+            }
+        }
+    }
+
+    /// Execute Python code with `args` as `sys.argv[1:]` for this call only.
+    ///
+    /// Replaces the arguments configured with `SandboxConfigBuilder::args`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let result = sandbox
+    ///     .execute_with_args("import sys; print(sys.argv[1])", None, &["hello"])
+    ///     .await?;
+    /// assert_eq!(result.stdout, "hello\n");
+    /// ```
+    pub async fn execute_with_args(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        args: &[&str],
+    ) -> Result<ExecutionResult> {
+        let call = CallOptions {
+            args: Some(args.iter().map(|arg| arg.to_string()).collect()),
+            ..Default::default()
+        };
+        self.run_call(code, input.map(str::as_bytes), call)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Execute Python code with limits overridden for this call only.
+    ///
+    /// Lets one sandbox run lenient and strict scripts side by side
+    /// without compiling the interpreter twice. Each execution gets a
+    /// store of its own, so the memory limit can change freely; fuel can
+    /// only be overridden when the engine consumes it, either because the
+    /// config sets `max_fuel` or because the sandbox uses
+    /// `SharedEngine::with_fuel`.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if `overrides` sets fuel but the
+    /// engine does not consume it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let strict = ExecutionOverrides::new()
+    ///     .timeout(Duration::from_millis(500))
+    ///     .max_memory(16 * 1024 * 1024);
+    /// let result = sandbox.execute_with(code, None, strict).await?;
+    /// ```
+    pub async fn execute_with(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        overrides: ExecutionOverrides,
+    ) -> Result<ExecutionResult> {
+        let call = CallOptions {
+            timeout: overrides.timeout,
+            max_memory: overrides.max_memory,
+            max_fuel: overrides.max_fuel,
+            ..Default::default()
+        };
+        self.run_call(code, input.map(str::as_bytes), call)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Execute Python code until it finishes or `cancel` resolves.
+    ///
+    /// Wrapping `execute` in `tokio::time::timeout` (or dropping its future
+    /// in any other way) loses everything the code printed so far: the
+    /// caller only sees Tokio's elapsed error. Prefer making the sandbox's
+    /// own timeout authoritative by configuring `SandboxConfig::timeout`
+    /// (or `Session`'s per-call timeout). When the deadline or trigger
+    /// comes from outside, pass it here as `cancel` instead of wrapping:
+    /// the guest is interrupted and the output captured so far is kept.
+    ///
+    /// # Errors
+    /// When `cancel` resolves first, returns `SandboxError::Interrupted`
+    /// wrapping `SandboxError::Cancelled` with the partial output, or plain
+    /// `SandboxError::Cancelled` if the interpreter had not started running
+    /// code yet. Use `SandboxError::is_cancelled` and
+    /// `SandboxError::partial_output` to inspect it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let deadline = tokio::time::sleep(Duration::from_secs(1));
+    /// match sandbox.execute_with_external_cancel(code, None, deadline).await {
+    ///     Ok(result) => println!("{}", result.stdout),
+    ///     Err(e) if e.is_cancelled() => {
+    ///         println!("partial: {:?}", e.partial_output().map(|o| &o.stdout))
+    ///     }
+    ///     Err(e) => return Err(e),
+    /// }
+    /// ```
+    pub async fn execute_with_external_cancel(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<ExecutionResult> {
+        let call = CallOptions {
+            keep_partial_output: true,
+            ..CallOptions::default()
+        };
+        self.run_until(code, input.map(str::as_bytes), call, cancel)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Execute Python code until it finishes or `token` is cancelled.
+    ///
+    /// Cancelling interrupts the guest the same way the timeout does, so a
+    /// run can be abandoned early, e.g. when the client that asked for it
+    /// disconnects. This is `execute_with_external_cancel` driven by a
+    /// token instead of a future. With the `tokio-util` feature, `token`
+    /// can also be a `tokio_util::sync::CancellationToken`.
+    ///
+    /// # Errors
+    /// Once `token` is cancelled, returns `SandboxError::Interrupted`
+    /// wrapping `SandboxError::Cancelled` with the partial output, or plain
+    /// `SandboxError::Cancelled` if the interpreter had not started running
+    /// code yet. `SandboxError::is_cancelled` is true for both.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let token = CancellationToken::new();
+    /// let on_disconnect = token.clone();
+    /// tokio::spawn(async move {
+    ///     client_gone().await;
+    ///     on_disconnect.cancel();
+    /// });
+    /// match sandbox.execute_cancellable(code, None, token).await {
+    ///     Err(e) if e.is_cancelled() => println!("client went away"),
+    ///     other => println!("{:?}", other?.stdout),
+    /// }
+    /// ```
+    pub async fn execute_cancellable(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        token: impl Into<CancellationToken>,
+    ) -> Result<ExecutionResult> {
+        let token = token.into();
+        self.execute_with_external_cancel(code, input, token.cancelled())
+            .await
+    }
+
+    /// Execute Python code, streaming its output and resource usage as events.
+    ///
+    /// Yields `ExecEvent::Stdout` and `ExecEvent::Stderr` for every chunk
+    /// the code writes, an `ExecEvent::ResourceSample` on every epoch tick
+    /// (`SandboxConfig::epoch_tick_interval`) while the guest runs Wasm
+    /// code, and finally `ExecEvent::Finished` with the same result
+    /// `execute` returns, or `ExecEvent::Failed`. See `ExecEvents` for the
+    /// ordering guarantees.
+    ///
+    /// Nothing runs until `ExecEvents::next` is awaited. Report lines of
+    /// guest instrumentation are left out of the stderr chunks, as they are
+    /// out of the final result's `stderr`. No samples are taken while the
+    /// guest is blocked in a host call.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let mut events = sandbox.execute_events(code, None);
+    /// while let Some(event) = events.next().await {
+    ///     match event {
+    ///         ExecEvent::Stdout(chunk) => print!("{}", String::from_utf8_lossy(&chunk)),
+    ///         ExecEvent::ResourceSample { memory, .. } => println!("memory: {}", memory),
+    ///         ExecEvent::Finished(result) => println!("exit code {}", result.exit_code),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// ```
+    pub fn execute_events<'a>(&'a self, code: &'a str, input: Option<&'a str>) -> ExecEvents<'a> {
+        let (sink, events) = EventSink::new();
+        let call = CallOptions {
+            events: Some(sink),
+            ..CallOptions::default()
+        };
+        ExecEvents::new(
+            async move {
+                self.run_call(code, input.map(str::as_bytes), call)
+                    .await
+                    .map(|(result, _)| result)
+            },
+            events,
+        )
+    }
+
+    /// Start a session that keeps one interpreter instance alive across calls.
+    ///
+    /// Unlike `execute`, which instantiates the interpreter afresh every
+    /// time, the session instantiates it once and runs each
+    /// `PersistentSession::eval` in the same store, so Python globals
+    /// persist from one call to the next. This needs an interpreter built
+    /// as a reactor exporting an eval entry point; see
+    /// `sandbox::persistent` for the exports it must provide.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::ModuleLoad` if the interpreter lacks a
+    /// required export, `SandboxError::Expired` once the sandbox is older
+    /// than `max_age`, and the usual limit errors if its initialization
+    /// runs past them.
+    pub async fn persistent_session(&self) -> Result<PersistentSession> {
+        let (ticker, instance_pre) = self.execution_engine()?;
+        PersistentSession::start(ticker, instance_pre, self.config.clone(), self.created_at).await
+    }
+
+    /// Execute Python code as a task of its own, streaming its output.
+    ///
+    /// Returns the output as it is written, chunk by chunk, together with
+    /// a handle to the task. The handle resolves to the same result
+    /// `execute` returns, with the complete stdout and stderr, so the
+    /// chunks need not be collected to get it. Output reaches the stream
+    /// when the guest writes it; whatever Python still has buffered,
+    /// including a trailing partial line, is written when it exits, before
+    /// the stream ends.
+    ///
+    /// As with `execute_events`, report lines of guest instrumentation are
+    /// left out of the stderr chunks. Dropping the stream does not stop the
+    /// execution; its later stdout is handled as set by
+    /// `SandboxConfigBuilder::on_output_closed`. Dropping the handle does
+    /// not stop it either.
+    ///
+    /// The execution runs on a task of its own, which has to own the
+    /// sandbox, so this takes it behind an `Arc` rather than by reference.
+    /// The handle resolves to a `Result` because the execution can fail
+    /// like `execute` does, and a task cannot hand back its error any other
+    /// way.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if the sandbox runs without stdio,
+    /// since there would be no output to stream.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let sandbox = Arc::new(PythonSandbox::new(config)?);
+    /// let (mut output, handle) = sandbox.execute_streaming(code, None)?;
+    /// while let Some(chunk) = output.next().await {
+    ///     if let OutputChunk::Stdout(bytes) = chunk {
+    ///         print!("{}", String::from_utf8_lossy(&bytes));
+    ///     }
+    /// }
+    /// let result = handle.await.expect("execution task panicked")?;
+    /// ```
+    pub fn execute_streaming(
+        self: &Arc<Self>,
+        code: &str,
+        input: Option<&str>,
+    ) -> Result<(OutputStream, JoinHandle<Result<ExecutionResult>>)> {
+        if self.config.no_stdio {
+            return Err(SandboxError::Config(
+                "execute_streaming requires stdio".to_string(),
+            ));
+        }
+        let (sink, events) = EventSink::new();
+        let call = CallOptions {
+            events: Some(sink),
+            ..CallOptions::default()
+        };
+        let sandbox = Arc::clone(self);
+        let code = code.to_string();
+        let input = input.map(str::to_string);
+        let handle = tokio::spawn(async move {
+            sandbox
+                .run_call(&code, input.as_deref().map(str::as_bytes), call)
+                .await
+                .map(|(result, _)| result)
+        });
+        Ok((OutputStream::new(events), handle))
+    }
+
+    /// Execute Python code until it prints `sentinel`, then return.
+    ///
+    /// Meant for cooperative patterns such as a request handler that
+    /// prints its result followed by an agreed-upon marker and then keeps
+    /// running (e.g. waiting for more work). Stdout is watched as it is
+    /// written; once the sentinel appears the rest of the execution is
+    /// interrupted and the output printed before the sentinel is returned
+    /// as a successful result. The script must flush stdout after printing
+    /// the sentinel (`print(marker, flush=True)`), since buffered output
+    /// only reaches the host when Python flushes it.
+    ///
+    /// If the code exits without printing the sentinel, its result is
+    /// returned as from `execute`. Errors after the sentinel appeared are
+    /// ignored; errors before it are returned.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if `sentinel` is empty or the sandbox
+    /// runs without stdio.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let code = "print(handle(request))\nprint('<<DONE>>', flush=True)\nserve_forever()";
+    /// let result = sandbox.execute_until_sentinel(code, "<<DONE>>", None).await?;
+    /// println!("{}", result.stdout);
+    /// ```
+    pub async fn execute_until_sentinel(
+        &self,
         code: &str,
+        sentinel: &str,
         input: Option<&str>,
-        stdin_data: Option<&str>,
-        env_vars: &[(String, String)],
-        prelude: Option<&str>,
-        max_memory: u64,
-        max_fuel: Option<u64>,
-        module_was_cached: bool,
     ) -> Result<ExecutionResult> {
+        if sentinel.is_empty() {
+            return Err(SandboxError::Config(
+                "sentinel must not be empty".to_string(),
+            ));
+        }
+        if self.config.no_stdio {
+            return Err(SandboxError::Config(
+                "execute_until_sentinel requires stdio".to_string(),
+            ));
+        }
+
         let start_time = Instant::now();
-        let initial_fuel = max_fuel;
+        let watch = SentinelWatch::new(sentinel);
+        let call = CallOptions {
+            keep_partial_output: true,
+            stdout_sentinel: Some(watch.clone()),
+            ..CallOptions::default()
+        };
+        let outcome = self
+            .run_until(code, input.map(str::as_bytes), call, watch.found())
+            .await;
 
-        // Combine prelude with user code if prelude is provided
-        let full_code = if let Some(prelude_code) = prelude {
-            format!("{}\n{}", prelude_code, code)
-        } else {
-            code.to_string()
+        let mut result = match (
+            outcome,
+            watch
+                .output_before()
+                .map(|stdout| redact(&self.config, stdout)),
+        ) {
+            (Ok((mut result, _)), Some(stdout)) => {
+                result.stdout = stdout;
+                result.metadata.trimmed_newline = None;
+                result
+            }
+            (Err(e), Some(stdout)) => {
+                let stderr = e
+                    .partial_output()
+                    .map(|output| output.stderr.clone())
+                    .unwrap_or_default();
+                ExecutionResult {
+                    stdout,
+                    user_stderr: stderr.clone(),
+                    stderr,
+                    exit_code: 0,
+                    exception: None,
+                    output_files: HashMap::new(),
+                    output_file_modes: HashMap::new(),
+                    output_line_limited: false,
+                    output_truncated: false,
+                    metadata: ExecutionMetadata {
+                        duration: start_time.elapsed(),
+                        ..ExecutionMetadata::empty()
+                    },
+                    #[cfg(feature = "audit")]
+                    access_attempts: Vec::new(),
+                }
+            }
+            (outcome, None) => return outcome.map(|(result, _)| result),
+        };
+        if self.config.trim_trailing_newline {
+            result.trim_trailing_newline();
+        }
+        Ok(result)
+    }
+
+    /// Execute Python code, treating resource exhaustion as "no result".
+    ///
+    /// A best-effort wrapper around `execute` for callers that do not want
+    /// to match on limit errors. The mapping is:
+    ///
+    /// - `SandboxError::Timeout` (wall-clock timeout) → `Ok(None)`
+    /// - `SandboxError::OutOfFuel` (instruction limit) → `Ok(None)`
+    /// - either of these wrapped in `SandboxError::Interrupted` (see
+    ///   `SandboxConfig::flush_on_trap`) → `Ok(None)`, dropping the partial
+    ///   output
+    /// - any successful execution, including a non-zero exit code → `Ok(Some(result))`
+    /// - every other error (memory limit, configuration, interpreter
+    ///   loading, internal failures) → `Err`
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// match sandbox.try_execute("while True: pass", None).await? {
+    ///     Some(result) => println!("{}", result.stdout),
+    ///     None => println!("gave up"),
+    /// }
+    /// ```
+    pub async fn try_execute(
+        &self,
+        code: &str,
+        input: Option<&str>,
+    ) -> Result<Option<ExecutionResult>> {
+        match self.execute(code, input).await {
+            Ok(result) => Ok(Some(result)),
+            Err(e) if e.is_timeout() || e.is_out_of_fuel() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Execute Python code that is expected to produce no output.
+    ///
+    /// Useful for validating setup snippets that should only define
+    /// things. Fails with `SandboxError::ExecutionFailed` if anything was
+    /// written to stdout or stderr; otherwise returns the result as-is
+    /// (which may still have a non-zero exit code).
+    pub async fn execute_expect_silent(
+        &self,
+        code: &str,
+        input: Option<&str>,
+    ) -> Result<ExecutionResult> {
+        let result = self.execute(code, input).await?;
+
+        if !result.stdout.is_empty() || !result.stderr.is_empty() {
+            return Err(SandboxError::ExecutionFailed(format!(
+                "expected no output, got {} bytes of stdout and {} bytes of stderr",
+                result.stdout.len(),
+                result.stderr.len()
+            )));
+        }
+
+        Ok(result)
+    }
+
+    /// Execute Python code and capture the final global namespace.
+    ///
+    /// After the code finishes (including when it raises), every global
+    /// whose name does not start with `__` is serialized to JSON. Values
+    /// that are not JSON-serializable are replaced by their type name, so
+    /// `x = 42` becomes `{"x": 42}` while `import math` becomes
+    /// `{"math": "module"}`.
+    ///
+    /// Globals may hold large data. If the serialized namespace exceeds
+    /// `SandboxConfig::max_value_bytes` the call fails with
+    /// `SandboxError::ValueTooLarge`. If the interpreter exits before the
+    /// namespace can be captured (e.g. via `os._exit`), `Value::Null` is
+    /// returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let (result, globals) = sandbox.execute_with_globals("x = 42", None).await?;
+    /// assert_eq!(globals["x"], 42);
+    /// ```
+    pub async fn execute_with_globals(
+        &self,
+        code: &str,
+        input: Option<&str>,
+    ) -> Result<(ExecutionResult, serde_json::Value)> {
+        let call = CallOptions {
+            capture_globals: true,
+            ..CallOptions::default()
+        };
+        let (result, reports) = self.run_call(code, input.map(str::as_bytes), call).await?;
+
+        if let Some(size) = reports.get_u64(GLOBALS_TOO_LARGE_KEY) {
+            return Err(SandboxError::ValueTooLarge {
+                size,
+                limit: self.config.max_value_bytes as u64,
+            });
+        }
+
+        let globals = match reports.get(GLOBALS_KEY) {
+            Some(json) => serde_json::from_str(json).map_err(|e| {
+                SandboxError::ExecutionFailed(format!("failed to decode globals: {}", e))
+            })?,
+            None => serde_json::Value::Null,
+        };
+
+        Ok((result, globals))
+    }
+
+    /// Execute Python code and capture the final value of one global variable.
+    ///
+    /// A lighter alternative to `execute_with_globals` when only one value
+    /// is needed. The variable is read after the code finishes (including
+    /// when it raises) and serialized to JSON; a value that is not
+    /// JSON-serializable is returned as its `repr` string. Returns `None`
+    /// as the value if no global of that name exists, or if the interpreter
+    /// exits before it can be read (e.g. via `os._exit`).
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if `var_name` is not a Python
+    /// identifier, and `SandboxError::ValueTooLarge` if the serialized
+    /// value exceeds `SandboxConfig::max_value_bytes`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let (_, total) = sandbox
+    ///     .execute_capture_var("total = sum(range(10))", "total", None)
+    ///     .await?;
+    /// assert_eq!(total, Some(serde_json::json!(45)));
+    /// ```
+    pub async fn execute_capture_var(
+        &self,
+        code: &str,
+        var_name: &str,
+        input: Option<&str>,
+    ) -> Result<(ExecutionResult, Option<serde_json::Value>)> {
+        if !guest::is_identifier(var_name) {
+            return Err(SandboxError::Config(format!(
+                "{:?} is not a valid Python identifier",
+                var_name
+            )));
+        }
+        let call = CallOptions {
+            capture_var: Some(var_name.to_string()),
+            ..CallOptions::default()
+        };
+        let (result, reports) = self.run_call(code, input.map(str::as_bytes), call).await?;
+
+        if let Some(size) = reports.get_u64(VAR_TOO_LARGE_KEY) {
+            return Err(SandboxError::ValueTooLarge {
+                size,
+                limit: self.config.max_value_bytes as u64,
+            });
+        }
+
+        let value = reports
+            .get(VAR_KEY)
+            .map(|json| {
+                serde_json::from_str(json).map_err(|e| {
+                    SandboxError::ExecutionFailed(format!("failed to decode {}: {}", var_name, e))
+                })
+            })
+            .transpose()?;
+
+        Ok((result, value))
+    }
+
+    /// Evaluate a Python expression and return the `repr` of its value.
+    ///
+    /// The value is passed back to the host apart from stdout, so anything
+    /// the expression prints (e.g. from a function it calls) is returned in
+    /// `EvalResult::stdout` without being mixed up with the value. The
+    /// prelude, if any, runs first, so its definitions can be used.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::PythonException` if the expression raises,
+    /// including a `SyntaxError` for source that is not an expression, and
+    /// `SandboxError::ValueTooLarge` if the `repr` exceeds
+    /// `SandboxConfig::max_value_bytes`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let eval = sandbox.execute_eval("sorted({3, 1, 2})").await?;
+    /// assert_eq!(eval.value, "[1, 2, 3]");
+    /// ```
+    pub async fn execute_eval(&self, expr: &str) -> Result<EvalResult> {
+        let call = CallOptions {
+            eval_expr: true,
+            ..CallOptions::default()
+        };
+        let (result, reports) = self.run_call(expr, None, call).await?;
+
+        if let Some(size) = reports.get_u64(RESULT_TOO_LARGE_KEY) {
+            return Err(SandboxError::ValueTooLarge {
+                size,
+                limit: self.config.max_value_bytes as u64,
+            });
+        }
+
+        let value = match reports.get(RESULT_KEY) {
+            Some(json) => serde_json::from_str(json).map_err(|e| {
+                SandboxError::ExecutionFailed(format!("failed to decode result: {}", e))
+            })?,
+            None => {
+                return Err(result.python_exception().unwrap_or_else(|| {
+                    SandboxError::ExecutionFailed(format!(
+                        "expression was not evaluated (exit code {})",
+                        result.exit_code
+                    ))
+                }))
+            }
+        };
+
+        Ok(EvalResult {
+            value,
+            stdout: result.stdout,
+            stderr: result.stderr,
+            metadata: result.metadata,
+        })
+    }
+
+    /// Execute Python code with a JSON value bound to the global `context`.
+    ///
+    /// The value is serialized on the host and decoded by the guest with
+    /// `json.loads` before user code runs. A context of up to
+    /// `SandboxConfig::max_context_bytes` serialized bytes is embedded in
+    /// the code itself. Anything larger is written to stdin ahead of
+    /// `input` and read back in full before user code starts, so user code
+    /// still sees exactly `input` on stdin; in that case the sandbox's
+    /// stdin generator, if any, is not used.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if the context needs the stdin path
+    /// but the sandbox runs without stdio.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let context = serde_json::json!({"user": "ada", "scores": [1, 2, 3]});
+    /// let result = sandbox
+    ///     .execute_with_context("print(sum(context['scores']))", None, &context)
+    ///     .await?;
+    /// assert_eq!(result.stdout.trim(), "6");
+    /// ```
+    pub async fn execute_with_context(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        context: &serde_json::Value,
+    ) -> Result<ExecutionResult> {
+        let json = context.to_string();
+        if json.len() > self.config.max_context_bytes && self.config.no_stdio {
+            return Err(SandboxError::Config(format!(
+                "context of {} bytes exceeds max_context_bytes ({}) and needs stdin, \
+                 which is disabled by no_stdio",
+                json.len(),
+                self.config.max_context_bytes
+            )));
+        }
+
+        let call = CallOptions {
+            context: Some(json),
+            ..CallOptions::default()
+        };
+        self.run_call(code, input.map(str::as_bytes), call)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Run the doctests found in the docstrings of the given code.
+    ///
+    /// The code is executed as the `__main__` module, then every example
+    /// in its docstrings is run with the standard `doctest` machinery.
+    /// Output printed by the examples is compared, not returned; the
+    /// outcome is summarized in a `DoctestReport`.
+    ///
+    /// Requires the interpreter to bundle the `doctest` module (included
+    /// in RustPython `freeze-stdlib` builds); otherwise this fails with
+    /// `SandboxError::ExecutionFailed`. If the code itself raises before
+    /// the doctests run, the parsed Python exception is returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let report = sandbox.run_doctests(r#"
+    /// def add(a, b):
+    ///     '''
+    ///     >>> add(1, 2)
+    ///     3
+    ///     '''
+    ///     return a + b
+    /// "#).await?;
+    /// assert!(report.all_passed());
+    /// ```
+    pub async fn run_doctests(&self, code: &str) -> Result<DoctestReport> {
+        let call = CallOptions {
+            run_doctests: true,
+            ..CallOptions::default()
+        };
+        let (result, reports) = self.run_call(code, None, call).await?;
+
+        if reports.contains(DOCTEST_UNAVAILABLE_KEY) {
+            return Err(SandboxError::ExecutionFailed(
+                "doctest module is not available in this interpreter".to_string(),
+            ));
+        }
+
+        match reports.get(DOCTESTS_KEY) {
+            Some(json) => DoctestReport::from_json(json),
+            None => Err(result.python_exception().unwrap_or_else(|| {
+                SandboxError::ExecutionFailed(format!(
+                    "doctests did not run (exit code {})",
+                    result.exit_code
+                ))
+            })),
+        }
+    }
+
+    /// Compile code to bytecode without running it.
+    ///
+    /// Cheaper than a full execution for validating user code, e.g. in a
+    /// linting endpoint. The code is passed to the builtin `compile()` and
+    /// the resulting code object is discarded. Syntax errors are returned
+    /// in the report with their line and column rather than as an `Err`,
+    /// along with any warnings the compiler emitted (e.g. `SyntaxWarning`).
+    ///
+    /// The configured prelude still runs before the check.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let report = sandbox.compile_check("x = = 1").await?;
+    /// assert_eq!(report.error.unwrap().line, Some(1));
+    /// ```
+    pub async fn compile_check(&self, code: &str) -> Result<CompileReport> {
+        let call = CallOptions {
+            compile_only: true,
+            ..CallOptions::default()
         };
+        let (result, reports) = self.run_call(code, None, call).await?;
+
+        match reports.get(COMPILE_KEY) {
+            Some(json) => CompileReport::from_json(json),
+            None => Err(result.python_exception().unwrap_or_else(|| {
+                SandboxError::ExecutionFailed(format!(
+                    "compile check did not run (exit code {})",
+                    result.exit_code
+                ))
+            })),
+        }
+    }
+
+    /// Get the interpreter's version string, as `sys.version` reports it.
+    ///
+    /// The interpreter is queried on the first call only; later calls
+    /// return the cached string.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// println!("running Python {}", sandbox.python_version().await?);
+    /// ```
+    ///
+    /// # Errors
+    /// Returns `SandboxError::ExecutionFailed` if the interpreter does not
+    /// report its version, e.g. because the prelude fails.
+    pub async fn python_version(&self) -> Result<String> {
+        let version = self
+            .python_version
+            .get_or_try_init(|| async {
+                let call = CallOptions {
+                    report_version: true,
+                    ..CallOptions::default()
+                };
+                let (result, reports) = self.run_call("", None, call).await?;
+                reports
+                    .get(VERSION_KEY)
+                    .and_then(|json| serde_json::from_str::<String>(json).ok())
+                    .ok_or_else(|| {
+                        SandboxError::ExecutionFailed(format!(
+                            "interpreter did not report its version (exit code {})",
+                            result.exit_code
+                        ))
+                    })
+            })
+            .await?;
+        Ok(version.clone())
+    }
+
+    /// Run the prelude once and reuse its compiled form for later executions.
+    ///
+    /// The prelude normally reruns from source on every execution. Priming
+    /// runs it once to check that it succeeds, then caches its marshalled
+    /// bytecode; later executions load that bytecode instead of parsing and
+    /// compiling the prelude again.
+    ///
+    /// The interpreter cannot be snapshotted part-way through a run, so the
+    /// prelude's statements still execute on every call: priming saves the
+    /// compile cost, not the cost of running the prelude. Every execution
+    /// still starts from a fresh interpreter and isolation is unchanged.
+    /// Tracebacks from prelude code report the file as `<prelude>`.
+    ///
+    /// Requires the interpreter to support `marshal` for code objects.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if no prelude is configured, or the
+    /// parsed Python exception if the prelude fails.
+    pub async fn prime_prelude(&mut self) -> Result<()> {
+        if self.config.prelude.is_none() {
+            return Err(SandboxError::Config(
+                "cannot prime the prelude: no prelude is configured".to_string(),
+            ));
+        }
+
+        let call = CallOptions {
+            prime_prelude: true,
+            ..CallOptions::default()
+        };
+        let (result, reports) = self.run_call("", None, call).await?;
+
+        match reports.get(PRELUDE_BYTECODE_KEY) {
+            Some(hex) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                self.primed_prelude = Some(guest::primed_prelude(hex));
+                Ok(())
+            }
+            _ => Err(result.python_exception().unwrap_or_else(|| {
+                SandboxError::ExecutionFailed(format!(
+                    "prelude could not be primed (exit code {})",
+                    result.exit_code
+                ))
+            })),
+        }
+    }
+
+    /// Check if the prelude has been primed with `prime_prelude`.
+    pub fn is_prelude_primed(&self) -> bool {
+        self.primed_prelude.is_some()
+    }
+
+    /// Execute several independent code blocks in one interpreter startup.
+    ///
+    /// Starting the interpreter dominates the cost of short snippets, so
+    /// running a batch of blocks in one go is much faster than calling
+    /// `execute` for each. Output is split per block at boundary lines
+    /// that carry a random nonce, so stray output is not mistaken for a
+    /// boundary; a block that reads the nonce from the driver script can
+    /// still fake one.
+    ///
+    /// **Blocks are not isolated from each other.** They share one global
+    /// namespace, imported modules and interpreter state, and resource
+    /// limits (timeout, memory, fuel) apply to the whole batch. Only use
+    /// this for trusted blocks where cross-contamination is acceptable.
+    ///
+    /// An exception in one block is printed to that block's `stderr` and
+    /// the next block still runs. If the interpreter exits early (e.g. via
+    /// `sys.exit()`), the block that was running is returned as failed and
+    /// later blocks are omitted.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let results = sandbox.execute_sequence(&["print(1)", "print(2)"]).await?;
+    /// assert_eq!(results[1].stdout.trim(), "2");
+    /// ```
+    pub async fn execute_sequence(&self, blocks: &[&str]) -> Result<Vec<BlockResult>> {
+        let boundary = Boundary::new();
+        let script = sequence::build_script(blocks, &boundary);
+        let result = self.execute(&script, None).await?;
+
+        Ok(sequence::split_blocks(
+            &result.stdout,
+            &result.stderr,
+            blocks.len(),
+            &boundary,
+        ))
+    }
+
+    /// Measure how much memory code needs, to help choose `max_memory`.
+    ///
+    /// The code first runs once with a limit of `max_probe` bytes to record
+    /// its peak usage. A binary search over whole wasm pages up to that peak
+    /// then finds the smallest limit at which the code still finishes with
+    /// the same exit code. Each limit is enforced on top of
+    /// `SandboxConfig::memory_policy`, if set.
+    ///
+    /// This is expensive: the code runs roughly `1 + log2(peak / 64 KiB)`
+    /// times (about 11 runs for a 64 MiB peak), each paying the full
+    /// interpreter startup. The code should be deterministic and free of
+    /// side effects that matter, since it is executed repeatedly. Set
+    /// `SandboxConfigBuilder::total_budget` to bound the time of all runs.
+    ///
+    /// # Errors
+    /// Returns the error from the initial run, e.g.
+    /// `SandboxError::MemoryLimitExceeded` if `max_probe` is too small, or
+    /// `SandboxError::Timeout` once the total budget is used up.
+    pub async fn probe_memory(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        max_probe: u64,
+    ) -> Result<MemoryProbe> {
+        let budget = TotalBudget::start(self.config.total_budget);
+        let run_with_limit = |limit: u64| async move {
+            let call = CallOptions {
+                max_memory: Some(limit),
+                timeout: Some(budget.timeout(self.config.timeout)?),
+                ..Default::default()
+            };
+            budget.check(self.run_call(code, input.map(str::as_bytes), call).await)
+        };
+
+        let (baseline, _) = run_with_limit(max_probe).await?;
+        let peak_memory = baseline.metadata.peak_memory;
+        let mut runs = 1;
+
+        // Invariant: `high` pages succeed, `low` pages are not known to.
+        let mut low = 0;
+        let mut high = peak_memory.div_ceil(WASM_PAGE_SIZE);
+        while low + 1 < high {
+            let mid = low + (high - low) / 2;
+            let succeeded = match run_with_limit(mid * WASM_PAGE_SIZE).await {
+                Ok((result, _)) => result.exit_code == baseline.exit_code,
+                Err(e) if budget.exhausted() => return Err(e),
+                Err(_) => false,
+            };
+            runs += 1;
+            if succeeded {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+
+        Ok(MemoryProbe {
+            peak_memory,
+            minimum_limit: high * WASM_PAGE_SIZE,
+            runs,
+        })
+    }
+
+    /// Measure how long code takes, to help choose `timeout`.
+    ///
+    /// Runs the code `samples` times, each in a fresh store, and summarizes
+    /// the durations reported in `ExecutionMetadata::duration`. Each run may
+    /// take up to ten times the configured timeout, so code that is close
+    /// to its current limit can still be measured; pick a timeout with
+    /// margin above `p99`. Like `probe_memory`, every run pays the full
+    /// interpreter startup, and `SandboxConfigBuilder::total_budget` bounds
+    /// the time of all runs.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if `samples` is zero, and otherwise
+    /// the first error of any run, e.g. `SandboxError::Timeout` if a sample
+    /// exceeds the generous per-run limit.
+    pub async fn probe_duration(
+        &self,
+        code: &str,
+        input: Option<&str>,
+        samples: usize,
+    ) -> Result<DurationStats> {
+        if samples == 0 {
+            return Err(SandboxError::Config(
+                "probe_duration needs at least one sample".to_string(),
+            ));
+        }
+        let budget = TotalBudget::start(self.config.total_budget);
+        let per_run = self.config.timeout * DURATION_PROBE_TIMEOUT_FACTOR;
+
+        let mut durations = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            let call = CallOptions {
+                timeout: Some(budget.timeout(per_run)?),
+                ..Default::default()
+            };
+            let (result, _) =
+                budget.check(self.run_call(code, input.map(str::as_bytes), call).await)?;
+            durations.push(result.metadata.duration);
+        }
+
+        Ok(DurationStats::from_samples(durations))
+    }
+
+    /// Run the same code once for each input in a corpus.
+    ///
+    /// A lightweight harness for exercising parsing code with varied stdin.
+    /// Inputs are consumed lazily: each call to `InputRuns::next` runs the
+    /// code against the next input, reusing this sandbox's compiled module.
+    /// Inputs are passed to stdin as raw bytes and need not be valid UTF-8.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let corpus = vec![b"1 2".to_vec(), Vec::new(), b"not a number".to_vec()];
+    /// let mut runs = sandbox.run_inputs(code, corpus).stop_on_error(true);
+    /// while let Some(result) = runs.next().await {
+    ///     println!("{:?}", result.map(|r| r.exit_code));
+    /// }
+    /// ```
+    pub fn run_inputs<I>(&self, code: &str, inputs: I) -> InputRuns<'_, I::IntoIter>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        InputRuns {
+            sandbox: self,
+            code: code.to_string(),
+            inputs: inputs.into_iter(),
+            stop_on_error: false,
+            stopped: false,
+            budget: None,
+        }
+    }
+
+    /// Run code with per-call options, returning the guest reports alongside the result.
+    pub(crate) async fn run_call(
+        &self,
+        code: &str,
+        input: Option<&[u8]>,
+        call: CallOptions,
+    ) -> Result<(ExecutionResult, GuestReports)> {
+        self.run_until(code, input, call, std::future::pending())
+            .await
+    }
+
+    /// Run code until it finishes, times out, or `cancel` resolves.
+    ///
+    /// With the `tracing` feature the run is wrapped in a
+    /// `sandbox.execute` span (see `sandbox::telemetry`).
+    async fn run_until(
+        &self,
+        code: &str,
+        input: Option<&[u8]>,
+        call: CallOptions,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<(ExecutionResult, GuestReports)> {
+        #[cfg(feature = "tracing")]
+        {
+            let span = telemetry::execution_span(
+                call.timeout.unwrap_or(self.config.timeout).as_millis() as u64,
+                call.max_memory.unwrap_or(self.config.max_memory),
+            );
+            let result = self
+                .run_untraced(code, input, call, cancel)
+                .instrument(span.clone())
+                .await;
+            telemetry::record_result(&span, &result);
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        self.run_untraced(code, input, call, cancel).await
+    }
+
+    /// Body of `run_until`, outside any execution span.
+    async fn run_untraced(
+        &self,
+        code: &str,
+        input: Option<&[u8]>,
+        mut call: CallOptions,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<(ExecutionResult, GuestReports)> {
+        #[cfg(feature = "tracing")]
+        debug!("Starting Python code execution");
+
+        if let Some(max_age) = self.config.max_age {
+            let age = self.age();
+            if age >= max_age {
+                return Err(SandboxError::Expired { age, max_age });
+            }
+        }
+
+        if call.max_fuel.is_some() && !engine_consumes_fuel(self.ticker.engine()) {
+            return Err(SandboxError::Config(
+                "per-call fuel requires an engine with fuel consumption enabled".to_string(),
+            ));
+        }
+        call.coredump_dir = self.coredump_dir.clone();
+        call.stdin_generator = self.stdin_generator.clone();
+
+        let code = code.to_string();
+        let input = input.map(<[u8]>::to_vec);
+        let mut config = self.config.clone();
+        if let Some(max_memory) = call.max_memory {
+            config.max_memory = max_memory;
+        }
+        if let Some(max_fuel) = call.max_fuel {
+            config.max_fuel = Some(max_fuel);
+        }
+        if let Some(timeout) = call.timeout {
+            config.timeout = timeout;
+        }
+        if let Some(ref primed) = self.primed_prelude {
+            if !call.prime_prelude {
+                config.prelude = Some(primed.clone());
+            }
+        }
+        let timeout = config.timeout;
+        let keep_partial_output = config.flush_on_trap || call.keep_partial_output;
+        let epoch_interval = config.epoch_tick_interval;
+        let (ticker, instance_pre) = self.execution_engine()?;
+        let engine = Arc::clone(ticker.engine());
+        let module_was_cached = self.is_using_cached_module() && !self.isolated_engine;
+        let used_fallback = self.used_fallback;
+        let interprets_exit_code = call.compile_only || call.run_doctests || call.report_version;
+        let started = Arc::new(AtomicBool::new(false));
+        let exec_started = Arc::clone(&started);
+
+        // Keep the engine's epoch advancing. The lease is held until the
+        // guest has actually stopped, so an interrupted guest that had not
+        // yet set its deadline still reaches it and traps.
+        let lease = ticker.acquire(epoch_interval);
+        let stop = Arc::clone(&call.stop);
+        let finished = Arc::new(AtomicBool::new(false));
+        let exec_finished = Arc::clone(&finished);
+        let mut guard = InterruptOnDrop {
+            engine: Arc::clone(&engine),
+            stop: Arc::clone(&stop),
+            finished: Arc::clone(&finished),
+            armed: true,
+        };
+
+        let mut exec_handle = tokio::task::spawn_blocking(move || {
+            let result = Self::execute_sync(
+                &instance_pre,
+                &code,
+                input.as_deref(),
+                &config,
+                &call,
+                &exec_started,
+            );
+            exec_finished.store(true, Ordering::Release);
+            drop(lease);
+            result
+        });
+
+        // Race between execution, timeout and cancellation
+        let mut result = tokio::select! {
+            result = &mut exec_handle => {
+                guard.armed = false;
+                #[cfg(feature = "tracing")]
+                debug!("Execution completed normally");
+                match result {
+                    Ok(inner_result) => inner_result,
+                    Err(e) => Err(SandboxError::ExecutionFailed(format!("task panicked: {}", e))),
+                }
+            }
+            _ = tokio::time::sleep(timeout) => {
+                guard.armed = false;
+                #[cfg(feature = "tracing")]
+                warn!(?timeout, "Execution timed out");
+                let phase = if started.load(Ordering::Acquire) {
+                    TimeoutPhase::Execution
+                } else {
+                    TimeoutPhase::Instantiation
+                };
+                let error = SandboxError::Timeout { elapsed: timeout, phase };
+                Err(interrupt(&engine, &stop, &finished, &mut exec_handle, error, keep_partial_output).await)
+            }
+            _ = cancel => {
+                guard.armed = false;
+                #[cfg(feature = "tracing")]
+                warn!("Execution cancelled");
+                Err(interrupt(&engine, &stop, &finished, &mut exec_handle, SandboxError::Cancelled, keep_partial_output).await)
+            }
+        };
+
+        if let Ok((ref mut res, _)) = result {
+            res.metadata.used_cached_module = module_was_cached;
+            res.metadata.used_fallback_interpreter = used_fallback;
+        }
+
+        if let (Some(allowed), Ok((res, _))) = (&self.config.allowed_exit_codes, &result) {
+            if !allowed.contains(&res.exit_code) && !interprets_exit_code {
+                return Err(SandboxError::ExecutionFailed(format!(
+                    "exit code {} is not one of the allowed exit codes {:?}",
+                    res.exit_code, allowed
+                )));
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        if let Ok((ref res, _)) = result {
+            info!(
+                exit_code = res.exit_code,
+                duration_ms = res.metadata.duration.as_millis() as u64,
+                peak_memory = res.metadata.peak_memory,
+                "Execution finished"
+            );
+        }
+
+        result
+    }
+
+    /// Synchronous execution (runs in blocking task).
+    fn execute_sync(
+        instance_pre: &InstancePre<StoreData>,
+        code: &str,
+        input: Option<&[u8]>,
+        config: &SandboxConfig,
+        call: &CallOptions,
+        started: &AtomicBool,
+    ) -> Result<(ExecutionResult, GuestReports)> {
+        let start_time = Instant::now();
+        let max_memory = config.max_memory;
+        let max_fuel = config.max_fuel;
+        let initial_fuel = max_fuel;
+
+        // Collect instrumentation snippets to run before user code
+        let mut setup = Vec::new();
+        if config.disable_gc {
+            setup.push(guest::gc_disable());
+        }
+        if let Some(seed) = config.deterministic_seed {
+            setup.push(guest::random_seed(seed));
+        }
+        if config.separate_exceptions {
+            setup.push(guest::exception_hook());
+        }
+        if config.no_stdio {
+            setup.push(guest::no_stdio());
+        }
+        #[cfg(feature = "audit")]
+        if config.audit_access {
+            setup.push(audit::harness());
+        }
+        if mounts::has_virtual_files(&config.mounted_files) {
+            setup.push(guest::chdir(VIRTUAL_ROOT));
+        }
+        if let Some(ref paths) = config.sys_path {
+            setup.push(guest::sys_path(paths));
+        }
+        if !config.preimport.is_empty() {
+            setup.push(guest::preimport(&config.preimport));
+        }
+        if call.capture_globals {
+            setup.push(guest::globals_capture(config.max_value_bytes));
+        }
+        if let Some(ref name) = call.capture_var {
+            setup.push(guest::var_capture(name, config.max_value_bytes));
+        }
+        // Small contexts are embedded in the code; larger ones go via stdin
+        let mut context_stdin = None;
+        if let Some(ref json) = call.context {
+            if json.len() <= config.max_context_bytes {
+                setup.push(guest::context_literal(json));
+            } else {
+                setup.push(guest::context_from_stdin(json.len()));
+                context_stdin = Some(json.as_bytes());
+            }
+        }
+        // After every snippet that imports modules of its own
+        if let Some(limit) = config.max_imports {
+            setup.push(guest::import_limit(limit));
+        }
+        // After the other snippets, which may raise and catch exceptions
+        if config.count_exceptions {
+            setup.push(guest::exception_counter(config.max_exceptions));
+        }
+        // Must be the last setup snippet
+        if config.line_coverage && !call.compile_only {
+            let source = config.auto_print_last_expr.then_some("<string>");
+            setup.push(guest::line_coverage(code.lines().count(), source));
+        }
+
+        // Collect instrumentation snippets to run after user code
+        let mut teardown = Vec::new();
+        if call.run_doctests {
+            teardown.push(doctest::harness());
+        }
+        if call.report_version {
+            teardown.push(guest::version_report());
+        }
+        let auto_print;
+        let eval_code;
+        let code = if call.compile_only {
+            teardown.push(compile::harness(code));
+            ""
+        } else if call.eval_expr {
+            teardown.push(guest::result_report(config.max_value_bytes));
+            eval_code = guest::eval_expr(code);
+            &eval_code
+        } else if config.auto_print_last_expr {
+            auto_print = guest::auto_print(code);
+            &auto_print
+        } else {
+            code
+        };
+        let prelude = match config.prelude.as_deref() {
+            Some(prelude) if call.prime_prelude => {
+                teardown.push(guest::prelude_bytecode(prelude));
+                None
+            }
+            prelude => prelude,
+        };
+
+        // Combine prelude and instrumentation with user code
+        let channel = ReportChannel::new();
+        let support = channel.support(&setup, &teardown);
+        let full_code = config.interpreter.assemble(Script {
+            support: &support,
+            prelude,
+            setup: &setup,
+            code,
+            teardown: &teardown,
+        });
+
+        // Set up I/O capture
+        let normalized_input;
+        let effective_input = match effective_input(config, input) {
+            Some(data) if config.normalize_stdin_newlines => {
+                normalized_input = io::normalize_newlines(data);
+                Some(&*normalized_input)
+            }
+            other => other,
+        };
+        let stdin_with_context;
+        let effective_input = match context_stdin {
+            Some(context) => {
+                stdin_with_context = [context, effective_input.unwrap_or_default()].concat();
+                Some(stdin_with_context.as_slice())
+            }
+            None => effective_input,
+        };
+        let mut io = SandboxIo::from_bytes(effective_input);
+        if config.trace_stdin {
+            io.stdin = io.stdin.traced();
+        }
+        let stdout_pipe = MemoryOutputPipe::new(OUTPUT_PIPE_CAPACITY);
+        let stderr_pipe = MemoryOutputPipe::new(OUTPUT_PIPE_CAPACITY);
+
+        // Build WASI context with controlled access
+        let mut wasi_builder = WasiCtxBuilder::new();
+
+        // Pass the code (or module) via the command line, followed by the
+        // script's own arguments
+        let mut argv = match call.module {
+            Some(ref module) => {
+                if config.prelude.is_some() {
+                    return Err(SandboxError::Config(format!(
+                        "cannot run module '{module}': a prelude cannot run before a module"
+                    )));
+                }
+                if config.strict_env
+                    && mounts::has_virtual_files(&config.mounted_files)
+                    && !config.env_vars.iter().any(|(key, _)| key == PYTHONPATH_VAR)
+                {
+                    return Err(SandboxError::Config(format!(
+                        "cannot run module '{module}': strict_env leaves out the \
+                         {PYTHONPATH_VAR} that finds virtual files; set it with env"
+                    )));
+                }
+                config.interpreter.module_args(module).ok_or_else(|| {
+                    SandboxError::Config(format!(
+                        "cannot run module '{module}': the interpreter cannot run modules"
+                    ))
+                })?
+            }
+            None => config.interpreter.args(&full_code),
+        };
+        argv.extend_from_slice(call.args.as_deref().unwrap_or(&config.args));
+        wasi_builder.args(&argv);
+
+        // Add environment variables
+        for (key, value) in &config.env_vars {
+            wasi_builder.env(key, value);
+        }
+        // The support code reads the report marker from the environment
+        // and removes it before user code runs
+        if call.module.is_none() && !support.is_empty() {
+            wasi_builder.env(REPORT_MARKER_VAR, channel.marker());
+        }
+        // A module does not run the chdir snippet, so find virtual files
+        // through the import path instead
+        if call.module.is_some()
+            && mounts::has_virtual_files(&config.mounted_files)
+            && !config.env_vars.iter().any(|(key, _)| key == PYTHONPATH_VAR)
+        {
+            wasi_builder.env(PYTHONPATH_VAR, VIRTUAL_ROOT);
+        }
+        // Fix the hash seed, stop the clocks and derive randomness from the seed
+        deterministic::configure(&mut wasi_builder, config);
+
+        // Mount read-only files; the directory lives until this call returns
+        let _mounts = if config.mounted_files.is_empty() {
+            None
+        } else {
+            let mounts = MountDir::create(&config.mounted_files)?;
+            wasi_builder
+                .preopened_dir(mounts.path(), MOUNT_ROOT, DirPerms::READ, FilePerms::READ)
+                .map_err(|e| {
+                    SandboxError::RuntimeInit(anyhow::anyhow!("failed to mount files: {}", e))
+                })?;
+            Some(mounts)
+        };
+
+        // Give the guest an empty directory to write into, read back below.
+        // What it writes is measured against the output limit as it runs.
+        let mut output_quota = None;
+        let output_dir = if config.collect_output_files {
+            let limit = config.max_output_bytes.ok_or_else(|| {
+                SandboxError::Config(
+                    "collect_output_files requires max_output_bytes to be set".to_string(),
+                )
+            })?;
+            let dir = MountDir::empty("output")?;
+            output_quota = Some(Arc::new(OutputQuota::new(&dir, limit)));
+            wasi_builder
+                .preopened_dir(dir.path(), OUTPUT_ROOT, DirPerms::all(), FilePerms::all())
+                .map_err(|e| {
+                    SandboxError::RuntimeInit(anyhow::anyhow!(
+                        "failed to create output directory: {}",
+                        e
+                    ))
+                })?;
+            Some(dir)
+        } else {
+            None
+        };
+
+        // Connect stdin to our I/O capture, or to the generator if there is
+        // no input of its own
+        if !config.no_stdio {
+            match call.stdin_generator {
+                Some(ref generator) if effective_input.is_none() => {
+                    wasi_builder.stdin(GeneratedInput::new(generator.clone()));
+                }
+                _ => {
+                    wasi_builder.stdin(io.stdin.clone());
+                }
+            }
+        }
+
+        // Capture stdout and stderr. Without stdio, stderr is still needed
+        // as the report channel but its regular content is discarded.
+        // Report lines are taken out of stderr before anything else sees
+        // it; what is left of both is subject to the output limits.
+        let limits = OutputLimits::new(config, start_time);
+        let report_lines = ReportFilter::new(channel.marker());
+        if config.no_stdio {
+            wasi_builder.stdout(ClosedOutputStream);
+        } else if let Some(ref watch) = call.stdout_sentinel {
+            wasi_builder.stdout(limits.stdout(watch.stdout(stdout_pipe.clone())));
+        } else if let Some(ref events) = call.events {
+            wasi_builder.stdout(limits.stdout(events.output(
+                stdout_pipe.clone(),
+                ExecEvent::Stdout,
+                config.on_output_closed,
+            )));
+        } else {
+            wasi_builder.stdout(limits.stdout(stdout_pipe.clone()));
+        }
+        match call.events {
+            Some(ref events) => {
+                wasi_builder.stderr(report_lines.wrap(limits.stderr(events.output(
+                    stderr_pipe.clone(),
+                    ExecEvent::Stderr,
+                    OutputClosedAction::Discard,
+                ))));
+            }
+            None => {
+                wasi_builder.stderr(report_lines.wrap(limits.stderr(stderr_pipe.clone())));
+            }
+        }
+
+        // Build the WASI Preview 1 context
+        let wasi_ctx = wasi_builder.build_p1();
+
+        // Create store with resource limiter. A limit set for this call
+        // holds under the memory policy too.
+        let mut store = configured_store(
+            instance_pre.module().engine(),
+            config,
+            call.max_memory.is_some(),
+            wasi_ctx,
+        );
+
+        // Check for a timeout or interruption on every epoch tick. The epoch
+        // is shared with other executions on the engine, so each store
+        // decides for itself whether it should stop. Each tick also closes
+        // the output rate window once it has elapsed. When streaming events,
+        // each tick also takes a resource sample.
+        let stop = Arc::clone(&call.stop);
+        let events = call.events.clone();
+        let timeout = config.timeout;
+        let rate = limits.rate.clone();
+        let quota = output_quota.clone();
+        store.epoch_deadline_callback(move |ctx| {
+            if stop.load(Ordering::Acquire)
+                || start_time.elapsed() >= timeout
+                || rate.check()
+                || quota.as_ref().is_some_and(|quota| quota.check())
+            {
+                return Err(Trap::Interrupt.into());
+            }
+            if let Some(ref events) = events {
+                events.send(ExecEvent::ResourceSample {
+                    memory: ctx.data().limiter.current_memory(),
+                    fuel: initial_fuel.map(|f| f.saturating_sub(ctx.get_fuel().unwrap_or(0))),
+                    elapsed: start_time.elapsed(),
+                });
+            }
+            Ok(UpdateDeadline::Continue(1))
+        });
+        store.set_epoch_deadline(1);
+
+        // Set fuel limit if configured
+        if let Some(fuel) = max_fuel {
+            store.set_fuel(fuel).map_err(|e| {
+                SandboxError::RuntimeInit(anyhow::anyhow!("failed to set fuel: {}", e))
+            })?;
+        }
+
+        // Instantiate the module into the fresh store. The start function, if any, runs here under
+        // the same epoch deadline as `_start`, so a module that hangs during
+        // instantiation is interrupted like any other long-running code.
+        let instantiation_start = Instant::now();
+        let instance = instance_pre.instantiate(&mut store).map_err(|e| {
+            if is_epoch_interrupt(&e) {
+                return SandboxError::Timeout {
+                    elapsed: start_time.elapsed(),
+                    phase: TimeoutPhase::Instantiation,
+                };
+            }
+
+            // Check if it was a resource limit issue
+            if store.data().limiter.limit_exceeded() {
+                let current_memory = store.data().limiter.current_memory();
+                return SandboxError::MemoryLimitExceeded(format!(
+                    "memory limit exceeded during instantiation (used {} bytes, limit {} bytes)",
+                    current_memory, max_memory
+                ));
+            }
+            SandboxError::ModuleLoad(anyhow::anyhow!("failed to instantiate: {}", e))
+        })?;
+
+        // Get the entry point (`_start` for WASI commands)
+        let entry_point = config.interpreter.entry_point();
+        let start = instance
+            .get_typed_func::<(), ()>(&mut store, entry_point)
+            .map_err(|e| {
+                SandboxError::ModuleLoad(anyhow::anyhow!(
+                    "failed to get {} function: {}",
+                    entry_point,
+                    e
+                ))
+            })?;
+
+        let instantiation_time = instantiation_start.elapsed();
+
+        // Execute
+        started.store(true, Ordering::Release);
+        let execution_start = Instant::now();
+        let called = start.call(&mut store, ());
+        let execution_time = execution_start.elapsed();
+        let outcome = match called {
+            Ok(()) => Ok(0),
+            Err(_) if limits.lines.terminated() => Ok(0),
+            Err(e) => {
+                // Check for various error conditions. A guest that exited on its
+                // own after a failed grow already reported the error itself.
+                let exited = e.downcast_ref::<I32Exit>().is_some();
+                if store.data().limiter.limit_exceeded()
+                    && !(config.memory_limit_as_python_error && exited)
+                {
+                    let current_memory = store.data().limiter.current_memory();
+                    Err(SandboxError::MemoryLimitExceeded(format!(
+                        "memory limit exceeded during execution (used {} bytes, limit {} bytes)",
+                        current_memory, max_memory
+                    )))
+                } else if let Some(limit) = config
+                    .max_io_time
+                    .filter(|_| store.data().host_calls.limit_exceeded())
+                {
+                    Err(SandboxError::IoTimeExceeded(limit))
+                } else if let Some((rate, limit)) =
+                    limits.rate.exceeded().zip(config.max_output_rate)
+                {
+                    Err(SandboxError::OutputRateExceeded { rate, limit })
+                } else if let Some(quota) = output_quota.as_ref().filter(|quota| quota.exceeded()) {
+                    Err(SandboxError::OutputFilesExceeded {
+                        limit: quota.limit(),
+                    })
+                } else if is_epoch_interrupt(&e) {
+                    // Epoch interrupt (timeout), by trap code or root cause
+                    Err(SandboxError::Timeout {
+                        elapsed: start_time.elapsed(),
+                        phase: TimeoutPhase::Execution,
+                    })
+                } else if is_out_of_fuel(&e) {
+                    // Out-of-fuel trap
+                    let fuel_remaining = store.get_fuel().unwrap_or(0);
+                    let fuel_consumed = initial_fuel.map(|f| f.saturating_sub(fuel_remaining));
+                    Err(SandboxError::OutOfFuel {
+                        consumed: fuel_consumed,
+                    })
+                } else if is_stack_overflow(&e) {
+                    Err(SandboxError::StackOverflow)
+                } else if let Some(exit) = e.downcast_ref::<I32Exit>() {
+                    // WASI exit code
+                    Ok(exit.0)
+                } else if let Some(coredump) = call
+                    .coredump_dir
+                    .as_deref()
+                    .and_then(|dir| write_coredump(&e, &mut store, dir))
+                {
+                    Err(SandboxError::InterpreterTrap {
+                        message: e.to_string(),
+                        coredump,
+                    })
+                } else {
+                    // Other error - check for Python exceptions in stderr
+                    Err(SandboxError::ExecutionFailed(e.to_string()))
+                }
+            }
+        };
+
+        // Writes since the last tick have not been measured yet
+        let outcome = match output_quota {
+            Some(ref quota) if outcome.is_ok() && quota.check() => {
+                Err(SandboxError::OutputFilesExceeded {
+                    limit: quota.limit(),
+                })
+            }
+            _ => outcome,
+        };
+
+        // Keep the output captured so far if the run was interrupted
+        let exit_code = match outcome {
+            Ok(exit_code) => exit_code,
+            Err(error)
+                if (config.flush_on_trap || call.keep_partial_output) && !config.no_stdio =>
+            {
+                let stderr = String::from_utf8_lossy(&stderr_pipe.contents()).into_owned();
+                let stdout = String::from_utf8_lossy(&stdout_pipe.contents()).into_owned();
+                return Err(SandboxError::Interrupted {
+                    error: Box::new(error),
+                    output: PartialOutput {
+                        stdout: redact(config, stdout),
+                        stderr: redact(config, stderr),
+                    },
+                });
+            }
+            Err(error) => return Err(error),
+        };
+
+        // Instrumentation reports were kept out of the program's own stderr
+        let mut stderr = String::from_utf8_lossy(&stderr_pipe.contents()).into_owned();
+        let reports = channel.parse(&report_lines.lines());
+        let mut traceback = reports
+            .get(TRACEBACK_KEY)
+            .filter(|_| config.separate_exceptions)
+            .and_then(|json| serde_json::from_str::<String>(json).ok());
+        let mut traceback_truncated = false;
+        if let Some(max_frames) = config.max_traceback_frames {
+            (stderr, traceback_truncated) = truncate_traceback(&stderr, max_frames);
+            if let Some(ref mut text) = traceback {
+                let truncated;
+                (*text, truncated) = truncate_traceback(text, max_frames);
+                traceback_truncated |= truncated;
+            }
+        }
+        if !config.no_stdio {
+            io.stdout.write_all(&stdout_pipe.contents())?;
+            io.stderr.write_all(stderr.as_bytes())?;
+        }
+        // Nothing can be appended after this point
+        let (stdout, user_stderr) = io.finalize();
+        // Python would have printed the traceback after the program's writes
+        let stderr = format!("{user_stderr}{}", traceback.as_deref().unwrap_or_default());
+
+        if let Some((module, reason)) = reports
+            .get(PREIMPORT_FAILED_KEY)
+            .and_then(|json| serde_json::from_str::<(String, String)>(json).ok())
+        {
+            return Err(SandboxError::Config(format!(
+                "pre-imported module {} is not available: {}",
+                module, reason
+            )));
+        }
+        if let Some(limit) = config.max_exceptions {
+            if reports.contains(EXCEPTION_LIMIT_KEY) {
+                return Err(SandboxError::ExceptionLimitExceeded { limit });
+            }
+        }
+
+        // Collect execution metadata
+        let duration = start_time.elapsed();
+        let peak_memory = store.data().limiter.current_memory();
+        let fuel_consumed = if let Some(initial) = initial_fuel {
+            let remaining = store.get_fuel().unwrap_or(0);
+            Some(initial.saturating_sub(remaining))
+        } else {
+            None
+        };
+
+        // Read before the result, whose truncation flag the files can set
+        let output = match output_dir {
+            Some(ref dir) => dir.read_files(&limits.bytes, config.default_file_mode)?,
+            None => OutputFiles::default(),
+        };
+
+        let mut result = ExecutionResult {
+            stdout,
+            stderr,
+            user_stderr,
+            exit_code,
+            exception: traceback
+                .and_then(|text| PythonException::chain(&redact(config, text)).pop()),
+            output_files: output.files,
+            output_file_modes: output.modes,
+            output_line_limited: limits.lines.reached(),
+            output_truncated: limits.bytes.reached(),
+            metadata: ExecutionMetadata {
+                duration,
+                instantiation_time,
+                execution_time,
+                peak_memory,
+                fuel_consumed,
+                used_cached_module: false,
+                exceptions_raised: if config.count_exceptions {
+                    reports.get_u64(EXCEPTIONS_KEY)
+                } else {
+                    None
+                },
+                lines_covered: reports
+                    .get(LINES_KEY)
+                    .filter(|_| config.line_coverage)
+                    .and_then(|json| serde_json::from_str(json).ok()),
+                gc_enabled: reports
+                    .get_u64(GC_ENABLED_KEY)
+                    .filter(|_| config.disable_gc)
+                    .map(|enabled| enabled != 0),
+                stdin_reads: io.stdin.reads(),
+                exceeded_warn_threshold: store.data().limiter.warn_threshold_exceeded(),
+                host_call_duration: config
+                    .track_host_calls
+                    .then(|| store.data().host_calls.total()),
+                used_fallback_interpreter: false,
+                traceback_truncated,
+                trimmed_newline: None,
+                imports: config
+                    .max_imports
+                    .and_then(|_| reports.get_u64(IMPORTS_KEY)),
+                banner: None,
+                time_to_first_output: limits.first_output.elapsed(),
+                label: None,
+            },
+            #[cfg(feature = "audit")]
+            access_attempts: AccessAttempt::from_reports(&reports),
+        };
+        if config.suppress_banner {
+            if let (Some(banner), stdout) = split_banner(&result.stdout) {
+                result.metadata.banner = Some(banner.to_string());
+                result.stdout = stdout.to_string();
+            }
+        }
+        if config.trim_trailing_newline {
+            result.trim_trailing_newline();
+        }
+        result.stdout = redact(config, std::mem::take(&mut result.stdout));
+        result.stderr = redact(config, std::mem::take(&mut result.stderr));
+        result.user_stderr = redact(config, std::mem::take(&mut result.user_stderr));
+
+        Ok((result, reports))
+    }
+
+    /// Get the shared engine used by this sandbox.
+    pub fn engine(&self) -> &Engine {
+        self.ticker.engine()
+    }
+
+    /// Serialize the compiled interpreter module.
+    ///
+    /// The bytes (a `.cwasm` image) can be written to disk or sent to
+    /// another process and loaded there with `from_serialized_module`,
+    /// using an engine configured the same way as this sandbox's.
+    pub fn serialize_module(&self) -> Result<Vec<u8>> {
+        self.instance_pre.module().serialize().map_err(|e| {
+            SandboxError::ModuleLoad(anyhow::anyhow!("failed to serialize module: {}", e))
+        })
+    }
+
+    /// Time since this sandbox was created.
+    pub fn age(&self) -> Duration {
+        self.created_at.elapsed()
+    }
+
+    /// Check if this sandbox is using a cached module.
+    pub fn is_using_cached_module(&self) -> bool {
+        self.cache_status == CacheStatus::Hit
+    }
+
+    /// Check if this sandbox loaded the fallback interpreter.
+    pub fn is_using_fallback_interpreter(&self) -> bool {
+        self.used_fallback
+    }
+
+    /// Get how this sandbox's module was obtained.
+    pub fn cache_status(&self) -> CacheStatus {
+        self.cache_status
+    }
+}
+
+/// Interrupts the guest if an execution's future is dropped before it ends.
+///
+/// Without this, a caller dropping `execute` (e.g. via `tokio::time::timeout`)
+/// would leave the blocking task running until the sandbox's own timeout.
+struct InterruptOnDrop {
+    engine: Arc<Engine>,
+    stop: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+    armed: bool,
+}
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            stop_guest(&self.engine, &self.stop, &self.finished);
+        }
+    }
+}
+
+/// Make a guest trap at its next epoch check, unless it has `finished`.
+///
+/// The forced epoch tick reaches every store on the engine. Other stores
+/// just run their deadline callback early, but skipping the tick once the
+/// guest has returned avoids disturbing them for nothing.
+fn stop_guest(engine: &Engine, stop: &AtomicBool, finished: &AtomicBool) {
+    stop.store(true, Ordering::Release);
+    if !finished.load(Ordering::Acquire) {
+        engine.increment_epoch();
+    }
+}
+
+/// Interrupt a running execution, attaching its partial output to `error`.
+///
+/// Setting `stop` and forcing an epoch tick makes the guest trap at its
+/// next epoch check; when `keep_output` is set it is given a moment to hand
+/// back its output. No tick is forced if the execution has `finished`.
+async fn interrupt(
+    engine: &Engine,
+    stop: &AtomicBool,
+    finished: &AtomicBool,
+    exec_handle: &mut JoinHandle<Result<(ExecutionResult, GuestReports)>>,
+    error: SandboxError,
+    keep_output: bool,
+) -> SandboxError {
+    stop_guest(engine, stop, finished);
+    if keep_output {
+        if let Ok(Ok(Err(SandboxError::Interrupted { output, .. }))) =
+            tokio::time::timeout(TRAP_FLUSH_GRACE, exec_handle).await
+        {
+            return SandboxError::Interrupted {
+                error: Box::new(error),
+                output,
+            };
+        }
+    }
+    error
+}
+
+/// Replacement for text matching `SandboxConfig::redact_output_patterns`.
+#[cfg(feature = "regex")]
+const REDACTED: &str = "[REDACTED]";
+
+/// Mask matches of the configured redaction patterns in captured output.
+#[cfg(feature = "regex")]
+pub(crate) fn redact(config: &SandboxConfig, text: String) -> String {
+    config
+        .redact_output_patterns
+        .iter()
+        .fold(text, |text, pattern| {
+            pattern.replace_all(&text, REDACTED).into_owned()
+        })
+}
+
+/// Output is returned as captured without the `regex` feature.
+#[cfg(not(feature = "regex"))]
+pub(crate) fn redact(_config: &SandboxConfig, text: String) -> String {
+    text
+}
+
+/// Stdin for one call: the call's own input, else the configured default.
+fn effective_input<'a>(config: &'a SandboxConfig, input: Option<&'a [u8]>) -> Option<&'a [u8]> {
+    input.or(config.stdin.as_deref().map(str::as_bytes))
+}
+
+/// Write the coredump attached to a trap to `dir`, returning its path.
+fn write_coredump(
+    error: &anyhow::Error,
+    store: &mut Store<StoreData>,
+    dir: &Path,
+) -> Option<PathBuf> {
+    let dump = error.downcast_ref::<WasmCoreDump>()?;
+    let path = dir.join(format!("python-sandbox-{:016x}.coredump", guest::nonce()));
+    match std::fs::write(&path, dump.serialize(store, "python-sandbox")) {
+        Ok(()) => Some(path),
+        Err(_e) => {
+            #[cfg(feature = "tracing")]
+            warn!(error = %_e, path = %path.display(), "Failed to write coredump");
+            None
+        }
+    }
+}
+
+/// Check if stores created from an engine can be given fuel.
+fn engine_consumes_fuel(engine: &Engine) -> bool {
+    Store::new(engine, ()).set_fuel(0).is_ok()
+}
+
+/// Check if an error is an epoch interrupt (timeout).
+pub(crate) fn is_epoch_interrupt(error: &anyhow::Error) -> bool {
+    // Check if the error is a Trap::Interrupt
+    if let Some(trap) = error.downcast_ref::<Trap>() {
+        return *trap == Trap::Interrupt;
+    }
+
+    // Check the error chain for interrupt indicators
+    for cause in error.chain() {
+        if let Some(trap) = cause.downcast_ref::<Trap>() {
+            if *trap == Trap::Interrupt {
+                return true;
+            }
+        }
+        // Fallback: check error message (for compatibility)
+        let msg = cause.to_string().to_lowercase();
+        if msg.contains("epoch") || msg.contains("interrupt") {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Check if an error is an out-of-fuel trap.
+pub(crate) fn is_out_of_fuel(error: &anyhow::Error) -> bool {
+    // Check if the error is a Trap::OutOfFuel
+    if let Some(trap) = error.downcast_ref::<Trap>() {
+        return *trap == Trap::OutOfFuel;
+    }
+
+    // Check the error chain
+    for cause in error.chain() {
+        if let Some(trap) = cause.downcast_ref::<Trap>() {
+            if *trap == Trap::OutOfFuel {
+                return true;
+            }
+        }
+        // Fallback: check error message
+        let msg = cause.to_string().to_lowercase();
+        if msg.contains("out of fuel") || msg.contains("fuel") {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Check if an error is a Wasm stack overflow trap.
+pub(crate) fn is_stack_overflow(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.downcast_ref::<Trap>() == Some(&Trap::StackOverflow))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::cache::{WasmFeatures, GLOBALS_LOCK};
+    use crate::sandbox::config::InputSpec;
+    use crate::sandbox::preludes::global_preludes;
+
+    fn failed_result(stdout: &str, stderr: &str) -> ExecutionResult {
+        ExecutionResult {
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            user_stderr: stderr.to_string(),
+            ..ExecutionResult::with_exit_code(1)
+        }
+    }
+
+    #[test]
+    fn test_failure_kind_syntax_error() {
+        let result = failed_result(
+            "",
+            "  File \"<string>\", line 1\n    print(\n         ^\nSyntaxError: unexpected EOF while parsing",
+        );
+        assert_eq!(result.failure_kind(), Some(FailureKind::SyntaxError));
+    }
+
+    #[test]
+    fn test_failure_kind_runtime_exception() {
+        let result = failed_result(
+            "started\n",
+            "Traceback (most recent call last):\n  File \"<string>\", line 2, in <module>\nZeroDivisionError: division by zero",
+        );
+        assert_eq!(result.failure_kind(), Some(FailureKind::RuntimeException));
+    }
+
+    #[test]
+    fn test_effective_input_precedence() {
+        let with_default = SandboxConfig::builder().stdin("default").build();
+        let without_default = SandboxConfig::default();
+
+        assert_eq!(
+            effective_input(&with_default, None),
+            Some(b"default".as_slice())
+        );
+        assert_eq!(
+            effective_input(&without_default, Some(b"call")),
+            Some(b"call".as_slice())
+        );
+        assert_eq!(
+            effective_input(&with_default, Some(b"call")),
+            Some(b"call".as_slice())
+        );
+        assert_eq!(effective_input(&without_default, None), None);
+    }
+
+    #[test]
+    fn test_stdout_trimmed() {
+        let printed = failed_result("2\n", "");
+        let blank_lines = failed_result("2\n\n\n", "");
+        let crlf = failed_result("2\r\n", "");
+        let bare = failed_result("2", "");
+
+        assert_eq!(printed.stdout_trimmed(), "2");
+        assert_eq!(blank_lines.stdout_trimmed(), "2\n\n");
+        assert_eq!(crlf.stdout_trimmed(), "2");
+        assert_eq!(bare.stdout_trimmed(), "2");
+    }
+
+    #[test]
+    fn test_matches_expected() {
+        let result = failed_result("x = 3.14159\n3.14159\n", "");
+
+        assert!(!result.matches_expected("x = 3.14159\n3.1416\n", &DiffOptions::new()));
+        assert!(result.matches_expected(
+            "x = 3.14159\n3.1416\n",
+            &DiffOptions::new().numeric_tolerance(1e-4, 0.0)
+        ));
+    }
+
+    #[test]
+    fn test_trim_trailing_newline_keeps_raw() {
+        let mut printed = failed_result("2\n", "");
+        let mut blank_lines = failed_result("2\n\n", "");
+        printed.trim_trailing_newline();
+        blank_lines.trim_trailing_newline();
+
+        assert_eq!(printed.stdout, "2");
+        assert_eq!(printed.raw_stdout(), "2\n");
+        assert_eq!(blank_lines.stdout, "2\n");
+        assert_eq!(blank_lines.raw_stdout(), "2\n\n");
+    }
+
+    #[test]
+    fn test_split_banner() {
+        let banner = "Welcome to the magnificent Rust Python 0.4.0 interpreter\n";
+        let stdout = format!("{banner}hello\n");
+        assert_eq!(split_banner(&stdout), (Some(banner), "hello\n"));
+        assert_eq!(split_banner("hello\n"), (None, "hello\n"));
+
+        // Only leading lines are treated as a banner
+        let later = format!("hello\n{banner}");
+        assert_eq!(split_banner(&later), (None, later.as_str()));
+    }
+
+    #[test]
+    fn test_python_exception_prefers_traceback() {
+        let merged = failed_result(
+            "",
+            "warning\nTraceback (most recent call last):\nKeyError: 'x'\n",
+        );
+        assert!(matches!(
+            merged.python_exception(),
+            Some(SandboxError::PythonException { ref exception_type, .. }) if exception_type == "KeyError"
+        ));
+
+        // The program's own stderr looks like an exception, too
+        let separated = ExecutionResult {
+            exception: PythonException::chain(
+                "Traceback (most recent call last):\nValueError: bad\n",
+            )
+            .pop(),
+            ..failed_result("", "KeyError: 'x'\n")
+        };
+        assert!(matches!(
+            separated.python_exception(),
+            Some(SandboxError::PythonException { ref exception_type, .. }) if exception_type == "ValueError"
+        ));
+        assert_eq!(
+            separated.failure_kind(),
+            Some(FailureKind::RuntimeException)
+        );
+    }
+
+    #[test]
+    fn test_failure_kind_non_zero_exit() {
+        let result = failed_result("", "");
+        assert_eq!(result.failure_kind(), Some(FailureKind::NonZeroExit));
+
+        let success = ExecutionResult {
+            exit_code: 0,
+            ..failed_result("ok\n", "")
+        };
+        assert_eq!(success.failure_kind(), None);
+    }
+
+    /// Write a minimal module to a temporary file to stand in for the interpreter.
+    fn stub_interpreter(name: &str) -> std::path::PathBuf {
+        wat_interpreter(name, "(module)")
+    }
+
+    /// Write a module to a temporary file to stand in for the interpreter.
+    fn wat_interpreter(name: &str, wat: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-{}-{}.wat",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, wat).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_serialized_module_round_trip() {
+        let path = wat_interpreter(
+            "serialize",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "hello\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 6))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let parent =
+            PythonSandbox::new_with_options(config.clone(), SandboxOptions::no_cache()).unwrap();
+        let bytes = parent.serialize_module().unwrap();
+
+        let engine = SharedEngine::new().unwrap();
+        let worker =
+            unsafe { PythonSandbox::from_serialized_module(engine.clone(), &bytes, config) }
+                .unwrap();
+        let result = worker.execute("", None).await.unwrap();
+
+        assert_eq!(result.stdout, "hello\n");
+        assert_eq!(worker.cache_status(), CacheStatus::Disabled);
+
+        // Bytes from an incompatibly configured engine are rejected
+        let fuel_engine = SharedEngine::with_fuel().unwrap();
+        let mismatched = unsafe {
+            PythonSandbox::from_serialized_module(fuel_engine, &bytes, SandboxConfig::default())
+        };
+        assert!(matches!(mismatched, Err(SandboxError::ModuleLoad(_))));
+    }
+
+    #[tokio::test]
+    async fn test_interpreter_bytes() {
+        let wasm: Arc<[u8]> = Arc::from(
+            &br#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "embedded\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 9))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#[..],
+        );
+        // The bytes take precedence over a path that does not exist
+        let config = SandboxConfig::builder()
+            .interpreter_path("/nonexistent/rustpython.wasm")
+            .interpreter_bytes(Arc::clone(&wasm))
+            .build();
+        let cache = Arc::new(ModuleCache::new());
+
+        let first = PythonSandbox::new_with_options(
+            config.clone(),
+            SandboxOptions::with_cache(Arc::clone(&cache)),
+        )
+        .unwrap();
+        let second = PythonSandbox::new_with_options(
+            config.clone(),
+            SandboxOptions::with_cache(Arc::clone(&cache)),
+        )
+        .unwrap();
+
+        assert_eq!(first.cache_status(), CacheStatus::Compiled);
+        assert_eq!(second.cache_status(), CacheStatus::Hit);
+        assert!(cache.contains_bytes(&wasm));
+        assert_eq!(second.execute("", None).await.unwrap().stdout, "embedded\n");
+
+        let capped = SandboxConfig {
+            max_module_bytes: Some(4),
+            ..config
+        };
+        let err = PythonSandbox::new_with_options(capped, SandboxOptions::no_cache())
+            .err()
+            .expect("module over the cap should fail to load");
+        assert!(matches!(err, SandboxError::ModuleLoad(_)));
+    }
+
+    #[tokio::test]
+    async fn test_collect_output_files() {
+        // Writes 8 bytes to stdout, then creates out.json in the first
+        // preopen and writes the same 8 bytes to it
+        let path = wat_interpreter(
+            "output-files",
+            r#"(module
+                (import "wasi_snapshot_preview1" "path_open"
+                    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 32) "out.json")
+                (data (i32.const 64) "{\"ok\":1}")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 64))
+                    (i32.store (i32.const 4) (i32.const 8))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (if (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 8)
+                            (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 100))
+                        (then unreachable))
+                    (drop (call $fd_write (i32.load (i32.const 100)) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let builder = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .collect_output_files(true)
+            .max_output_bytes(1024);
+
+        let sandbox =
+            PythonSandbox::new_with_options(builder.clone().build(), SandboxOptions::no_cache())
+                .unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+        assert_eq!(result.output_files.len(), 1);
+        assert_eq!(result.output_files[Path::new("out.json")], br#"{"ok":1}"#);
+        assert!(result.output_file_modes.contains_key(Path::new("out.json")));
+        assert!(!result.output_truncated);
+
+        // The default mode is applied as the files are read back
+        let config = builder.clone().default_file_mode(0o600).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+        assert_eq!(result.output_file_modes[Path::new("out.json")], 0o600);
+
+        // Files count towards the output byte limit together with stdout
+        let config = builder.clone().max_output_bytes(12).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+        assert_eq!(result.stdout, r#"{"ok":1}"#);
+        assert_eq!(result.output_files[Path::new("out.json")], br#"{"ok"#);
+        assert!(result.output_truncated);
+
+        // Writing more than the limit to /out fails the execution, even
+        // when it finishes before the next epoch tick
+        let config = builder.max_output_bytes(4).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let err = sandbox.execute("", None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SandboxError::OutputFilesExceeded { limit: 4 }
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_output_files_quota_stops_writer() {
+        // Creates out.bin in the first preopen and appends to it forever
+        let path = wat_interpreter(
+            "output-files-quota",
+            r#"(module
+                (import "wasi_snapshot_preview1" "path_open"
+                    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 32) "out.bin")
+                (func (export "_start")
+                    (if (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 7)
+                            (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 100))
+                        (then unreachable))
+                    (i32.store (i32.const 0) (i32.const 1024))
+                    (i32.store (i32.const 4) (i32.const 4096))
+                    (loop $write
+                        (drop (call $fd_write (i32.load (i32.const 100)) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (br $write))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .collect_output_files(true)
+            .max_output_bytes(1024 * 1024)
+            .timeout(Duration::from_secs(30))
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let started = Instant::now();
+        let err = sandbox.execute("", None).await.unwrap_err();
+
+        assert!(err.is_output_files_limit(), "unexpected error: {err}");
+        assert!(!err.is_timeout());
+        assert!(started.elapsed() < Duration::from_secs(30));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_script_args() {
+        // Exits with the number of command line arguments
+        let path = wat_interpreter(
+            "script-args",
+            r#"(module
+                (import "wasi_snapshot_preview1" "args_sizes_get"
+                    (func $args_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+                    (call $proc_exit (i32.load (i32.const 0)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .args(vec!["a".to_string(), "b".to_string()])
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        // python -c <code> a b
+        assert_eq!(sandbox.execute("", None).await.unwrap().exit_code, 5);
+        let result = sandbox.execute_with_args("", None, &["x"]).await.unwrap();
+        assert_eq!(result.exit_code, 4);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_source_module() {
+        // Exits with the number of command line arguments
+        let path = wat_interpreter(
+            "module-args",
+            r#"(module
+                (import "wasi_snapshot_preview1" "args_sizes_get"
+                    (func $args_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+                    (call $proc_exit (i32.load (i32.const 0)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .args(vec!["a".to_string()])
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        // python -m mymod a
+        let module = ExecutionSource::Module("mymod".to_string());
+        let result = sandbox.execute_source(&module, None).await.unwrap();
+        assert_eq!(result.exit_code, 4);
+        let empty = ExecutionSource::Module(String::new());
+        let err = sandbox.execute_source(&empty, None).await.unwrap_err();
+        assert!(matches!(err, SandboxError::Config(_)));
+
+        // A prelude has nowhere to run
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .prelude("x = 1")
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let err = sandbox.execute_source(&module, None).await.unwrap_err();
+        assert!(err.to_string().contains("prelude"), "{err}");
+        let code = ExecutionSource::Code(String::new());
+        assert_eq!(
+            sandbox.execute_source(&code, None).await.unwrap().exit_code,
+            3
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mount_file_preopens_directory() {
+        // Exits with the result of fd_prestat_get on the first preopen fd
+        let path = wat_interpreter(
+            "mount-file",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_prestat_get"
+                    (func $fd_prestat_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (call $proc_exit (call $fd_prestat_get (i32.const 3) (i32.const 0)))))"#,
+        );
+        let plain = SandboxConfig::builder().interpreter_path(&path).build();
+        let mounted = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .mount_file("/data/input.bin", vec![0; 16])
+            .build();
+
+        let sandbox = PythonSandbox::new_with_options(plain, SandboxOptions::no_cache()).unwrap();
+        assert_ne!(sandbox.execute("", None).await.unwrap().exit_code, 0);
+        let sandbox = PythonSandbox::new_with_options(mounted, SandboxOptions::no_cache()).unwrap();
+        assert_eq!(sandbox.execute("", None).await.unwrap().exit_code, 0);
+
+        let relative = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .mount_file("input.bin", Vec::new())
+            .build();
+        let result = PythonSandbox::new_with_options(relative, SandboxOptions::no_cache());
+        assert!(matches!(result, Err(SandboxError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_time_to_first_output() {
+        let path = wat_interpreter(
+            "first-output",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "done\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+
+        let first = result.metadata.time_to_first_output.unwrap();
+        assert!(first <= result.metadata.duration);
+
+        let silent = wat_interpreter(
+            "first-output-silent",
+            r#"(module (memory (export "memory") 1) (func (export "_start")))"#,
+        );
+        let config = SandboxConfig::builder().interpreter_path(&silent).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+
+        assert_eq!(result.metadata.time_to_first_output, None);
+    }
+
+    #[tokio::test]
+    async fn test_phase_timings() {
+        // The start function runs during instantiation, `_start` after it
+        let path = wat_interpreter(
+            "phase-timings",
+            r#"(module
+                (memory (export "memory") 1)
+                (func $spin (local $i i32)
+                    (loop $again
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $again (i32.lt_u (local.get $i) (i32.const 2000000)))))
+                (start $spin)
+                (func (export "_start")
+                    (call $spin)
+                    (call $spin)))"#,
+        );
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+
+        let metadata = &result.metadata;
+        assert!(metadata.instantiation_time > Duration::ZERO);
+        assert!(metadata.execution_time > Duration::ZERO);
+        assert!(metadata.instantiation_time + metadata.execution_time <= metadata.duration);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_normalize_stdin_newlines() {
+        // Copies up to 64 bytes of stdin to stdout
+        let path = wat_interpreter(
+            "normalize-stdin",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_read"
+                    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 64))
+                    (i32.store (i32.const 4) (i32.const 64))
+                    (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (i32.store (i32.const 4) (i32.load (i32.const 8)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let raw = SandboxConfig::builder().interpreter_path(&path).build();
+        let normalized = SandboxConfig {
+            normalize_stdin_newlines: true,
+            ..raw.clone()
+        };
+
+        let sandbox = PythonSandbox::new_with_options(raw, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", Some("a\r\nb\r\n")).await.unwrap();
+        assert_eq!(result.stdout, "a\r\nb\r\n");
+
+        let sandbox =
+            PythonSandbox::new_with_options(normalized, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", Some("a\r\nb\r\n")).await.unwrap();
+        assert_eq!(result.stdout, "a\nb\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_streaming() {
+        use crate::sandbox::events::OutputChunk;
+
+        // Writes "partial" to stdout, "warn\n" to stderr, then spins until
+        // interrupted
+        let path = wat_interpreter(
+            "execute-streaming",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 32) "partial")
+                (data (i32.const 48) "warn\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 32))
+                    (i32.store (i32.const 4) (i32.const 7))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (i32.store (i32.const 0) (i32.const 48))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (loop $spin (br $spin))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .timeout(Duration::from_secs(1))
+            .flush_on_trap(true)
+            .build();
+        let sandbox =
+            Arc::new(PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap());
+
+        let (mut output, handle) = sandbox.execute_streaming("", None).unwrap();
+
+        // Output arrives while the guest is still running
+        assert_eq!(
+            output.next().await,
+            Some(OutputChunk::Stdout(b"partial".to_vec()))
+        );
+        assert!(!handle.is_finished());
+        assert_eq!(
+            output.next().await,
+            Some(OutputChunk::Stderr(b"warn\n".to_vec()))
+        );
+        assert_eq!(output.next().await, None);
+        let err = handle.await.unwrap().unwrap_err();
+        assert!(err.is_timeout());
+        assert_eq!(err.partial_output().unwrap().stdout, "partial");
+
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .no_stdio(true)
+            .build();
+        let sandbox =
+            Arc::new(PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap());
+        assert!(matches!(
+            sandbox.execute_streaming("", None),
+            Err(SandboxError::Config(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_streaming_hides_reports() {
+        use crate::sandbox::events::OutputChunk;
+
+        // Writes a report line with the marker taken from the environment,
+        // as the instrumentation would, followed by "warn\n"
+        let path = wat_interpreter(
+            "streaming-reports",
+            r#"(module
+                (import "wasi_snapshot_preview1" "environ_sizes_get"
+                    (func $environ_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "environ_get"
+                    (func $environ_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 32) "warn\n")
+                (data (i32.const 48) " exceptions 3\n")
+                (func (export "_start")
+                    (local $len i32)
+                    (drop (call $environ_sizes_get (i32.const 16) (i32.const 20)))
+                    (drop (call $environ_get (i32.const 1024) (i32.const 4096)))
+                    ;; Skip "__SANDBOX_REPORT_MARKER=" and the trailing NUL
+                    (local.set $len (i32.sub (i32.load (i32.const 20)) (i32.const 25)))
+                    (memory.copy (i32.const 8192) (i32.const 4120) (local.get $len))
+                    (memory.copy
+                        (i32.add (i32.const 8192) (local.get $len)) (i32.const 48) (i32.const 14))
+                    (i32.store (i32.const 0) (i32.const 8192))
+                    (i32.store (i32.const 4) (i32.add (local.get $len) (i32.const 14)))
+                    (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (i32.store (i32.const 0) (i32.const 32))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .count_exceptions(true)
+            .build();
+        let sandbox =
+            Arc::new(PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap());
+
+        let (mut output, handle) = sandbox.execute_streaming("", None).unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = output.next().await {
+            chunks.push(chunk);
+        }
+        let result = handle.await.unwrap().unwrap();
+
+        assert_eq!(chunks, [OutputChunk::Stderr(b"warn\n".to_vec())]);
+        assert_eq!(result.stderr, "warn\n");
+        assert_eq!(result.metadata.exceptions_raised, Some(3));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Write an interpreter stub that prints each of `reports` to stderr as
+    /// an instrumentation report, with the marker taken from the
+    /// environment. The marker must be the only environment variable.
+    fn reporting_interpreter(name: &str, reports: &[&str]) -> std::path::PathBuf {
+        let mut data = String::new();
+        let mut writes = String::new();
+        let mut offset = 32768;
+        for report in reports {
+            let line = format!(" {report}\n");
+            let bytes: String = line.bytes().map(|byte| format!("\\{byte:02x}")).collect();
+            data.push_str(&format!("(data (i32.const {offset}) \"{bytes}\")\n"));
+            writes.push_str(&format!(
+                "(memory.copy (i32.add (i32.const 8192) (local.get $len)) (i32.const {offset}) (i32.const {len}))
+                (i32.store (i32.const 4) (i32.add (local.get $marker) (i32.const {len})))
+                (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))\n",
+                len = line.len()
+            ));
+            offset += line.len();
+        }
+        wat_interpreter(
+            name,
+            &format!(
+                r#"(module
+                (import "wasi_snapshot_preview1" "environ_sizes_get"
+                    (func $environ_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "environ_get"
+                    (func $environ_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                {data}
+                (func (export "_start")
+                    (local $len i32)
+                    (local $marker i32)
+                    (drop (call $environ_sizes_get (i32.const 16) (i32.const 20)))
+                    (drop (call $environ_get (i32.const 1024) (i32.const 4096)))
+                    ;; Skip "__SANDBOX_REPORT_MARKER=" and the trailing NUL
+                    (local.set $len (i32.sub (i32.load (i32.const 20)) (i32.const 25)))
+                    (local.set $marker (local.get $len))
+                    (memory.copy (i32.const 8192) (i32.const 4120) (local.get $len))
+                    (i32.store (i32.const 0) (i32.const 8192))
+                    {writes}))"#
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_python_version_reads_report() {
+        let path = reporting_interpreter("version", &[r#"version "3.12.0 (stub)""#]);
+        let sandbox = PythonSandbox::new_with_options(
+            SandboxConfig::builder().interpreter_path(&path).build(),
+            SandboxOptions::no_cache(),
+        )
+        .unwrap();
+        assert_eq!(sandbox.python_version().await.unwrap(), "3.12.0 (stub)");
+        std::fs::remove_file(&path).unwrap();
+
+        let path = reporting_interpreter("no-version", &[]);
+        let sandbox = PythonSandbox::new_with_options(
+            SandboxConfig::builder().interpreter_path(&path).build(),
+            SandboxOptions::no_cache(),
+        )
+        .unwrap();
+        let error = sandbox.python_version().await.unwrap_err();
+        assert!(matches!(error, SandboxError::ExecutionFailed(_)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disable_gc_reads_report() {
+        for (enabled, expected) in [("1", true), ("0", false)] {
+            let path = reporting_interpreter(
+                &format!("gc-{enabled}"),
+                &[&format!("{GC_ENABLED_KEY} {enabled}")],
+            );
+            let config = SandboxConfig::builder()
+                .interpreter_path(&path)
+                .disable_gc(true)
+                .build();
+            let sandbox =
+                PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+            let result = sandbox.execute("", None).await.unwrap();
+
+            assert_eq!(result.metadata.gc_enabled, Some(expected));
+            assert_eq!(result.stderr, "");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_separate_exceptions_reads_report() {
+        let path = reporting_interpreter(
+            "traceback",
+            &[r#"traceback "Traceback (most recent call last):\nValueError: bad\n""#],
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .separate_exceptions(true)
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+
+        let exception = result.exception.unwrap();
+        assert_eq!(exception.exception_type, "ValueError");
+        assert_eq!(exception.message, "bad");
+        assert_eq!(result.user_stderr, "");
+        assert_eq!(
+            result.stderr,
+            "Traceback (most recent call last):\nValueError: bad\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_preimport_reads_report() {
+        let path = reporting_interpreter(
+            "preimport",
+            &[r#"preimport_failed ["numpy", "No module named 'numpy'"]"#],
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .preimport(&["numpy"])
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let error = sandbox.execute("", None).await.unwrap_err();
+
+        match error {
+            SandboxError::Config(message) => {
+                assert!(message.contains("numpy is not available: No module named"));
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_streaming_python() {
+        use crate::sandbox::events::OutputChunk;
+
+        let sandbox = Arc::new(PythonSandbox::new(SandboxConfig::default()).unwrap());
+        let code =
+            "import sys\nfor i in range(3):\n    print(i, flush=True)\nsys.stdout.write('done')";
+
+        let (mut output, handle) = sandbox.execute_streaming(code, None).unwrap();
+        let mut streamed = Vec::new();
+        while let Some(chunk) = output.next().await {
+            if let OutputChunk::Stdout(bytes) = chunk {
+                streamed.extend(bytes);
+            }
+        }
+        let result = handle.await.unwrap().unwrap();
+
+        assert_eq!(streamed, b"0\n1\n2\ndone");
+        assert_eq!(result.stdout, "0\n1\n2\ndone");
+    }
+
+    #[tokio::test]
+    async fn test_run_terminations() {
+        let sandbox = |name: &str,
+                       wat: &str,
+                       configure: fn(
+            crate::SandboxConfigBuilder,
+        ) -> crate::SandboxConfigBuilder| {
+            let path = wat_interpreter(name, wat);
+            let config = configure(SandboxConfig::builder().interpreter_path(&path)).build();
+            let sandbox =
+                PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+            (sandbox, path)
+        };
+        let mut paths = Vec::new();
+
+        let (exits, path) = sandbox(
+            "termination-exit",
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start") (call $proc_exit (i32.const 3))))"#,
+            |b| b,
+        );
+        paths.push(path);
+        let termination = exits.run("", None).await.unwrap();
+        assert!(
+            matches!(termination, Termination::Completed { exit_code: 3, ref result } if result.exit_code == 3)
+        );
+
+        let spin = r#"(module
+            (memory (export "memory") 1)
+            (func (export "_start") (loop $spin (br $spin))))"#;
+        let (spins, path) = sandbox("termination-timeout", spin, |b| {
+            b.timeout(Duration::from_millis(50))
+        });
+        paths.push(path);
+        assert!(matches!(
+            spins.run("", None).await.unwrap(),
+            Termination::Timeout {
+                phase: TimeoutPhase::Execution,
+                ..
+            }
+        ));
+
+        let (grows, path) = sandbox(
+            "termination-memory",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (if (i32.lt_s (memory.grow (i32.const 100)) (i32.const 0))
+                        (then unreachable))))"#,
+            |b| b.max_memory(2 * 64 * 1024),
+        );
+        paths.push(path);
+        assert!(matches!(
+            grows.run("", None).await.unwrap(),
+            Termination::MemoryLimit { .. }
+        ));
+
+        let (burns, path) = sandbox("termination-fuel", spin, |b| b.max_fuel(10_000));
+        paths.push(path);
+        assert!(matches!(
+            burns.run("", None).await.unwrap(),
+            Termination::OutOfFuel { .. }
+        ));
+
+        let (recurses, path) = sandbox(
+            "termination-stack",
+            r#"(module
+                (memory (export "memory") 1)
+                (func $recurse (call $recurse))
+                (func (export "_start") (call $recurse)))"#,
+            |b| b,
+        );
+        paths.push(path);
+        assert!(matches!(
+            recurses.run("", None).await.unwrap(),
+            Termination::StackOverflow
+        ));
+
+        let (floods, path) = sandbox(
+            "termination-output",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "spam\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (loop $print
+                        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (br $print))))"#,
+            |b| b.max_output_lines(2).terminate_on_output_lines(true),
+        );
+        paths.push(path);
+        assert!(matches!(
+            floods.run("", None).await.unwrap(),
+            Termination::OutputLimit { ref output } if output.stdout == "spam\nspam\n"
+        ));
+
+        // A trap is only an interpreter trap when a coredump was written
+        let trap = r#"(module (memory (export "memory") 1) (func (export "_start") unreachable))"#;
+        let (traps, path) = sandbox("termination-trap", trap, |b| b);
+        paths.push(path.clone());
+        assert!(matches!(
+            traps.run("", None).await.unwrap(),
+            Termination::Failed(SandboxError::ExecutionFailed(_))
+        ));
+        let dir = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-termination-dumps-{}",
+            std::process::id()
+        ));
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let options = SandboxOptions::with_engine(SharedEngine::with_coredump(&dir).unwrap())
+            .use_cache(false);
+        let dumps = PythonSandbox::new_with_options(config, options).unwrap();
+        assert!(matches!(
+            dumps.run("", None).await.unwrap(),
+            Termination::InterpreterTrap { ref coredump, .. } if coredump.starts_with(&dir)
+        ));
+
+        // Failing to start is still an error
+        let (expired, path) = sandbox("termination-expired", trap, |b| b.max_age(Duration::ZERO));
+        paths.push(path);
+        assert!(matches!(
+            expired.run("", None).await,
+            Err(SandboxError::Expired { .. })
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        for path in paths {
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_output_bytes() {
+        // Writes "hello world\n" to stdout and "oops\n" to stderr, then
+        // exits with the errno of the second write
+        let path = wat_interpreter(
+            "max-output-bytes",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 32) "hello world\n")
+                (data (i32.const 48) "oops\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 32))
+                    (i32.store (i32.const 4) (i32.const 12))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (i32.store (i32.const 0) (i32.const 48))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (call $proc_exit
+                        (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let sandbox = |limit| {
+            let mut builder = SandboxConfig::builder().interpreter_path(&path);
+            if let Some(limit) = limit {
+                builder = builder.max_output_bytes(limit);
+            }
+            PythonSandbox::new_with_options(builder.build(), SandboxOptions::no_cache()).unwrap()
+        };
+
+        let result = sandbox(Some(8)).execute("", None).await.unwrap();
+        assert_eq!(result.stdout, "hello wo");
+        assert_eq!(result.stderr, "");
+        assert_eq!(result.exit_code, 0);
+        assert!(result.output_truncated);
+
+        let result = sandbox(Some(14)).execute("", None).await.unwrap();
+        assert_eq!(result.stdout, "hello world\n");
+        assert_eq!(result.stderr, "oo");
+
+        let result = sandbox(None).execute("", None).await.unwrap();
+        assert_eq!(result.stderr, "oops\n");
+        assert!(!result.output_truncated);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_max_output_bytes_python() {
+        let config = SandboxConfig::builder().max_output_bytes(1000).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let result = sandbox
+            .execute("for i in range(10000):\n    print('x')\nprint('end')", None)
+            .await
+            .unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.len(), 1000);
+        assert!(result.output_truncated);
+    }
+
+    #[tokio::test]
+    async fn test_max_output_rate() {
+        // Writes "spam\n" in a tight loop
+        let flood = wat_interpreter(
+            "output-rate-flood",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "spam\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (loop $print
+                        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (br $print))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&flood)
+            .timeout(Duration::from_secs(30))
+            .max_output_lines(10)
+            .max_output_rate(10_000)
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let start = Instant::now();
+        let err = sandbox.execute("", None).await.unwrap_err();
+
+        assert!(err.is_output_rate_limit());
+        assert!(
+            matches!(err, SandboxError::OutputRateExceeded { rate, limit: 10_000 } if rate > 10_000)
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // A single short burst stays within the limit
+        let burst = wat_interpreter(
+            "output-rate-burst",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "spam\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&burst)
+            .max_output_rate(1)
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+        assert_eq!(result.stdout, "spam\n");
+
+        std::fs::remove_file(&flood).unwrap();
+        std::fs::remove_file(&burst).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_max_output_rate_python() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(30))
+            .max_output_rate(100_000)
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let err = sandbox
+            .execute("while True:\n    print('spam' * 100)", None)
+            .await
+            .unwrap_err();
+
+        assert!(err.is_output_rate_limit());
+    }
+
+    #[tokio::test]
+    async fn test_max_output_lines() {
+        // Writes "x\n" 1000 times, then "done\n" to stderr
+        let path = wat_interpreter(
+            "output-lines",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "x\n")
+                (data (i32.const 32) "done\n")
+                (func (export "_start")
+                    (local $n i32)
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 2))
+                    (local.set $n (i32.const 1000))
+                    (loop $write
+                        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br_if $write (local.get $n)))
+                    (i32.store (i32.const 0) (i32.const 32))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .max_output_lines(10)
+            .build();
+
+        let sandbox =
+            PythonSandbox::new_with_options(config.clone(), SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+        assert_eq!(result.stdout, "x\n".repeat(10));
+        assert_eq!(result.stderr, "done\n");
+        assert!(result.output_line_limited);
+
+        let config = SandboxConfig {
+            terminate_on_output_lines: true,
+            ..config
+        };
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let result = sandbox.execute("", None).await.unwrap();
+        assert_eq!(result.stdout, "x\n".repeat(10));
+        assert_eq!(result.stderr, "");
+        assert!(result.output_line_limited);
+        assert!(result.is_success());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_clock_and_random() {
+        // Exits with the first random byte modulo 100, or 125 if the wall
+        // clock is not at the fixed time
+        let fixed_nanos = deterministic::FIXED_TIME.as_nanos();
+        let path = wat_interpreter(
+            "deterministic",
+            &format!(
+                r#"(module
+                (import "wasi_snapshot_preview1" "clock_time_get"
+                    (func $clock_time_get (param i32 i64 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "random_get"
+                    (func $random_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 0)))
+                    (drop (call $random_get (i32.const 8) (i32.const 1)))
+                    (call $proc_exit
+                        (select
+                            (i32.rem_u (i32.load8_u (i32.const 8)) (i32.const 100))
+                            (i32.const 125)
+                            (i64.eq (i64.load (i32.const 0)) (i64.const {fixed_nanos}))))))"#
+            ),
+        );
+        let run = |seed| {
+            let config = SandboxConfig::builder()
+                .interpreter_path(&path)
+                .deterministic(seed)
+                .build();
+            async {
+                let sandbox =
+                    PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+                sandbox.execute("", None).await.unwrap().exit_code
+            }
+        };
+
+        let first = run(1).await;
+        assert_ne!(first, 125, "clock not fixed");
+        assert_eq!(run(1).await, first);
+        assert_ne!(run(2).await, first);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_collections_sets_hash_seed() {
+        // Prints the environment block, variables separated by NUL bytes
+        let path = wat_interpreter(
+            "hash-seed",
+            r#"(module
+                (import "wasi_snapshot_preview1" "environ_sizes_get"
+                    (func $environ_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "environ_get"
+                    (func $environ_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (call $environ_sizes_get (i32.const 16) (i32.const 20)))
+                    (drop (call $environ_get (i32.const 1024) (i32.const 4096)))
+                    (i32.store (i32.const 0) (i32.const 4096))
+                    (i32.store (i32.const 4) (i32.load (i32.const 20)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let run = |config: SandboxConfig| async {
+            let sandbox =
+                PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+            sandbox.execute("", None).await.unwrap().stdout
+        };
+        let builder = SandboxConfig::builder().interpreter_path(&path);
+
+        let env = run(builder.clone().build()).await;
+        assert!(!env.contains("PYTHONHASHSEED"));
+
+        let env = run(builder.clone().deterministic_collections(true).build()).await;
+        assert!(env.contains("PYTHONHASHSEED=0\0"));
+
+        let env = run(builder.clone().deterministic(42).build()).await;
+        assert!(env.contains("PYTHONHASHSEED=42\0"));
+
+        let env = run(builder
+            .clone()
+            .env("PYTHONHASHSEED", "7")
+            .deterministic_collections(true)
+            .build())
+        .await;
+        assert!(env.contains("PYTHONHASHSEED=7\0"));
+        assert!(!env.contains("PYTHONHASHSEED=0"));
+
+        // Strict mode passes the configured variables and nothing else,
+        // apart from the report marker the support code removes, so the
+        // hash seed must be configured too
+        let strict = builder
+            .env("ALLOWED", "1")
+            .deterministic(42)
+            .strict_env(true);
+        assert!(matches!(
+            PythonSandbox::new_with_options(strict.clone().build(), SandboxOptions::no_cache()),
+            Err(SandboxError::Config(_))
+        ));
+        let env = run(strict.env("PYTHONHASHSEED", "42").build()).await;
+        let vars: Vec<&str> = env
+            .split_terminator('\0')
+            .filter(|var| !var.starts_with(REPORT_MARKER_VAR))
+            .collect();
+        assert_eq!(vars, ["ALLOWED=1", "PYTHONHASHSEED=42"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_age() {
+        let path = wat_interpreter(
+            "max-age",
+            r#"(module (memory (export "memory") 1) (func (export "_start")))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .max_age(Duration::from_millis(100))
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        assert!(sandbox.execute("", None).await.is_ok());
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let err = sandbox.execute("", None).await.unwrap_err();
+        assert!(err.is_expired());
+        assert!(matches!(
+            err,
+            SandboxError::Expired { age, max_age }
+                if age >= Duration::from_millis(150) && max_age == Duration::from_millis(100)
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_execution_span_attributes() {
+        use std::collections::HashMap;
+        use std::sync::atomic::AtomicU64;
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Collects the fields of every span, by field name.
+        #[derive(Default)]
+        struct Fields(Arc<Mutex<HashMap<String, String>>>, AtomicU64);
+
+        impl Visit for &Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                let mut fields = self.0.lock().unwrap();
+                fields.insert(field.name().to_string(), format!("{:?}", value));
+            }
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                let mut fields = self.0.lock().unwrap();
+                fields.insert(field.name().to_string(), value.to_string());
+            }
+        }
+
+        impl Subscriber for Fields {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                span.record(&mut &*self);
+                Id::from_u64(self.1.fetch_add(1, Ordering::Relaxed) + 1)
+            }
+            fn record(&self, _: &Id, values: &Record<'_>) {
+                values.record(&mut &*self);
+            }
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let path = wat_interpreter(
+            "span-attributes",
+            r#"(module (memory (export "memory") 1) (func (export "_start")))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .timeout(Duration::from_secs(3))
+            .max_memory(32 * 1024 * 1024)
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let subscriber = Fields::default();
+        let fields = Arc::clone(&subscriber.0);
+
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let result = sandbox.execute("", None).await.unwrap();
+
+        let fields = fields.lock().unwrap();
+        assert_eq!(fields["code.language"], "python");
+        assert_eq!(fields["sandbox.timeout_ms"], "3000");
+        assert_eq!(fields["sandbox.max_memory"], (32 * 1024 * 1024).to_string());
+        assert_eq!(
+            fields["sandbox.peak_memory"],
+            result.metadata.peak_memory.to_string()
+        );
+        assert_eq!(fields["sandbox.outcome"], "success");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_allowed_exit_codes() {
+        let path = wat_interpreter(
+            "allowed-exit-codes",
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (call $proc_exit (i32.const 2))))"#,
+        );
+        let builder = SandboxConfig::builder().interpreter_path(&path);
+        let run = |config: SandboxConfig| async {
+            let sandbox =
+                PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+            sandbox.execute("", None).await
+        };
+
+        assert_eq!(run(builder.clone().build()).await.unwrap().exit_code, 2);
+        let allowed = run(builder.clone().allowed_exit_codes(&[0, 2]).build()).await;
+        assert_eq!(allowed.unwrap().exit_code, 2);
+        let err = run(builder.allowed_exit_codes(&[0]).build())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, SandboxError::ExecutionFailed(ref msg) if msg.contains("exit code 2"))
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_custom_interpreter() {
+        use crate::sandbox::interpreter::{Interpreter, Script};
+
+        /// Runs the code as the only argument, through a `run` export.
+        #[derive(Debug)]
+        struct Echo;
+
+        impl Interpreter for Echo {
+            fn args(&self, script: &str) -> Vec<String> {
+                vec!["echo".to_string(), script.to_string()]
+            }
+            fn entry_point(&self) -> &str {
+                "run"
+            }
+            fn assemble(&self, script: Script<'_>) -> String {
+                format!("{}|{}", script.prelude.unwrap_or_default(), script.code)
+            }
+        }
+
+        // Prints its second argument
+        let path = wat_interpreter(
+            "custom-interpreter",
+            r#"(module
+                (import "wasi_snapshot_preview1" "args_sizes_get"
+                    (func $args_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "args_get"
+                    (func $args_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+                    (drop (call $args_get (i32.const 64) (i32.const 1024)))
+                    (i32.store (i32.const 16) (i32.load (i32.const 68)))
+                    (i32.store (i32.const 20)
+                        (i32.sub
+                            (i32.sub (i32.add (i32.const 1024) (i32.load (i32.const 4)))
+                                (i32.load (i32.const 68)))
+                            (i32.const 1)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .interpreter(Echo)
+            .prelude("setup")
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let result = sandbox.execute("hello", None).await.unwrap();
+
+        assert_eq!(result.stdout, "setup|hello");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "regex")]
+    #[tokio::test]
+    async fn test_redact_output_patterns() {
+        let path = wat_interpreter(
+            "redact-output",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "token=sk-abc123XYZ, user=alice\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 31))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .redact_output_patterns(vec![regex::Regex::new(r"sk-[A-Za-z0-9]+").unwrap()])
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let result = sandbox.execute("", None).await.unwrap();
+
+        assert_eq!(result.stdout, "token=[REDACTED], user=alice\n");
+        assert_eq!(result.stderr, "token=[REDACTED], user=alice\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_duration_stats() {
+        let ms = Duration::from_millis;
+
+        let stats = DurationStats::from_samples(vec![ms(30), ms(10), ms(20)]);
+        assert_eq!(stats.min, ms(10));
+        assert_eq!(stats.mean, ms(20));
+        assert_eq!(stats.p99, ms(30));
+        assert_eq!(stats.max, ms(30));
+        assert_eq!(stats.samples, 3);
+
+        // With 200 samples, the two slowest lie above the 99th percentile
+        let samples = (1..=200).map(ms).collect();
+        let stats = DurationStats::from_samples(samples);
+        assert_eq!(stats.p99, ms(198));
+        assert_eq!(stats.max, ms(200));
+    }
+
+    #[tokio::test]
+    async fn test_probe_duration() {
+        let spin_briefly = wat_interpreter(
+            "probe-duration",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (local $n i32)
+                    (local.set $n (i32.const 1000000))
+                    (loop $spin
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br_if $spin (local.get $n)))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&spin_briefly)
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let stats = sandbox.probe_duration("", None, 5).await.unwrap();
+        assert_eq!(stats.samples, 5);
+        assert!(stats.min > Duration::ZERO);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.p99);
+        assert!(stats.p99 <= stats.max);
+
+        let err = sandbox.probe_duration("", None, 0).await.unwrap_err();
+        assert!(matches!(err, SandboxError::Config(_)));
+
+        // The per-run cap is ten times the configured timeout
+        let spin_forever = wat_interpreter(
+            "probe-duration-forever",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start") (loop $spin (br $spin))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&spin_forever)
+            .timeout(Duration::from_millis(20))
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let start = Instant::now();
+        let err = sandbox.probe_duration("", None, 3).await.unwrap_err();
+        assert!(err.is_timeout());
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        std::fs::remove_file(&spin_briefly).unwrap();
+        std::fs::remove_file(&spin_forever).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_labeled() {
+        let path = wat_interpreter(
+            "labeled",
+            r#"(module (memory (export "memory") 1) (func (export "_start")))"#,
+        );
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let result = sandbox
+            .execute_labeled("", None, "request-42".to_string())
+            .await
+            .unwrap();
+        assert_eq!(result.metadata.label.as_deref(), Some("request-42"));
+
+        let result = sandbox.execute("", None).await.unwrap();
+        assert_eq!(result.metadata.label, None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_labeled_error() {
+        let path = wat_interpreter(
+            "labeled-error",
+            r#"(module (memory (export "memory") 1) (func (export "_start") unreachable))"#,
+        );
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let err = sandbox
+            .execute_labeled("", None, "request-42".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(err.label(), Some("request-42"));
+        assert!(err.to_string().starts_with("request-42: "));
+        assert!(!matches!(err.cause(), SandboxError::Labeled { .. }));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unhealthy_shared_engine_is_recreated() {
+        let path = stub_interpreter("recreate-engine");
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let engine = SharedEngine::new()
+            .unwrap()
+            .failure_threshold(1, Duration::from_secs(60));
+        let cache = Arc::new(ModuleCache::new());
+        let options = SandboxOptions::with_engine(engine.clone()).cache(cache);
+
+        let before = PythonSandbox::new_with_options(config.clone(), options.clone()).unwrap();
+        engine.record_failure();
+        let after = PythonSandbox::new_with_options(config, options).unwrap();
+
+        assert!(engine.health().is_healthy());
+        assert_eq!(engine.health().recreations, 1);
+        assert!(!Engine::same(before.engine(), after.engine()));
+        // The module cached for the old engine is compiled again
+        assert_eq!(after.cache_status(), CacheStatus::Compiled);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_timeout_does_not_disturb_shared_engine() {
+        let spin_forever = wat_interpreter(
+            "timeout-shared-forever",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start") (loop $spin (br $spin))))"#,
+        );
+        let noop = wat_interpreter(
+            "timeout-shared-noop",
+            r#"(module (memory (export "memory") 1) (func (export "_start")))"#,
+        );
+        let engine = SharedEngine::new().unwrap();
+        let sandbox = |path: &std::path::Path, timeout: Duration| {
+            let config = SandboxConfig::builder()
+                .interpreter_path(path)
+                .timeout(timeout)
+                .build();
+            let options = SandboxOptions::with_engine(engine.clone()).use_cache(false);
+            Arc::new(PythonSandbox::new_with_options(config, options).unwrap())
+        };
+        let timing_out = sandbox(&spin_forever, Duration::from_millis(50));
+        let quick = sandbox(&noop, Duration::from_secs(10));
+
+        let spinner = tokio::spawn(async move { timing_out.execute("", None).await });
+        let mut quick_runs = Vec::new();
+        for _ in 0..20 {
+            let quick = Arc::clone(&quick);
+            quick_runs.push(tokio::spawn(async move { quick.execute("", None).await }));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert!(spinner.await.unwrap().unwrap_err().is_timeout());
+        for run in quick_runs {
+            assert!(run.await.unwrap().unwrap().is_success());
+        }
+
+        std::fs::remove_file(&spin_forever).unwrap();
+        std::fs::remove_file(&noop).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_timeouts_share_ticker() {
+        let spin_forever = wat_interpreter(
+            "spin-forever",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start") (loop $spin (br $spin))))"#,
+        );
+        let spin_briefly = wat_interpreter(
+            "spin-briefly",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (local $n i32)
+                    (local.set $n (i32.const 50000000))
+                    (loop $spin
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br_if $spin (local.get $n)))))"#,
+        );
+        let engine = SharedEngine::new().unwrap();
+        let sandbox = |path: &std::path::Path, timeout: Duration| {
+            let config = SandboxConfig::builder()
+                .interpreter_path(path)
+                .timeout(timeout)
+                .build();
+            let options = SandboxOptions::with_engine(engine.clone()).use_cache(false);
+            Arc::new(PythonSandbox::new_with_options(config, options).unwrap())
+        };
+        let short = sandbox(&spin_forever, Duration::from_millis(100));
+        let long = sandbox(&spin_forever, Duration::from_millis(400));
+        let finite = sandbox(&spin_briefly, Duration::from_secs(10));
+
+        let run = |sandbox: Arc<PythonSandbox>| {
+            tokio::spawn(async move {
+                let start = Instant::now();
+                let result = sandbox.execute("", None).await;
+                (result, start.elapsed())
+            })
+        };
+        let mut handles = Vec::new();
+        for _ in 0..8 {
+            handles.push((Duration::from_millis(100), run(Arc::clone(&short))));
+            handles.push((Duration::from_millis(400), run(Arc::clone(&long))));
+        }
+        let finite = run(finite);
+
+        for (timeout, handle) in handles {
+            let (result, elapsed) = handle.await.unwrap();
+            assert!(matches!(result, Err(SandboxError::Timeout { .. })));
+            assert!(elapsed >= timeout, "{elapsed:?} < {timeout:?}");
+            assert!(
+                elapsed < timeout + Duration::from_millis(300),
+                "{elapsed:?}"
+            );
+        }
+        // Interrupting the others never interrupts an execution on the same engine
+        let (result, _) = finite.await.unwrap();
+        assert!(result.unwrap().is_success());
+        // Interrupted guests release the ticker once they have unwound
+        let idle = Instant::now();
+        while engine.ticker().is_running() {
+            assert!(
+                idle.elapsed() < Duration::from_secs(1),
+                "ticker still running"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[test]
+    fn test_cache_status() {
+        let path = stub_interpreter("cache-status");
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let cache = Arc::new(ModuleCache::new());
+
+        let disabled =
+            PythonSandbox::new_with_options(config.clone(), SandboxOptions::no_cache()).unwrap();
+        let cold = PythonSandbox::new_with_options(
+            config.clone(),
+            SandboxOptions::with_cache(Arc::clone(&cache)),
+        )
+        .unwrap();
+        let warm =
+            PythonSandbox::new_with_options(config, SandboxOptions::with_cache(cache)).unwrap();
+
+        assert_eq!(disabled.cache_status(), CacheStatus::Disabled);
+        assert_eq!(cold.cache_status(), CacheStatus::Compiled);
+        assert_eq!(warm.cache_status(), CacheStatus::Hit);
+        assert!(warm.is_using_cached_module());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Note: These tests require rustpython.wasm to be present
+    // They are marked as ignored by default
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_simple_execution() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(32 * 1024 * 1024)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox.execute("print(1 + 1)", None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.trim(), "2");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_stdout_has_no_banner() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+        let result = sandbox.execute("print('hello')", None).await.unwrap();
+
+        assert_eq!(result.stdout, "hello\n");
+        assert_eq!(result.metadata.banner, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_time_to_first_output_after_sleep() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+        let result = sandbox
+            .execute("import time\ntime.sleep(0.2)\nprint('late')", None)
+            .await
+            .unwrap();
+
+        let first = result.metadata.time_to_first_output.unwrap();
+        assert!(first >= Duration::from_millis(200));
+
+        let silent = sandbox.execute("x = 1", None).await.unwrap();
+        assert_eq!(silent.metadata.time_to_first_output, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_mount_file() {
+        let config = SandboxConfig::builder()
+            .mount_file("/data/input.bin", vec![b'x'; 1024 * 1024])
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "with open('/data/input.bin', 'rb') as f:\n    print(len(f.read()))";
+
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert_eq!(result.stdout.trim(), "1048576");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_virtual_file() {
+        let config = SandboxConfig::builder()
+            .virtual_file("data.csv", b"a,b\n1,2\n".to_vec())
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "with open('data.csv') as f:\n    print(f.read().splitlines()[1])\n\
+                    with open('/sandbox/data.csv') as f:\n    print(len(f.read()))\n\
+                    try:\n    open('data.csv', 'w')\nexcept OSError:\n    print('read-only')";
+
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert_eq!(result.stdout, "1,2\n8\nread-only\n");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_collect_output_files_python() {
+        let config = SandboxConfig::builder()
+            .collect_output_files(true)
+            .max_output_bytes(1024)
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code =
+            "import json\nwith open('/out/out.json', 'w') as f:\n    json.dump({'ok': True}, f)";
+
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert_eq!(
+            result.output_files[Path::new("out.json")],
+            br#"{"ok": true}"#
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_script_args_python() {
+        let config = SandboxConfig::builder()
+            .args(vec!["first".to_string(), "second".to_string()])
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "import sys; print(sys.argv[1:])";
+
+        let result = sandbox.execute(code, None).await.unwrap();
+        assert_eq!(result.stdout, "['first', 'second']\n");
+
+        let result = sandbox
+            .execute_with_args("import sys; print(sys.argv[1])", None, &["only"])
+            .await
+            .unwrap();
+        assert_eq!(result.stdout, "only\n");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_source_module_python() {
+        let config = SandboxConfig::builder()
+            .virtual_file(
+                "greet.py",
+                b"import sys\nif __name__ == '__main__':\n    print('hello', sys.argv[1])\n"
+                    .to_vec(),
+            )
+            .args(vec!["world".to_string()])
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let source = ExecutionSource::Module("greet".to_string());
+        let result = sandbox.execute_source(&source, None).await.unwrap();
+        assert_eq!(result.stdout, "hello world\n");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_allowed_exit_codes_python() {
+        let code = "import sys\nsys.exit(2)";
+
+        let config = SandboxConfig::builder().allowed_exit_codes(&[0, 2]).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        assert_eq!(sandbox.execute(code, None).await.unwrap().exit_code, 2);
+
+        let config = SandboxConfig::builder().allowed_exit_codes(&[0]).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let err = sandbox.execute(code, None).await.unwrap_err();
+        assert!(matches!(err, SandboxError::ExecutionFailed(_)));
+        assert!(sandbox.execute("print(1)", None).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_separate_exceptions() {
+        let code = "import sys\nprint('warning: low disk', file=sys.stderr)\nraise ValueError('bad input')";
+
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+        let merged = sandbox.execute(code, None).await.unwrap();
+        assert!(merged.stderr.contains("warning: low disk"));
+        assert!(merged.stderr.contains("ValueError: bad input"));
+        assert_eq!(merged.user_stderr, merged.stderr);
+        assert_eq!(merged.exception, None);
+
+        let config = SandboxConfig::builder().separate_exceptions(true).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let separated = sandbox.execute(code, None).await.unwrap();
+        assert_eq!(separated.user_stderr, "warning: low disk\n");
+        assert!(separated.stderr.starts_with("warning: low disk\nTraceback"));
+        assert!(separated.stderr.contains("ValueError: bad input"));
+        let exception = separated.exception.as_ref().unwrap();
+        assert_eq!(exception.exception_type, "ValueError");
+        assert_eq!(exception.message, "bad input");
+        assert!(matches!(
+            separated.python_exception(),
+            Some(SandboxError::PythonException { ref message, .. }) if message == "bad input"
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_disable_gc() {
+        // Without collections, the count of tracked allocations keeps growing
+        let code = "import gc\nfor _ in range(10000):\n    a = []\n    a.append(a)\nprint(gc.isenabled(), gc.get_count()[0] > 1000)";
+
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+        let enabled = sandbox.execute(code, None).await.unwrap();
+        assert_eq!(enabled.stdout, "True False\n");
+        assert_eq!(enabled.metadata.gc_enabled, None);
+
+        let config = SandboxConfig::builder().disable_gc(true).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let disabled = sandbox.execute(code, None).await.unwrap();
+        assert_eq!(disabled.stdout, "False True\n");
+        assert_eq!(disabled.metadata.gc_enabled, Some(false));
+
+        let reenabled = sandbox
+            .execute("import gc\ngc.enable()", None)
+            .await
+            .unwrap();
+        assert_eq!(reenabled.metadata.gc_enabled, Some(true));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_line_coverage() {
+        let config = SandboxConfig::builder()
+            .prelude("def helper():\n    return 1\n")
+            .line_coverage(true)
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "x = helper()\nif x > 5:\n    print('big')\nelse:\n    print('small')\ndef unused():\n    pass";
+
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert_eq!(result.stdout, "small\n");
+        assert_eq!(result.metadata.lines_covered, Some(vec![1, 2, 5, 6]));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_inputs() {
+        let inputs = InputSpec::new()
+            .stdin("3\n")
+            .file("/data/a.txt", b"1 2".to_vec())
+            .file("/data/b.txt", b"4 5".to_vec());
+        let config = SandboxConfig::builder().inputs(inputs).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "total = int(input())\nfor name in ['/data/a.txt', '/data/b.txt']:\n    total += sum(map(int, open(name).read().split()))\nprint(total)";
+
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert_eq!(result.stdout.trim(), "15");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_auto_print_last_expr() {
+        let config = SandboxConfig::builder().auto_print_last_expr(true).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let expr = sandbox.execute("1 + 1", None).await.unwrap();
+        assert_eq!(expr.stdout, "2\n");
+
+        let printed = sandbox.execute("print(1)", None).await.unwrap();
+        assert_eq!(printed.stdout, "1\n");
+
+        let assignment = sandbox.execute("x = 1 + 1", None).await.unwrap();
+        assert_eq!(assignment.stdout, "");
+
+        let earlier = sandbox.execute("1 + 1\nx = 3", None).await.unwrap();
+        assert_eq!(earlier.stdout, "");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_timeout() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_millis(100))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox.execute("while True: pass", None).await;
+
+        assert!(matches!(result, Err(SandboxError::Timeout { .. })));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execution_metadata() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(32 * 1024 * 1024)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox.execute("print('hello')", None).await.unwrap();
+
+        assert!(result.metadata.duration.as_nanos() > 0);
+        assert!(result.metadata.execution_time > result.metadata.instantiation_time);
+        assert!(result.metadata.peak_memory > 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_module_caching() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(32 * 1024 * 1024)
+            .build();
+
+        // First sandbox compiles the module
+        let sandbox1 = PythonSandbox::new(config.clone()).unwrap();
+        let was_cached_1 = sandbox1.is_using_cached_module();
+
+        // Second sandbox should use cached module
+        let sandbox2 = PythonSandbox::new(config).unwrap();
+        let was_cached_2 = sandbox2.is_using_cached_module();
+
+        // First should have compiled fresh (or found existing cache)
+        // Second should definitely find it cached
+        assert!(was_cached_2 || !was_cached_1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_stdin_input() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(32 * 1024 * 1024)
+            .stdin("hello world")
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox.execute("print(input())", None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.trim(), "hello world");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_env_vars() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(32 * 1024 * 1024)
+            .env("MY_VAR", "my_value")
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox
+            .execute(
+                "import os; print(os.environ.get('MY_VAR', 'not found'))",
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.trim(), "my_value");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_prelude() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(32 * 1024 * 1024)
+            .prelude("def greet(name): return f'Hello, {name}!'")
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox
+            .execute("print(greet('World'))", None)
+            .await
+            .unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.trim(), "Hello, World!");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_use_preludes() {
+        // The fragments are resolved when the sandbox is created
+        let sandbox = {
+            let _globals = GLOBALS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            global_preludes().register("test_math_helpers", "def double(x): return 2 * x");
+            global_preludes().register("test_safe_io", "def shout(s): return s.upper()");
+            let config = SandboxConfig::builder()
+                .use_preludes(&["test_math_helpers", "test_safe_io"])
+                .build();
+            PythonSandbox::new(config).unwrap()
+        };
+        let result = sandbox
+            .execute("print(double(21), shout('ok'))", None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.stdout.trim(), "42 OK");
+    }
+
+    #[tokio::test]
+    async fn test_use_preludes_missing_fragment() {
+        let path = stub_interpreter("missing-prelude");
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .use_preludes(&["never_registered"])
+            .build();
+
+        let result = PythonSandbox::new_with_options(config, SandboxOptions::no_cache());
+
+        assert!(matches!(result, Err(SandboxError::Config(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_deterministic_collections() {
+        let config = SandboxConfig::builder()
+            .deterministic_collections(true)
+            .build();
+        let code = "print({'apple', 'banana', 'cherry', 'durian', 'elderberry'})";
+
+        let mut outputs = Vec::new();
+        for _ in 0..3 {
+            // A new sandbox each time, so nothing is carried over
+            let sandbox = PythonSandbox::new(config.clone()).unwrap();
+            outputs.push(sandbox.execute(code, None).await.unwrap().stdout);
+        }
+
+        assert!(outputs.iter().all(|output| output == &outputs[0]));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_deterministic() {
+        let config = SandboxConfig::builder().deterministic(1234).build();
+        let code =
+            "import random, time, os\nprint(random.random(), time.time(), os.urandom(4).hex())";
+
+        let mut outputs = Vec::new();
+        for _ in 0..2 {
+            let sandbox = PythonSandbox::new(config.clone()).unwrap();
+            outputs.push(sandbox.execute(code, None).await.unwrap().stdout);
+        }
+
+        assert_eq!(outputs[0], outputs[1]);
+        assert!(outputs[0].contains(" 1577836800.0 "), "{}", outputs[0]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_exception_count() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .count_exceptions(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = r#"
+for i in range(5):
+    try:
+        raise ValueError(i)
+    except ValueError:
+        pass
+"#;
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.metadata.exceptions_raised, Some(5));
+        assert!(!result.stderr.contains("__sandbox_report__"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_exception_count_ignores_setup() {
+        // Importing `traceback` for the hook raises and catches internally
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_exceptions(0)
+            .separate_exceptions(true)
+            .preimport(&["json"])
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox.execute("x = 1", None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.metadata.exceptions_raised, Some(0));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_max_imports() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(10))
+            .max_imports(2)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "import math\nimport json\nimport math\ntry:\n    import random\nexcept ImportError as e:\n    print(e)";
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.trim(), "import limit of 2 reached");
+        assert_eq!(result.metadata.imports, Some(2));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_max_imports_ignores_sandbox_imports() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(10))
+            .max_imports(1)
+            .separate_exceptions(true)
+            .disable_gc(true)
+            .deterministic(7)
+            .line_coverage(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "import math\nx = math.floor(2.5)";
+        let (result, globals) = sandbox.execute_with_globals(code, None).await.unwrap();
+
+        // Only the user's import counts, and the at-exit captures still run
+        assert!(result.is_success());
+        assert_eq!(result.metadata.imports, Some(1));
+        assert_eq!(globals["x"], 2);
+        assert_eq!(result.metadata.lines_covered, Some(vec![1, 2]));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_exception_limit() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_exceptions(3)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = r#"
+while True:
+    try:
+        raise ValueError()
+    except ValueError:
+        pass
+"#;
+        let result = sandbox.execute(code, None).await;
+
+        assert!(matches!(
+            result,
+            Err(SandboxError::ExceptionLimitExceeded { limit: 3 })
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_engine_without_threads() {
+        let features = WasmFeatures {
+            threads: false,
+            ..WasmFeatures::default()
+        };
+        let engine = SharedEngine::with_features(features).unwrap();
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        let options = SandboxOptions::with_engine(engine).use_cache(false);
+        let sandbox = PythonSandbox::new_with_options(config, options).unwrap();
+        let result = sandbox.execute("print(1 + 1)", None).await.unwrap();
+
+        assert_eq!(result.stdout.trim(), "2");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_with_globals() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let (result, globals) = sandbox
+            .execute_with_globals("import math\nx = 42", None)
+            .await
+            .unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(globals["x"], 42);
+        assert_eq!(globals["math"], "module");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_capture_var() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+
+        let (result, value) = sandbox
+            .execute_capture_var("x = 6 * 7", "x", None)
+            .await
+            .unwrap();
+        assert!(result.is_success());
+        assert_eq!(value, Some(serde_json::json!(42)));
+
+        let (_, missing) = sandbox
+            .execute_capture_var("x = 1", "y", None)
+            .await
+            .unwrap();
+        assert_eq!(missing, None);
+
+        let (_, repr) = sandbox
+            .execute_capture_var("s = {1}", "s", None)
+            .await
+            .unwrap();
+        assert_eq!(repr, Some(serde_json::json!("{1}")));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_eval() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+
+        let eval = sandbox.execute_eval("sorted({3, 1, 2})").await.unwrap();
+        assert_eq!(eval.value, "[1, 2, 3]");
+        assert_eq!(eval.stdout, "");
+
+        // Output printed along the way stays out of the value
+        let eval = sandbox
+            .execute_eval("print('side effect') or 'done'")
+            .await
+            .unwrap();
+        assert_eq!(eval.value, "'done'");
+        assert_eq!(eval.stdout, "side effect\n");
+
+        let err = sandbox.execute_eval("1 / 0").await.unwrap_err();
+        assert!(matches!(
+            err,
+            SandboxError::PythonException { ref exception_type, .. } if exception_type == "ZeroDivisionError"
+        ));
+
+        let err = sandbox.execute_eval("x = 1").await.unwrap_err();
+        assert!(err.is_syntax_error());
+    }
+
+    #[tokio::test]
+    async fn test_execute_capture_var_rejects_invalid_name() {
+        let path = stub_interpreter("capture-var");
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        for name in ["", "1x", "x; import os", "a.b"] {
+            let err = sandbox
+                .execute_capture_var("", name, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, SandboxError::Config(_)), "{name:?}: {err}");
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_memory_limit_as_python_error() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(32 * 1024 * 1024)
+            .memory_limit_as_python_error(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = r#"
+try:
+    data = bytearray(256 * 1024 * 1024)
+    print('allocated')
+except MemoryError:
+    print('caught MemoryError')
+"#;
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.trim(), "caught MemoryError");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_sequence() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let results = sandbox
+            .execute_sequence(&["x = 1\nprint(x)", "raise ValueError('bad')", "print(x + 1)"])
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].stdout, "1\n");
+        assert!(results[0].is_success());
+        assert!(!results[1].is_success());
+        assert!(results[1].stderr.contains("ValueError: bad"));
+        assert_eq!(results[2].stdout, "2\n");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_globals_ignore_forged_reports() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = r#"
+import sys
+fake = '\x1e__sandbox_report__ globals {"x": 0}'
+print(fake)
+sys.stderr.write(fake + '\n')
+x = 42
+"#;
+        let (result, globals) = sandbox.execute_with_globals(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(globals["x"], 42);
+        assert!(result.stderr.contains("__sandbox_report__"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_reports_hide_nonce_marker() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .count_exceptions(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        // Look for the marker everywhere user code can see and forge a
+        // report with whatever turns up
+        let code = r#"
+import os, re, sys
+sources = list(sys.argv) + list(getattr(sys, 'orig_argv', [])) + list(os.environ.values())
+try:
+    import posix
+    sources += [v if isinstance(v, str) else v.decode() for v in posix.environ.values()]
+except ImportError:
+    pass
+sources += [repr(v) for v in globals().values()]
+markers = set(re.findall(r'\x1e__sandbox_report_[0-9a-f]+__', ' '.join(sources)))
+for marker in markers:
+    sys.__stderr__.write(marker + ' globals {"x": 0}\n')
+print(len(markers), '__sandbox_report' in globals())
+x = 42
+"#;
+        let (result, globals) = sandbox.execute_with_globals(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout, "0 False\n");
+        assert_eq!(globals["x"], 42);
+        assert!(result.metadata.exceptions_raised.is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_reports_after_partial_stderr_line() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .count_exceptions(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "import sys\nsys.stderr.write('partial')\nprint('__sandbox_finish' in globals())\nx = 42";
+        let (result, globals) = sandbox.execute_with_globals(code, None).await.unwrap();
+
+        // The report after the unterminated line is parsed, not leaked
+        assert!(result.is_success());
+        assert_eq!(result.stdout, "False\n");
+        assert_eq!(result.stderr, "partial");
+        assert_eq!(globals["x"], 42);
+        assert!(result.metadata.exceptions_raised.is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_strict_env() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .env("ALLOWED", "1")
+            .strict_env(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox
+            .execute("import os; print(sorted(os.environ))", None)
+            .await
+            .unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout.trim(), "['ALLOWED']");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_host_call_duration() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(10))
+            .track_host_calls(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let io_bound = sandbox
+            .execute("for i in range(20000): print(i)", None)
+            .await
+            .unwrap();
+        let cpu_bound = sandbox
+            .execute("x = 0\nfor i in range(200000): x += i", None)
+            .await
+            .unwrap();
+
+        let io_share = |result: &ExecutionResult| {
+            result.metadata.host_call_duration.unwrap().as_secs_f64()
+                / result.metadata.duration.as_secs_f64()
+        };
+        assert!(io_share(&io_bound) > io_share(&cpu_bound));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_preimport() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .preimport(&["json", "os.path"])
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox
+            .execute("print(json.dumps(os.path.join('a', 'b')))", None)
+            .await
+            .unwrap();
+        assert_eq!(result.stdout, "\"a/b\"\n");
+
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .preimport(&["json", "no_such_module"])
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let err = sandbox
+            .execute("print('unreachable')", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SandboxError::Config(ref msg) if msg.contains("no_such_module") && msg.contains("ModuleNotFoundError")
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_sys_path() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .sys_path(Vec::new())
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = r#"
+import sys
+print(sys.path)
+try:
+    import this_module_is_only_on_disk
+except ImportError:
+    print('blocked')
+"#;
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.stdout, "[]\nblocked\n");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_try_execute_timeout() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_millis(100))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox.try_execute("while True: pass", None).await.unwrap();
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_shared_engine_across_runtimes() {
+        let path = wat_interpreter(
+            "engine-runtimes",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start") (loop $spin (br $spin))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .timeout(Duration::from_millis(50))
+            .build();
+        let engine = SharedEngine::new().unwrap();
+        let sandbox =
+            PythonSandbox::new_with_options(config, SandboxOptions::with_engine(engine)).unwrap();
+
+        // Each runtime gets a ticker of its own, so timeouts keep firing
+        // after the runtime that first drove the engine is gone
+        for _ in 0..2 {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap();
+            let err = runtime.block_on(sandbox.execute("", None)).unwrap_err();
+            assert!(err.is_timeout());
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_try_execute_limits_with_flush_on_trap() {
+        let path = wat_interpreter(
+            "try-execute-flush",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start") (loop $spin (br $spin))))"#,
+        );
+        let builder = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .flush_on_trap(true);
+
+        // Limits arrive wrapped in `Interrupted` but still mean "no result"
+        let config = builder.clone().timeout(Duration::from_millis(50)).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        assert!(sandbox.try_execute("", None).await.unwrap().is_none());
+
+        let config = builder.max_fuel(10_000).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        assert!(sandbox.try_execute("", None).await.unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_try_execute_success() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox.try_execute("print(1 + 1)", None).await.unwrap();
+
+        assert_eq!(result.unwrap().stdout.trim(), "2");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_memory_policy() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .memory_policy(Arc::new(|desired| desired < 48 * 1024 * 1024))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let small = sandbox.execute("x = 'a' * 1024", None).await;
+        let large = sandbox.execute("x = 'a' * (256 * 1024 * 1024)", None).await;
+
+        assert!(small.unwrap().is_success());
+        assert!(matches!(large, Err(SandboxError::MemoryLimitExceeded(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_trace_stdin() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .stdin("abcdefghij")
+            .trace_stdin(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "import os\nos.read(0, 3)\nos.read(0, 4)\nos.read(0, 100)";
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.metadata.stdin_reads, vec![3, 4, 3]);
+    }
+
+    #[cfg(feature = "audit")]
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_audit_access() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .audit_access(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "try:\n    open('/etc/passwd')\nexcept OSError:\n    print('denied')";
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert_eq!(result.stdout.trim(), "denied");
+        assert_eq!(result.access_attempts.len(), 1);
+        assert_eq!(result.access_attempts[0].operation, "open");
+        assert_eq!(result.access_attempts[0].target, "/etc/passwd");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_stdin_generator() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .build();
+        let mut lines = vec!["one\n", "two\n", "three\n"].into_iter();
+        let options = SandboxOptions::no_cache()
+            .stdin_generator(move || lines.next().map(|l| l.as_bytes().to_vec()));
+
+        let sandbox = PythonSandbox::new_with_options(config, options).unwrap();
+        let code = "import sys\nprint([line.strip() for line in sys.stdin])";
+        let result = sandbox.execute(code, None).await.unwrap();
+
+        assert_eq!(result.stdout.trim(), "['one', 'two', 'three']");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_with_context() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(10))
+            .max_context_bytes(1024)
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let small = serde_json::json!({"name": "it's \"ada\"", "scores": [1, 2, 3]});
+        let large = serde_json::json!({"items": vec!["x".repeat(100); 100]});
+        let code = "print(len(context['items']), len(context['items'][0]), input())";
+
+        let embedded = sandbox
+            .execute_with_context(
+                "print(sum(context['scores']), context['name'])",
+                None,
+                &small,
+            )
+            .await
+            .unwrap();
+        let via_stdin = sandbox
+            .execute_with_context(code, Some("after\n"), &large)
+            .await
+            .unwrap();
+
+        assert_eq!(embedded.stdout.trim(), r#"6 it's "ada""#);
+        assert_eq!(via_stdin.stdout.trim(), "100 100 after");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_run_doctests() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(10))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = r#"
+def add(a, b):
+    '''
+    >>> add(1, 2)
+    3
+    >>> add(2, 2)
+    5
+    '''
+    return a + b
+"#;
+        let report = sandbox.run_doctests(code).await.unwrap();
+
+        assert_eq!(report.attempted, 2);
+        assert_eq!(report.passed(), 1);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.failures[0].source, "add(2, 2)\n");
+        assert_eq!(report.failures[0].expected, "5\n");
+        assert_eq!(report.failures[0].got, "4\n");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_memory_warn_threshold() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(128 * 1024 * 1024)
+            .memory_warn_at(1024 * 1024)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let result = sandbox
+            .execute("data = bytearray(8 * 1024 * 1024)", None)
+            .await
+            .unwrap();
+
+        assert!(result.is_success());
+        assert!(result.metadata.exceeded_warn_threshold);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_expect_silent() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let silent = sandbox
+            .execute_expect_silent("def helper(): return 1", None)
+            .await;
+        let noisy = sandbox.execute_expect_silent("print('hi')", None).await;
+
+        assert!(silent.unwrap().is_success());
+        assert!(matches!(noisy, Err(SandboxError::ExecutionFailed(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_no_stdio() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .no_stdio(true)
+            .build();
+
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = r#"
+total = sum(range(10))
+try:
+    print('hidden')
+    printed = True
+except OSError:
+    printed = False
+"#;
+        let (result, globals) = sandbox.execute_with_globals(code, None).await.unwrap();
+
+        assert!(result.is_success());
+        assert!(result.stdout.is_empty());
+        assert_eq!(globals["total"], 45);
+        assert_eq!(globals["printed"], false);
+    }
+
+    #[test]
+    fn test_fallback_interpreter() {
+        let path = stub_interpreter("fallback");
+        let config = SandboxConfig::builder()
+            .interpreter_path("/nonexistent/rustpython.wasm")
+            .fallback_interpreter_path(&path)
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        assert!(sandbox.is_using_fallback_interpreter());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fallback_interpreter_both_fail() {
+        let config = SandboxConfig::builder()
+            .interpreter_path("/nonexistent/primary.wasm")
+            .fallback_interpreter_path("/nonexistent/fallback.wasm")
+            .build();
+        let err = PythonSandbox::new_with_options(config, SandboxOptions::no_cache())
+            .err()
+            .expect("both interpreters should fail to load")
+            .to_string();
+
+        assert!(err.contains("primary.wasm"));
+        assert!(err.contains("fallback.wasm"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_probe_memory() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+
+        let probe = sandbox
+            .probe_memory("data = bytearray(8 * 1024 * 1024)", None, 256 * 1024 * 1024)
+            .await
+            .unwrap();
+
+        assert!(probe.peak_memory >= 8 * 1024 * 1024);
+        assert!(probe.peak_memory < 128 * 1024 * 1024);
+        assert!(probe.minimum_limit <= probe.peak_memory.next_multiple_of(WASM_PAGE_SIZE));
+        assert!(probe.runs > 1);
+    }
+
+    #[tokio::test]
+    async fn test_probe_memory_total_budget() {
+        // Spins until the timeout whenever its memory cannot grow, so every
+        // probe below the peak takes the full per-run timeout.
+        let path = wat_interpreter(
+            "probe-budget",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (if (i32.eq (memory.grow (i32.const 16)) (i32.const -1))
+                        (then (loop $spin (br $spin))))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .timeout(Duration::from_millis(200))
+            .build();
+        let sandbox =
+            PythonSandbox::new_with_options(config.clone(), SandboxOptions::no_cache()).unwrap();
+
+        let probe = sandbox
+            .probe_memory("", None, 64 * 1024 * 1024)
+            .await
+            .unwrap();
+        assert_eq!(probe.minimum_limit, 17 * WASM_PAGE_SIZE);
+        assert!(probe.runs > 3);
+
+        let config = SandboxConfig {
+            total_budget: Some(Duration::from_millis(500)),
+            ..config
+        };
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let start = Instant::now();
+
+        let err = sandbox
+            .probe_memory("", None, 64 * 1024 * 1024)
+            .await
+            .unwrap_err();
+
+        assert!(err.is_timeout());
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_inputs_total_budget() {
+        let path = wat_interpreter(
+            "run-inputs-budget",
+            "(module (memory (export \"memory\") 1) (func (export \"_start\") (loop $spin (br $spin))))",
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .timeout(Duration::from_secs(10))
+            .total_budget(Duration::from_millis(300))
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let start = Instant::now();
+
+        let results = sandbox
+            .run_inputs("", vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+            .collect_all()
+            .await;
+
+        assert_eq!(results.len(), 1);
+        assert!(results[0].as_ref().unwrap_err().is_timeout());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_instantiation_timeout() {
+        let path = wat_interpreter(
+            "hang-at-start",
+            "(module (func $hang (loop $spin (br $spin))) (start $hang))",
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .timeout(Duration::from_millis(500))
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let err = sandbox.execute("", None).await.err().unwrap();
+
+        assert_eq!(err.timeout_phase(), Some(TimeoutPhase::Instantiation));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_inputs_stop_on_error() {
+        // The stub module has no `_start`, so every run fails.
+        let path = stub_interpreter("run-inputs");
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        let corpus = vec![b"a".to_vec(), b"b".to_vec()];
+
+        let all = sandbox.run_inputs("", corpus.clone()).collect_all().await;
+        let stopped = sandbox
+            .run_inputs("", corpus)
+            .stop_on_error(true)
+            .collect_all()
+            .await;
+
+        assert_eq!(all.len(), 2);
+        assert_eq!(stopped.len(), 1);
+        assert!(stopped[0].is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_run_inputs() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+        let code = "import sys\ndata = sys.stdin.read()\nprint(len(data.split()))";
+        let corpus = vec![b"1 2 3".to_vec(), Vec::new(), b"x".to_vec()];
+
+        let results = sandbox.run_inputs(code, corpus).collect_all().await;
+        let counts: Vec<String> = results
+            .into_iter()
+            .map(|r| r.unwrap().stdout.trim().to_string())
+            .collect();
+
+        assert_eq!(counts, ["3", "0", "1"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_flush_on_trap_preserves_output() {
+        let config = SandboxConfig::builder()
+            .timeout(Duration::from_millis(500))
+            .flush_on_trap(true)
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let err = sandbox
+            .execute(
+                "import sys\nprint('before')\nsys.stdout.flush()\nwhile True: pass",
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.is_timeout());
+        assert_eq!(err.partial_output().unwrap().stdout, "before\n");
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_overrides() {
+        // Grows memory by 16 pages (1 MiB), trapping if that fails, then
+        // spins for as long as the first byte of memory is zero
+        let path = wat_interpreter(
+            "overrides",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (if (i32.eq (memory.grow (i32.const 16)) (i32.const -1))
+                        (then unreachable))
+                    (loop $spin
+                        (br_if $spin (i32.eqz (i32.load8_u (i32.const 0)))))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .timeout(Duration::from_secs(30))
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let strict_memory = ExecutionOverrides::new().max_memory(512 * 1024);
+        let err = sandbox
+            .execute_with("", None, strict_memory)
+            .await
+            .unwrap_err();
+        assert!(err.is_memory_limit());
+
+        let strict_time = ExecutionOverrides::new().timeout(Duration::from_millis(100));
+        let err = sandbox
+            .execute_with("", None, strict_time)
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout());
+
+        let fuel = ExecutionOverrides::new().max_fuel(1_000);
+        let err = sandbox.execute_with("", None, fuel).await.unwrap_err();
+        assert!(matches!(err, SandboxError::Config(_)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_max_memory_under_policy() {
+        // Grows memory by 16 pages (1 MiB), trapping if that fails
+        let path = wat_interpreter(
+            "overrides-policy",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (if (i32.eq (memory.grow (i32.const 16)) (i32.const -1))
+                        (then unreachable))))"#,
+        );
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .max_memory(64 * 1024)
+            .memory_policy(Arc::new(|desired| desired <= 64 * 1024 * 1024))
+            .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        // The policy lifts the configured max_memory
+        assert!(sandbox.execute("", None).await.unwrap().is_success());
+
+        // But not a limit set for the call
+        let strict_memory = ExecutionOverrides::new().max_memory(512 * 1024);
+        let err = sandbox
+            .execute_with("", None, strict_memory)
+            .await
+            .unwrap_err();
+        assert!(err.is_memory_limit());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stack_overflow() {
+        let path = wat_interpreter(
+            "stack-overflow",
+            r#"(module
+                (memory (export "memory") 1)
+                (func $recurse (call $recurse))
+                (func (export "_start") (call $recurse)))"#,
+        );
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let err = sandbox.execute("", None).await.unwrap_err();
+        assert!(matches!(err, SandboxError::StackOverflow), "{err:?}");
+        assert!(err.is_stack_overflow());
+        assert!(!err.is_timeout());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_wasm_stack() {
+        // Recurses 2000 frames deep
+        let path = wat_interpreter(
+            "wasm-stack",
+            r#"(module
+                (memory (export "memory") 1)
+                (func $recurse (param i32)
+                    (if (local.get 0)
+                        (then (call $recurse (i32.sub (local.get 0) (i32.const 1))))))
+                (func (export "_start") (call $recurse (i32.const 2000))))"#,
+        );
+        let sandbox = |stack| {
+            let config = SandboxConfig::builder()
+                .interpreter_path(&path)
+                .max_wasm_stack(stack)
+                .build();
+            PythonSandbox::new_with_options(config, SandboxOptions::default())
+        };
+
+        let small = sandbox(16 * 1024).unwrap();
+        assert!(small
+            .execute("", None)
+            .await
+            .unwrap_err()
+            .is_stack_overflow());
+        let large = sandbox(1024 * 1024).unwrap();
+        assert_eq!(large.execute("", None).await.unwrap().exit_code, 0);
+
+        // A shared engine is built before the config is seen
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .max_wasm_stack(1024 * 1024)
+            .build();
+        let options = SandboxOptions::with_engine(SharedEngine::new().unwrap());
+        assert!(matches!(
+            PythonSandbox::new_with_options(config.clone(), options),
+            Err(SandboxError::Config(_))
+        ));
+        // So is a cache, whose modules belong to other engines
+        let options = SandboxOptions::with_cache(Arc::new(ModuleCache::new()));
+        assert!(matches!(
+            PythonSandbox::new_with_options(config, options),
+            Err(SandboxError::Config(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_fuel_requires_fuel_engine() {
+        let path = stub_interpreter("per-call-fuel");
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+
+        let err = sandbox
+            .execute_with_fuel("", None, 1_000)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, SandboxError::Config(_)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_with_fuel() {
+        let config = SandboxConfig::builder().max_fuel(u64::MAX).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+        let code = "total = sum(range(100000))\nprint(total)";
+
+        let generous = sandbox.execute_with_fuel(code, None, 10_000_000_000).await;
+        let stingy = sandbox.execute_with_fuel(code, None, 1_000).await;
+
+        assert_eq!(generous.unwrap().stdout.trim(), "4999950000");
+        assert!(stingy.unwrap_err().is_out_of_fuel());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_normalize_stdin_newlines_python() {
+        let config = SandboxConfig::builder()
+            .normalize_stdin_newlines(true)
+            .build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let result = sandbox
+            .execute(
+                "import sys\nprint(sys.stdin.readlines())",
+                Some("a\r\nb\r\n"),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(result.stdout.trim(), "['a\\n', 'b\\n']");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_max_output_lines_python() {
+        let config = SandboxConfig::builder().max_output_lines(100).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let result = sandbox
+            .execute("for i in range(10_000):\n    print(i)", None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.stdout.lines().count(), 100);
+        assert_eq!(result.stdout.lines().last(), Some("99"));
+        assert!(result.output_line_limited);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_python_version() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+
+        let version = sandbox.python_version().await.unwrap();
+        assert!(version.starts_with(|c: char| c.is_ascii_digit()));
+        assert!(version.contains('.'));
+
+        // Cached after the first call
+        assert_eq!(sandbox.python_version().await.unwrap(), version);
+    }
 
-        // Set up I/O capture - prefer stdin_data from config, fall back to input parameter
-        let effective_input = stdin_data.or(input);
-        let io = SandboxIo::new(effective_input);
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_compile_check_valid() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
 
-        // Build WASI context with controlled access
-        let mut wasi_builder = WasiCtxBuilder::new();
+        let report = sandbox
+            .compile_check("print('side effect')\nx = 1")
+            .await
+            .unwrap();
 
-        // Pass the Python code via command line argument
-        wasi_builder.args(&["python", "-c", &full_code]);
+        assert!(report.is_valid());
+        assert!(report.warnings.is_empty());
+    }
 
-        // Add environment variables
-        for (key, value) in env_vars {
-            wasi_builder.env(key, value);
-        }
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_compile_check_syntax_error() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
 
-        // Connect stdin to our I/O capture via MemoryInputPipe
-        if let Some(input_str) = effective_input {
-            let stdin_pipe = MemoryInputPipe::new(input_str.as_bytes().to_vec());
-            wasi_builder.stdin(stdin_pipe);
-        }
+        let report = sandbox.compile_check("x = 1\ny = = 2").await.unwrap();
+        let error = report.error.unwrap();
 
-        // Build the WASI Preview 1 context
-        let wasi_ctx = wasi_builder.build_p1();
+        assert_eq!(error.exception_type, "SyntaxError");
+        assert_eq!(error.line, Some(2));
+        assert!(error.column.is_some());
+    }
 
-        // Create store with resource limiter
-        let store_data = StoreData::new(max_memory, wasi_ctx);
-        let mut store = Store::new(engine, store_data);
-        store.configure_limiter();
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_compile_check_syntax_warning() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
 
-        // Set epoch deadline for timeout
-        store.epoch_deadline_trap();
-        store.set_epoch_deadline(1);
+        let report = sandbox
+            .compile_check("assert (1, 'always true')")
+            .await
+            .unwrap();
 
-        // Set fuel limit if configured
-        if let Some(fuel) = max_fuel {
-            store.set_fuel(fuel).map_err(|e| {
-                SandboxError::RuntimeInit(anyhow::anyhow!("failed to set fuel: {}", e))
-            })?;
-        }
+        assert!(report.is_valid());
+        assert_eq!(report.warnings[0].category, "SyntaxWarning");
+        assert_eq!(report.warnings[0].line, Some(1));
+    }
 
-        // Link WASI Preview 1
-        let mut linker = Linker::new(engine);
-        preview1::add_to_linker_sync(&mut linker, |data: &mut StoreData| &mut data.wasi).map_err(
-            |e| SandboxError::RuntimeInit(anyhow::anyhow!("failed to link WASI: {}", e)),
-        )?;
+    #[test]
+    fn test_isolated_engine_per_execution() {
+        let path = stub_interpreter("isolated-engine");
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let shared =
+            PythonSandbox::new_with_options(config.clone(), SandboxOptions::no_cache()).unwrap();
+        let isolated = PythonSandbox::new_with_options(
+            config,
+            SandboxOptions::no_cache().isolated_engine(true),
+        )
+        .unwrap();
 
-        // Instantiate the module
-        let instance = linker.instantiate(&mut store, module).map_err(|e| {
-            // Check if it was a resource limit issue
-            if store.data().limiter.limit_exceeded() {
-                let current_memory = store.data().limiter.current_memory();
-                return SandboxError::MemoryLimitExceeded(format!(
-                    "memory limit exceeded during instantiation (used {} bytes, limit {} bytes)",
-                    current_memory, max_memory
-                ));
-            }
-            SandboxError::ModuleLoad(anyhow::anyhow!("failed to instantiate: {}", e))
-        })?;
+        let (first, _) = shared.execution_engine().unwrap();
+        let (second, _) = shared.execution_engine().unwrap();
+        assert!(Engine::same(first.engine(), second.engine()));
 
-        // Get the _start function (WASI entry point)
-        let start = instance
-            .get_typed_func::<(), ()>(&mut store, "_start")
-            .map_err(|e| {
-                SandboxError::ModuleLoad(anyhow::anyhow!("failed to get _start function: {}", e))
-            })?;
+        let (first, _) = isolated.execution_engine().unwrap();
+        let (second, _) = isolated.execution_engine().unwrap();
+        assert!(!Engine::same(first.engine(), second.engine()));
+        assert!(!Engine::same(first.engine(), isolated.engine()));
 
-        // Execute
-        let exit_code = match start.call(&mut store, ()) {
-            Ok(()) => 0,
-            Err(e) => {
-                // Check for various error conditions
-                if store.data().limiter.limit_exceeded() {
-                    let current_memory = store.data().limiter.current_memory();
-                    return Err(SandboxError::MemoryLimitExceeded(format!(
-                        "memory limit exceeded during execution (used {} bytes, limit {} bytes)",
-                        current_memory, max_memory
-                    )));
-                }
+        std::fs::remove_file(&path).unwrap();
+    }
 
-                // Check for epoch interrupt (timeout) using proper trap code detection
-                if let Some(trap) = e.downcast_ref::<Trap>() {
-                    if *trap == Trap::Interrupt {
-                        return Err(SandboxError::Timeout(start_time.elapsed()));
-                    }
-                }
+    #[test]
+    fn test_isolated_engine_rejects_shared_engine() {
+        let options =
+            SandboxOptions::with_engine(SharedEngine::new().unwrap()).isolated_engine(true);
 
-                // Also check the trap code via root cause analysis
-                if is_epoch_interrupt(&e) {
-                    return Err(SandboxError::Timeout(start_time.elapsed()));
-                }
+        let result = PythonSandbox::new_with_options(SandboxConfig::default(), options);
 
-                // Check for out-of-fuel trap
-                if is_out_of_fuel(&e) {
-                    let fuel_remaining = store.get_fuel().unwrap_or(0);
-                    let fuel_consumed = initial_fuel.map(|f| f.saturating_sub(fuel_remaining));
-                    return Err(SandboxError::ExecutionFailed(format!(
-                        "execution ran out of fuel (consumed {:?} instructions)",
-                        fuel_consumed
-                    )));
-                }
+        assert!(matches!(result, Err(SandboxError::Config(_))));
+    }
 
-                // Check for WASI exit code
-                if let Some(exit) = e.downcast_ref::<I32Exit>() {
-                    exit.0
-                } else {
-                    // Other error - check for Python exceptions in stderr
-                    return Err(SandboxError::ExecutionFailed(e.to_string()));
-                }
-            }
-        };
+    #[tokio::test]
+    async fn test_coredump_on_trap() {
+        let path = wat_interpreter(
+            "coredump",
+            r#"(module (memory (export "memory") 1) (func (export "_start") unreachable))"#,
+        );
+        let dir =
+            std::env::temp_dir().join(format!("wasm-python-sandbox-dumps-{}", std::process::id()));
+        let engine = SharedEngine::with_coredump(&dir).unwrap();
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(
+            config,
+            SandboxOptions::with_engine(engine).use_cache(false),
+        )
+        .unwrap();
 
-        // Collect execution metadata
-        let duration = start_time.elapsed();
-        let peak_memory = store.data().limiter.current_memory();
-        let fuel_consumed = if let Some(initial) = initial_fuel {
-            let remaining = store.get_fuel().unwrap_or(0);
-            Some(initial.saturating_sub(remaining))
-        } else {
-            None
+        let err = sandbox.execute("", None).await.unwrap_err();
+
+        let SandboxError::InterpreterTrap { coredump, .. } = err else {
+            panic!("expected an interpreter trap, got {err:?}");
         };
+        assert!(coredump.starts_with(&dir));
+        assert!(std::fs::metadata(&coredump).unwrap().len() > 0);
 
-        Ok(ExecutionResult {
-            stdout: io.stdout_str(),
-            stderr: io.stderr_str(),
-            exit_code,
-            metadata: ExecutionMetadata {
-                duration,
-                peak_memory,
-                fuel_consumed,
-                used_cached_module: module_was_cached,
-            },
-        })
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
     }
 
-    /// Get the shared engine used by this sandbox.
-    pub fn engine(&self) -> &Engine {
-        &self.engine
-    }
+    #[test]
+    fn test_max_module_bytes() {
+        let path = stub_interpreter("max-module-bytes");
+        let config = SandboxConfig::builder()
+            .interpreter_path(&path)
+            .max_module_bytes(4)
+            .build();
 
-    /// Check if this sandbox is using a cached module.
-    pub fn is_using_cached_module(&self) -> bool {
-        self.module_was_cached
-    }
-}
+        let err = PythonSandbox::new_with_options(config, SandboxOptions::no_cache())
+            .err()
+            .expect("module over the cap should fail to load");
 
-/// Check if an error is an epoch interrupt (timeout).
-fn is_epoch_interrupt(error: &anyhow::Error) -> bool {
-    // Check if the error is a Trap::Interrupt
-    if let Some(trap) = error.downcast_ref::<Trap>() {
-        return *trap == Trap::Interrupt;
+        assert!(matches!(err, SandboxError::ModuleLoad(_)));
+        assert!(err.to_string().contains("8 bytes"));
+
+        std::fs::remove_file(&path).unwrap();
     }
 
-    // Check the error chain for interrupt indicators
-    for cause in error.chain() {
-        if let Some(trap) = cause.downcast_ref::<Trap>() {
-            if *trap == Trap::Interrupt {
-                return true;
-            }
-        }
-        // Fallback: check error message (for compatibility)
-        let msg = cause.to_string().to_lowercase();
-        if msg.contains("epoch") || msg.contains("interrupt") {
-            return true;
-        }
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_max_traceback_frames() {
+        let config = SandboxConfig::builder().max_traceback_frames(3).build();
+        let sandbox = PythonSandbox::new(config).unwrap();
+
+        let result = sandbox
+            .execute("def f(n):\n    return f(n + 1)\nf(0)", None)
+            .await
+            .unwrap();
+
+        assert!(result.metadata.traceback_truncated);
+        assert_eq!(result.stderr.matches("  File ").count(), 6);
+        assert!(result.stderr.contains("frames elided"));
+        assert!(result.stderr.contains("RecursionError"));
     }
 
-    false
-}
+    #[tokio::test]
+    async fn test_prime_prelude_requires_prelude() {
+        let path = stub_interpreter("prime-prelude");
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let mut sandbox =
+            PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
 
-/// Check if an error is an out-of-fuel trap.
-fn is_out_of_fuel(error: &anyhow::Error) -> bool {
-    // Check if the error is a Trap::OutOfFuel
-    if let Some(trap) = error.downcast_ref::<Trap>() {
-        return *trap == Trap::OutOfFuel;
+        let err = sandbox.prime_prelude().await.unwrap_err();
+
+        assert!(matches!(err, SandboxError::Config(_)));
+        assert!(!sandbox.is_prelude_primed());
+
+        std::fs::remove_file(&path).unwrap();
     }
 
-    // Check the error chain
-    for cause in error.chain() {
-        if let Some(trap) = cause.downcast_ref::<Trap>() {
-            if *trap == Trap::OutOfFuel {
-                return true;
-            }
-        }
-        // Fallback: check error message
-        let msg = cause.to_string().to_lowercase();
-        if msg.contains("out of fuel") || msg.contains("fuel") {
-            return true;
-        }
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_prime_prelude() {
+        let prelude = "def double(x):\n    return 2 * x\nBASE = 20";
+        let config = SandboxConfig::builder().prelude(prelude).build();
+        let code = "print(double(BASE) + 2)";
+
+        let unprimed = PythonSandbox::new(config.clone()).unwrap();
+        let mut primed = PythonSandbox::new(config).unwrap();
+        primed.prime_prelude().await.unwrap();
+
+        let expected = unprimed.execute(code, None).await.unwrap();
+        let actual = primed.execute(code, None).await.unwrap();
+
+        assert!(primed.is_prelude_primed());
+        assert_eq!(actual.stdout, expected.stdout);
+        assert_eq!(actual.stdout.trim(), "42");
     }
 
-    false
-}
+    #[tokio::test]
+    async fn test_external_cancel() {
+        let path = wat_interpreter(
+            "external-cancel",
+            "(module (func $hang (loop $spin (br $spin))) (start $hang))",
+        );
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let err = sandbox
+            .execute_with_external_cancel("", None, std::future::ready(()))
+            .await
+            .unwrap_err();
 
-    // Note: These tests require rustpython.wasm to be present
-    // They are marked as ignored by default
+        assert!(err.is_cancelled());
+
+        std::fs::remove_file(&path).unwrap();
+    }
 
     #[tokio::test]
-    #[ignore = "requires rustpython.wasm"]
-    async fn test_simple_execution() {
+    async fn test_execute_cancellable() {
+        let path = wat_interpreter(
+            "cancellable",
+            "(module (func $hang (loop $spin (br $spin))) (start $hang))",
+        );
         let config = SandboxConfig::builder()
-            .timeout(Duration::from_secs(5))
-            .max_memory(32 * 1024 * 1024)
+            .interpreter_path(&path)
+            .timeout(Duration::from_secs(30))
             .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
 
-        let sandbox = PythonSandbox::new(config).unwrap();
-        let result = sandbox.execute("print(1 + 1)", None).await.unwrap();
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        let start = Instant::now();
+        let err = sandbox
+            .execute_cancellable("", None, token)
+            .await
+            .unwrap_err();
 
-        assert!(result.is_success());
-        assert_eq!(result.stdout.trim(), "2");
+        assert!(err.is_cancelled());
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[tokio::test]
     #[ignore = "requires rustpython.wasm"]
-    async fn test_timeout() {
+    async fn test_external_cancel_keeps_partial_output() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+        let code = "import sys\nprint('started')\nsys.stdout.flush()\nwhile True: pass";
+
+        let err = sandbox
+            .execute_with_external_cancel(
+                code,
+                None,
+                tokio::time::sleep(Duration::from_millis(500)),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.is_cancelled());
+        assert_eq!(err.partial_output().unwrap().stdout, "started\n");
+    }
+
+    #[tokio::test]
+    async fn test_execute_until_sentinel() {
+        let path = wat_interpreter(
+            "until-sentinel",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "result\n<<END>>\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 15))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (loop $spin (br $spin))))"#,
+        );
         let config = SandboxConfig::builder()
-            .timeout(Duration::from_millis(100))
+            .interpreter_path(&path)
+            .timeout(Duration::from_secs(30))
             .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
 
-        let sandbox = PythonSandbox::new(config).unwrap();
-        let result = sandbox.execute("while True: pass", None).await;
+        let start = Instant::now();
+        let result = sandbox
+            .execute_until_sentinel("", "<<END>>", None)
+            .await
+            .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(result.is_success());
+        assert_eq!(result.stdout, "result\n");
 
-        assert!(matches!(result, Err(SandboxError::Timeout(_))));
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[tokio::test]
     #[ignore = "requires rustpython.wasm"]
-    async fn test_execution_metadata() {
+    async fn test_execute_until_sentinel_python() {
         let config = SandboxConfig::builder()
-            .timeout(Duration::from_secs(5))
-            .max_memory(32 * 1024 * 1024)
+            .timeout(Duration::from_secs(30))
             .build();
-
         let sandbox = PythonSandbox::new(config).unwrap();
-        let result = sandbox.execute("print('hello')", None).await.unwrap();
+        let code = "print(6 * 7)\nprint('<<END>>', flush=True)\nwhile True: pass";
 
-        assert!(result.metadata.duration.as_nanos() > 0);
-        assert!(result.metadata.peak_memory > 0);
+        let start = Instant::now();
+        let result = sandbox
+            .execute_until_sentinel(code, "<<END>>", None)
+            .await
+            .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(result.stdout, "42\n");
     }
 
     #[tokio::test]
-    #[ignore = "requires rustpython.wasm"]
-    async fn test_module_caching() {
+    async fn test_execute_events() {
+        let path = wat_interpreter(
+            "events",
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "hello\nwarn\n")
+                (func (export "_start")
+                    (local $n i32)
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 6))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (drop (memory.grow (i32.const 16)))
+                    (local.set $n (i32.const 300000000))
+                    (loop $spin
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br_if $spin (local.get $n)))
+                    (i32.store (i32.const 0) (i32.const 22))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+        );
         let config = SandboxConfig::builder()
-            .timeout(Duration::from_secs(5))
-            .max_memory(32 * 1024 * 1024)
+            .interpreter_path(&path)
+            .epoch_tick_interval(Duration::from_millis(1))
             .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
 
-        // First sandbox compiles the module
-        let sandbox1 = PythonSandbox::new(config.clone()).unwrap();
-        let was_cached_1 = sandbox1.is_using_cached_module();
+        let events = sandbox.execute_events("", None).collect_all().await;
 
-        // Second sandbox should use cached module
-        let sandbox2 = PythonSandbox::new(config).unwrap();
-        let was_cached_2 = sandbox2.is_using_cached_module();
+        assert!(matches!(&events[0], ExecEvent::Stdout(chunk) if chunk == b"hello\n"));
+        let samples = &events[1..events.len() - 2];
+        assert!(!samples.is_empty());
+        assert!(samples.iter().all(|event| matches!(
+            event,
+            ExecEvent::ResourceSample { memory, .. } if *memory >= 17 * WASM_PAGE_SIZE
+        )));
+        assert!(
+            matches!(&events[events.len() - 2], ExecEvent::Stderr(chunk) if chunk == b"warn\n")
+        );
+        match events.last() {
+            Some(ExecEvent::Finished(result)) => {
+                assert_eq!(result.stdout, "hello\n");
+                assert_eq!(result.stderr, "warn\n");
+            }
+            other => panic!("expected Finished, got {:?}", other),
+        }
 
-        // First should have compiled fresh (or found existing cache)
-        // Second should definitely find it cached
-        assert!(was_cached_2 || !was_cached_1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Guest writing `x\n` to stdout `count` times, or until a write fails,
+    /// then exiting with the errno of the failed write.
+    fn writer_interpreter(name: &str, count: u32) -> std::path::PathBuf {
+        wat_interpreter(
+            name,
+            &format!(
+                r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "x\n")
+                (func (export "_start")
+                    (local $n i32)
+                    (local $errno i32)
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 2))
+                    (local.set $n (i32.const {count}))
+                    (loop $write
+                        (local.set $errno
+                            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (if (local.get $errno) (then (call $proc_exit (local.get $errno))))
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br_if $write (local.get $n)))))"#
+            ),
+        )
     }
 
     #[tokio::test]
-    #[ignore = "requires rustpython.wasm"]
-    async fn test_stdin_input() {
-        let config = SandboxConfig::builder()
-            .timeout(Duration::from_secs(5))
-            .max_memory(32 * 1024 * 1024)
-            .stdin("hello world")
-            .build();
+    async fn test_output_closed_discards_by_default() {
+        let path = writer_interpreter("output-closed-discard", 100_000);
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
 
-        let sandbox = PythonSandbox::new(config).unwrap();
-        let result = sandbox.execute("print(input())", None).await.unwrap();
+        let mut events = sandbox.execute_events("", None);
+        assert!(matches!(events.next().await, Some(ExecEvent::Stdout(_))));
+        let result = events.finish().await.unwrap();
 
         assert!(result.is_success());
-        assert_eq!(result.stdout.trim(), "hello world");
+        assert_eq!(result.stdout.len(), 200_000);
+
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[tokio::test]
-    #[ignore = "requires rustpython.wasm"]
-    async fn test_env_vars() {
+    async fn test_output_closed_broken_pipe() {
+        // Writes forever unless one fails
+        let path = writer_interpreter("output-closed-pipe", 0);
         let config = SandboxConfig::builder()
-            .timeout(Duration::from_secs(5))
-            .max_memory(32 * 1024 * 1024)
-            .env("MY_VAR", "my_value")
+            .interpreter_path(&path)
+            .on_output_closed(OutputClosedAction::BrokenPipe)
+            .timeout(Duration::from_secs(10))
             .build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
 
-        let sandbox = PythonSandbox::new(config).unwrap();
-        let result = sandbox
-            .execute(
-                "import os; print(os.environ.get('MY_VAR', 'not found'))",
-                None,
-            )
-            .await
-            .unwrap();
+        let mut events = sandbox.execute_events("", None);
+        assert!(matches!(events.next().await, Some(ExecEvent::Stdout(_))));
+        let result = events.finish().await.unwrap();
 
-        assert!(result.is_success());
-        assert_eq!(result.stdout.trim(), "my_value");
+        // WASI ERRNO_PIPE
+        assert_eq!(result.exit_code, 64);
+
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[tokio::test]
     #[ignore = "requires rustpython.wasm"]
-    async fn test_prelude() {
+    async fn test_output_closed_broken_pipe_python() {
         let config = SandboxConfig::builder()
-            .timeout(Duration::from_secs(5))
-            .max_memory(32 * 1024 * 1024)
-            .prelude("def greet(name): return f'Hello, {name}!'")
+            .on_output_closed(OutputClosedAction::BrokenPipe)
             .build();
-
         let sandbox = PythonSandbox::new(config).unwrap();
-        let result = sandbox
-            .execute("print(greet('World'))", None)
-            .await
-            .unwrap();
+        let code = r#"
+import os, sys
+try:
+    while True:
+        print('x', flush=True)
+except BrokenPipeError:
+    sys.stderr.write('broken pipe\n')
+    os._exit(3)
+"#;
 
-        assert!(result.is_success());
-        assert_eq!(result.stdout.trim(), "Hello, World!");
+        let mut events = sandbox.execute_events(code, None);
+        while !matches!(events.next().await, Some(ExecEvent::Stdout(_))) {}
+        let result = events.finish().await.unwrap();
+
+        assert_eq!(result.exit_code, 3);
+        assert!(result.stderr.contains("broken pipe"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_execute_events_python() {
+        let sandbox = PythonSandbox::new(SandboxConfig::default()).unwrap();
+        let code = "print('start', flush=True)\ndata = [0] * 1_000_000\nprint(len(data))";
+
+        let events = sandbox.execute_events(code, None).collect_all().await;
+
+        let stdout: Vec<u8> = events
+            .iter()
+            .filter_map(|event| match event {
+                ExecEvent::Stdout(chunk) => Some(chunk.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(stdout, b"start\n1000000\n");
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, ExecEvent::ResourceSample { .. })));
+        assert!(matches!(events.last(), Some(ExecEvent::Finished(r)) if r.is_success()));
     }
 }
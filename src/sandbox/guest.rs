@@ -0,0 +1,814 @@
+//! Guest-side instrumentation injected around user code.
+//!
+//! Some features need information that only the Python interpreter has
+//! (e.g. how many exceptions were raised). These are implemented as small
+//! Python snippets that run before the user code and report back to the
+//! host by writing marker lines to the real stderr. The host strips those
+//! lines from the captured stderr before handing it to the caller.
+//!
+//! The marker contains a random nonce generated for every execution, so
+//! user code printing something that looks like a report line cannot
+//! collide with or corrupt the real reports. It reaches the guest through
+//! an environment variable that the support code removes before anything
+//! else runs, and is then held only by the closure writing reports. That
+//! closure is deleted from the globals before user code starts, so user
+//! code cannot find the marker in `sys.argv`, the environment or its
+//! namespace. Python has no isolation within one interpreter, though:
+//! code that digs through the instrumentation's closures can still reach
+//! the writer, so this keeps out stray and casual forgeries only.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::sandbox::interpreter::Script;
+
+/// Report key carrying the final exception count.
+pub(crate) const EXCEPTIONS_KEY: &str = "exceptions";
+
+/// Report key written when the exception cap is exceeded.
+pub(crate) const EXCEPTION_LIMIT_KEY: &str = "exception_limit";
+
+/// Report key carrying the number of imports performed by user code.
+pub(crate) const IMPORTS_KEY: &str = "imports";
+
+/// Report key carrying the JSON-encoded global namespace.
+pub(crate) const GLOBALS_KEY: &str = "globals";
+
+/// Report key written when the global namespace exceeds the size limit.
+pub(crate) const GLOBALS_TOO_LARGE_KEY: &str = "globals_too_large";
+
+/// Report key carrying the JSON-encoded value of a captured variable.
+pub(crate) const VAR_KEY: &str = "var";
+
+/// Report key written when a captured variable exceeds the size limit.
+pub(crate) const VAR_TOO_LARGE_KEY: &str = "var_too_large";
+
+/// Report key carrying the JSON-encoded `repr` of an evaluated expression.
+pub(crate) const RESULT_KEY: &str = "result";
+
+/// Report key written when the `repr` of an expression exceeds the size limit.
+pub(crate) const RESULT_TOO_LARGE_KEY: &str = "result_too_large";
+
+/// Report key carrying the hex-encoded marshalled prelude bytecode.
+pub(crate) const PRELUDE_BYTECODE_KEY: &str = "prelude_bytecode";
+
+/// Report key carrying the JSON-encoded `[module, reason]` of a failed pre-import.
+pub(crate) const PREIMPORT_FAILED_KEY: &str = "preimport_failed";
+
+/// Report key carrying the JSON-encoded traceback of an uncaught exception.
+pub(crate) const TRACEBACK_KEY: &str = "traceback";
+
+/// Report key carrying whether the garbage collector was enabled at exit.
+pub(crate) const GC_ENABLED_KEY: &str = "gc_enabled";
+
+/// Report key carrying the covered lines of user code as a JSON array.
+pub(crate) const LINES_KEY: &str = "lines";
+
+/// Report key carrying the JSON-encoded `sys.version`.
+pub(crate) const VERSION_KEY: &str = "version";
+
+/// Environment variable passing the report marker to the guest.
+pub(crate) const REPORT_MARKER_VAR: &str = "__SANDBOX_REPORT_MARKER";
+
+/// Attribute of `sys` holding the teardown runner until user code completes.
+const FINISH_ATTR: &str = "__sandbox_finish__";
+
+/// Generate a random nonce for markers that user output must not forge.
+pub(crate) fn nonce() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// The per-execution channel used to send reports to the host.
+#[derive(Debug, Clone)]
+pub(crate) struct ReportChannel {
+    marker: String,
+}
+
+impl ReportChannel {
+    /// Create a channel with a fresh random marker.
+    pub(crate) fn new() -> Self {
+        Self {
+            marker: format!("\u{1e}__sandbox_report_{:016x}__", nonce()),
+        }
+    }
+
+    /// Prefix of every report line.
+    pub(crate) fn marker(&self) -> &str {
+        &self.marker
+    }
+
+    /// Definitions needed by the given instrumentation, empty if there is none.
+    ///
+    /// Binds `__sandbox_report(key, value)`, which emits a report line, for
+    /// the setup snippets, and keeps a function running `teardown` with
+    /// `__sandbox_report` bound on `sys`, out of the user's globals, for
+    /// `assemble` to call. The marker is read from `REPORT_MARKER_VAR`, so
+    /// it never appears in the script itself.
+    ///
+    /// Teardown is the sandbox's own code, so it runs with the `__import__`
+    /// there was before any instrumentation replaced it, and untraced.
+    ///
+    /// Each report flushes `sys.__stderr__` and is then written straight to
+    /// the file descriptor, so it starts a write of its own even after a
+    /// partial line of user output; that is what `ReportFilter` looks for.
+    pub(crate) fn support(&self, setup: &[String], teardown: &[String]) -> String {
+        if setup.is_empty() && teardown.is_empty() {
+            return String::new();
+        }
+        // A JSON string literal is also a valid Python string literal.
+        let teardown =
+            serde_json::to_string(&teardown.join("\n")).expect("string serialization cannot fail");
+        format!(
+            r#"def __sandbox_open_channel(name, teardown):
+    import builtins, os, sys
+    marker = os.environ.get(name, '')
+    try:
+        del os.environ[name]
+    except Exception:
+        try:
+            os.environ._data.pop(os.environ.encodekey(name), None)
+        except Exception:
+            pass
+    try:
+        import posix
+        posix.environ.pop(name, None)
+        posix.environ.pop(name.encode(), None)
+    except Exception:
+        pass
+    write = os.write
+    stream = sys.__stderr__
+    def report(key, value):
+        try:
+            stream.flush()
+        except Exception:
+            pass
+        data = (marker + ' %s %s\n' % (key, value)).encode('utf-8', 'backslashreplace')
+        while data:
+            data = data[write(2, data):]
+    unlimited_import = builtins.__import__
+    done = []
+    def finish():
+        if done:
+            return
+        done.append(True)
+        builtins.__import__ = unlimited_import
+        sys.settrace(None)
+        namespace = dict(globals())
+        namespace['__sandbox_report'] = report
+        exec(teardown, namespace)
+    setattr(sys, '{FINISH_ATTR}', finish)
+    return report
+__sandbox_report = __sandbox_open_channel('{REPORT_MARKER_VAR}', {teardown})
+del __sandbox_open_channel
+"#
+        )
+    }
+
+    /// Parse report lines taken out of stderr.
+    ///
+    /// Lines that do not start with this channel's marker are skipped.
+    pub(crate) fn parse(&self, lines: &str) -> GuestReports {
+        let mut reports = GuestReports::default();
+        for line in lines.lines() {
+            if let Some(rest) = line.strip_prefix(&self.marker) {
+                let mut parts = rest.trim().splitn(2, ' ');
+                if let Some(key) = parts.next() {
+                    let value = parts.next().unwrap_or("").to_string();
+                    reports.entries.push((key.to_string(), value));
+                }
+            }
+        }
+        reports
+    }
+}
+
+/// Values reported by the guest instrumentation during an execution.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct GuestReports {
+    entries: Vec<(String, String)>,
+}
+
+impl GuestReports {
+    /// Get the last value reported under `key`.
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get the last value reported under `key`, parsed as a `u64`.
+    pub(crate) fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get(key).and_then(|v| v.parse().ok())
+    }
+
+    /// Get every value reported under `key`, in order.
+    #[cfg(feature = "audit")]
+    pub(crate) fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Check whether anything was reported under `key`.
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Build reports from key/value pairs.
+    #[cfg(all(test, feature = "audit"))]
+    pub(crate) fn from_entries(entries: &[(&str, &str)]) -> Self {
+        Self {
+            entries: entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Bind the global `context` to a JSON value embedded in the code.
+pub(crate) fn context_literal(json: &str) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let literal = serde_json::to_string(json).expect("string serialization cannot fail");
+    format!(
+        r#"def __sandbox_load_context(source):
+    import json
+    return json.loads(source)
+context = __sandbox_load_context({literal})
+del __sandbox_load_context
+"#
+    )
+}
+
+/// Bind the global `context` to a JSON value read from the first `len` bytes of stdin.
+///
+/// Reads the raw file descriptor so `sys.stdin` has buffered nothing yet
+/// when user code starts reading its own input.
+pub(crate) fn context_from_stdin(len: usize) -> String {
+    format!(
+        r#"def __sandbox_read_context(size):
+    import json, os
+    data = b''
+    while len(data) < size:
+        chunk = os.read(0, size - len(data))
+        if not chunk:
+            break
+        data += chunk
+    return json.loads(data.decode('utf-8'))
+context = __sandbox_read_context({len})
+del __sandbox_read_context
+"#
+    )
+}
+
+/// Instrumentation that counts exceptions raised during user code.
+///
+/// Uses `sys.settrace` to observe `exception` events. An exception that
+/// propagates through several frames is counted once. When `limit` is set
+/// and the count exceeds it, the run is terminated with `os._exit`. Only
+/// `line_coverage` may follow it, so exceptions raised and caught by the
+/// other setup snippets are not counted.
+pub(crate) fn exception_counter(limit: Option<u64>) -> String {
+    let limit = limit.map_or_else(|| "None".to_string(), |l| l.to_string());
+    format!(
+        r#"def __sandbox_count_exceptions(report, limit):
+    import sys, os, atexit
+    state = {{'count': 0, 'last': None}}
+    def local(frame, event, arg):
+        if event == 'exception' and arg[1] is not state['last']:
+            state['last'] = arg[1]
+            state['count'] += 1
+            if limit is not None and state['count'] > limit:
+                report('{EXCEPTIONS_KEY}', state['count'])
+                report('{EXCEPTION_LIMIT_KEY}', limit)
+                os._exit(1)
+        return local
+    atexit.register(lambda: report('{EXCEPTIONS_KEY}', state['count']))
+    sys.settrace(lambda frame, event, arg: local)
+    try:
+        sys._getframe(1).f_trace = local
+    except Exception:
+        pass
+__sandbox_count_exceptions(__sandbox_report, {limit})
+del __sandbox_count_exceptions
+"#
+    )
+}
+
+/// Instrumentation that reports an uncaught exception instead of printing it.
+///
+/// Installs a `sys.excepthook` that sends the formatted traceback to the
+/// host, keeping it out of the program's stderr. Falls back to the default
+/// hook if the traceback cannot be formatted. The modules it needs are
+/// imported when it is installed, before `import_limit` applies.
+pub(crate) fn exception_hook() -> String {
+    format!(
+        r#"def __sandbox_hook_exceptions(report):
+    import sys
+    try:
+        import json, traceback
+    except ImportError:
+        return
+    def hook(exc_type, exc, tb):
+        try:
+            text = ''.join(traceback.format_exception(exc_type, exc, tb))
+        except Exception:
+            return sys.__excepthook__(exc_type, exc, tb)
+        report('{TRACEBACK_KEY}', json.dumps(text))
+    sys.excepthook = hook
+__sandbox_hook_exceptions(__sandbox_report)
+del __sandbox_hook_exceptions
+"#
+    )
+}
+
+/// Instrumentation that disables the cyclic garbage collector.
+///
+/// Reports whether the collector was enabled when the interpreter exits,
+/// so code re-enabling it is visible. Nothing is reported if the
+/// interpreter has no usable `gc` module.
+pub(crate) fn gc_disable() -> String {
+    format!(
+        r#"def __sandbox_disable_gc(report):
+    import atexit
+    try:
+        import gc
+        gc.disable()
+    except Exception:
+        return
+    atexit.register(lambda: report('{GC_ENABLED_KEY}', int(gc.isenabled())))
+__sandbox_disable_gc(__sandbox_report)
+del __sandbox_disable_gc
+"#
+    )
+}
+
+/// Instrumentation that records which lines of user code run.
+///
+/// This must be the last setup snippet, and user code must have
+/// `line_count` lines; line numbers are reported relative to its first
+/// line. When user
+/// code is instead compiled under the file name `source` (as by
+/// `auto_print`), lines of that file are recorded as numbered. Any trace
+/// function installed earlier, such as `exception_counter`, keeps running.
+pub(crate) fn line_coverage(line_count: usize, source: Option<&str>) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let source = source.map_or_else(
+        || "None".to_string(),
+        |name| serde_json::to_string(name).expect("string serialization cannot fail"),
+    );
+    format!(
+        r#"def __sandbox_cover_lines(report, count, source):
+    import sys, atexit
+    caller = sys._getframe(1)
+    offset = 0
+    if source is None:
+        source, offset = caller.f_code.co_filename, caller.f_lineno + 2
+    lines = set()
+    def tracer(inner):
+        def local(frame, event, arg):
+            nonlocal inner
+            if event == 'line' and frame.f_code.co_filename == source:
+                line = frame.f_lineno - offset
+                if 0 < line <= count:
+                    lines.add(line)
+            if inner is not None:
+                inner = inner(frame, event, arg)
+            return local
+        return local
+    outer = sys.gettrace()
+    def trace(frame, event, arg):
+        return tracer(outer(frame, event, arg) if outer is not None else None)
+    # Formatted by hand: `json` would count against `import_limit`
+    atexit.register(lambda: report('{LINES_KEY}', '[%s]' % ', '.join(map(str, sorted(lines)))))
+    sys.settrace(trace)
+    try:
+        caller.f_trace = tracer(caller.f_trace)
+    except Exception:
+        pass
+__sandbox_cover_lines(__sandbox_report, {line_count}, {source})
+del __sandbox_cover_lines
+"#
+    )
+}
+
+/// Instrumentation that counts imports of new modules and caps them at `limit`.
+///
+/// Wraps `builtins.__import__`. Only imports made directly by user code
+/// are counted; modules imported while another import is running (a
+/// package loading its own dependencies) are not. Re-importing a module
+/// already in `sys.modules` is free. Once `limit` imports have been made,
+/// further ones raise `ImportError`. Best effort only: `importlib` does not
+/// go through `__import__`, and user code can put the original back.
+///
+/// The sandbox's own imports must not count: this goes after every setup
+/// snippet that imports modules, and puts the original `__import__` back
+/// at exit, before the at-exit captures registered earlier run. Teardown
+/// runs with the original restored as well (see `ReportChannel::support`).
+pub(crate) fn import_limit(limit: u64) -> String {
+    format!(
+        r#"def __sandbox_limit_imports(report, limit):
+    import builtins, sys, atexit
+    original = builtins.__import__
+    state = {{'count': 0, 'depth': 0}}
+    def limited(name, globals=None, locals=None, fromlist=(), level=0):
+        if state['depth'] > 0:
+            return original(name, globals, locals, fromlist, level)
+        if not (level == 0 and name in sys.modules) and state['count'] >= limit:
+            raise ImportError('import limit of %d reached' % limit, name=name)
+        before = len(sys.modules)
+        state['depth'] += 1
+        try:
+            module = original(name, globals, locals, fromlist, level)
+        finally:
+            state['depth'] -= 1
+        if len(sys.modules) > before:
+            state['count'] += 1
+        return module
+    builtins.__import__ = limited
+    def stop():
+        if builtins.__import__ is limited:
+            builtins.__import__ = original
+        report('{IMPORTS_KEY}', state['count'])
+    atexit.register(stop)
+__sandbox_limit_imports(__sandbox_report, {limit})
+del __sandbox_limit_imports
+"#
+    )
+}
+
+/// Instrumentation that reports the global namespace as JSON at exit.
+///
+/// Names starting with `__` are skipped. Values that `json.dumps` rejects
+/// are replaced by their type name. If the encoded namespace is larger than
+/// `max_bytes`, only its size is reported.
+pub(crate) fn globals_capture(max_bytes: usize) -> String {
+    format!(
+        r#"def __sandbox_capture_globals(report, limit):
+    import atexit
+    def capture():
+        import json
+        out = {{}}
+        for name, value in list(globals().items()):
+            if name.startswith('__'):
+                continue
+            try:
+                json.dumps(value, allow_nan=False)
+                out[name] = value
+            except Exception:
+                out[name] = type(value).__name__
+        data = json.dumps(out, allow_nan=False)
+        size = len(data.encode('utf-8'))
+        if size > limit:
+            report('{GLOBALS_TOO_LARGE_KEY}', size)
+        else:
+            report('{GLOBALS_KEY}', data)
+    atexit.register(capture)
+__sandbox_capture_globals(__sandbox_report, {max_bytes})
+del __sandbox_capture_globals
+"#
+    )
+}
+
+/// Check whether `name` is a Python identifier.
+///
+/// Keywords are accepted: they can never name a variable, so capturing
+/// one simply finds nothing.
+pub(crate) fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first == '_' || first.is_alphabetic())
+        && chars.all(|c| c == '_' || c.is_alphanumeric())
+}
+
+/// Instrumentation that reports the global `name` as JSON at exit.
+///
+/// Values that `json.dumps` rejects are reported as their `repr` string.
+/// Nothing is reported if the global does not exist; if the encoded value
+/// is larger than `max_bytes`, only its size is reported.
+pub(crate) fn var_capture(name: &str, max_bytes: usize) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let name = serde_json::to_string(name).expect("string serialization cannot fail");
+    format!(
+        r#"def __sandbox_capture_var(report, name, limit):
+    import atexit
+    def capture():
+        import json
+        namespace = globals()
+        if name not in namespace:
+            return
+        value = namespace[name]
+        try:
+            data = json.dumps(value, allow_nan=False)
+        except Exception:
+            data = json.dumps(repr(value))
+        size = len(data.encode('utf-8'))
+        if size > limit:
+            report('{VAR_TOO_LARGE_KEY}', size)
+        else:
+            report('{VAR_KEY}', data)
+    atexit.register(capture)
+__sandbox_capture_var(__sandbox_report, {name}, {max_bytes})
+del __sandbox_capture_var
+"#
+    )
+}
+
+/// Code evaluating `expr` into the global `__sandbox_result`.
+///
+/// The expression is compiled in `eval` mode, so statements are rejected
+/// with a `SyntaxError` instead of running.
+pub(crate) fn eval_expr(expr: &str) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let source = serde_json::to_string(expr).expect("string serialization cannot fail");
+    format!("__sandbox_result = eval(compile({source}, '<expr>', 'eval'))\n")
+}
+
+/// Instrumentation that reports the `repr` of `__sandbox_result`.
+///
+/// If the encoded `repr` is larger than `max_bytes`, only its size is
+/// reported.
+pub(crate) fn result_report(max_bytes: usize) -> String {
+    format!(
+        r#"def __sandbox_report_result(limit):
+    import json
+    data = json.dumps(repr(__sandbox_result))
+    size = len(data.encode('utf-8'))
+    if size > limit:
+        __sandbox_report('{RESULT_TOO_LARGE_KEY}', size)
+    else:
+        __sandbox_report('{RESULT_KEY}', data)
+__sandbox_report_result({max_bytes})
+del __sandbox_report_result
+"#
+    )
+}
+
+/// Instrumentation that makes Python-level stdio unusable.
+///
+/// `sys.stdout` and `sys.stderr` are replaced by objects whose writes raise
+/// `OSError`. The report channel writes to `sys.__stderr__` and keeps working.
+pub(crate) fn no_stdio() -> String {
+    r#"class __SandboxNoStdio:
+    def write(self, data):
+        raise OSError('stdio is disabled in this sandbox')
+    def flush(self):
+        pass
+    def isatty(self):
+        return False
+import sys as __sandbox_sys
+__sandbox_sys.stdout = __sandbox_sys.stderr = __SandboxNoStdio()
+del __sandbox_sys, __SandboxNoStdio
+"#
+    .to_string()
+}
+
+/// Instrumentation that changes the working directory to `dir`.
+///
+/// Leaves it unchanged if the interpreter cannot change directories, so
+/// files in `dir` stay reachable by absolute path.
+pub(crate) fn chdir(dir: &str) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let dir = serde_json::to_string(dir).expect("string serialization cannot fail");
+    format!(
+        r#"def __sandbox_chdir(path):
+    import os
+    try:
+        os.chdir(path)
+    except OSError:
+        pass
+__sandbox_chdir({dir})
+del __sandbox_chdir
+"#
+    )
+}
+
+/// Instrumentation that seeds `random`.
+///
+/// Interpreters without a `random` module are left alone.
+pub(crate) fn random_seed(seed: u64) -> String {
+    format!(
+        "try:\n    import random as __sandbox_random\n    __sandbox_random.seed({seed})\n    del __sandbox_random\nexcept ImportError:\n    pass\n"
+    )
+}
+
+/// Instrumentation that replaces `sys.path`.
+pub(crate) fn sys_path(paths: &[String]) -> String {
+    // A JSON array of strings is also a valid Python list literal.
+    let paths = serde_json::to_string(paths).expect("string serialization cannot fail");
+    format!("import sys as __sandbox_sys\n__sandbox_sys.path[:] = {paths}\ndel __sandbox_sys\n")
+}
+
+/// Instrumentation that imports `modules` and binds them as globals.
+///
+/// Each name is bound like `import name` would, so `os.path` binds `os`.
+/// The first module that fails to import is reported and the interpreter
+/// exits before user code runs.
+pub(crate) fn preimport(modules: &[String]) -> String {
+    // A JSON array of strings is also a valid Python list literal.
+    let modules = serde_json::to_string(modules).expect("string serialization cannot fail");
+    format!(
+        r#"def __sandbox_preimport(report, names):
+    import json, sys
+    for name in names:
+        try:
+            module = __import__(name)
+        except Exception as e:
+            reason = '%s: %s' % (type(e).__name__, e)
+            report('{PREIMPORT_FAILED_KEY}', json.dumps([name, reason]))
+            sys.exit(1)
+        globals()[name.partition('.')[0]] = module
+__sandbox_preimport(__sandbox_report, {modules})
+del __sandbox_preimport
+"#
+    )
+}
+
+/// Harness that compiles and runs the prelude once, reporting its bytecode.
+///
+/// The prelude runs in a fresh namespace to check that it succeeds; the
+/// marshalled code object is only reported if it does.
+pub(crate) fn prelude_bytecode(prelude: &str) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let literal = serde_json::to_string(prelude).expect("string serialization cannot fail");
+    format!(
+        r#"def __sandbox_prime_prelude(source):
+    import marshal
+    code = compile(source, '<prelude>', 'exec')
+    exec(code, {{'__name__': '__main__', '__builtins__': __builtins__}})
+    __sandbox_report('{PRELUDE_BYTECODE_KEY}', marshal.dumps(code).hex())
+__sandbox_prime_prelude({literal})
+"#
+    )
+}
+
+/// Prelude replacement that runs previously compiled prelude bytecode.
+///
+/// `hex` must contain only hex digits.
+pub(crate) fn primed_prelude(hex: &str) -> String {
+    format!(
+        "import marshal as __sandbox_marshal\nexec(__sandbox_marshal.loads(bytes.fromhex('{hex}')))\ndel __sandbox_marshal\n"
+    )
+}
+
+/// Instrumentation that reports the interpreter's `sys.version`.
+pub(crate) fn version_report() -> String {
+    format!(
+        "import json as __sandbox_json, sys as __sandbox_sys\n__sandbox_report('{VERSION_KEY}', __sandbox_json.dumps(__sandbox_sys.version))\ndel __sandbox_json, __sandbox_sys\n"
+    )
+}
+
+/// Harness that runs `code`, then displays the value of a final bare expression.
+///
+/// Only the last top-level statement is considered. Its value is shown with
+/// `sys.displayhook`, as at the interactive prompt, so `None` (e.g. the
+/// result of `print(1)`) displays nothing.
+pub(crate) fn auto_print(code: &str) -> String {
+    // A JSON string literal is also a valid Python string literal.
+    let literal = serde_json::to_string(code).expect("string serialization cannot fail");
+    format!(
+        r#"def __sandbox_auto_print(source, namespace):
+    import ast, sys
+    tree = ast.parse(source, '<string>')
+    last = None
+    if tree.body and isinstance(tree.body[-1], ast.Expr):
+        last = ast.Expression(tree.body.pop().value)
+    exec(compile(tree, '<string>', 'exec'), namespace)
+    if last is not None:
+        sys.displayhook(eval(compile(last, '<string>', 'eval'), namespace))
+__sandbox_auto_print({literal}, globals())
+del __sandbox_auto_print
+"#
+    )
+}
+
+/// Assemble the full Python script passed to the interpreter.
+///
+/// `setup` snippets run after the prelude, immediately before user code,
+/// which starts on the line after `__sandbox_report` is deleted.
+/// `teardown` snippets are embedded in `support` and run by a line after
+/// user code, if it completes, which takes the runner `support` left on
+/// `sys`.
+pub(crate) fn assemble(parts: &Script<'_>) -> String {
+    let mut script = String::new();
+    script.push_str(parts.support);
+    if let Some(prelude) = parts.prelude {
+        script.push_str(prelude);
+        script.push('\n');
+    }
+    for snippet in parts.setup {
+        script.push_str(snippet);
+    }
+    if !parts.support.is_empty() {
+        script.push_str("del __sandbox_report\n");
+    }
+    script.push_str(parts.code);
+    if !parts.teardown.is_empty() {
+        script.push_str(&format!(
+            "\n__import__('sys').__dict__.pop('{FINISH_ATTR}', lambda: None)()\n"
+        ));
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reports() {
+        let channel = ReportChannel::new();
+        let lines = format!("{} exceptions 3\n", channel.marker);
+        let reports = channel.parse(&lines);
+
+        assert_eq!(reports.get_u64(EXCEPTIONS_KEY), Some(3));
+        assert!(!reports.contains(EXCEPTION_LIMIT_KEY));
+    }
+
+    #[test]
+    fn test_parse_reports_ignores_forged_marker() {
+        let channel = ReportChannel::new();
+        let forged = ReportChannel::new();
+        let lines = format!(
+            "{} globals {{\"x\": 1}}\n{} globals {{\"x\": 42}}\n",
+            channel.marker, forged.marker
+        );
+        let reports = channel.parse(&lines);
+
+        assert_eq!(reports.get(GLOBALS_KEY), Some(r#"{"x": 1}"#));
+    }
+
+    #[test]
+    fn test_assemble_without_setup() {
+        let channel = ReportChannel::new();
+        let script = assemble(&Script {
+            support: &channel.support(&[], &[]),
+            prelude: Some("x = 1"),
+            setup: &[],
+            code: "print(x)",
+            teardown: &[],
+        });
+        assert_eq!(script, "x = 1\nprint(x)");
+    }
+
+    #[test]
+    fn test_assemble_with_setup() {
+        let channel = ReportChannel::new();
+        let setup = [exception_counter(Some(5))];
+        let script = assemble(&Script {
+            support: &channel.support(&setup, &[]),
+            prelude: None,
+            setup: &setup,
+            code: "pass",
+            teardown: &[],
+        });
+        assert!(script.starts_with("def __sandbox_open_channel("));
+        assert!(script.ends_with("\ndel __sandbox_report\npass"));
+        // The script is passed on the command line, so it must not give
+        // the marker away
+        assert!(!script.contains(&channel.marker()[1..]));
+    }
+
+    #[test]
+    fn test_assemble_with_teardown() {
+        let channel = ReportChannel::new();
+        let teardown = [version_report()];
+        let script = assemble(&Script {
+            support: &channel.support(&[], &teardown),
+            prelude: None,
+            setup: &[],
+            code: "pass",
+            teardown: &teardown,
+        });
+        assert!(script.ends_with(
+            "\ndel __sandbox_report\npass\n__import__('sys').__dict__.pop('__sandbox_finish__', lambda: None)()\n"
+        ));
+        assert!(!script.contains(&channel.marker()[1..]));
+    }
+
+    #[test]
+    fn test_is_identifier() {
+        assert!(is_identifier("x"));
+        assert!(is_identifier("_private2"));
+        assert!(is_identifier("résumé"));
+
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("2x"));
+        assert!(!is_identifier("x.y"));
+        assert!(!is_identifier("x'); import os; ('"));
+    }
+
+    #[test]
+    fn test_eval_expr_quotes_source() {
+        let code = eval_expr("len('a\nb')");
+
+        assert_eq!(
+            code,
+            "__sandbox_result = eval(compile(\"len('a\\nb')\", '<expr>', 'eval'))\n"
+        );
+    }
+}
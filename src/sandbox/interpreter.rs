@@ -0,0 +1,104 @@
+//! How the sandbox drives the interpreter module.
+//!
+//! Everything that depends on the particular interpreter build lives behind
+//! the `Interpreter` trait: the command line it expects, the export that
+//! starts it, and how the script run for each call is put together from the
+//! prelude, the sandbox's instrumentation and user code. `RustPythonInterpreter`
+//! is the default; other Python-to-wasm builds can be supported by
+//! implementing the trait and passing it to `SandboxConfigBuilder::interpreter`.
+//!
+//! The instrumentation snippets (exception counting, context injection,
+//! ...) are Python source. An interpreter for another language can still
+//! run plain code by ignoring them in `assemble`, at the cost of the
+//! features that rely on them.
+
+use std::fmt::Debug;
+
+use crate::sandbox::guest;
+
+/// The pieces of the script run for one call, in execution order.
+#[derive(Debug, Clone, Copy)]
+pub struct Script<'a> {
+    /// Definitions used by the instrumentation to report to the host.
+    ///
+    /// Also embeds `teardown`, run by the line `guest::assemble` appends
+    /// after user code. Empty when there is no instrumentation.
+    pub support: &'a str,
+    /// The configured prelude, if any.
+    pub prelude: Option<&'a str>,
+    /// Instrumentation to run after the prelude, directly before user code.
+    pub setup: &'a [String],
+    /// User code.
+    pub code: &'a str,
+    /// Instrumentation to run after user code, if it completes.
+    ///
+    /// Already part of `support`; listed for interpreters that assemble
+    /// scripts differently.
+    pub teardown: &'a [String],
+}
+
+/// An interpreter build the sandbox can run code with.
+///
+/// The module is still instantiated with WASI preview 1; the entry point
+/// must take no arguments and return nothing.
+pub trait Interpreter: Debug + Send + Sync {
+    /// Command line, including the program name, that runs `script`.
+    fn args(&self, script: &str) -> Vec<String>;
+
+    /// Command line, including the program name, that runs `module` as the
+    /// main module.
+    ///
+    /// `None`, the default, means the interpreter cannot run modules by name.
+    fn module_args(&self, module: &str) -> Option<Vec<String>> {
+        let _ = module;
+        None
+    }
+
+    /// Name of the exported function that runs the program.
+    fn entry_point(&self) -> &str {
+        "_start"
+    }
+
+    /// Combine the parts of a call's script into the source to run.
+    ///
+    /// The default concatenates the parts as Python source, which is what
+    /// the sandbox's instrumentation expects.
+    fn assemble(&self, script: Script<'_>) -> String {
+        guest::assemble(&script)
+    }
+}
+
+/// RustPython, run as `python -c <script>`, or `python -m <module>`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RustPythonInterpreter;
+
+impl Interpreter for RustPythonInterpreter {
+    fn args(&self, script: &str) -> Vec<String> {
+        vec!["python".to_string(), "-c".to_string(), script.to_string()]
+    }
+
+    fn module_args(&self, module: &str) -> Option<Vec<String>> {
+        Some(vec![
+            "python".to_string(),
+            "-m".to_string(),
+            module.to_string(),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rustpython_interpreter() {
+        let interpreter = RustPythonInterpreter;
+
+        assert_eq!(interpreter.args("print(1)"), ["python", "-c", "print(1)"]);
+        assert_eq!(
+            interpreter.module_args("json.tool").unwrap(),
+            ["python", "-m", "json.tool"]
+        );
+        assert_eq!(interpreter.entry_point(), "_start");
+    }
+}
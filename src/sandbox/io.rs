@@ -1,12 +1,29 @@
 //! I/O capture for sandbox stdin/stdout/stderr.
 
+use std::borrow::Cow;
 use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::sync::Notify;
+use wasmtime_wasi::pipe::MemoryOutputPipe;
+use wasmtime_wasi::{
+    HostOutputStream, StdinStream, StdoutStream, StreamError, StreamResult, Subscribe,
+};
+
+use crate::sandbox::config::SandboxConfig;
 
 /// A writer that captures output to a buffer.
+///
+/// Clones share the same buffer. Once `finalize` has been called, further
+/// writes through any clone fail, so the finalized contents are final.
 #[derive(Clone, Debug)]
 pub struct CapturedOutput {
     buffer: Arc<Mutex<Vec<u8>>>,
+    /// Set, with the buffer locked, by `finalize`.
+    finalized: Arc<AtomicBool>,
 }
 
 impl CapturedOutput {
@@ -14,9 +31,31 @@ impl CapturedOutput {
     pub fn new() -> Self {
         Self {
             buffer: Arc::new(Mutex::new(Vec::new())),
+            finalized: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Close the buffer to writes and return its complete contents.
+    ///
+    /// Every write that returned before `finalize` was called, on any
+    /// thread, is included: writes and `finalize` are serialized by the
+    /// buffer's lock, which orders each completed write before the
+    /// snapshot. Writes that start afterwards fail with
+    /// `ErrorKind::BrokenPipe` instead of appending, so the returned bytes
+    /// and any later `to_bytes` agree. Calling it again returns the same
+    /// contents.
+    pub fn finalize(&self) -> Vec<u8> {
+        let buffer = self.buffer.lock().unwrap();
+        // The lock orders this store; a relaxed load under it sees it.
+        self.finalized.store(true, Ordering::Relaxed);
+        buffer.clone()
+    }
+
+    /// Check whether `finalize` has been called.
+    pub fn is_finalized(&self) -> bool {
+        self.finalized.load(Ordering::Relaxed)
+    }
+
     /// Get the captured output as a string.
     pub fn to_string_lossy(&self) -> String {
         let buffer = self.buffer.lock().unwrap();
@@ -56,6 +95,12 @@ impl Default for CapturedOutput {
 impl Write for CapturedOutput {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let mut buffer = self.buffer.lock().unwrap();
+        if self.finalized.load(Ordering::Relaxed) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "captured output was finalized",
+            ));
+        }
         buffer.extend_from_slice(buf);
         Ok(buf.len())
     }
@@ -66,9 +111,13 @@ impl Write for CapturedOutput {
 }
 
 /// A reader that provides input from a buffer.
+///
+/// Also serves as the sandbox's WASI stdin. When tracing is enabled, the
+/// size of every read is recorded.
 #[derive(Clone, Debug)]
 pub struct ProvidedInput {
     buffer: Arc<Mutex<std::io::Cursor<Vec<u8>>>>,
+    reads: Option<Arc<Mutex<Vec<usize>>>>,
 }
 
 impl ProvidedInput {
@@ -76,7 +125,33 @@ impl ProvidedInput {
     pub fn new(data: Vec<u8>) -> Self {
         Self {
             buffer: Arc::new(Mutex::new(std::io::Cursor::new(data))),
+            reads: None,
+        }
+    }
+
+    /// Enable recording of the size of each read.
+    pub fn traced(mut self) -> Self {
+        self.reads = Some(Arc::new(Mutex::new(Vec::new())));
+        self
+    }
+
+    /// Get the sizes of all reads so far (empty unless tracing is enabled).
+    pub fn reads(&self) -> Vec<usize> {
+        self.reads
+            .as_ref()
+            .map(|reads| reads.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Read up to `buf.len()` bytes, recording the size if tracing.
+    fn read_traced(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.buffer.lock().unwrap().read(buf)?;
+        if let Some(ref reads) = self.reads {
+            if n > 0 {
+                reads.lock().unwrap().push(n);
+            }
         }
+        Ok(n)
     }
 
     /// Create an empty input provider.
@@ -98,11 +173,964 @@ impl Default for ProvidedInput {
 
 impl Read for ProvidedInput {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let mut cursor = self.buffer.lock().unwrap();
-        cursor.read(buf)
+        self.read_traced(buf)
+    }
+}
+
+impl wasmtime_wasi::HostInputStream for ProvidedInput {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let mut buf = vec![0u8; size];
+        let n = self
+            .read_traced(&mut buf)
+            .map_err(|e| StreamError::LastOperationFailed(e.into()))?;
+        if n == 0 && size > 0 {
+            return Err(StreamError::Closed);
+        }
+        buf.truncate(n);
+        Ok(Bytes::from(buf))
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for ProvidedInput {
+    async fn ready(&mut self) {}
+}
+
+impl StdinStream for ProvidedInput {
+    fn stream(&self) -> Box<dyn wasmtime_wasi::HostInputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+/// A callback producing stdin on demand, one chunk per call.
+///
+/// Returning `None` signals end of input. The callback is shared by all
+/// executions of a sandbox and is called under a lock from the blocking
+/// thread running the guest, so concurrent executions take turns drawing
+/// from it. Each call blocks the guest's read until it returns, and a
+/// timed-out guest cannot be interrupted while it is waiting on the callback.
+#[derive(Clone)]
+pub struct StdinGenerator {
+    generator: Arc<Mutex<dyn FnMut() -> Option<Vec<u8>> + Send>>,
+}
+
+impl StdinGenerator {
+    /// Wrap a callback producing the next chunk of input.
+    pub fn new(generator: impl FnMut() -> Option<Vec<u8>> + Send + 'static) -> Self {
+        Self {
+            generator: Arc::new(Mutex::new(generator)),
+        }
+    }
+
+    /// Call the generator for the next chunk.
+    fn next_chunk(&self) -> Option<Vec<u8>> {
+        (self.generator.lock().unwrap())()
+    }
+}
+
+impl std::fmt::Debug for StdinGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StdinGenerator").finish_non_exhaustive()
+    }
+}
+
+/// WASI stdin for one execution, fed by a `StdinGenerator`.
+///
+/// Chunks larger than a read are buffered here, so nothing carries over
+/// from one execution to the next.
+#[derive(Clone, Debug)]
+pub struct GeneratedInput {
+    generator: StdinGenerator,
+    pending: Arc<Mutex<GeneratedState>>,
+}
+
+#[derive(Debug, Default)]
+struct GeneratedState {
+    chunk: Vec<u8>,
+    offset: usize,
+    exhausted: bool,
+}
+
+impl GeneratedInput {
+    /// Create stdin for one execution drawing from `generator`.
+    pub fn new(generator: StdinGenerator) -> Self {
+        Self {
+            generator,
+            pending: Arc::new(Mutex::new(GeneratedState::default())),
+        }
+    }
+}
+
+impl wasmtime_wasi::HostInputStream for GeneratedInput {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let mut state = self.pending.lock().unwrap();
+        while state.offset == state.chunk.len() {
+            if state.exhausted {
+                return Err(StreamError::Closed);
+            }
+            match self.generator.next_chunk() {
+                Some(chunk) => {
+                    state.chunk = chunk;
+                    state.offset = 0;
+                }
+                None => state.exhausted = true,
+            }
+        }
+        let end = state.chunk.len().min(state.offset + size);
+        let bytes = Bytes::copy_from_slice(&state.chunk[state.offset..end]);
+        state.offset = end;
+        Ok(bytes)
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for GeneratedInput {
+    async fn ready(&mut self) {}
+}
+
+impl StdinStream for GeneratedInput {
+    fn stream(&self) -> Box<dyn wasmtime_wasi::HostInputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+/// Watches stdout for a sentinel and signals when it first appears.
+///
+/// Output is recorded up to the sentinel so it can be returned even if
+/// the guest never hands back its output after being interrupted.
+#[derive(Clone, Debug)]
+pub(crate) struct SentinelWatch {
+    sentinel: Arc<[u8]>,
+    state: Arc<Mutex<SentinelState>>,
+    found: Arc<Notify>,
+}
+
+#[derive(Debug, Default)]
+struct SentinelState {
+    output: Vec<u8>,
+    found_at: Option<usize>,
+}
+
+impl SentinelWatch {
+    /// Watch for `sentinel`, which must not be empty.
+    pub(crate) fn new(sentinel: &str) -> Self {
+        debug_assert!(!sentinel.is_empty());
+        Self {
+            sentinel: Arc::from(sentinel.as_bytes()),
+            state: Arc::new(Mutex::new(SentinelState::default())),
+            found: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Record output written to stdout, checking for the sentinel.
+    fn observe(&self, bytes: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if state.found_at.is_some() {
+            return;
+        }
+        // The sentinel may straddle the previous write
+        let start = state.output.len().saturating_sub(self.sentinel.len() - 1);
+        state.output.extend_from_slice(bytes);
+        if let Some(pos) = state.output[start..]
+            .windows(self.sentinel.len())
+            .position(|window| window == &*self.sentinel)
+        {
+            state.found_at = Some(start + pos);
+            self.found.notify_one();
+        }
+    }
+
+    /// Wait until the sentinel has been written.
+    pub(crate) async fn found(&self) {
+        self.found.notified().await;
+    }
+
+    /// Output written before the sentinel, if it has appeared.
+    pub(crate) fn output_before(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .found_at
+            .map(|end| String::from_utf8_lossy(&state.output[..end]).into_owned())
+    }
+
+    /// Wrap `pipe` so writes to it are also checked for the sentinel.
+    pub(crate) fn stdout(&self, pipe: MemoryOutputPipe) -> SentinelOutput {
+        SentinelOutput {
+            pipe,
+            watch: self.clone(),
+        }
+    }
+}
+
+/// WASI stdout that feeds a `SentinelWatch` as well as a capture pipe.
+#[derive(Clone, Debug)]
+pub(crate) struct SentinelOutput {
+    pipe: MemoryOutputPipe,
+    watch: SentinelWatch,
+}
+
+impl HostOutputStream for SentinelOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.watch.observe(&bytes);
+        self.pipe.write(bytes)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.pipe.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.pipe.check_write()
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for SentinelOutput {
+    async fn ready(&mut self) {}
+}
+
+impl StdoutStream for SentinelOutput {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+/// Largest write a `CapturedStream` accepts at once.
+const CAPTURED_WRITE_CHUNK: usize = 64 * 1024;
+
+/// WASI stdout or stderr appending to a `CapturedOutput`.
+///
+/// Unlike a `MemoryOutputPipe` the buffer can be cleared, so one stream
+/// can serve many calls into the same instance.
+#[derive(Clone, Debug)]
+pub(crate) struct CapturedStream {
+    output: CapturedOutput,
+}
+
+impl CapturedStream {
+    /// Create a stream writing into `output`.
+    pub(crate) fn new(output: CapturedOutput) -> Self {
+        Self { output }
+    }
+}
+
+impl HostOutputStream for CapturedStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.output
+            .write_all(&bytes)
+            .map_err(|e| StreamError::LastOperationFailed(e.into()))
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(CAPTURED_WRITE_CHUNK)
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for CapturedStream {
+    async fn ready(&mut self) {}
+}
+
+impl StdoutStream for CapturedStream {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+/// Separates the guest's report lines from the program's stderr.
+///
+/// The guest sends each report line with writes of its own, the first of
+/// which starts with the report marker. A long line can reach the host
+/// over several writes, so once a write starts with the marker, everything
+/// up to and including the next newline is part of the report, whichever
+/// write it arrives in. Report lines are collected here and everything
+/// else is passed on, so the streams further down (output limits, rate,
+/// first output, events) only ever see program output.
+#[derive(Clone, Debug)]
+pub(crate) struct ReportFilter {
+    marker: Arc<str>,
+    state: Arc<Mutex<ReportFilterState>>,
+}
+
+#[derive(Debug, Default)]
+struct ReportFilterState {
+    /// Whether the last write ended inside a report line.
+    in_report: bool,
+    lines: Vec<u8>,
+}
+
+impl ReportFilter {
+    /// Filter out report lines starting with `marker`.
+    pub(crate) fn new(marker: &str) -> Self {
+        Self {
+            marker: Arc::from(marker),
+            state: Arc::new(Mutex::new(ReportFilterState::default())),
+        }
+    }
+
+    /// The report lines written so far.
+    pub(crate) fn lines(&self) -> String {
+        String::from_utf8_lossy(&self.state.lock().unwrap().lines).into_owned()
+    }
+
+    /// Wrap `stream` so report lines are taken out of what is written to it.
+    pub(crate) fn wrap<S: StdoutStream>(&self, stream: S) -> ReportFilteredOutput<S> {
+        ReportFilteredOutput {
+            stream,
+            filter: self.clone(),
+        }
+    }
+
+    /// Collect the report lines in `bytes`, returning the program output left.
+    fn take_reports(&self, mut bytes: Bytes) -> Bytes {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if !state.in_report {
+                if !bytes.starts_with(self.marker.as_bytes()) {
+                    return bytes;
+                }
+                state.in_report = true;
+            }
+            match bytes.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    state.lines.extend_from_slice(&bytes[..=end]);
+                    state.in_report = false;
+                    bytes = bytes.slice(end + 1..);
+                }
+                None => {
+                    state.lines.extend_from_slice(&bytes);
+                    return Bytes::new();
+                }
+            }
+        }
+    }
+}
+
+/// WASI output with the guest's report lines taken out by a `ReportFilter`.
+pub(crate) struct ReportFilteredOutput<S> {
+    stream: S,
+    filter: ReportFilter,
+}
+
+impl<S: StdoutStream> StdoutStream for ReportFilteredOutput<S> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(ReportFilteredStream {
+            inner: self.stream.stream(),
+            filter: self.filter.clone(),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.stream.isatty()
+    }
+}
+
+struct ReportFilteredStream {
+    inner: Box<dyn HostOutputStream>,
+    filter: ReportFilter,
+}
+
+impl HostOutputStream for ReportFilteredStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        if bytes.is_empty() {
+            return self.inner.write(bytes);
+        }
+        let output = self.filter.take_reports(bytes);
+        if output.is_empty() {
+            return Ok(());
+        }
+        self.inner.write(output)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for ReportFilteredStream {
+    async fn ready(&mut self) {
+        self.inner.ready().await
+    }
+}
+
+/// The limits on an execution's captured output, shared by its streams.
+///
+/// Stdout is cut at the line limit and both streams at the byte limit
+/// before anything sees them, but the output rate and the first write
+/// count everything written.
+#[derive(Clone, Debug)]
+pub(crate) struct OutputLimits {
+    pub(crate) first_output: FirstOutput,
+    pub(crate) lines: LineLimit,
+    pub(crate) bytes: ByteLimit,
+    pub(crate) rate: OutputRate,
+}
+
+impl OutputLimits {
+    /// The output limits of `config`, measuring time from `start`.
+    pub(crate) fn new(config: &SandboxConfig, start: Instant) -> Self {
+        Self {
+            first_output: FirstOutput::new(start),
+            lines: LineLimit::new(config.max_output_lines, config.terminate_on_output_lines),
+            bytes: ByteLimit::new(config.max_output_bytes),
+            rate: OutputRate::new(config.max_output_rate, start),
+        }
+    }
+
+    /// Wrap the program's stdout in every limit.
+    pub(crate) fn stdout<S: StdoutStream>(
+        &self,
+        stream: S,
+    ) -> TimedOutput<RateMeasuredOutput<LineLimitedOutput<ByteLimitedOutput<S>>>> {
+        self.first_output
+            .watch(self.rate.wrap(self.lines.wrap(self.bytes.wrap(stream))))
+    }
+
+    /// Wrap the program's stderr in every limit but the line limit.
+    pub(crate) fn stderr<S: StdoutStream>(
+        &self,
+        stream: S,
+    ) -> TimedOutput<RateMeasuredOutput<ByteLimitedOutput<S>>> {
+        self.first_output
+            .watch(self.rate.wrap(self.bytes.wrap(stream)))
+    }
+
+    /// Start counting afresh from `start`, for another run on the same streams.
+    pub(crate) fn reset(&self, start: Instant) {
+        self.first_output.reset(start);
+        *self.lines.state.lock().unwrap() = LineLimitState::default();
+        *self.bytes.state.lock().unwrap() = ByteLimitState::default();
+        *self.rate.state.lock().unwrap() = OutputRateState::new(start);
+    }
+}
+
+/// Records when an execution first writes output.
+#[derive(Clone, Debug)]
+pub(crate) struct FirstOutput {
+    state: Arc<Mutex<FirstOutputState>>,
+}
+
+#[derive(Debug)]
+struct FirstOutputState {
+    start: Instant,
+    at: Option<Duration>,
+}
+
+impl FirstOutput {
+    /// Measure from `start`.
+    pub(crate) fn new(start: Instant) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FirstOutputState { start, at: None })),
+        }
+    }
+
+    /// Time from the start until the first byte was written, if any was.
+    pub(crate) fn elapsed(&self) -> Option<Duration> {
+        self.state.lock().unwrap().at
+    }
+
+    /// Forget any write so far and measure from `start`.
+    fn reset(&self, start: Instant) {
+        *self.state.lock().unwrap() = FirstOutputState { start, at: None };
+    }
+
+    /// Wrap `stream` so its first non-empty write is recorded.
+    pub(crate) fn watch<S: StdoutStream>(&self, stream: S) -> TimedOutput<S> {
+        TimedOutput {
+            stream,
+            first: self.clone(),
+        }
+    }
+
+    fn record(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.at.is_none() {
+            state.at = Some(state.start.elapsed());
+        }
+    }
+}
+
+/// WASI output that records its first write in a `FirstOutput`.
+pub(crate) struct TimedOutput<S> {
+    stream: S,
+    first: FirstOutput,
+}
+
+impl<S: StdoutStream> StdoutStream for TimedOutput<S> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(TimedStream {
+            inner: self.stream.stream(),
+            first: self.first.clone(),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.stream.isatty()
+    }
+}
+
+struct TimedStream {
+    inner: Box<dyn HostOutputStream>,
+    first: FirstOutput,
+}
+
+impl HostOutputStream for TimedStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        if !bytes.is_empty() {
+            self.first.record();
+        }
+        self.inner.write(bytes)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for TimedStream {
+    async fn ready(&mut self) {
+        self.inner.ready().await
+    }
+}
+
+/// Caps the number of lines an output stream passes on.
+///
+/// Every line up to and including the `limit`-th newline is written
+/// through; everything after it is dropped, or traps the guest when
+/// `terminate` is set. Without a limit, writes pass through unchanged.
+#[derive(Clone, Debug)]
+pub(crate) struct LineLimit {
+    limit: Option<usize>,
+    terminate: bool,
+    state: Arc<Mutex<LineLimitState>>,
+}
+
+#[derive(Debug, Default)]
+struct LineLimitState {
+    lines: usize,
+    reached: bool,
+}
+
+impl LineLimit {
+    /// Limit output to `limit` lines, if any.
+    pub(crate) fn new(limit: Option<usize>, terminate: bool) -> Self {
+        Self {
+            limit,
+            terminate,
+            state: Arc::new(Mutex::new(LineLimitState::default())),
+        }
+    }
+
+    /// Check whether output past the limit was dropped.
+    pub(crate) fn reached(&self) -> bool {
+        self.state.lock().unwrap().reached
+    }
+
+    /// Check whether the guest was stopped for writing past the limit.
+    pub(crate) fn terminated(&self) -> bool {
+        self.terminate && self.reached()
+    }
+
+    /// Wrap `stream` so at most `limit` lines are written to it.
+    pub(crate) fn wrap<S: StdoutStream>(&self, stream: S) -> LineLimitedOutput<S> {
+        LineLimitedOutput {
+            stream,
+            limit: self.clone(),
+        }
+    }
+
+    /// Cut `bytes` to the part still within the limit, recording what was written.
+    ///
+    /// Returns `None` when nothing past the limit was dropped.
+    fn cut(&self, bytes: &Bytes) -> Option<Bytes> {
+        let limit = self.limit?;
+        let mut state = self.state.lock().unwrap();
+        if state.reached {
+            return Some(Bytes::new());
+        }
+        if state.lines == limit {
+            if bytes.is_empty() {
+                return None;
+            }
+            state.reached = true;
+            return Some(Bytes::new());
+        }
+        for (pos, _) in bytes.iter().enumerate().filter(|(_, b)| **b == b'\n') {
+            state.lines += 1;
+            if state.lines == limit {
+                if pos + 1 == bytes.len() {
+                    break;
+                }
+                state.reached = true;
+                return Some(bytes.slice(..=pos));
+            }
+        }
+        None
+    }
+}
+
+/// WASI output that passes at most a `LineLimit` of lines to its stream.
+pub(crate) struct LineLimitedOutput<S> {
+    stream: S,
+    limit: LineLimit,
+}
+
+impl<S: StdoutStream> StdoutStream for LineLimitedOutput<S> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(LineLimitedStream {
+            inner: self.stream.stream(),
+            limit: self.limit.clone(),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.stream.isatty()
+    }
+}
+
+struct LineLimitedStream {
+    inner: Box<dyn HostOutputStream>,
+    limit: LineLimit,
+}
+
+impl HostOutputStream for LineLimitedStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let Some(kept) = self.limit.cut(&bytes) else {
+            return self.inner.write(bytes);
+        };
+        if !kept.is_empty() {
+            self.inner.write(kept)?;
+        }
+        if self.limit.terminate {
+            return Err(StreamError::Trap(anyhow::anyhow!(
+                "output line limit reached"
+            )));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for LineLimitedStream {
+    async fn ready(&mut self) {
+        self.inner.ready().await
+    }
+}
+
+/// Caps the number of bytes a set of output streams pass on, together.
+///
+/// Writes are passed through until `limit` bytes have been written across
+/// all wrapped streams. The write that crosses the limit is cut at exactly
+/// the limit, and everything after it is dropped while still reporting
+/// success to the guest. Without a limit, writes pass through unchanged.
+#[derive(Clone, Debug)]
+pub(crate) struct ByteLimit {
+    limit: Option<u64>,
+    state: Arc<Mutex<ByteLimitState>>,
+}
+
+#[derive(Debug, Default)]
+struct ByteLimitState {
+    written: u64,
+    reached: bool,
+}
+
+impl ByteLimit {
+    /// Limit output to `limit` bytes in total, if any.
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            state: Arc::new(Mutex::new(ByteLimitState::default())),
+        }
+    }
+
+    /// Check whether output past the limit was dropped.
+    pub(crate) fn reached(&self) -> bool {
+        self.state.lock().unwrap().reached
+    }
+
+    /// Wrap `stream` so its writes count towards the limit.
+    pub(crate) fn wrap<S: StdoutStream>(&self, stream: S) -> ByteLimitedOutput<S> {
+        ByteLimitedOutput {
+            stream,
+            limit: self.clone(),
+        }
+    }
+
+    /// Read `reader` to its end as output written outside the output
+    /// streams, keeping what fits.
+    ///
+    /// Nothing past the limit is read: one byte beyond it is enough to know
+    /// the data was cut.
+    pub(crate) fn read(&self, reader: impl Read) -> std::io::Result<Vec<u8>> {
+        let remaining = self
+            .limit
+            .map(|limit| limit - self.state.lock().unwrap().written);
+        let mut data = Vec::new();
+        reader
+            .take(remaining.map_or(u64::MAX, |remaining| remaining.saturating_add(1)))
+            .read_to_end(&mut data)?;
+        let bytes = Bytes::from(data);
+        Ok(match self.cut(&bytes) {
+            Some(kept) => kept.to_vec(),
+            None => bytes.into(),
+        })
+    }
+
+    /// Cut `bytes` to the part still within the limit, recording what was written.
+    ///
+    /// Returns `None` when nothing was dropped.
+    fn cut(&self, bytes: &Bytes) -> Option<Bytes> {
+        let limit = self.limit?;
+        let mut state = self.state.lock().unwrap();
+        let remaining = limit - state.written;
+        if (bytes.len() as u64) <= remaining {
+            state.written += bytes.len() as u64;
+            return None;
+        }
+        state.written = limit;
+        state.reached = true;
+        Some(bytes.slice(..remaining as usize))
+    }
+}
+
+/// WASI output that passes on at most a `ByteLimit` of bytes.
+pub(crate) struct ByteLimitedOutput<S> {
+    stream: S,
+    limit: ByteLimit,
+}
+
+impl<S: StdoutStream> StdoutStream for ByteLimitedOutput<S> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(ByteLimitedStream {
+            inner: self.stream.stream(),
+            limit: self.limit.clone(),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.stream.isatty()
+    }
+}
+
+struct ByteLimitedStream {
+    inner: Box<dyn HostOutputStream>,
+    limit: ByteLimit,
+}
+
+impl HostOutputStream for ByteLimitedStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let Some(kept) = self.limit.cut(&bytes) else {
+            return self.inner.write(bytes);
+        };
+        if !kept.is_empty() {
+            self.inner.write(kept)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for ByteLimitedStream {
+    async fn ready(&mut self) {
+        self.inner.ready().await
+    }
+}
+
+/// Length of the windows over which `OutputRate` averages output.
+pub(crate) const OUTPUT_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Measures how fast an execution writes output.
+///
+/// Bytes written through wrapped streams are counted in consecutive
+/// windows of `OUTPUT_RATE_WINDOW`. A window is closed by the first
+/// `check` after it has elapsed; if its average rate is above the limit,
+/// the rate stays exceeded. Without a limit nothing is counted.
+#[derive(Clone, Debug)]
+pub(crate) struct OutputRate {
+    limit: Option<u64>,
+    state: Arc<Mutex<OutputRateState>>,
+}
+
+#[derive(Debug)]
+struct OutputRateState {
+    window_start: Instant,
+    bytes: u64,
+    exceeded: Option<u64>,
+}
+
+impl OutputRateState {
+    fn new(start: Instant) -> Self {
+        Self {
+            window_start: start,
+            bytes: 0,
+            exceeded: None,
+        }
+    }
+}
+
+impl OutputRate {
+    /// Limit output to `limit` bytes per second, measuring from `start`.
+    pub(crate) fn new(limit: Option<u64>, start: Instant) -> Self {
+        Self {
+            limit,
+            state: Arc::new(Mutex::new(OutputRateState::new(start))),
+        }
+    }
+
+    /// The average rate of the window that went over the limit, if one did.
+    pub(crate) fn exceeded(&self) -> Option<u64> {
+        self.state.lock().unwrap().exceeded
+    }
+
+    /// Close the current window if it has elapsed; true once the limit is exceeded.
+    pub(crate) fn check(&self) -> bool {
+        self.check_at(Instant::now())
+    }
+
+    fn check_at(&self, now: Instant) -> bool {
+        let Some(limit) = self.limit else {
+            return false;
+        };
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.window_start);
+        if state.exceeded.is_none() && elapsed >= OUTPUT_RATE_WINDOW {
+            let rate = (u128::from(state.bytes) * 1_000_000_000 / elapsed.as_nanos()) as u64;
+            if rate > limit {
+                state.exceeded = Some(rate);
+            }
+            state.window_start = now;
+            state.bytes = 0;
+        }
+        state.exceeded.is_some()
+    }
+
+    /// Wrap `stream` so the bytes written to it are counted.
+    pub(crate) fn wrap<S: StdoutStream>(&self, stream: S) -> RateMeasuredOutput<S> {
+        RateMeasuredOutput {
+            stream,
+            rate: self.clone(),
+        }
+    }
+
+    fn record(&self, bytes: &[u8]) {
+        if self.limit.is_none() {
+            return;
+        }
+        self.state.lock().unwrap().bytes += bytes.len() as u64;
+    }
+}
+
+/// WASI output whose writes are counted by an `OutputRate`.
+pub(crate) struct RateMeasuredOutput<S> {
+    stream: S,
+    rate: OutputRate,
+}
+
+impl<S: StdoutStream> StdoutStream for RateMeasuredOutput<S> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(RateMeasuredStream {
+            inner: self.stream.stream(),
+            rate: self.rate.clone(),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.stream.isatty()
     }
 }
 
+struct RateMeasuredStream {
+    inner: Box<dyn HostOutputStream>,
+    rate: OutputRate,
+}
+
+impl HostOutputStream for RateMeasuredStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.rate.record(&bytes);
+        self.inner.write(bytes)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Subscribe for RateMeasuredStream {
+    async fn ready(&mut self) {
+        self.inner.ready().await
+    }
+}
+
+/// Convert `\r\n` and lone `\r` line endings to `\n`.
+pub(crate) fn normalize_newlines(data: &[u8]) -> Cow<'_, [u8]> {
+    if !data.contains(&b'\r') {
+        return Cow::Borrowed(data);
+    }
+    let mut normalized = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' {
+            bytes.next_if_eq(&&b'\n');
+            normalized.push(b'\n');
+        } else {
+            normalized.push(byte);
+        }
+    }
+    Cow::Owned(normalized)
+}
+
 /// I/O configuration for a sandbox execution.
 #[derive(Clone)]
 pub struct SandboxIo {
@@ -117,8 +1145,15 @@ pub struct SandboxIo {
 impl SandboxIo {
     /// Create a new I/O configuration with optional input.
     pub fn new(input: Option<&str>) -> Self {
+        Self::from_bytes(input.map(str::as_bytes))
+    }
+
+    /// Create a new I/O configuration with optional raw input bytes.
+    pub fn from_bytes(input: Option<&[u8]>) -> Self {
         Self {
-            stdin: input.map(ProvidedInput::from_string).unwrap_or_default(),
+            stdin: input
+                .map(|data| ProvidedInput::new(data.to_vec()))
+                .unwrap_or_default(),
             stdout: CapturedOutput::new(),
             stderr: CapturedOutput::new(),
         }
@@ -133,6 +1168,19 @@ impl SandboxIo {
     pub fn stderr_str(&self) -> String {
         self.stderr.to_string_lossy()
     }
+
+    /// Finalize stdout and stderr and return them as strings.
+    ///
+    /// See `CapturedOutput::finalize`; afterwards `stdout_str` and
+    /// `stderr_str` return the same, complete output.
+    pub fn finalize(&self) -> (String, String) {
+        let stdout = self.stdout.finalize();
+        let stderr = self.stderr.finalize();
+        (
+            String::from_utf8_lossy(&stdout).into_owned(),
+            String::from_utf8_lossy(&stderr).into_owned(),
+        )
+    }
 }
 
 impl Default for SandboxIo {
@@ -145,6 +1193,126 @@ impl Default for SandboxIo {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_line_limit() {
+        let pipe = MemoryOutputPipe::new(1024);
+        let limit = LineLimit::new(Some(3), false);
+        let mut stdout = limit.wrap(pipe.clone()).stream();
+
+        stdout.write(Bytes::from_static(b"1\n2")).unwrap();
+        stdout.write(Bytes::from_static(b"\n3\n")).unwrap();
+        assert!(!limit.reached());
+        stdout.write(Bytes::from_static(b"4\n5\n")).unwrap();
+        assert!(limit.reached());
+        assert!(!limit.terminated());
+        assert_eq!(&pipe.contents()[..], b"1\n2\n3\n");
+
+        let pipe = MemoryOutputPipe::new(1024);
+        let limit = LineLimit::new(Some(1), true);
+        let mut stdout = limit.wrap(pipe.clone()).stream();
+
+        assert!(stdout.write(Bytes::from_static(b"a\nb\n")).is_err());
+        assert!(limit.terminated());
+        assert_eq!(&pipe.contents()[..], b"a\n");
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let stdout_pipe = MemoryOutputPipe::new(1024);
+        let stderr_pipe = MemoryOutputPipe::new(1024);
+        let limit = ByteLimit::new(Some(8));
+        let reports = ReportFilter::new("\x1e");
+        let mut stdout = limit.wrap(stdout_pipe.clone()).stream();
+        let mut stderr = reports.wrap(limit.wrap(stderr_pipe.clone())).stream();
+
+        stdout.write(Bytes::from_static(b"hello")).unwrap();
+        stderr.write(Bytes::from_static(b"\x1e report\n")).unwrap();
+        assert!(!limit.reached());
+        stderr.write(Bytes::from_static(b"world\n")).unwrap();
+        assert!(limit.reached());
+        stdout.write(Bytes::from_static(b"dropped")).unwrap();
+        stderr.write(Bytes::from_static(b"\x1e kept\n")).unwrap();
+
+        assert_eq!(&stdout_pipe.contents()[..], b"hello");
+        assert_eq!(&stderr_pipe.contents()[..], b"wor");
+        assert_eq!(reports.lines(), "\x1e report\n\x1e kept\n");
+
+        let pipe = MemoryOutputPipe::new(1024);
+        let unlimited = ByteLimit::new(None);
+        let mut stdout = unlimited.wrap(pipe.clone()).stream();
+        stdout.write(Bytes::from_static(b"anything")).unwrap();
+        assert!(!unlimited.reached());
+        assert_eq!(&pipe.contents()[..], b"anything");
+    }
+
+    #[test]
+    fn test_output_rate() {
+        let start = Instant::now();
+        let rate = OutputRate::new(Some(100), start);
+        let mut stdout = rate.wrap(MemoryOutputPipe::new(1024)).stream();
+
+        // Nothing is judged before a window has elapsed
+        stdout.write(Bytes::from(vec![b'x'; 150])).unwrap();
+        assert!(!rate.check_at(start + OUTPUT_RATE_WINDOW / 2));
+
+        // 150 bytes over two seconds is within the limit
+        assert!(!rate.check_at(start + OUTPUT_RATE_WINDOW * 2));
+        assert_eq!(rate.exceeded(), None);
+
+        // 300 bytes in the next second is not, and stays exceeded
+        stdout.write(Bytes::from(vec![b'x'; 300])).unwrap();
+        assert!(rate.check_at(start + OUTPUT_RATE_WINDOW * 3));
+        assert_eq!(rate.exceeded(), Some(300));
+        assert!(rate.check_at(start + OUTPUT_RATE_WINDOW * 10));
+
+        // Report lines and unlimited rates are not counted
+        let rate = OutputRate::new(Some(0), start);
+        let mut stderr = ReportFilter::new("\x1e")
+            .wrap(rate.wrap(MemoryOutputPipe::new(1024)))
+            .stream();
+        stderr.write(Bytes::from_static(b"\x1e report\n")).unwrap();
+        assert!(!rate.check_at(start + OUTPUT_RATE_WINDOW));
+        let rate = OutputRate::new(None, start);
+        let mut stdout = rate.wrap(MemoryOutputPipe::new(1024)).stream();
+        stdout.write(Bytes::from_static(b"flood")).unwrap();
+        assert!(!rate.check_at(start + OUTPUT_RATE_WINDOW));
+    }
+
+    #[test]
+    fn test_report_filter_across_writes() {
+        let pipe = MemoryOutputPipe::new(1024);
+        let limit = ByteLimit::new(Some(4));
+        let reports = ReportFilter::new("\x1e");
+        let mut stderr = reports.wrap(limit.wrap(pipe.clone())).stream();
+
+        // A report line split over several writes is kept whole and out
+        // of the program's output, however long it is
+        stderr.write(Bytes::from_static(b"partial")).unwrap();
+        stderr
+            .write(Bytes::from_static(b"\x1e globals {\"x\""))
+            .unwrap();
+        stderr.write(Bytes::from_static(b": 1}")).unwrap();
+        stderr.write(Bytes::from_static(b"\n")).unwrap();
+        stderr
+            .write(Bytes::from_static(b"\x1e version \"3\"\n"))
+            .unwrap();
+        stderr.write(Bytes::from_static(b"more\n")).unwrap();
+
+        assert_eq!(
+            reports.lines(),
+            "\x1e globals {\"x\": 1}\n\x1e version \"3\"\n"
+        );
+        assert_eq!(&pipe.contents()[..], b"part");
+        assert!(limit.reached());
+    }
+
+    #[test]
+    fn test_normalize_newlines() {
+        assert_eq!(&*normalize_newlines(b"a\r\nb\r\n"), b"a\nb\n");
+        assert_eq!(&*normalize_newlines(b"a\rb\r\r\nc"), b"a\nb\n\nc");
+        assert!(matches!(normalize_newlines(b"a\nb\n"), Cow::Borrowed(_)));
+    }
+
     #[test]
     fn test_captured_output() {
         let mut output = CapturedOutput::new();
@@ -153,6 +1321,38 @@ mod tests {
         assert_eq!(output.to_string_lossy(), "hello world");
     }
 
+    #[test]
+    fn test_captured_output_finalize() {
+        let output = CapturedOutput::new();
+        let writers: Vec<_> = (0..8u8)
+            .map(|n| {
+                let mut output = output.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        output.write_all(&[b'a' + n; 7]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let contents = output.finalize();
+        assert_eq!(contents.len(), 8 * 1000 * 7);
+        for n in 0..8u8 {
+            let count = contents.iter().filter(|&&b| b == b'a' + n).count();
+            assert_eq!(count, 7000);
+        }
+
+        let mut late = output.clone();
+        let err = late.write_all(b"late").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert!(output.is_finalized());
+        assert_eq!(output.finalize(), contents);
+        assert_eq!(output.to_bytes(), contents);
+    }
+
     #[test]
     fn test_provided_input() {
         let mut input = ProvidedInput::from_string("test input");
@@ -162,6 +1362,79 @@ mod tests {
         assert_eq!(&buf, b"test");
     }
 
+    #[test]
+    fn test_provided_input_traced() {
+        use wasmtime_wasi::HostInputStream;
+
+        let mut input = ProvidedInput::from_string("abcdefghij").traced();
+
+        assert_eq!(
+            HostInputStream::read(&mut input, 3).unwrap().as_ref(),
+            b"abc"
+        );
+        assert_eq!(
+            HostInputStream::read(&mut input, 4).unwrap().as_ref(),
+            b"defg"
+        );
+        assert_eq!(
+            HostInputStream::read(&mut input, 100).unwrap().as_ref(),
+            b"hij"
+        );
+        assert!(HostInputStream::read(&mut input, 100).is_err());
+
+        assert_eq!(input.reads(), vec![3, 4, 3]);
+    }
+
+    #[test]
+    fn test_generated_input() {
+        use wasmtime_wasi::HostInputStream;
+
+        let mut lines = vec!["one\n", "two\n", "three\n"].into_iter();
+        let generator = StdinGenerator::new(move || lines.next().map(|l| l.as_bytes().to_vec()));
+        let mut input = GeneratedInput::new(generator);
+
+        assert_eq!(input.read(100).unwrap().as_ref(), b"one\n");
+        assert_eq!(input.read(2).unwrap().as_ref(), b"tw");
+        assert_eq!(input.read(100).unwrap().as_ref(), b"o\n");
+        assert_eq!(input.read(100).unwrap().as_ref(), b"three\n");
+        assert!(input.read(100).is_err());
+        assert!(input.read(100).is_err());
+    }
+
+    #[test]
+    fn test_sentinel_watch_across_writes() {
+        let watch = SentinelWatch::new("<<END>>");
+        let mut stdout = watch.stdout(MemoryOutputPipe::new(1024));
+
+        stdout.write(Bytes::from_static(b"result\n<<E")).unwrap();
+        assert_eq!(watch.output_before(), None);
+        stdout.write(Bytes::from_static(b"ND>>\nmore")).unwrap();
+
+        assert_eq!(watch.output_before().as_deref(), Some("result\n"));
+    }
+
+    #[test]
+    fn test_first_output_ignores_reports() {
+        let first = FirstOutput::new(Instant::now());
+        let mut stderr = ReportFilter::new("\u{1e}report")
+            .wrap(first.watch(MemoryOutputPipe::new(1024)))
+            .stream();
+
+        stderr.write(Bytes::from_static(b"")).unwrap();
+        stderr
+            .write(Bytes::from_static(b"\x1ereport exceptions 0\n"))
+            .unwrap();
+        assert_eq!(first.elapsed(), None);
+
+        std::thread::sleep(Duration::from_millis(5));
+        stderr.write(Bytes::from_static(b"warning\n")).unwrap();
+        let elapsed = first.elapsed().unwrap();
+        assert!(elapsed >= Duration::from_millis(5));
+
+        stderr.write(Bytes::from_static(b"later\n")).unwrap();
+        assert_eq!(first.elapsed(), Some(elapsed));
+    }
+
     #[test]
     fn test_sandbox_io() {
         let io = SandboxIo::new(Some("input data"));
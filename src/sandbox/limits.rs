@@ -1,11 +1,49 @@
 //! Resource limiting for the Wasm sandbox.
 
-use wasmtime::{ResourceLimiter, Store};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use wasmtime::{CallHook, Engine, ResourceLimiter, Store};
+
+use crate::error::{Result, SandboxError, TimeoutPhase};
+use crate::sandbox::config::SandboxConfig;
+
+/// A dynamic policy deciding whether memory may grow to a given size.
+///
+/// The function receives the desired total memory size in bytes and
+/// returns whether the growth is allowed.
+#[derive(Clone)]
+pub struct MemoryPolicy(Arc<dyn Fn(u64) -> bool + Send + Sync>);
+
+impl MemoryPolicy {
+    /// Create a policy from a function.
+    pub fn new(policy: Arc<dyn Fn(u64) -> bool + Send + Sync>) -> Self {
+        Self(policy)
+    }
+
+    /// Check whether memory may grow to `desired` bytes.
+    pub fn allows(&self, desired: u64) -> bool {
+        (self.0)(desired)
+    }
+}
+
+impl std::fmt::Debug for MemoryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MemoryPolicy").field(&"<fn>").finish()
+    }
+}
 
 /// Resource limiter that enforces memory and table size limits.
 pub struct SandboxLimiter {
     /// Maximum memory in bytes.
     max_memory: u64,
+    /// Policy replacing the `max_memory` comparison, if set.
+    policy: Option<MemoryPolicy>,
+    /// Whether `max_memory` is enforced on top of the policy.
+    enforce_max_memory: bool,
     /// Current memory allocation.
     current_memory: u64,
     /// Peak memory allocation (highest ever seen).
@@ -14,6 +52,10 @@ pub struct SandboxLimiter {
     max_table_elements: u64,
     /// Whether the limit has been exceeded.
     limit_exceeded: bool,
+    /// Soft threshold that triggers a one-time warning.
+    warn_at: Option<u64>,
+    /// Whether memory has crossed the warning threshold.
+    warn_threshold_exceeded: bool,
 }
 
 impl SandboxLimiter {
@@ -21,13 +63,49 @@ impl SandboxLimiter {
     pub fn new(max_memory: u64) -> Self {
         Self {
             max_memory,
+            policy: None,
+            enforce_max_memory: false,
             current_memory: 0,
             peak_memory: 0,
             max_table_elements: 10_000, // Reasonable default
             limit_exceeded: false,
+            warn_at: None,
+            warn_threshold_exceeded: false,
         }
     }
 
+    /// Set a soft memory threshold that is flagged the first time it is crossed.
+    pub fn set_warn_threshold(&mut self, bytes: Option<u64>) {
+        self.warn_at = bytes;
+    }
+
+    /// Check if memory has crossed the warning threshold.
+    pub fn warn_threshold_exceeded(&self) -> bool {
+        self.warn_threshold_exceeded
+    }
+
+    /// Create a limiter whose memory growth is decided by `policy`.
+    ///
+    /// The policy replaces the `max_memory` comparison entirely;
+    /// `max_memory` is only used for reporting, unless
+    /// `enforce_max_memory` is called.
+    pub fn with_policy(max_memory: u64, policy: MemoryPolicy) -> Self {
+        Self {
+            policy: Some(policy),
+            ..Self::new(max_memory)
+        }
+    }
+
+    /// Deny growth beyond `max_memory` even when the policy allows it.
+    ///
+    /// Growth then needs both the policy and `max_memory` to allow it.
+    /// Used for limits set on a single call, which the policy must not
+    /// lift.
+    pub fn enforce_max_memory(mut self) -> Self {
+        self.enforce_max_memory = true;
+        self
+    }
+
     /// Check if any limit has been exceeded.
     pub fn limit_exceeded(&self) -> bool {
         self.limit_exceeded
@@ -58,12 +136,31 @@ impl ResourceLimiter for SandboxLimiter {
     ) -> anyhow::Result<bool> {
         let desired_bytes = desired as u64;
 
-        if desired_bytes > self.max_memory {
+        let allowed = match self.policy {
+            Some(ref policy) => {
+                (!self.enforce_max_memory || desired_bytes <= self.max_memory)
+                    && policy.allows(desired_bytes)
+            }
+            None => desired_bytes <= self.max_memory,
+        };
+        if !allowed {
             self.limit_exceeded = true;
             return Ok(false);
         }
 
         self.current_memory = desired_bytes;
+        if let Some(warn_at) = self.warn_at {
+            if !self.warn_threshold_exceeded && desired_bytes >= warn_at {
+                self.warn_threshold_exceeded = true;
+                #[cfg(feature = "tracing")]
+                warn!(
+                    current_memory = desired_bytes,
+                    warn_at,
+                    max_memory = self.max_memory,
+                    "Memory usage crossed warning threshold"
+                );
+            }
+        }
         // Track peak memory
         if desired_bytes > self.peak_memory {
             self.peak_memory = desired_bytes;
@@ -85,10 +182,119 @@ impl ResourceLimiter for SandboxLimiter {
     }
 }
 
+/// Accumulates the time spent inside host (WASI) calls.
+#[derive(Debug, Default)]
+pub struct HostCallTimer {
+    /// When the current host call started, if one is in progress.
+    entered: Option<Instant>,
+    /// Total time spent in completed host calls.
+    total: Duration,
+    /// Maximum total time allowed in host calls.
+    max: Option<Duration>,
+    /// Whether the limit has been exceeded.
+    limit_exceeded: bool,
+}
+
+impl HostCallTimer {
+    /// Create a new timer with an optional limit on total host call time.
+    pub fn new(max: Option<Duration>) -> Self {
+        Self {
+            max,
+            ..Self::default()
+        }
+    }
+
+    /// Get the total time spent in host calls.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Check if the host call time limit has been exceeded.
+    pub fn limit_exceeded(&self) -> bool {
+        self.limit_exceeded
+    }
+
+    /// Record a transition between wasm and host code.
+    ///
+    /// Returns an error (trapping the guest) once the limit is exceeded.
+    pub fn on_call_hook(&mut self, hook: CallHook) -> anyhow::Result<()> {
+        match hook {
+            CallHook::CallingHost => self.entered = Some(Instant::now()),
+            CallHook::ReturningFromHost => {
+                if let Some(start) = self.entered.take() {
+                    self.total += start.elapsed();
+                }
+                if self.max.is_some_and(|max| self.total > max) {
+                    self.limit_exceeded = true;
+                    anyhow::bail!("host call time limit exceeded");
+                }
+            }
+            CallHook::CallingWasm | CallHook::ReturningFromWasm => {}
+        }
+        Ok(())
+    }
+}
+
+/// Cumulative time limit shared by the executions of one composite call.
+///
+/// See `SandboxConfigBuilder::total_budget`. Without a limit, every
+/// method passes its input through unchanged.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TotalBudget {
+    start: Instant,
+    limit: Option<Duration>,
+}
+
+impl TotalBudget {
+    /// Start spending a budget of `limit`, if any.
+    pub(crate) fn start(limit: Option<Duration>) -> Self {
+        Self {
+            start: Instant::now(),
+            limit,
+        }
+    }
+
+    /// Check whether the budget is used up.
+    pub(crate) fn exhausted(&self) -> bool {
+        self.limit
+            .is_some_and(|limit| self.start.elapsed() >= limit)
+    }
+
+    /// Cut the timeout of the next execution to the remaining budget.
+    ///
+    /// Fails with the budget's timeout error if nothing is left.
+    pub(crate) fn timeout(&self, timeout: Duration) -> Result<Duration> {
+        match self.limit {
+            None => Ok(timeout),
+            Some(limit) => match limit.checked_sub(self.start.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Ok(timeout.min(remaining)),
+                _ => Err(self.error()),
+            },
+        }
+    }
+
+    /// Replace the error of an execution cut short by the budget.
+    pub(crate) fn check<T>(&self, outcome: Result<T>) -> Result<T> {
+        match outcome {
+            Err(_) if self.exhausted() => Err(self.error()),
+            outcome => outcome,
+        }
+    }
+
+    fn error(&self) -> SandboxError {
+        SandboxError::Timeout {
+            elapsed: self.start.elapsed(),
+            phase: TimeoutPhase::Execution,
+        }
+    }
+}
+
 /// Store data that includes the resource limiter and execution context.
 pub struct StoreData {
     /// The resource limiter.
     pub limiter: SandboxLimiter,
+    /// Timer for host calls (only updated when configured on the store).
+    pub host_calls: HostCallTimer,
     /// WASI Preview 1 context for the sandbox.
     pub wasi: wasmtime_wasi::preview1::WasiP1Ctx,
 }
@@ -98,21 +304,62 @@ impl StoreData {
     pub fn new(max_memory: u64, wasi: wasmtime_wasi::preview1::WasiP1Ctx) -> Self {
         Self {
             limiter: SandboxLimiter::new(max_memory),
+            host_calls: HostCallTimer::default(),
             wasi,
         }
     }
 }
 
+/// Create a store on `engine` limited as `config` asks.
+///
+/// Installs the memory limiter, with `memory_policy` and `memory_warn_at`,
+/// and the host call timer with `max_io_time` if `track_host_calls` is
+/// set. Shared by every way of running code, so a configuration limits a
+/// persistent session exactly as it limits `execute`. With
+/// `enforce_max_memory`, `max_memory` holds under the policy too (see
+/// `SandboxLimiter::enforce_max_memory`).
+pub(crate) fn configured_store(
+    engine: &Engine,
+    config: &SandboxConfig,
+    enforce_max_memory: bool,
+    wasi: wasmtime_wasi::preview1::WasiP1Ctx,
+) -> Store<StoreData> {
+    let mut store_data = StoreData::new(config.max_memory, wasi);
+    if let Some(ref policy) = config.memory_policy {
+        let limiter = SandboxLimiter::with_policy(config.max_memory, policy.clone());
+        store_data.limiter = if enforce_max_memory {
+            limiter.enforce_max_memory()
+        } else {
+            limiter
+        };
+    }
+    store_data.limiter.set_warn_threshold(config.memory_warn_at);
+    let mut store = Store::new(engine, store_data);
+    store.configure_limiter();
+    if config.track_host_calls {
+        store.configure_host_call_timer(config.max_io_time);
+    }
+    store
+}
+
 /// Extension trait for Store to configure resource limiting.
 pub trait StoreLimiterExt {
     /// Configure the store with resource limiting enabled.
     fn configure_limiter(&mut self);
+
+    /// Configure the store to time host calls, with an optional limit.
+    fn configure_host_call_timer(&mut self, max: Option<Duration>);
 }
 
 impl StoreLimiterExt for Store<StoreData> {
     fn configure_limiter(&mut self) {
         self.limiter(|data| &mut data.limiter);
     }
+
+    fn configure_host_call_timer(&mut self, max: Option<Duration>) {
+        self.data_mut().host_calls = HostCallTimer::new(max);
+        self.call_hook(|mut store, hook| store.data_mut().host_calls.on_call_hook(hook));
+    }
 }
 
 #[cfg(test)]
@@ -136,4 +383,83 @@ mod tests {
         assert!(!result);
         assert!(limiter.limit_exceeded());
     }
+
+    #[test]
+    fn test_limiter_warn_threshold() {
+        let mut limiter = SandboxLimiter::new(4 * 1024 * 1024); // 4MB
+        limiter.set_warn_threshold(Some(2 * 1024 * 1024));
+
+        assert!(limiter.memory_growing(0, 1024 * 1024, None).unwrap());
+        assert!(!limiter.warn_threshold_exceeded());
+
+        assert!(limiter.memory_growing(0, 3 * 1024 * 1024, None).unwrap());
+        assert!(limiter.warn_threshold_exceeded());
+        assert!(!limiter.limit_exceeded());
+    }
+
+    #[test]
+    fn test_limiter_policy() {
+        let policy = MemoryPolicy::new(Arc::new(|desired| desired < 4 * 1024 * 1024));
+        let mut limiter = SandboxLimiter::with_policy(1024 * 1024, policy);
+
+        // The policy overrides max_memory in both directions
+        assert!(limiter.memory_growing(0, 2 * 1024 * 1024, None).unwrap());
+        assert!(!limiter.limit_exceeded());
+        assert!(!limiter.memory_growing(0, 8 * 1024 * 1024, None).unwrap());
+        assert!(limiter.limit_exceeded());
+
+        // An enforced max_memory holds whatever the policy allows
+        let policy = MemoryPolicy::new(Arc::new(|desired| desired < 4 * 1024 * 1024));
+        let mut limiter = SandboxLimiter::with_policy(1024 * 1024, policy).enforce_max_memory();
+        assert!(limiter.memory_growing(0, 512 * 1024, None).unwrap());
+        assert!(!limiter.memory_growing(0, 2 * 1024 * 1024, None).unwrap());
+        assert!(limiter.limit_exceeded());
+    }
+
+    #[test]
+    fn test_host_call_timer_accumulates() {
+        let mut timer = HostCallTimer::new(None);
+
+        timer.on_call_hook(CallHook::CallingHost).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        timer.on_call_hook(CallHook::ReturningFromHost).unwrap();
+
+        assert!(timer.total() >= Duration::from_millis(5));
+        assert!(!timer.limit_exceeded());
+    }
+
+    #[test]
+    fn test_host_call_timer_limit() {
+        let mut timer = HostCallTimer::new(Some(Duration::from_millis(1)));
+
+        timer.on_call_hook(CallHook::CallingHost).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(timer.on_call_hook(CallHook::ReturningFromHost).is_err());
+        assert!(timer.limit_exceeded());
+    }
+
+    #[test]
+    fn test_total_budget() {
+        let unlimited = TotalBudget::start(None);
+        assert_eq!(
+            unlimited.timeout(Duration::from_secs(30)).unwrap(),
+            Duration::from_secs(30)
+        );
+
+        let budget = TotalBudget::start(Some(Duration::from_millis(20)));
+        assert!(budget.timeout(Duration::from_secs(30)).unwrap() <= Duration::from_millis(20));
+        assert!(budget.check(Err::<(), _>(SandboxError::Cancelled)).is_err());
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(budget.exhausted());
+        assert!(budget
+            .timeout(Duration::from_secs(30))
+            .unwrap_err()
+            .is_timeout());
+        let err = budget
+            .check(Err::<(), _>(SandboxError::Cancelled))
+            .unwrap_err();
+        assert!(err.is_timeout());
+    }
 }
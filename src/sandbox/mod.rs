@@ -1,7 +1,27 @@
 //! Sandbox module containing all execution-related components.
 
+#[cfg(feature = "audit")]
+pub mod audit;
 pub mod cache;
+pub mod cancel;
+pub mod compare;
+pub mod compile;
 pub mod config;
+pub(crate) mod deterministic;
+pub mod doctest;
+pub mod events;
 pub mod executor;
+pub(crate) mod guest;
+pub mod interpreter;
 pub mod io;
 pub mod limits;
+pub(crate) mod mounts;
+pub mod persistent;
+pub mod pool;
+pub mod preludes;
+pub mod sequence;
+pub mod session;
+#[cfg(feature = "tracing")]
+pub(crate) mod telemetry;
+pub mod termination;
+pub(crate) mod ticker;
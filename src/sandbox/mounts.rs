@@ -0,0 +1,375 @@
+//! Read-only files mounted into the guest filesystem.
+//!
+//! wasmtime-wasi has no in-memory filesystem: the only way to give the
+//! guest a file it can `open()` is a preopened host directory. Mounted
+//! files are therefore written into a fresh temporary directory for each
+//! execution, laid out at their guest paths, and that directory is
+//! preopened as `/` with read-only directory and file permissions. The
+//! guest sees only the mounted files, cannot modify them, and the
+//! directory is removed when the execution ends.
+//!
+//! Virtual files are mounted files under `VIRTUAL_ROOT`, which the guest
+//! makes its working directory so they can be opened by relative path.
+//!
+//! Output files work the same way in reverse: an empty temporary directory
+//! is preopened writable at `OUTPUT_ROOT`, and whatever the guest leaves
+//! in it is read back once the execution ends. WASI gives the host no hook
+//! on the guest's file writes, so an `OutputQuota` measures the directory
+//! while the guest runs instead, and stops it once it holds too much.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{Result, SandboxError};
+use crate::sandbox::guest::nonce;
+use crate::sandbox::io::ByteLimit;
+
+/// Guest path at which the mount directory is preopened.
+pub(crate) const MOUNT_ROOT: &str = "/";
+
+/// Guest path at which the output directory is preopened.
+pub(crate) const OUTPUT_ROOT: &str = "/out";
+
+/// Most files, directories and links the guest may create under `OUTPUT_ROOT`.
+pub(crate) const MAX_OUTPUT_ENTRIES: u64 = 4096;
+
+/// Guest directory holding virtual files, and the working directory when
+/// there are any.
+pub(crate) const VIRTUAL_ROOT: &str = "/sandbox";
+
+/// Guest path of the virtual file at `path`, relative to `VIRTUAL_ROOT`.
+pub(crate) fn virtual_path(path: &str) -> String {
+    format!("{}/{}", VIRTUAL_ROOT, path.trim_start_matches('/'))
+}
+
+/// Check whether any of `files` is a virtual file.
+pub(crate) fn has_virtual_files(files: &[(String, Arc<[u8]>)]) -> bool {
+    files
+        .iter()
+        .any(|(guest_path, _)| Path::new(guest_path).starts_with(VIRTUAL_ROOT))
+}
+
+/// Check that `guest_path` can be mounted.
+///
+/// The path must be absolute, name a file, and contain no `..` components.
+pub(crate) fn validate_guest_path(guest_path: &str) -> Result<()> {
+    let path = Path::new(guest_path);
+    let mut components = path.components();
+    let valid = components.next() == Some(Component::RootDir)
+        && path.file_name().is_some()
+        && components.all(|c| matches!(c, Component::Normal(_)));
+    if valid {
+        Ok(())
+    } else {
+        Err(SandboxError::Config(format!(
+            "mounted file path {:?} must be absolute and name a file",
+            guest_path
+        )))
+    }
+}
+
+/// Host directory holding the mounted files of one execution.
+///
+/// Removed, with its contents, when dropped.
+#[derive(Debug)]
+pub(crate) struct MountDir {
+    path: PathBuf,
+}
+
+impl MountDir {
+    /// Write `files` into a new temporary directory.
+    pub(crate) fn create(files: &[(String, Arc<[u8]>)]) -> Result<Self> {
+        let dir = Self::empty("mount")?;
+
+        for (guest_path, data) in files {
+            validate_guest_path(guest_path)?;
+            let host_path = dir.path.join(guest_path.trim_start_matches('/'));
+            if let Some(parent) = host_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&host_path, data)?;
+        }
+        Ok(dir)
+    }
+
+    /// Create a new, empty temporary directory.
+    pub(crate) fn empty(kind: &str) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-{}-{}-{:016x}",
+            kind,
+            std::process::id(),
+            nonce()
+        ));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+
+    /// Path of the directory on the host.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read every regular file, keyed by its path relative to the directory.
+    ///
+    /// Files are read in path order, so `limit` applies to them in a stable
+    /// order, and no more of a file is read than `limit` has room for: a
+    /// file of any size costs at most the remaining budget in memory.
+    /// Symbolic links are not followed. Files are given the permission bits
+    /// `mode`, if set, once read.
+    pub(crate) fn read_files(&self, limit: &ByteLimit, mode: Option<u32>) -> Result<OutputFiles> {
+        let mut paths = Vec::new();
+        collect_files(&self.path, &mut paths)?;
+        paths.sort();
+
+        let mut files = HashMap::with_capacity(paths.len());
+        let mut modes = HashMap::with_capacity(paths.len());
+        for path in paths {
+            let data = limit.read(std::fs::File::open(&path)?)?;
+            let relative = path
+                .strip_prefix(&self.path)
+                .expect("collected paths are inside the directory")
+                .to_path_buf();
+            modes.insert(relative.clone(), file_mode(&path, mode)?);
+            files.insert(relative, data);
+        }
+        Ok(OutputFiles { files, modes })
+    }
+}
+
+/// Limit on what the guest may write to an output directory.
+///
+/// The directory is measured by walking it, so `check` is called on every
+/// epoch tick and once more when the guest returns: a guest can go over
+/// the limit by what it writes within one tick, but not keep going. The
+/// walk stops as soon as the limit is passed, so it stays cheap however
+/// many entries the guest creates.
+#[derive(Debug)]
+pub(crate) struct OutputQuota {
+    path: PathBuf,
+    limit: u64,
+    exceeded: AtomicBool,
+}
+
+impl OutputQuota {
+    /// Allow at most `limit` bytes and `MAX_OUTPUT_ENTRIES` entries in `dir`.
+    pub(crate) fn new(dir: &MountDir, limit: u64) -> Self {
+        Self {
+            path: dir.path.clone(),
+            limit,
+            exceeded: AtomicBool::new(false),
+        }
+    }
+
+    /// Measure the directory, returning whether it is over the limit.
+    ///
+    /// Once over the limit it stays over, so the result can be read again
+    /// with `exceeded` after the guest has been stopped.
+    pub(crate) fn check(&self) -> bool {
+        if self.exceeded() {
+            return true;
+        }
+        let mut usage = Usage::default();
+        let over = !usage.add_dir(&self.path, self.limit);
+        if over {
+            self.exceeded.store(true, Ordering::Release);
+        }
+        over
+    }
+
+    /// Check whether a previous `check` found the directory over the limit.
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Acquire)
+    }
+
+    /// The byte limit.
+    pub(crate) fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+/// Bytes and entries found so far while measuring a directory.
+#[derive(Debug, Default)]
+struct Usage {
+    bytes: u64,
+    entries: u64,
+}
+
+impl Usage {
+    /// Add the entries under `dir`, recursively, returning whether they
+    /// still fit in `limit` bytes and `MAX_OUTPUT_ENTRIES` entries.
+    ///
+    /// Files count with their apparent size, which a sparse file can only
+    /// overstate; other entries only count towards the entry limit.
+    /// Entries that vanish or cannot be read are skipped.
+    fn add_dir(&mut self, dir: &Path, limit: u64) -> bool {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return true;
+        };
+        for entry in entries.flatten() {
+            // `DirEntry::metadata` does not follow symbolic links
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            self.entries += 1;
+            if metadata.is_file() {
+                self.bytes = self.bytes.saturating_add(metadata.len());
+            }
+            if self.entries > MAX_OUTPUT_ENTRIES || self.bytes > limit {
+                return false;
+            }
+            if metadata.is_dir() && !self.add_dir(&entry.path(), limit) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Files read back from a directory, keyed by relative path.
+#[derive(Debug, Default)]
+pub(crate) struct OutputFiles {
+    /// Contents of each file.
+    pub(crate) files: HashMap<PathBuf, Vec<u8>>,
+    /// Permission bits of each file.
+    pub(crate) modes: HashMap<PathBuf, u32>,
+}
+
+/// Permission bits of `path`, after setting them to `mode` if given.
+#[cfg(unix)]
+fn file_mode(path: &Path, mode: Option<u32>) -> Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(std::fs::metadata(path)?.permissions().mode() & 0o7777)
+}
+
+/// Permission bits of `path`: `mode` if given, else derived from its
+/// read-only flag, the only permission there is.
+#[cfg(not(unix))]
+fn file_mode(path: &Path, mode: Option<u32>) -> Result<u32> {
+    let readonly = std::fs::metadata(path)?.permissions().readonly();
+    Ok(mode.unwrap_or(if readonly { 0o444 } else { 0o666 }))
+}
+
+/// Add the regular files under `dir` to `paths`, recursively.
+fn collect_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // `DirEntry::file_type` does not follow symbolic links
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), paths)?;
+        } else if file_type.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+impl Drop for MountDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_guest_path() {
+        assert!(validate_guest_path("/data/input.json").is_ok());
+        assert!(validate_guest_path("/input.json").is_ok());
+
+        assert!(validate_guest_path("data/input.json").is_err());
+        assert!(validate_guest_path("/").is_err());
+        assert!(validate_guest_path("/data/../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_virtual_path() {
+        assert_eq!(virtual_path("data.csv"), "/sandbox/data.csv");
+        assert_eq!(virtual_path("/in/a.txt"), "/sandbox/in/a.txt");
+        assert!(validate_guest_path(&virtual_path("../etc/passwd")).is_err());
+
+        let files = vec![(virtual_path("data.csv"), Arc::from(&b""[..]))];
+        assert!(has_virtual_files(&files));
+        let files = vec![("/sandboxed.txt".to_string(), Arc::from(&b""[..]))];
+        assert!(!has_virtual_files(&files));
+    }
+
+    #[test]
+    fn test_mount_dir_layout_and_cleanup() {
+        let files = vec![
+            ("/data/input.bin".to_string(), Arc::from(&b"abc"[..])),
+            ("/pkg/sub/mod.py".to_string(), Arc::from(&b"x = 1\n"[..])),
+        ];
+
+        let dir = MountDir::create(&files).unwrap();
+        let root = dir.path().to_path_buf();
+        assert_eq!(std::fs::read(root.join("data/input.bin")).unwrap(), b"abc");
+        assert_eq!(
+            std::fs::read(root.join("pkg/sub/mod.py")).unwrap(),
+            b"x = 1\n"
+        );
+
+        drop(dir);
+        assert!(!root.exists());
+    }
+
+    #[test]
+    fn test_read_files() {
+        let dir = MountDir::empty("output").unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("b.txt"), b"bbbb").unwrap();
+        std::fs::write(dir.path().join("sub/a.txt"), b"aa").unwrap();
+
+        let limit = ByteLimit::new(Some(5));
+        let output = dir.read_files(&limit, Some(0o640)).unwrap();
+
+        // Files are kept in path order until the budget runs out
+        assert!(limit.reached());
+        assert_eq!(output.files.len(), 2);
+        assert_eq!(output.files[Path::new("b.txt")], b"bbbb");
+        assert_eq!(output.files[Path::new("sub/a.txt")], b"a");
+        assert_eq!(output.modes[Path::new("sub/a.txt")], 0o640);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.path().join("b.txt"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+    }
+
+    #[test]
+    fn test_output_quota() {
+        let dir = MountDir::empty("output").unwrap();
+        let quota = OutputQuota::new(&dir, 10);
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/a.txt"), b"0123456789").unwrap();
+        assert!(!quota.check());
+
+        // Bytes in subdirectories count, and the result sticks
+        std::fs::write(dir.path().join("b.txt"), b"x").unwrap();
+        assert!(quota.check());
+        std::fs::remove_file(dir.path().join("b.txt")).unwrap();
+        assert!(quota.exceeded());
+
+        // So does the number of entries, whatever their size
+        let dir = MountDir::empty("output").unwrap();
+        let quota = OutputQuota::new(&dir, u64::MAX);
+        for i in 0..MAX_OUTPUT_ENTRIES {
+            std::fs::write(dir.path().join(i.to_string()), b"").unwrap();
+        }
+        assert!(!quota.check());
+        std::fs::create_dir(dir.path().join("one-more")).unwrap();
+        assert!(quota.check());
+    }
+}
@@ -0,0 +1,597 @@
+//! Running many snippets against one warm interpreter instance.
+//!
+//! `PythonSandbox::execute` instantiates the interpreter into a new store
+//! for every call (see `sandbox::pool`), which isolates calls from each
+//! other but dominates the latency of tiny scripts. A `PersistentSession`
+//! instantiates it once and keeps the store, and with it the Python
+//! globals, alive between calls: `x = 5` in one `eval` is visible in the
+//! next.
+//!
+//! The stock RustPython build is a WASI command: `_start` runs one program
+//! given with `-c` and exits, tearing the interpreter down. A session needs
+//! a reactor build instead, exporting:
+//!
+//! - `memory`: the guest's linear memory.
+//! - `_initialize` (optional): called once after instantiation to set up
+//!   the interpreter, as for any WASI reactor.
+//! - `sandbox_alloc(len: i32) -> i32`: reserve `len` bytes of guest memory
+//!   for the source of the next call and return their address.
+//! - `sandbox_eval(ptr: i32, len: i32) -> i32`: run the UTF-8 source at
+//!   `ptr` in the persistent `__main__` namespace, release the buffer, and
+//!   return 0 on success. An uncaught exception should have its traceback
+//!   printed to stderr and make the call return non-zero.
+//!
+//! Stdout and stderr are captured per call. Limits apply per call as well:
+//! the timeout, fuel and `max_io_time` restart with each `eval`, while
+//! `max_memory` (or `memory_policy`) caps the instance, which keeps
+//! everything earlier calls allocated, and `memory_warn_at` stays set once
+//! crossed. `max_age` counts from the creation of the sandbox that started
+//! the session. A trap (timeout, memory, fuel, ...) leaves the interpreter
+//! stopped mid-call in a state that cannot be trusted, so it ends the
+//! session; every later `eval` fails.
+//!
+//! The output limits (`max_output_bytes`, `max_output_lines`,
+//! `max_output_rate`), `flush_on_trap` and `deterministic` apply to each
+//! call as they do to `execute`. Only the variables set with `env`, and
+//! the hash seed of the deterministic options, are passed to the
+//! interpreter, so `strict_env` holds as well. The guest
+//! instrumentation `execute` wraps around user code is not available here:
+//! options that rely on it, stdin, and mounted files have no effect. The
+//! configured prelude is evaluated once when the session starts.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use wasmtime::{InstancePre, Memory, Store, Trap, TypedFunc, UpdateDeadline};
+use wasmtime_wasi::{I32Exit, WasiCtxBuilder};
+
+use crate::error::{PartialOutput, Result, SandboxError, TimeoutPhase};
+use crate::sandbox::config::SandboxConfig;
+use crate::sandbox::deterministic;
+use crate::sandbox::executor::{
+    is_epoch_interrupt, is_out_of_fuel, is_stack_overflow, redact, ExecutionMetadata,
+    ExecutionResult,
+};
+use crate::sandbox::io::{CapturedOutput, CapturedStream, OutputLimits};
+use crate::sandbox::limits::{configured_store, HostCallTimer, StoreData};
+use crate::sandbox::ticker::EpochTicker;
+
+/// Export called once after instantiation, if present.
+pub const INITIALIZE_EXPORT: &str = "_initialize";
+
+/// Export reserving guest memory for the source of a call.
+pub const ALLOC_EXPORT: &str = "sandbox_alloc";
+
+/// Export running the source of a call.
+pub const EVAL_EXPORT: &str = "sandbox_eval";
+
+/// An interpreter instance kept alive across calls.
+///
+/// Created with `PythonSandbox::persistent_session`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let mut session = sandbox.persistent_session().await?;
+/// session.eval("x = 5").await?;
+/// let result = session.eval("print(x + 1)").await?;
+/// assert_eq!(result.stdout, "6\n");
+/// ```
+pub struct PersistentSession {
+    /// The live instance, or `None` once a call has ended the session.
+    state: Option<SessionState>,
+    ticker: EpochTicker,
+    config: Arc<SandboxConfig>,
+    /// When the sandbox that started the session was created, for `max_age`.
+    created_at: Instant,
+}
+
+/// The store and entry points of a live instance.
+struct SessionState {
+    store: Store<StoreData>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    eval: TypedFunc<(i32, i32), i32>,
+    stdout: CapturedOutput,
+    stderr: CapturedOutput,
+    /// Limits on the output of the current call.
+    limits: OutputLimits,
+    /// When the current call started, for its timeout.
+    call_started: Arc<Mutex<Instant>>,
+}
+
+impl PersistentSession {
+    /// Instantiate the interpreter and run its initialization.
+    pub(crate) async fn start(
+        ticker: EpochTicker,
+        instance_pre: Arc<InstancePre<StoreData>>,
+        config: SandboxConfig,
+        created_at: Instant,
+    ) -> Result<Self> {
+        check_age(&config, created_at)?;
+        let config = Arc::new(config);
+        let lease = ticker.acquire(config.epoch_tick_interval);
+        let init_config = Arc::clone(&config);
+        let state = tokio::task::spawn_blocking(move || {
+            let state = SessionState::instantiate(&instance_pre, &init_config);
+            drop(lease);
+            state
+        })
+        .await
+        .map_err(|e| SandboxError::ExecutionFailed(format!("task panicked: {}", e)))??;
+
+        let mut session = Self {
+            state: Some(state),
+            ticker,
+            config,
+            created_at,
+        };
+        if let Some(prelude) = session.config.prelude.clone() {
+            let result = session.eval(&prelude).await?;
+            if !result.is_success() {
+                return Err(SandboxError::ExecutionFailed(format!(
+                    "prelude failed in persistent session: {}",
+                    result.stderr.trim()
+                )));
+            }
+        }
+        Ok(session)
+    }
+
+    /// Run `code` in the session's interpreter.
+    ///
+    /// Globals defined by earlier calls are visible, and those defined
+    /// here are kept for later ones. An uncaught exception is returned as
+    /// a result with a non-zero exit code and leaves the session usable.
+    ///
+    /// # Errors
+    /// Returns the limit errors `execute` does if the call runs past the
+    /// session's limits. Those, like a guest that exits the process, end
+    /// the session: later calls return `SandboxError::ExecutionFailed`.
+    /// Returns `SandboxError::Expired`, without running the code, once the
+    /// sandbox is older than `max_age`.
+    pub async fn eval(&mut self, code: &str) -> Result<ExecutionResult> {
+        check_age(&self.config, self.created_at)?;
+        let Some(mut state) = self.state.take() else {
+            return Err(SandboxError::ExecutionFailed(
+                "persistent session was ended by an earlier call".to_string(),
+            ));
+        };
+        let lease = self.ticker.acquire(self.config.epoch_tick_interval);
+        let config = Arc::clone(&self.config);
+        let code = code.to_string();
+        let (state, result, alive) = tokio::task::spawn_blocking(move || {
+            let (result, alive) = state.eval(&code, &config);
+            drop(lease);
+            (state, result, alive)
+        })
+        .await
+        .map_err(|e| SandboxError::ExecutionFailed(format!("task panicked: {}", e)))?;
+
+        if alive {
+            self.state = Some(state);
+        }
+        result
+    }
+
+    /// Check whether the session can still run code.
+    pub fn is_alive(&self) -> bool {
+        self.state.is_some()
+    }
+}
+
+impl SessionState {
+    /// Instantiate the interpreter into a new store and initialize it.
+    fn instantiate(instance_pre: &InstancePre<StoreData>, config: &SandboxConfig) -> Result<Self> {
+        let start_time = Instant::now();
+        let stdout = CapturedOutput::new();
+        let stderr = CapturedOutput::new();
+
+        let mut wasi_builder = WasiCtxBuilder::new();
+        wasi_builder.args(&["python"]);
+        for (key, value) in &config.env_vars {
+            wasi_builder.env(key, value);
+        }
+        deterministic::configure(&mut wasi_builder, config);
+        let limits = OutputLimits::new(config, start_time);
+        wasi_builder.stdout(limits.stdout(CapturedStream::new(stdout.clone())));
+        wasi_builder.stderr(limits.stderr(CapturedStream::new(stderr.clone())));
+
+        let mut store = configured_store(
+            instance_pre.module().engine(),
+            config,
+            false,
+            wasi_builder.build_p1(),
+        );
+
+        // The store outlives any one call, so the deadline is measured
+        // from the start of whichever call is running
+        let call_started = Arc::new(Mutex::new(start_time));
+        let started = Arc::clone(&call_started);
+        let timeout = config.timeout;
+        let rate = limits.rate.clone();
+        store.epoch_deadline_callback(move |_| {
+            if started.lock().unwrap().elapsed() >= timeout || rate.check() {
+                return Err(Trap::Interrupt.into());
+            }
+            Ok(UpdateDeadline::Continue(1))
+        });
+        store.set_epoch_deadline(1);
+        if let Some(fuel) = config.max_fuel {
+            store.set_fuel(fuel).map_err(|e| {
+                SandboxError::RuntimeInit(anyhow::anyhow!("failed to set fuel: {}", e))
+            })?;
+        }
+
+        let instance = instance_pre.instantiate(&mut store).map_err(|e| {
+            classify(
+                e,
+                &store,
+                &limits,
+                config,
+                start_time,
+                TimeoutPhase::Instantiation,
+            )
+        })?;
+        let missing = |name: &str| {
+            SandboxError::ModuleLoad(anyhow::anyhow!(
+                "interpreter has no `{}` export; persistent sessions need a reactor build",
+                name
+            ))
+        };
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| missing("memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, ALLOC_EXPORT)
+            .map_err(|_| missing(ALLOC_EXPORT))?;
+        let eval = instance
+            .get_typed_func::<(i32, i32), i32>(&mut store, EVAL_EXPORT)
+            .map_err(|_| missing(EVAL_EXPORT))?;
+        if let Ok(initialize) = instance.get_typed_func::<(), ()>(&mut store, INITIALIZE_EXPORT) {
+            initialize.call(&mut store, ()).map_err(|e| {
+                classify(
+                    e,
+                    &store,
+                    &limits,
+                    config,
+                    start_time,
+                    TimeoutPhase::Instantiation,
+                )
+            })?;
+        }
+
+        Ok(Self {
+            store,
+            memory,
+            alloc,
+            eval,
+            stdout,
+            stderr,
+            limits,
+            call_started,
+        })
+    }
+
+    /// Run one call, returning its result and whether the instance survived it.
+    fn eval(&mut self, code: &str, config: &SandboxConfig) -> (Result<ExecutionResult>, bool) {
+        let start_time = Instant::now();
+        *self.call_started.lock().unwrap() = start_time;
+        self.stdout.clear();
+        self.stderr.clear();
+        self.limits.reset(start_time);
+        if config.track_host_calls {
+            self.store.data_mut().host_calls = HostCallTimer::new(config.max_io_time);
+        }
+        if let Some(fuel) = config.max_fuel {
+            if let Err(e) = self.store.set_fuel(fuel) {
+                let error = SandboxError::RuntimeInit(anyhow::anyhow!("failed to set fuel: {}", e));
+                return (Err(error), true);
+            }
+        }
+
+        let called = self.call(code);
+        let execution_time = start_time.elapsed();
+        let stdout = String::from_utf8_lossy(&self.stdout.to_bytes()).into_owned();
+        let stderr = String::from_utf8_lossy(&self.stderr.to_bytes()).into_owned();
+        let (exit_code, alive) = match called {
+            Ok(status) => (status, true),
+            // Stopped at the line limit: the output is complete, but the
+            // interpreter was stopped mid-call
+            Err(_) if self.limits.lines.terminated() => (0, false),
+            Err(e) => match e.downcast_ref::<I32Exit>() {
+                Some(exit) => (exit.0, false),
+                None => {
+                    let mut error = classify(
+                        e,
+                        &self.store,
+                        &self.limits,
+                        config,
+                        start_time,
+                        TimeoutPhase::Execution,
+                    );
+                    if config.flush_on_trap {
+                        error = SandboxError::Interrupted {
+                            error: Box::new(error),
+                            output: PartialOutput {
+                                stdout: redact(config, stdout),
+                                stderr: redact(config, stderr),
+                            },
+                        };
+                    }
+                    return (Err(error), false);
+                }
+            },
+        };
+
+        let stderr = redact(config, stderr);
+        let result = ExecutionResult {
+            stdout: redact(config, stdout),
+            user_stderr: stderr.clone(),
+            stderr,
+            exit_code,
+            exception: None,
+            output_files: Default::default(),
+            output_file_modes: Default::default(),
+            output_line_limited: self.limits.lines.reached(),
+            output_truncated: self.limits.bytes.reached(),
+            metadata: ExecutionMetadata {
+                duration: start_time.elapsed(),
+                execution_time,
+                peak_memory: self.store.data().limiter.peak_memory(),
+                fuel_consumed: config
+                    .max_fuel
+                    .map(|fuel| fuel.saturating_sub(self.store.get_fuel().unwrap_or(0))),
+                exceeded_warn_threshold: self.store.data().limiter.warn_threshold_exceeded(),
+                host_call_duration: config
+                    .track_host_calls
+                    .then(|| self.store.data().host_calls.total()),
+                time_to_first_output: self.limits.first_output.elapsed(),
+                ..ExecutionMetadata::empty()
+            },
+            #[cfg(feature = "audit")]
+            access_attempts: Vec::new(),
+        };
+        (Ok(result), alive)
+    }
+
+    /// Copy `code` into guest memory and run it.
+    fn call(&mut self, code: &str) -> anyhow::Result<i32> {
+        let len = i32::try_from(code.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, code.as_bytes())?;
+        self.eval.call(&mut self.store, (ptr, len))
+    }
+}
+
+/// Fail with `SandboxError::Expired` once a sandbox created at `created_at`
+/// is older than `max_age`.
+fn check_age(config: &SandboxConfig, created_at: Instant) -> Result<()> {
+    match config.max_age {
+        Some(max_age) if created_at.elapsed() >= max_age => Err(SandboxError::Expired {
+            age: created_at.elapsed(),
+            max_age,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Turn a trap in the session's instance into a sandbox error.
+fn classify(
+    error: anyhow::Error,
+    store: &Store<StoreData>,
+    limits: &OutputLimits,
+    config: &SandboxConfig,
+    start_time: Instant,
+    phase: TimeoutPhase,
+) -> SandboxError {
+    let limiter = &store.data().limiter;
+    if limiter.limit_exceeded() {
+        SandboxError::MemoryLimitExceeded(format!(
+            "memory limit exceeded in persistent session (used {} bytes, limit {} bytes)",
+            limiter.current_memory(),
+            config.max_memory
+        ))
+    } else if let Some(limit) = config
+        .max_io_time
+        .filter(|_| store.data().host_calls.limit_exceeded())
+    {
+        SandboxError::IoTimeExceeded(limit)
+    } else if let Some((rate, limit)) = limits.rate.exceeded().zip(config.max_output_rate) {
+        SandboxError::OutputRateExceeded { rate, limit }
+    } else if is_epoch_interrupt(&error) {
+        SandboxError::Timeout {
+            elapsed: start_time.elapsed(),
+            phase,
+        }
+    } else if is_out_of_fuel(&error) {
+        SandboxError::OutOfFuel {
+            consumed: config.max_fuel,
+        }
+    } else if is_stack_overflow(&error) {
+        SandboxError::StackOverflow
+    } else {
+        SandboxError::ExecutionFailed(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::config::SandboxConfigBuilder;
+    use crate::sandbox::executor::{PythonSandbox, SandboxOptions};
+    use std::time::Duration;
+
+    /// A stand-in reactor that echoes each call's source to stdout and
+    /// returns the number of earlier calls. The count starts at 100 and is
+    /// reset by `_initialize`; a source of `spin` loops forever.
+    const REACTOR_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (global $calls (mut i32) (i32.const 100))
+        (func (export "_initialize")
+            (global.set $calls (i32.const 0)))
+        (func (export "sandbox_alloc") (param i32) (result i32)
+            (i32.const 1024))
+        (func (export "sandbox_eval") (param $ptr i32) (param $len i32) (result i32)
+            (if (i32.and
+                    (i32.eq (local.get $len) (i32.const 4))
+                    (i32.eq (i32.load (local.get $ptr)) (i32.const 0x6e697073)))
+                (then (loop $spin (br $spin))))
+            (i32.store (i32.const 0) (local.get $ptr))
+            (i32.store (i32.const 4) (local.get $len))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (global.get $calls)
+            (global.set $calls (i32.add (global.get $calls) (i32.const 1)))))"#;
+
+    fn reactor_sandbox(name: &str, wat: &str, timeout: Duration) -> PythonSandbox {
+        reactor_sandbox_with(name, wat, SandboxConfig::builder().timeout(timeout))
+    }
+
+    fn reactor_sandbox_with(name: &str, wat: &str, builder: SandboxConfigBuilder) -> PythonSandbox {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-persistent-{}-{}.wat",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, wat).unwrap();
+        let config = builder.interpreter_path(&path).build();
+        let sandbox = PythonSandbox::new_with_options(config, SandboxOptions::no_cache()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        sandbox
+    }
+
+    #[tokio::test]
+    async fn test_eval_reuses_instance() {
+        let sandbox = reactor_sandbox("reuse", REACTOR_WAT, Duration::from_secs(5));
+        let mut session = sandbox.persistent_session().await.unwrap();
+
+        let first = session.eval("x = 5").await.unwrap();
+        let second = session.eval("print(x)").await.unwrap();
+
+        // Output is per call, state is kept across calls
+        assert_eq!(first.stdout, "x = 5");
+        assert_eq!(second.stdout, "print(x)");
+        assert_eq!(first.exit_code, 0);
+        assert_eq!(second.exit_code, 1);
+        assert!(session.is_alive());
+    }
+
+    #[tokio::test]
+    async fn test_output_limit_per_call() {
+        let builder = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_output_bytes(4);
+        let sandbox = reactor_sandbox_with("limit", REACTOR_WAT, builder);
+        let mut session = sandbox.persistent_session().await.unwrap();
+
+        let long = session.eval("print('x')").await.unwrap();
+        let short = session.eval("x").await.unwrap();
+
+        // Output is cut at the limit, which starts afresh with each call
+        assert_eq!(long.stdout, "prin");
+        assert!(long.output_truncated);
+        assert_eq!(short.stdout, "x");
+        assert!(!short.output_truncated);
+        assert!(session.is_alive());
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_trap_keeps_output() {
+        let builder = SandboxConfig::builder()
+            .timeout(Duration::from_millis(100))
+            .flush_on_trap(true);
+        let sandbox = reactor_sandbox_with("flush", REACTOR_WAT, builder);
+        let mut session = sandbox.persistent_session().await.unwrap();
+
+        let err = session.eval("spin").await.unwrap_err();
+
+        assert!(err.is_timeout());
+        assert!(err.partial_output().is_some());
+        assert!(!session.is_alive());
+    }
+
+    #[tokio::test]
+    async fn test_trap_ends_session() {
+        let sandbox = reactor_sandbox("trap", REACTOR_WAT, Duration::from_millis(100));
+        let mut session = sandbox.persistent_session().await.unwrap();
+
+        let err = session.eval("spin").await.unwrap_err();
+        assert!(matches!(
+            err,
+            SandboxError::Timeout {
+                phase: TimeoutPhase::Execution,
+                ..
+            }
+        ));
+        assert!(!session.is_alive());
+        assert!(matches!(
+            session.eval("x").await,
+            Err(SandboxError::ExecutionFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_store_limits_match_execute() {
+        // Grows memory by 16 pages (1 MiB) on every call, trapping if that fails
+        let grows = r#"(module
+            (memory (export "memory") 1)
+            (func (export "sandbox_alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func (export "sandbox_eval") (param i32 i32) (result i32)
+                (if (i32.eq (memory.grow (i32.const 16)) (i32.const -1))
+                    (then unreachable))
+                (i32.const 0)))"#;
+        let builder = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_memory(64 * 1024)
+            .memory_policy(Arc::new(|desired| desired <= 2 * 1024 * 1024))
+            .memory_warn_at(1024 * 1024)
+            .max_io_time(Duration::from_secs(5));
+        let sandbox = reactor_sandbox_with("policy", grows, builder);
+        let mut session = sandbox.persistent_session().await.unwrap();
+
+        // The policy, not max_memory, decides growth
+        let result = session.eval("x").await.unwrap();
+        assert!(result.metadata.exceeded_warn_threshold);
+        assert!(result.metadata.host_call_duration.is_some());
+        let err = session.eval("x").await.unwrap_err();
+        assert!(err.is_memory_limit());
+        assert!(!session.is_alive());
+    }
+
+    #[tokio::test]
+    async fn test_max_age() {
+        let builder = SandboxConfig::builder()
+            .timeout(Duration::from_secs(5))
+            .max_age(Duration::from_millis(100));
+        let sandbox = reactor_sandbox_with("max-age", REACTOR_WAT, builder);
+        let mut session = sandbox.persistent_session().await.unwrap();
+        assert!(session.eval("x").await.is_ok());
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(session.eval("x").await.unwrap_err().is_expired());
+        assert!(sandbox
+            .persistent_session()
+            .await
+            .err()
+            .unwrap()
+            .is_expired());
+    }
+
+    #[tokio::test]
+    async fn test_command_module_rejected() {
+        let sandbox = reactor_sandbox(
+            "command",
+            r#"(module (memory (export "memory") 1) (func (export "_start")))"#,
+            Duration::from_secs(5),
+        );
+
+        let err = sandbox.persistent_session().await.err().unwrap();
+
+        assert!(matches!(err, SandboxError::ModuleLoad(_)));
+        assert!(err.to_string().contains(ALLOC_EXPORT));
+    }
+}
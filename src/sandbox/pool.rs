@@ -0,0 +1,279 @@
+//! Reusing a sandbox's interpreter across executions.
+//!
+//! A `SandboxPool` creates a fixed number of sandboxes up front, sharing
+//! one engine and compiled interpreter, and lends them out one caller at a
+//! time. Since a `PythonSandbox` keeps no state between executions, the
+//! pool mainly bounds how many executions run at once and moves the cost
+//! of creating sandboxes out of the request path.
+//!
+//! A `PooledSandbox` is the unit a pool hands out: a sandbox whose
+//! compiled and pre-linked interpreter is reused for every execution.
+//! What is never reused is the Wasm instance itself. Linear memory, tables
+//! and globals belong to a wasmtime `Store`, which cannot be rolled back to
+//! a clean state, so every execution instantiates the pre-linked
+//! interpreter (`wasmtime::InstancePre`) into a new store. Copy-on-write
+//! memory images keep that instantiation cheap, and nothing a previous
+//! execution did to the interpreter can be observed by the next one.
+
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::{Result, SandboxError};
+use crate::sandbox::cache::{ModuleCache, SharedEngine};
+use crate::sandbox::config::SandboxConfig;
+use crate::sandbox::executor::{PythonSandbox, SandboxOptions};
+
+/// A fixed set of sandboxes lent out one caller at a time.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let pool = SandboxPool::new(config, 4)?.acquire_timeout(Duration::from_secs(1));
+/// let sandbox = pool.acquire().await?;
+/// let result = sandbox.execute("print(1 + 1)", None).await?;
+/// // Dropping `sandbox` returns it to the pool
+/// ```
+pub struct SandboxPool {
+    inner: Arc<PoolInner>,
+    size: usize,
+    acquire_timeout: Option<Duration>,
+}
+
+/// State shared between a pool and the sandboxes it has lent out.
+struct PoolInner {
+    /// Sandboxes not currently lent out.
+    idle: Mutex<Vec<PythonSandbox>>,
+    /// One permit per idle sandbox.
+    permits: Arc<Semaphore>,
+    engine: SharedEngine,
+}
+
+impl SandboxPool {
+    /// Create `size` sandboxes from `config`.
+    ///
+    /// The sandboxes share one engine, with fuel consumption enabled if
+    /// `config` sets `max_fuel`, and one compiled interpreter.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if `size` is zero or `config` sets
+    /// `max_wasm_stack`, which needs an engine of its own, and any error
+    /// creating a sandbox.
+    pub fn new(config: SandboxConfig, size: usize) -> Result<Self> {
+        if size == 0 {
+            return Err(SandboxError::Config(
+                "sandbox pool size must be at least 1".to_string(),
+            ));
+        }
+        if config.max_wasm_stack.is_some() {
+            return Err(SandboxError::Config(
+                "max_wasm_stack cannot be combined with a sandbox pool".to_string(),
+            ));
+        }
+        let engine = if config.max_fuel.is_some() {
+            SharedEngine::with_fuel()?
+        } else {
+            SharedEngine::new()?
+        };
+
+        // As for `Session`, the engine gets a cache of its own, so the
+        // interpreter is compiled once for the whole pool
+        let cache = Arc::new(ModuleCache::new());
+        let idle = (0..size)
+            .map(|_| {
+                let options = SandboxOptions::with_engine(engine.clone()).cache(Arc::clone(&cache));
+                PythonSandbox::new_with_options(config.clone(), options)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            inner: Arc::new(PoolInner {
+                idle: Mutex::new(idle),
+                permits: Arc::new(Semaphore::new(size)),
+                engine,
+            }),
+            size,
+            acquire_timeout: None,
+        })
+    }
+
+    /// Give up on `acquire` after waiting `timeout` for a free sandbox.
+    ///
+    /// Without a timeout, `acquire` waits as long as it takes.
+    pub fn acquire_timeout(mut self, timeout: Duration) -> Self {
+        self.acquire_timeout = Some(timeout);
+        self
+    }
+
+    /// Borrow a sandbox, waiting until one is free.
+    ///
+    /// The sandbox goes back to the pool when the returned guard is dropped.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::PoolExhausted` if the acquire timeout passes
+    /// before a sandbox is free.
+    pub async fn acquire(&self) -> Result<PooledSandbox> {
+        let permit = Arc::clone(&self.inner.permits).acquire_owned();
+        let permit = match self.acquire_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, permit)
+                .await
+                .map_err(|_| SandboxError::PoolExhausted { waited: timeout })?,
+            None => permit.await,
+        }
+        .expect("pool semaphore is never closed");
+        let sandbox = self
+            .inner
+            .idle
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("a permit is only available with an idle sandbox");
+
+        Ok(PooledSandbox {
+            sandbox: Some(sandbox),
+            pool: Arc::clone(&self.inner),
+            _permit: permit,
+        })
+    }
+
+    /// Number of sandboxes in the pool.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of sandboxes not currently lent out.
+    pub fn available(&self) -> usize {
+        self.inner.permits.available_permits()
+    }
+
+    /// Get the engine shared by the pool's sandboxes.
+    pub fn engine(&self) -> &SharedEngine {
+        &self.inner.engine
+    }
+}
+
+/// A sandbox borrowed from a `SandboxPool`, returned to it on drop.
+///
+/// Dereferences to the `PythonSandbox`.
+pub struct PooledSandbox {
+    /// Always `Some` until dropped.
+    sandbox: Option<PythonSandbox>,
+    pool: Arc<PoolInner>,
+    /// Released after the sandbox is back in the pool.
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledSandbox {
+    type Target = PythonSandbox;
+
+    fn deref(&self) -> &PythonSandbox {
+        self.sandbox
+            .as_ref()
+            .expect("sandbox is present until drop")
+    }
+}
+
+impl Drop for PooledSandbox {
+    fn drop(&mut self) {
+        if let Some(sandbox) = self.sandbox.take() {
+            self.pool.idle.lock().unwrap().push(sandbox);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in interpreter that traps if it sees state from an earlier run.
+    const STATEFUL_WAT: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $ran (mut i32) (i32.const 0))
+        (func (export "_start")
+            (if (i32.or (global.get $ran) (i32.load (i32.const 0)))
+                (then unreachable))
+            (global.set $ran (i32.const 1))
+            (i32.store (i32.const 0) (i32.const 1))))"#;
+
+    #[tokio::test]
+    async fn test_instance_state_does_not_leak() {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-pooled-state-{}.wat",
+            std::process::id()
+        ));
+        std::fs::write(&path, STATEFUL_WAT).unwrap();
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let pool = SandboxPool::new(config, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let sandbox = pool.acquire().await.unwrap();
+        let first = sandbox.execute("", None).await.unwrap();
+        drop(sandbox);
+        let sandbox = pool.acquire().await.unwrap();
+        let second = sandbox.execute("", None).await.unwrap();
+
+        assert!(first.is_success());
+        assert!(second.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_pool_acquire_and_release() {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-pool-{}.wat",
+            std::process::id()
+        ));
+        std::fs::write(&path, STATEFUL_WAT).unwrap();
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+        let pool = SandboxPool::new(config, 1)
+            .unwrap()
+            .acquire_timeout(Duration::from_millis(50));
+        std::fs::remove_file(&path).unwrap();
+
+        let sandbox = pool.acquire().await.unwrap();
+        assert!(sandbox.execute("", None).await.unwrap().is_success());
+        assert_eq!(pool.available(), 0);
+
+        let err = pool.acquire().await.err().unwrap();
+        assert!(err.is_pool_exhausted());
+
+        drop(sandbox);
+        assert_eq!(pool.available(), 1);
+        let again = pool.acquire().await.unwrap();
+        assert!(again.execute("", None).await.unwrap().is_success());
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn test_empty_pool_rejected() {
+        let result = SandboxPool::new(SandboxConfig::default(), 0);
+
+        assert!(matches!(result, Err(SandboxError::Config(_))));
+    }
+
+    #[test]
+    fn test_max_wasm_stack_rejected() {
+        let config = SandboxConfig::builder().max_wasm_stack(256 * 1024).build();
+        let result = SandboxPool::new(config, 1);
+
+        assert!(matches!(result, Err(SandboxError::Config(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_python_globals_do_not_leak() {
+        let pool = SandboxPool::new(SandboxConfig::default(), 1).unwrap();
+
+        let sandbox = pool.acquire().await.unwrap();
+        sandbox.execute("leaked = 42", None).await.unwrap();
+        drop(sandbox);
+        let sandbox = pool.acquire().await.unwrap();
+        let result = sandbox
+            .execute("print('leaked' in globals())", None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.stdout.trim(), "False");
+    }
+}
@@ -0,0 +1,111 @@
+//! Named prelude fragments shared between configurations.
+//!
+//! Reusable helper code (math helpers, safe I/O wrappers, ...) is
+//! registered once by name and picked per configuration with
+//! `SandboxConfigBuilder::use_preludes`, instead of pasting the same text
+//! into every prelude. Fragments are looked up in the global registry when
+//! a sandbox is created; later changes to the registry do not affect
+//! existing sandboxes.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::error::{Result, SandboxError};
+
+/// A set of prelude fragments, by name.
+#[derive(Debug, Default)]
+pub struct PreludeRegistry {
+    fragments: RwLock<HashMap<String, Arc<str>>>,
+}
+
+impl PreludeRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `source` under `name`, replacing any fragment of that name.
+    pub fn register(&self, name: impl Into<String>, source: impl Into<String>) {
+        self.fragments
+            .write()
+            .unwrap()
+            .insert(name.into(), Arc::from(source.into()));
+    }
+
+    /// Check whether a fragment is registered under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.fragments.read().unwrap().contains_key(name)
+    }
+
+    /// Remove all fragments.
+    pub fn clear(&self) {
+        self.fragments.write().unwrap().clear();
+    }
+
+    /// Concatenate the fragments registered under `names`, in order.
+    ///
+    /// Each fragment is placed on its own lines, so one missing a final
+    /// newline cannot run into the next.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` naming the first unregistered fragment.
+    pub fn compose<S: AsRef<str>>(&self, names: &[S]) -> Result<String> {
+        let fragments = self.fragments.read().unwrap();
+        let mut prelude = String::new();
+        for name in names {
+            let name = name.as_ref();
+            let source = fragments.get(name).ok_or_else(|| {
+                SandboxError::Config(format!("no prelude fragment registered as {:?}", name))
+            })?;
+            prelude.push_str(source);
+            if !prelude.ends_with('\n') {
+                prelude.push('\n');
+            }
+        }
+        Ok(prelude)
+    }
+}
+
+/// Registry consulted by `SandboxConfigBuilder::use_preludes`.
+static GLOBAL_PRELUDES: std::sync::LazyLock<PreludeRegistry> =
+    std::sync::LazyLock::new(PreludeRegistry::new);
+
+/// Get the global prelude registry.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// global_preludes().register("math_helpers", "def clamp(x, lo, hi): return max(lo, min(x, hi))");
+/// global_preludes().register("safe_io", "def read_all(): import sys; return sys.stdin.read()");
+///
+/// let config = SandboxConfig::builder()
+///     .use_preludes(&["math_helpers", "safe_io"])
+///     .build();
+/// ```
+pub fn global_preludes() -> &'static PreludeRegistry {
+    &GLOBAL_PRELUDES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_in_order() {
+        let registry = PreludeRegistry::new();
+        registry.register("a", "x = 1");
+        registry.register("b", "y = x + 1\n");
+
+        assert_eq!(registry.compose(&["b", "a"]).unwrap(), "y = x + 1\nx = 1\n");
+        assert_eq!(registry.compose::<&str>(&[]).unwrap(), "");
+    }
+
+    #[test]
+    fn test_compose_missing_fragment() {
+        let registry = PreludeRegistry::new();
+        registry.register("a", "x = 1");
+
+        let err = registry.compose(&["a", "missing"]).unwrap_err();
+        assert!(matches!(err, SandboxError::Config(ref msg) if msg.contains("\"missing\"")));
+    }
+}
@@ -0,0 +1,217 @@
+//! Running several code blocks in a single interpreter startup.
+//!
+//! Blocks are executed one after another by a generated driver script.
+//! After each block the driver writes a boundary line to both stdout and
+//! stderr, which the host uses to split the captured output per block.
+//! The boundary is always preceded by a newline, so it starts a line even
+//! when a block leaves a partial one; the host removes that newline again.
+
+use crate::sandbox::guest::nonce;
+
+/// Output of a single block run by `PythonSandbox::execute_sequence`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockResult {
+    /// Stdout written while the block ran.
+    pub stdout: String,
+    /// Stderr written while the block ran, including any traceback.
+    pub stderr: String,
+    /// Whether the block completed without raising an exception.
+    pub success: bool,
+}
+
+impl BlockResult {
+    /// Check if the block completed without raising an exception.
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+}
+
+/// Boundary marker written between blocks.
+///
+/// Contains a random nonce so output that merely resembles a boundary is
+/// not mistaken for one. The nonce is not secret: it is part of the driver
+/// script, which blocks can read through `sys.argv` or the driver's code
+/// object, so a block that goes looking for it can fake a boundary and
+/// shift how later output is attributed.
+#[derive(Debug, Clone)]
+pub(crate) struct Boundary {
+    marker: String,
+}
+
+impl Boundary {
+    /// Create a boundary with a fresh random nonce.
+    pub(crate) fn new() -> Self {
+        Self {
+            marker: format!("\u{1e}__sandbox_block_{:016x}__", nonce()),
+        }
+    }
+
+    /// Parse a boundary line, returning the block index and whether it succeeded.
+    fn parse(&self, line: &str) -> Option<(usize, bool)> {
+        let rest = line.strip_prefix(&self.marker)?;
+        let mut parts = rest.split_whitespace();
+        let index = parts.next()?.parse().ok()?;
+        let success = parts.next()? == "ok";
+        Some((index, success))
+    }
+}
+
+/// Build the driver script running each block in order.
+///
+/// Blocks share one global namespace; an exception in one block is printed
+/// to stderr and does not stop later blocks.
+pub(crate) fn build_script(blocks: &[&str], boundary: &Boundary) -> String {
+    let mut script = format!(
+        r#"def __sandbox_run_block(source, index):
+    import sys, traceback
+    status = 'ok'
+    try:
+        exec(compile(source, '<block %d>' % index, 'exec'), globals())
+    except Exception:
+        traceback.print_exc()
+        status = 'error'
+    marker = '\n{}' + ' %d %s\n' % (index, status)
+    sys.stdout.flush()
+    sys.stderr.flush()
+    sys.stdout.write(marker)
+    sys.stderr.write(marker)
+    sys.stdout.flush()
+    sys.stderr.flush()
+"#,
+        boundary.marker.replace('\u{1e}', "\\x1e")
+    );
+
+    for (index, block) in blocks.iter().enumerate() {
+        // A JSON string literal is also a valid Python string literal.
+        let literal = serde_json::to_string(block).expect("string serialization cannot fail");
+        script.push_str(&format!("__sandbox_run_block({}, {})\n", literal, index));
+    }
+
+    script
+}
+
+/// Split captured output into per-block results.
+///
+/// Blocks that never reached their boundary (because the interpreter exited
+/// early) are omitted, except for the block that was running at the time,
+/// which receives the remaining output and is marked as failed.
+pub(crate) fn split_blocks(
+    stdout: &str,
+    stderr: &str,
+    block_count: usize,
+    boundary: &Boundary,
+) -> Vec<BlockResult> {
+    let (stdout_segments, statuses, stdout_rest) = split_stream(stdout, boundary);
+    let (stderr_segments, _, stderr_rest) = split_stream(stderr, boundary);
+
+    let mut results: Vec<BlockResult> = statuses
+        .iter()
+        .enumerate()
+        .take(block_count)
+        .map(|(i, success)| BlockResult {
+            stdout: stdout_segments[i].clone(),
+            stderr: stderr_segments.get(i).cloned().unwrap_or_default(),
+            success: *success,
+        })
+        .collect();
+
+    if results.len() < block_count {
+        let stderr_rest = stderr_segments
+            .get(results.len())
+            .cloned()
+            .unwrap_or(stderr_rest);
+        results.push(BlockResult {
+            stdout: stdout_rest,
+            stderr: stderr_rest,
+            success: false,
+        });
+    }
+
+    results
+}
+
+/// Split one stream at boundary lines.
+///
+/// Returns the completed segments, the status of each, and trailing output.
+/// The newline written before each boundary is not part of its segment.
+fn split_stream(output: &str, boundary: &Boundary) -> (Vec<String>, Vec<bool>, String) {
+    let mut segments = Vec::new();
+    let mut statuses = Vec::new();
+    let mut current = String::new();
+
+    for line in output.split_inclusive('\n') {
+        if let Some((_, success)) = boundary.parse(line) {
+            current.pop();
+            segments.push(std::mem::take(&mut current));
+            statuses.push(success);
+        } else {
+            current.push_str(line);
+        }
+    }
+
+    (segments, statuses, current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_script_escapes_blocks() {
+        let boundary = Boundary::new();
+        let script = build_script(&["print(\"a\")\nx = 1", "print(x)"], &boundary);
+
+        assert!(script.contains(r#"__sandbox_run_block("print(\"a\")\nx = 1", 0)"#));
+        assert!(script.contains(r#"__sandbox_run_block("print(x)", 1)"#));
+    }
+
+    #[test]
+    fn test_split_blocks() {
+        let boundary = Boundary::new();
+        let m = &boundary.marker;
+        let stdout = format!("a\n\n{m} 0 ok\n\n{m} 1 error\nc\n\n{m} 2 ok\n");
+        let stderr = format!("\n{m} 0 ok\nTraceback\n\n{m} 1 error\n\n{m} 2 ok\n");
+
+        let results = split_blocks(&stdout, &stderr, 3, &boundary);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].stdout, "a\n");
+        assert!(results[0].success);
+        assert_eq!(results[1].stderr, "Traceback\n");
+        assert!(!results[1].success);
+        assert_eq!(results[2].stdout, "c\n");
+    }
+
+    #[test]
+    fn test_split_blocks_early_exit() {
+        let boundary = Boundary::new();
+        let m = &boundary.marker;
+        let stdout = format!("a\n\n{m} 0 ok\npartial\n");
+
+        let results = split_blocks(&stdout, "", 3, &boundary);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].stdout, "partial\n");
+        assert!(!results[1].success);
+    }
+
+    #[test]
+    fn test_split_blocks_partial_line() {
+        let boundary = Boundary::new();
+        let m = &boundary.marker;
+        // The first block ends without a newline
+        let stdout = format!("x\n{m} 0 ok\ny\n\n{m} 1 ok\n");
+
+        let results = split_blocks(&stdout, "", 2, &boundary);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].stdout, "x");
+        assert_eq!(results[1].stdout, "y\n");
+        assert!(results[1].success);
+    }
+
+    #[test]
+    fn test_boundaries_are_unique() {
+        assert_ne!(Boundary::new().marker, Boundary::new().marker);
+    }
+}
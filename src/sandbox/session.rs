@@ -0,0 +1,290 @@
+//! A high-level facade for the common case.
+//!
+//! A `Session` wires together a `SandboxConfig`, a `SharedEngine` and a
+//! module cache with sensible defaults, so most users can run code without
+//! learning how the lower-level pieces fit together:
+//!
+//! ```rust,ignore
+//! use wasm_python_sandbox_rs::prelude::*;
+//!
+//! let session = Session::builder().build()?;
+//! let result = session.run("print(1 + 1)").await?;
+//! assert_eq!(result.stdout.trim(), "2");
+//! ```
+//!
+//! For needs the facade does not cover (custom engine configuration, guest
+//! instrumentation, doctests, ...), drop down to the low-level API:
+//! `Session::sandbox` returns the underlying `PythonSandbox`, and
+//! `Session::engine` the `SharedEngine` other sandboxes can reuse through
+//! `SandboxOptions::with_engine`.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::sandbox::cache::{ModuleCache, SharedEngine};
+use crate::sandbox::config::SandboxConfig;
+use crate::sandbox::executor::{CallOptions, ExecutionResult, PythonSandbox, SandboxOptions};
+
+/// Default timeout for code run through a session.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A ready-to-use sandbox with best-practice defaults.
+///
+/// Build one with `Session::builder`. The interpreter is compiled once when
+/// the session is built; every `run` reuses it.
+pub struct Session {
+    sandbox: PythonSandbox,
+    engine: SharedEngine,
+}
+
+impl Session {
+    /// Create a builder for a session.
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::default()
+    }
+
+    /// Run Python code with the session's limits.
+    pub async fn run(&self, code: &str) -> Result<ExecutionResult> {
+        self.run_with(code, RunOpts::default()).await
+    }
+
+    /// Run Python code with per-call input and limit overrides.
+    ///
+    /// # Errors
+    /// Returns `SandboxError::Config` if `opts` sets fuel but the session
+    /// was built without `SessionBuilder::max_fuel`.
+    pub async fn run_with(&self, code: &str, opts: RunOpts) -> Result<ExecutionResult> {
+        let call = CallOptions {
+            timeout: opts.timeout,
+            max_memory: opts.max_memory,
+            max_fuel: opts.max_fuel,
+            ..CallOptions::default()
+        };
+        self.sandbox
+            .run_call(code, opts.input.as_deref().map(str::as_bytes), call)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Get the underlying sandbox, for the full low-level API.
+    pub fn sandbox(&self) -> &PythonSandbox {
+        &self.sandbox
+    }
+
+    /// Get the engine shared by this session.
+    pub fn engine(&self) -> &SharedEngine {
+        &self.engine
+    }
+}
+
+/// Builder for a `Session`.
+#[derive(Debug, Default)]
+pub struct SessionBuilder {
+    config: Option<SandboxConfig>,
+    interpreter_path: Option<PathBuf>,
+    timeout: Option<Duration>,
+    max_memory: Option<u64>,
+    max_fuel: Option<u64>,
+}
+
+impl SessionBuilder {
+    /// Start from a full sandbox configuration instead of the session defaults.
+    ///
+    /// Limits set on this builder still take precedence over `config`.
+    pub fn config(mut self, config: SandboxConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Set the path to the RustPython wasm interpreter.
+    pub fn interpreter_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.interpreter_path = Some(path.into());
+        self
+    }
+
+    /// Set the default execution timeout (10 seconds unless set).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the default memory limit in bytes.
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Set the default fuel limit.
+    ///
+    /// Enables fuel consumption on the session's engine, which also allows
+    /// `RunOpts::max_fuel` overrides.
+    pub fn max_fuel(mut self, fuel: u64) -> Self {
+        self.max_fuel = Some(fuel);
+        self
+    }
+
+    /// Build the session, compiling the interpreter.
+    pub fn build(self) -> Result<Session> {
+        let mut config = self.config.unwrap_or_else(|| SandboxConfig {
+            timeout: DEFAULT_TIMEOUT,
+            ..SandboxConfig::default()
+        });
+        if let Some(path) = self.interpreter_path {
+            config.interpreter_path = path;
+        }
+        if let Some(timeout) = self.timeout {
+            config.timeout = timeout;
+        }
+        if let Some(max_memory) = self.max_memory {
+            config.max_memory = max_memory;
+        }
+        if let Some(max_fuel) = self.max_fuel {
+            config.max_fuel = Some(max_fuel);
+        }
+
+        let engine = if config.max_fuel.is_some() {
+            SharedEngine::with_fuel()?
+        } else {
+            SharedEngine::new()?
+        };
+
+        // The global cache is keyed by path or content hash, not by engine,
+        // so give the session's engine a cache of its own.
+        let options =
+            SandboxOptions::with_engine(engine.clone()).cache(Arc::new(ModuleCache::new()));
+        let sandbox = PythonSandbox::new_with_options(config, options)?;
+
+        Ok(Session { sandbox, engine })
+    }
+}
+
+/// Per-call options for `Session::run_with`.
+#[derive(Debug, Clone, Default)]
+pub struct RunOpts {
+    /// Stdin input for the code.
+    pub input: Option<String>,
+    /// Timeout for this call.
+    pub timeout: Option<Duration>,
+    /// Memory limit in bytes for this call.
+    ///
+    /// Enforced on top of `SandboxConfig::memory_policy`, if set.
+    pub max_memory: Option<u64>,
+    /// Fuel limit for this call.
+    pub max_fuel: Option<u64>,
+}
+
+impl RunOpts {
+    /// Create options that use the session's defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the stdin input.
+    pub fn input(mut self, input: impl Into<String>) -> Self {
+        self.input = Some(input.into());
+        self
+    }
+
+    /// Set the timeout for this call.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the memory limit in bytes for this call.
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Set the fuel limit for this call.
+    pub fn max_fuel(mut self, fuel: u64) -> Self {
+        self.max_fuel = Some(fuel);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SandboxError;
+
+    #[tokio::test]
+    async fn test_builder_applies_overrides() {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-python-sandbox-session-spin-{}.wat",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_start") (loop $spin (br $spin))))"#,
+        )
+        .unwrap();
+        let config = SandboxConfig::builder().interpreter_path(&path).build();
+
+        // The overrides apply on top of a full configuration
+        let timed = Session::builder()
+            .config(config.clone())
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert!(timed.run("").await.unwrap_err().is_timeout());
+
+        let fueled = Session::builder()
+            .config(config)
+            .timeout(Duration::from_secs(10))
+            .max_fuel(10_000)
+            .build()
+            .unwrap();
+        assert!(fueled.run("").await.unwrap_err().is_out_of_fuel());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_interpreter() {
+        let result = Session::builder()
+            .interpreter_path("/nonexistent/rustpython.wasm")
+            .build();
+
+        assert!(matches!(result, Err(SandboxError::InterpreterNotFound(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_session_run() {
+        let session = Session::builder().build().unwrap();
+
+        let result = session.run("print(1 + 1)").await.unwrap();
+
+        assert_eq!(result.stdout.trim(), "2");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires rustpython.wasm"]
+    async fn test_session_run_with_overrides() {
+        let session = Session::builder().max_fuel(u64::MAX).build().unwrap();
+
+        let echoed = session
+            .run_with("print(input())", RunOpts::new().input("hello\n"))
+            .await
+            .unwrap();
+        let starved = session
+            .run_with("while True: pass", RunOpts::new().max_fuel(10_000))
+            .await;
+        let timed_out = session
+            .run_with(
+                "import time\ntime.sleep(5)",
+                RunOpts::new().timeout(Duration::from_millis(200)),
+            )
+            .await;
+
+        assert_eq!(echoed.stdout.trim(), "hello");
+        assert!(starved.unwrap_err().is_out_of_fuel());
+        assert!(timed_out.unwrap_err().is_timeout());
+    }
+}
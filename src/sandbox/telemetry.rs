@@ -0,0 +1,145 @@
+//! Execution spans with OpenTelemetry-friendly attributes.
+//!
+//! With the `tracing` feature, every execution runs inside a
+//! `sandbox.execute` span whose field names follow OpenTelemetry attribute
+//! conventions. Exported through the `tracing-opentelemetry` layer, the
+//! fields become span attributes as-is, so no custom mapping is needed:
+//!
+//! | Attribute             | Value                                        |
+//! |-----------------------|----------------------------------------------|
+//! | `code.language`       | Always `python`                              |
+//! | `sandbox.timeout_ms`  | Effective timeout of the execution           |
+//! | `sandbox.max_memory`  | Effective memory limit in bytes              |
+//! | `sandbox.peak_memory` | Peak linear memory in bytes, once finished   |
+//! | `sandbox.outcome`     | One of the values returned by `outcome`      |
+//!
+//! The spans created by `#[instrument]` on the public methods (`execute`,
+//! `new`, ...) are parents of this span and carry call-level fields such
+//! as `code_len`.
+
+use tracing::field::Empty;
+use tracing::Span;
+
+use crate::error::Result;
+use crate::sandbox::executor::ExecutionResult;
+
+/// Create the span for one execution with the given effective limits.
+pub(crate) fn execution_span(timeout_ms: u64, max_memory: u64) -> Span {
+    tracing::info_span!(
+        "sandbox.execute",
+        code.language = "python",
+        sandbox.timeout_ms = timeout_ms,
+        sandbox.max_memory = max_memory,
+        sandbox.peak_memory = Empty,
+        sandbox.outcome = Empty,
+    )
+}
+
+/// Record how an execution ended on its span.
+pub(crate) fn record_result<T>(span: &Span, result: &Result<(ExecutionResult, T)>) {
+    if let Ok((res, _)) = result {
+        span.record("sandbox.peak_memory", res.metadata.peak_memory);
+    }
+    span.record("sandbox.outcome", outcome(result));
+}
+
+/// Short, stable name for how an execution ended.
+///
+/// `success` and `failure` are executions that ran to completion with a
+/// zero and non-zero exit code; the others are errors.
+pub(crate) fn outcome<T>(result: &Result<(ExecutionResult, T)>) -> &'static str {
+    match result {
+        Ok((res, _)) if res.exit_code == 0 => "success",
+        Ok(_) => "failure",
+        Err(e) if e.is_timeout() => "timeout",
+        Err(e) if e.is_memory_limit() => "memory_limit",
+        Err(e) if e.is_out_of_fuel() => "out_of_fuel",
+        Err(e) if e.is_cancelled() => "cancelled",
+        Err(e) if e.is_expired() => "expired",
+        Err(_) => "error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    use super::*;
+    use crate::error::{SandboxError, TimeoutPhase};
+
+    /// A layer keeping the last value recorded for every span field.
+    #[derive(Clone, Default)]
+    struct FieldRecorder {
+        fields: Arc<Mutex<HashMap<String, String>>>,
+    }
+
+    impl Visit for FieldRecorder {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let mut fields = self.fields.lock().unwrap();
+            fields.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let mut fields = self.fields.lock().unwrap();
+            fields.insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for FieldRecorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    fn finished(exit_code: i32) -> Result<(ExecutionResult, ())> {
+        Ok((ExecutionResult::with_exit_code(exit_code), ()))
+    }
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(outcome(&finished(0)), "success");
+        assert_eq!(outcome(&finished(1)), "failure");
+
+        let timeout: Result<(ExecutionResult, ())> = Err(SandboxError::Timeout {
+            elapsed: std::time::Duration::from_secs(1),
+            phase: TimeoutPhase::Execution,
+        });
+        assert_eq!(outcome(&timeout), "timeout");
+        let cancelled: Result<(ExecutionResult, ())> = Err(SandboxError::Cancelled);
+        assert_eq!(outcome(&cancelled), "cancelled");
+        let config: Result<(ExecutionResult, ())> = Err(SandboxError::Config(String::new()));
+        assert_eq!(outcome(&config), "error");
+    }
+
+    #[test]
+    fn test_span_attributes() {
+        let recorder = FieldRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = execution_span(5000, 1 << 20);
+            let mut result = finished(0);
+            if let Ok((ref mut res, _)) = result {
+                res.metadata.peak_memory = 4096;
+            }
+            record_result(&span, &result);
+        });
+
+        let fields = recorder.fields.lock().unwrap();
+        assert_eq!(fields["code.language"], "python");
+        assert_eq!(fields["sandbox.timeout_ms"], "5000");
+        assert_eq!(fields["sandbox.max_memory"], "1048576");
+        assert_eq!(fields["sandbox.peak_memory"], "4096");
+        assert_eq!(fields["sandbox.outcome"], "success");
+    }
+}
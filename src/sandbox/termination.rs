@@ -0,0 +1,194 @@
+//! A single classification of how an execution ended.
+//!
+//! `PythonSandbox::execute` reports a run that ended on its own as
+//! `Ok(ExecutionResult)` and a run the sandbox stopped as one of several
+//! `SandboxError` variants, mixed in with errors that have nothing to do
+//! with the code. `PythonSandbox::run` folds both into a `Termination`,
+//! leaving `Err` for failures to start the execution at all.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::{PartialOutput, Result, SandboxError, TimeoutPhase};
+use crate::sandbox::executor::ExecutionResult;
+
+/// Why an execution ended, as returned by `PythonSandbox::run`.
+#[derive(Debug)]
+pub enum Termination {
+    /// The interpreter exited on its own, successfully or not.
+    ///
+    /// An uncaught Python exception ends here with a non-zero `exit_code`.
+    Completed {
+        /// Exit code of the interpreter (0 for success).
+        exit_code: i32,
+        /// The full result of the execution.
+        result: Box<ExecutionResult>,
+    },
+    /// The execution ran past its timeout.
+    Timeout {
+        /// Phase that was running when the timeout occurred.
+        phase: TimeoutPhase,
+        /// Time elapsed before the timeout was detected.
+        elapsed: Duration,
+    },
+    /// The execution tried to grow memory past its limit.
+    MemoryLimit {
+        /// Description of the memory use at the time.
+        message: String,
+    },
+    /// The execution ran out of fuel.
+    OutOfFuel {
+        /// Number of instructions consumed before running out.
+        consumed: Option<u64>,
+    },
+    /// The interpreter exhausted the Wasm call stack.
+    StackOverflow,
+    /// The interpreter hit a Wasm trap and a coredump was written for it.
+    ///
+    /// Traps without a coredump end as `Failed`.
+    InterpreterTrap {
+        /// Description of the trap.
+        message: String,
+        /// Path of the coredump file.
+        coredump: PathBuf,
+    },
+    /// The execution was stopped for its output.
+    ///
+    /// Either stdout went past `max_output_lines` with
+    /// `terminate_on_output_lines` set, the output rate went over
+    /// `max_output_rate`, or the files written to `/out` went over
+    /// `max_output_bytes`.
+    OutputLimit {
+        /// Output kept up to the point the execution was stopped. Empty for
+        /// a rate or file limit unless `flush_on_trap` is set.
+        output: PartialOutput,
+    },
+    /// The execution was stopped for another reason, such as
+    /// `max_exceptions` or `max_io_time`.
+    Failed(SandboxError),
+}
+
+impl Termination {
+    /// Classify the outcome of an execution.
+    ///
+    /// `stopped_on_output_lines` tells whether a result cut at the output
+    /// line limit was stopped there rather than left to finish. Errors that
+    /// prevent the execution from starting are returned as `Err`.
+    pub(crate) fn from_outcome(
+        outcome: Result<ExecutionResult>,
+        stopped_on_output_lines: bool,
+    ) -> Result<Self> {
+        let error = match outcome {
+            Ok(result) if stopped_on_output_lines && result.output_line_limited => {
+                return Ok(Termination::OutputLimit {
+                    output: PartialOutput {
+                        stdout: result.stdout,
+                        stderr: result.stderr,
+                    },
+                });
+            }
+            Ok(result) => {
+                return Ok(Termination::Completed {
+                    exit_code: result.exit_code,
+                    result: Box::new(result),
+                });
+            }
+            Err(error) => error,
+        };
+        let output = error.partial_output().cloned().unwrap_or_default();
+        let termination = match error.cause() {
+            SandboxError::Timeout { phase, elapsed } => Termination::Timeout {
+                phase: *phase,
+                elapsed: *elapsed,
+            },
+            SandboxError::MemoryLimitExceeded(message) => Termination::MemoryLimit {
+                message: message.clone(),
+            },
+            SandboxError::OutOfFuel { consumed } => Termination::OutOfFuel {
+                consumed: *consumed,
+            },
+            SandboxError::StackOverflow => Termination::StackOverflow,
+            SandboxError::InterpreterTrap { message, coredump } => Termination::InterpreterTrap {
+                message: message.clone(),
+                coredump: coredump.clone(),
+            },
+            SandboxError::OutputRateExceeded { .. } | SandboxError::OutputFilesExceeded { .. } => {
+                Termination::OutputLimit { output }
+            }
+            SandboxError::RuntimeInit(_)
+            | SandboxError::ModuleLoad(_)
+            | SandboxError::Config(_)
+            | SandboxError::InterpreterNotFound(_)
+            | SandboxError::Io(_)
+            | SandboxError::Expired { .. } => return Err(error),
+            _ => Termination::Failed(error),
+        };
+        Ok(termination)
+    }
+
+    /// Check if the interpreter exited on its own with exit code 0.
+    pub fn is_success(&self) -> bool {
+        matches!(self, Termination::Completed { exit_code: 0, .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(exit_code: i32, output_line_limited: bool) -> ExecutionResult {
+        ExecutionResult {
+            stdout: "1\n2\n".to_string(),
+            output_line_limited,
+            ..ExecutionResult::with_exit_code(exit_code)
+        }
+    }
+
+    #[test]
+    fn test_from_outcome_results() {
+        let completed = Termination::from_outcome(Ok(result(1, false)), true).unwrap();
+        assert!(matches!(
+            completed,
+            Termination::Completed { exit_code: 1, .. }
+        ));
+        assert!(!completed.is_success());
+
+        // A cut result only counts as stopped when the limit terminates
+        let cut = Termination::from_outcome(Ok(result(0, true)), false).unwrap();
+        assert!(cut.is_success());
+        let stopped = Termination::from_outcome(Ok(result(0, true)), true).unwrap();
+        assert!(
+            matches!(stopped, Termination::OutputLimit { ref output } if output.stdout == "1\n2\n")
+        );
+    }
+
+    #[test]
+    fn test_from_outcome_errors() {
+        let classify = |error| Termination::from_outcome(Err(error), false);
+
+        assert!(matches!(
+            classify(SandboxError::OutOfFuel { consumed: Some(7) }),
+            Ok(Termination::OutOfFuel { consumed: Some(7) })
+        ));
+        assert!(matches!(
+            classify(SandboxError::Interrupted {
+                error: Box::new(SandboxError::OutputRateExceeded { rate: 10, limit: 1 }),
+                output: PartialOutput {
+                    stdout: "spam".to_string(),
+                    stderr: String::new(),
+                },
+            }),
+            Ok(Termination::OutputLimit { ref output }) if output.stdout == "spam"
+        ));
+        assert!(matches!(
+            classify(SandboxError::ExceptionLimitExceeded { limit: 1 }),
+            Ok(Termination::Failed(SandboxError::ExceptionLimitExceeded {
+                limit: 1
+            }))
+        ));
+        assert!(matches!(
+            classify(SandboxError::Config("bad".to_string())),
+            Err(SandboxError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! The epoch ticker shared by all executions on one engine.
+//!
+//! Timeouts rely on wasmtime's epoch interruption: a background task
+//! increments the engine's epoch at a steady rate, and each store checks,
+//! whenever its epoch deadline is reached, whether its own execution should
+//! stop. The epoch is a property of the engine, not of the store, so one
+//! ticker per engine is enough however many executions run on it.
+//!
+//! Every execution holds a `TickerLease` for as long as its guest runs.
+//! The first lease starts the ticker and dropping the last one stops it,
+//! so an idle engine has no background task.
+//!
+//! The task lives on the runtime of whichever call started it. An engine
+//! shared between runtimes can outlive that runtime while leases are still
+//! held, so a lease also restarts the task, on its own runtime, if the
+//! previous one has died.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use wasmtime::Engine;
+
+/// Drives the epoch of one engine while executions are running on it.
+#[derive(Clone)]
+pub(crate) struct EpochTicker {
+    inner: Arc<TickerInner>,
+}
+
+struct TickerInner {
+    engine: Arc<Engine>,
+    state: Mutex<TickerState>,
+}
+
+#[derive(Default)]
+struct TickerState {
+    leases: usize,
+    interval: Option<Duration>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl EpochTicker {
+    /// Create a stopped ticker for `engine`.
+    pub(crate) fn new(engine: Arc<Engine>) -> Self {
+        Self {
+            inner: Arc::new(TickerInner {
+                engine,
+                state: Mutex::new(TickerState::default()),
+            }),
+        }
+    }
+
+    /// The engine whose epoch this ticker advances.
+    pub(crate) fn engine(&self) -> &Arc<Engine> {
+        &self.inner.engine
+    }
+
+    /// Keep the ticker running, at `interval` or faster, until the lease is dropped.
+    ///
+    /// The ticker runs at the smallest interval requested by any lease
+    /// taken since it last started. Must be called within a Tokio runtime.
+    pub(crate) fn acquire(&self, interval: Duration) -> TickerLease {
+        let mut state = self.inner.state.lock().unwrap();
+        state.leases += 1;
+        let fastest = match state.interval {
+            Some(current) => current.min(interval),
+            None => interval,
+        };
+        // The task stops with the runtime it was spawned on
+        let alive = matches!(state.task, Some(ref task) if !task.is_finished());
+        if !alive || state.interval != Some(fastest) {
+            if let Some(task) = state.task.take() {
+                task.abort();
+            }
+            state.interval = Some(fastest);
+            state.task = Some(self.spawn(fastest));
+        }
+        TickerLease {
+            ticker: self.clone(),
+        }
+    }
+
+    /// Check whether the ticker task is running.
+    #[cfg(test)]
+    pub(crate) fn is_running(&self) -> bool {
+        let state = self.inner.state.lock().unwrap();
+        matches!(state.task, Some(ref task) if !task.is_finished())
+    }
+
+    fn spawn(&self, period: Duration) -> JoinHandle<()> {
+        let engine = Arc::clone(&self.inner.engine);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            // Catching up after a stall would advance the epoch in a burst
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                engine.increment_epoch();
+            }
+        })
+    }
+
+    fn release(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.leases -= 1;
+        if state.leases == 0 {
+            if let Some(task) = state.task.take() {
+                task.abort();
+            }
+            state.interval = None;
+        }
+    }
+}
+
+/// Keeps an `EpochTicker` running while held.
+pub(crate) struct TickerLease {
+    ticker: EpochTicker,
+}
+
+impl Drop for TickerLease {
+    fn drop(&mut self) {
+        self.ticker.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ticker_runs_while_leased() {
+        let ticker = EpochTicker::new(Arc::new(Engine::default()));
+        assert!(!ticker.is_running());
+
+        let first = ticker.acquire(Duration::from_millis(10));
+        let second = ticker.acquire(Duration::from_millis(10));
+        assert!(ticker.is_running());
+
+        drop(first);
+        assert!(ticker.is_running());
+        drop(second);
+        assert!(!ticker.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_ticker_uses_smallest_interval() {
+        let ticker = EpochTicker::new(Arc::new(Engine::default()));
+
+        let _slow = ticker.acquire(Duration::from_millis(50));
+        let _fast = ticker.acquire(Duration::from_millis(5));
+        let _slower = ticker.acquire(Duration::from_millis(100));
+
+        let state = ticker.inner.state.lock().unwrap();
+        assert_eq!(state.interval, Some(Duration::from_millis(5)));
+        assert_eq!(state.leases, 3);
+    }
+
+    #[test]
+    fn test_ticker_restarts_on_new_runtime() {
+        let ticker = EpochTicker::new(Arc::new(Engine::default()));
+        let runtime = || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap()
+        };
+
+        // A lease outlives the runtime that started the ticker
+        let first = runtime();
+        let held = first.block_on(async { ticker.acquire(Duration::from_millis(5)) });
+        assert!(ticker.is_running());
+        drop(first);
+        assert!(!ticker.is_running());
+
+        // The next lease restarts it on its own runtime
+        let second = runtime();
+        second.block_on(async {
+            let _lease = ticker.acquire(Duration::from_millis(10));
+            assert!(ticker.is_running());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(ticker.is_running());
+            assert_eq!(
+                ticker.inner.state.lock().unwrap().interval,
+                Some(Duration::from_millis(5))
+            );
+        });
+        drop(held);
+    }
+}